@@ -31,6 +31,7 @@ pub fn run(_repository: &Path, pathspec: Vec<PathBuf>, all: bool, update: bool)
 
 fn add_all_files(repo: &gix::Repository) -> Result<()> {
     let mut index = repo.index_or_load_from_head_or_empty()?.into_owned();
+    let (mut pipeline, _) = repo.filter_pipeline(None)?;
     let mut added_files = 0;
 
     let status_iter = repo
@@ -44,7 +45,7 @@ fn add_all_files(repo: &gix::Repository) -> Result<()> {
                     .workdir()
                     .unwrap()
                     .join(gix::path::from_bstr(&rela_path));
-                if add_file_to_index(repo, &full_path, &mut index)? {
+                if add_file_to_index(repo, &mut pipeline, &full_path, &mut index)? {
                     added_files += 1;
                 }
             }
@@ -53,7 +54,7 @@ fn add_all_files(repo: &gix::Repository) -> Result<()> {
                     .workdir()
                     .unwrap()
                     .join(gix::path::from_bstr(&entry.rela_path));
-                if add_file_to_index(repo, &full_path, &mut index)? {
+                if add_file_to_index(repo, &mut pipeline, &full_path, &mut index)? {
                     added_files += 1;
                 }
             }
@@ -62,7 +63,7 @@ fn add_all_files(repo: &gix::Repository) -> Result<()> {
                     .workdir()
                     .unwrap()
                     .join(gix::path::from_bstr(&dirwalk_entry.rela_path));
-                if add_file_to_index(repo, &full_path, &mut index)? {
+                if add_file_to_index(repo, &mut pipeline, &full_path, &mut index)? {
                     added_files += 1;
                 }
             }
@@ -78,6 +79,7 @@ fn add_all_files(repo: &gix::Repository) -> Result<()> {
 
 fn add_pathspec_files(repo: &gix::Repository, pathspecs: Vec<PathBuf>) -> Result<()> {
     let mut index = repo.index_or_load_from_head_or_empty()?.into_owned();
+    let (mut pipeline, _) = repo.filter_pipeline(None)?;
     let mut added_files = 0;
 
     let work_dir = repo.workdir().unwrap();
@@ -91,7 +93,7 @@ fn add_pathspec_files(repo: &gix::Repository, pathspecs: Vec<PathBuf>) -> Result
 
         if full_path.exists() {
             if full_path.is_file() {
-                if add_file_to_index(repo, &full_path, &mut index)? {
+                if add_file_to_index(repo, &mut pipeline, &full_path, &mut index)? {
                     added_files += 1;
                 }
             } else if full_path.is_dir() {
@@ -100,7 +102,7 @@ fn add_pathspec_files(repo: &gix::Repository, pathspecs: Vec<PathBuf>) -> Result
                     .filter_map(|e| e.ok())
                     .filter(|e| e.file_type().is_file())
                 {
-                    if add_file_to_index(repo, entry.path(), &mut index)? {
+                    if add_file_to_index(repo, &mut pipeline, entry.path(), &mut index)? {
                         added_files += 1;
                     }
                 }
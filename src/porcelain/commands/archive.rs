@@ -0,0 +1,338 @@
+use anyhow::Result;
+use gix::bstr::{BStr, BString, ByteSlice};
+use gix::worktree::archive::Format;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+use super::index_utils::{matches_pathspec, normalize_pathspecs};
+use super::revision_utils::rev_parse_single;
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// The archive format, `tar`, `tgz` or `zip`, or `None` to pick it by the name of the output file
+    pub format: Option<String>,
+    /// Put in front of every path in the archive, verbatim
+    pub prefix: Option<String>,
+    /// Write the archive to this file instead of stdout
+    pub output: Option<PathBuf>,
+}
+
+/// Write an archive of the tree of `treeish`, limited to `paths` if there are any.
+///
+/// Paths with the `export-ignore` attribute are left out, and files with the `export-subst` attribute
+/// have their `$Format:...$` placeholders expanded if a commit is archived, whose time all entries get.
+pub fn run(treeish: String, paths: Vec<PathBuf>, opts: Options) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    // Expanded files are only written to memory, so archiving doesn't leave objects behind.
+    let repo = repo.with_object_memory();
+    let Some(format) = archive_format(&opts) else {
+        return Ok(());
+    };
+    let Some(object) = rev_parse_single(&repo, &treeish)
+        .ok()
+        .and_then(|id| id.object().ok())
+    else {
+        println!("fatal: not a valid object name: {}", treeish);
+        return Ok(());
+    };
+    let object = object.peel_tags_to_end()?;
+    let commit = object.clone().try_into_commit().ok();
+    let Ok(tree) = object.peel_to_tree() else {
+        println!("fatal: not a tree object: {}", treeish);
+        return Ok(());
+    };
+    let Some(tree_id) = archived_tree(&repo, tree.id, commit.as_ref(), &paths)? else {
+        return Ok(());
+    };
+
+    let mut options = gix::worktree::archive::Options {
+        format,
+        tree_prefix: opts.prefix.map(Into::into),
+        ..Default::default()
+    };
+    if let Some(commit) = &commit {
+        options.modification_time = commit.time()?.seconds;
+    }
+    let (stream, _) = repo.worktree_stream(tree_id)?;
+    let interrupt = std::sync::atomic::AtomicBool::new(false);
+    match &opts.output {
+        Some(path) => {
+            let file = match std::fs::File::create(path) {
+                Ok(file) => file,
+                Err(err) => {
+                    println!(
+                        "fatal: could not create archive file '{}': {}",
+                        path.display(),
+                        err
+                    );
+                    return Ok(());
+                }
+            };
+            let mut out = std::io::BufWriter::new(file);
+            repo.worktree_archive(
+                stream,
+                &mut out,
+                gix::progress::Discard,
+                &interrupt,
+                options,
+            )?;
+            out.flush()?;
+        }
+        None => {
+            // Zip archives are written by seeking back, which stdout can't do.
+            let mut out = std::io::Cursor::new(Vec::new());
+            repo.worktree_archive(
+                stream,
+                &mut out,
+                gix::progress::Discard,
+                &interrupt,
+                options,
+            )?;
+            std::io::stdout().write_all(out.get_ref())?;
+        }
+    }
+    Ok(())
+}
+
+/// The format given with `--format`, or the one matching the name of the output file, or tar.
+///
+/// Returns `None` after reporting an unknown format.
+fn archive_format(opts: &Options) -> Option<Format> {
+    let name = match (&opts.format, &opts.output) {
+        (Some(format), _) => format.clone(),
+        (None, Some(output)) => {
+            let file_name = output.to_string_lossy();
+            [(".tar.gz", "tgz"), (".tgz", "tgz"), (".zip", "zip")]
+                .into_iter()
+                .find(|(extension, _)| file_name.ends_with(extension))
+                .map_or("tar", |(_, format)| format)
+                .to_string()
+        }
+        (None, None) => "tar".to_string(),
+    };
+    match name.as_str() {
+        "tar" => Some(Format::Tar),
+        "tgz" | "tar.gz" => Some(Format::TarGz {
+            compression_level: None,
+        }),
+        "zip" => Some(Format::Zip {
+            compression_level: None,
+        }),
+        other => {
+            println!("fatal: Unknown archive format '{}'", other);
+            None
+        }
+    }
+}
+
+/// Write the tree to archive, which is `tree` with only the paths matching `paths` that aren't marked with
+/// `export-ignore`, and with the placeholders expanded in files marked with `export-subst` if there is a `commit`.
+///
+/// Returns `None` after reporting a path that matches nothing.
+fn archived_tree(
+    repo: &gix::Repository,
+    tree: gix::ObjectId,
+    commit: Option<&gix::Commit<'_>>,
+    paths: &[PathBuf],
+) -> Result<Option<gix::ObjectId>> {
+    let pathspecs = normalize_pathspecs(paths);
+    let index = super::promisor::index_from_tree(repo, &tree)?;
+    let mut attributes = Attributes::new(repo, &index)?;
+    let mut editor = repo.edit_tree(gix::ObjectId::empty_tree(repo.object_hash()))?;
+    let mut matched = vec![false; pathspecs.len()];
+    for entry in index.entries() {
+        let path = entry.path(&index);
+        let mut included = pathspecs.is_empty();
+        for (spec, matched) in pathspecs.iter().zip(matched.iter_mut()) {
+            if matches_pathspec(std::slice::from_ref(spec), path) {
+                *matched = true;
+                included = true;
+            }
+        }
+        let Some(mode) = entry.mode.to_tree_entry_mode() else {
+            continue;
+        };
+        if !included || attributes.export_ignore(path, entry.mode)? {
+            continue;
+        }
+        let id = match commit {
+            Some(commit) if mode.is_blob() && attributes.export_subst(path, entry.mode)? => {
+                let blob = repo.find_object(entry.id)?;
+                repo.write_blob(expand_placeholders(&blob.data, commit)?)?
+                    .detach()
+            }
+            _ => entry.id,
+        };
+        editor.upsert(path, mode.kind(), id)?;
+    }
+    if let Some(unmatched) = matched.iter().position(|matched| !matched) {
+        println!(
+            "fatal: pathspec '{}' did not match any files",
+            paths[unmatched].display()
+        );
+        return Ok(None);
+    }
+    Ok(Some(editor.write()?.detach()))
+}
+
+/// The archive attributes of paths, as set by the `.gitattributes` files in the archived tree
+struct Attributes<'repo> {
+    stack: gix::AttributeStack<'repo>,
+    outcome: gix::attrs::search::Outcome,
+    /// Whether directories are marked with `export-ignore`, which applies to everything in them
+    ignored_dirs: HashMap<BString, bool>,
+}
+
+impl<'repo> Attributes<'repo> {
+    fn new(repo: &'repo gix::Repository, index: &gix::index::File) -> Result<Self> {
+        let stack = repo.attributes_only(
+            index,
+            gix::worktree::stack::state::attributes::Source::IdMapping,
+        )?;
+        let outcome = stack.selected_attribute_matches(["export-ignore", "export-subst"]);
+        Ok(Attributes {
+            stack,
+            outcome,
+            ignored_dirs: HashMap::new(),
+        })
+    }
+
+    /// Whether the entry at `path` or one of the directories containing it is marked with `export-ignore`.
+    fn export_ignore(&mut self, path: &BStr, mode: gix::index::entry::Mode) -> Result<bool> {
+        let mut end = 0;
+        while let Some(slash) = path[end..].find_byte(b'/') {
+            end += slash;
+            let dir = path[..end].as_bstr();
+            let ignored = match self.ignored_dirs.get(dir) {
+                Some(ignored) => *ignored,
+                None => {
+                    let ignored =
+                        self.is_set(dir, gix::index::entry::Mode::DIR, "export-ignore")?;
+                    self.ignored_dirs.insert(dir.to_owned(), ignored);
+                    ignored
+                }
+            };
+            if ignored {
+                return Ok(true);
+            }
+            end += 1;
+        }
+        self.is_set(path, mode, "export-ignore")
+    }
+
+    /// Whether the file at `path` is marked with `export-subst`.
+    fn export_subst(&mut self, path: &BStr, mode: gix::index::entry::Mode) -> Result<bool> {
+        self.is_set(path, mode, "export-subst")
+    }
+
+    fn is_set(&mut self, path: &BStr, mode: gix::index::entry::Mode, name: &str) -> Result<bool> {
+        self.stack
+            .at_entry(path, Some(mode))?
+            .matching_attributes(&mut self.outcome);
+        Ok(self
+            .outcome
+            .iter_selected()
+            .any(|m| m.assignment.name.as_str() == name && m.assignment.state.is_set()))
+    }
+}
+
+/// Replace each `$Format:...$` in `data` with what its placeholders describe about `commit`.
+fn expand_placeholders(data: &[u8], commit: &gix::Commit<'_>) -> Result<Vec<u8>> {
+    const START: &[u8] = b"$Format:";
+    let mut out = Vec::with_capacity(data.len());
+    let mut rest = data;
+    while let Some(start) = rest.find(START) {
+        let format = &rest[start + START.len()..];
+        let Some(end) = format.find_byte(b'$') else {
+            break;
+        };
+        out.extend_from_slice(&rest[..start]);
+        out.extend_from_slice(&format_commit(format[..end].as_bstr(), commit)?);
+        rest = &format[end + 1..];
+    }
+    out.extend_from_slice(rest);
+    Ok(out)
+}
+
+/// Format `commit` according to `format`, supporting the most common placeholders of `git log --format`.
+///
+/// Unknown placeholders are kept as they are, like git does.
+fn format_commit(format: &BStr, commit: &gix::Commit<'_>) -> Result<BString> {
+    let mut out = BString::default();
+    let mut rest = format.as_bytes();
+    while let Some(percent) = rest.find_byte(b'%') {
+        out.extend_from_slice(&rest[..percent]);
+        rest = &rest[percent + 1..];
+        match placeholder(rest, commit)? {
+            Some((value, len)) => {
+                out.extend_from_slice(value.as_bytes());
+                rest = &rest[len..];
+            }
+            None => out.push(b'%'),
+        }
+    }
+    out.extend_from_slice(rest);
+    Ok(out)
+}
+
+/// The value of the placeholder at the start of `spec`, which follows a `%`, along with its length.
+fn placeholder(spec: &[u8], commit: &gix::Commit<'_>) -> Result<Option<(String, usize)>> {
+    let parents = |short: bool| {
+        commit
+            .parent_ids()
+            .map(|id| {
+                if short {
+                    id.shorten_or_id().to_string()
+                } else {
+                    id.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let value = match spec {
+        [b'%', ..] => "%".to_string(),
+        [b'n', ..] => "\n".to_string(),
+        [b'H', ..] => commit.id.to_string(),
+        [b'h', ..] => commit.short_id()?.to_string(),
+        [b'T', ..] => commit.tree_id()?.to_string(),
+        [b't', ..] => commit.tree_id()?.shorten_or_id().to_string(),
+        [b'P', ..] => parents(false),
+        [b'p', ..] => parents(true),
+        [b's', ..] => commit.message()?.summary().to_string(),
+        [who @ (b'a' | b'c'), field, ..] => {
+            let signature = if *who == b'a' {
+                commit.author()?
+            } else {
+                commit.committer()?
+            };
+            let value = match field {
+                b'n' => signature.name.to_string(),
+                b'e' => signature.email.to_string(),
+                b'd' => signature.time.format(gix::date::time::format::DEFAULT),
+                b'i' => signature.time.format(gix::date::time::format::ISO8601),
+                b'I' => signature
+                    .time
+                    .format(gix::date::time::format::ISO8601_STRICT),
+                b't' => signature.time.seconds.to_string(),
+                _ => return Ok(None),
+            };
+            return Ok(Some((value, 2)));
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some((value, 1)))
+}
+
+fn open_repo() -> Option<gix::Repository> {
+    match gix::discover(".") {
+        Ok(repo) => Some(repo),
+        Err(_) => {
+            println!("fatal: not a git repository (or any of the parent directories): .git");
+            None
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use anyhow::Result;
-use gitoxide_core as core;
+use gix::bstr::ByteSlice;
+use std::io::Write;
 use std::path::Path;
 
 pub fn run(
@@ -16,26 +17,51 @@ pub fn run(
         }
     };
 
-    let mut out = std::io::stdout();
-    let mut err = std::io::stderr();
+    let mut out = std::io::stdout().lock();
 
-    let diff_algorithm = repo.diff_algorithm()?;
-
-    let file_path = std::ffi::OsString::from(&file);
+    let options = gix::blame::Options {
+        diff_algorithm: repo.diff_algorithm()?,
+        range: gix::blame::BlameRanges::from_ranges(ranges),
+        since: None,
+        rewrites: Some(gix::diff::Rewrites::default()),
+        debug_track_path: false,
+    };
+    let suspect = repo.head_commit()?.id;
+    let cache = repo.commit_graph_if_enabled()?;
+    let mut resource_cache = repo.diff_resource_cache_for_tree_diff()?;
+    let file_path =
+        gix::path::to_unix_separators_on_windows(gix::path::into_bstr(Path::new(&file)));
 
-    core::repository::blame::blame_file(
-        repo,
-        &file_path,
-        gix::blame::Options {
-            diff_algorithm,
-            range: gix::blame::BlameRanges::from_ranges(ranges),
-            since: None,
-            rewrites: Some(gix::diff::Rewrites::default()),
-            debug_track_path: false,
-        },
-        &mut out,
-        if statistics { Some(&mut err) } else { None },
+    // Look up objects through the promisor remote, so that a partial clone only fetches the blobs and trees
+    // blame actually visits.
+    let outcome = gix::blame::file(
+        super::promisor::Objects::new(&repo)?,
+        suspect,
+        cache,
+        &mut resource_cache,
+        file_path.as_bstr(),
+        options,
     )?;
 
+    for (entry, lines) in outcome.entries_with_lines() {
+        for ((line_no, source_line_no), line) in entry
+            .range_in_blamed_file()
+            .zip(entry.range_in_source_file())
+            .zip(lines)
+        {
+            write!(
+                out,
+                "{} {} {} {}",
+                entry.commit_id.to_hex_with_len(8),
+                line_no + 1,
+                source_line_no + 1,
+                line
+            )?;
+        }
+    }
+    if statistics {
+        eprintln!("{:#?}", outcome.statistics);
+    }
+
     Ok(())
 }
@@ -0,0 +1,221 @@
+use anyhow::Result;
+use gix::bstr::{BStr, BString, ByteSlice};
+use gix::dir::entry::{Kind, Status};
+use gix::dir::walk::{EmissionMode, ForDeletionMode};
+use std::path::Path;
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Only print what would be removed
+    pub dry_run: bool,
+    /// How often `-f` was given, with two allowing nested repositories to be removed
+    pub force: u8,
+    /// Don't print the paths that are removed
+    pub quiet: bool,
+    /// Remove untracked directories too
+    pub directories: bool,
+    /// Remove ignored files too, ignoring the standard ignore rules
+    pub ignored: bool,
+    /// Remove only ignored files
+    pub only_ignored: bool,
+    /// Additional ignore patterns, which apply even with `ignored`
+    pub excludes: Vec<String>,
+}
+
+/// Remove the untracked files matching `pathspecs`, or all of them in the current directory.
+///
+/// Precious files, marked with `$` in `.gitignore`, are always kept, as are nested repositories unless forced twice.
+pub fn run(pathspecs: Vec<String>, opts: Options) -> Result<()> {
+    let repo = match gix::discover(".") {
+        Ok(repo) => repo,
+        Err(_) => {
+            println!("fatal: not a git repository (or any of the parent directories): .git");
+            return Ok(());
+        }
+    };
+    let Some(workdir) = repo.workdir().map(Path::to_owned) else {
+        println!("fatal: this operation must be run in a work tree");
+        return Ok(());
+    };
+    let require_force = repo
+        .config_snapshot()
+        .boolean("clean.requireForce")
+        .unwrap_or(true);
+    if require_force && opts.force == 0 && !opts.dry_run {
+        println!("fatal: clean.requireForce is true and -f not given: refusing to clean");
+        return Ok(());
+    }
+
+    let with_ignored = opts.ignored || opts.only_ignored;
+    let options = repo
+        .dirwalk_options()?
+        .emit_untracked(EmissionMode::CollapseDirectory)
+        .emit_ignored(with_ignored.then_some(EmissionMode::CollapseDirectory))
+        .emit_empty_directories(true)
+        .empty_patterns_match_prefix(true)
+        // Nested repositories must be found even in ignored directories so they aren't removed by accident.
+        .for_deletion(Some(if with_ignored {
+            ForDeletionMode::FindNonBareRepositoriesInIgnoredDirectories
+        } else {
+            ForDeletionMode::IgnoredDirectoriesCanHideNestedRepositories
+        }));
+    let patterns: Vec<BString> = pathspecs.iter().map(|spec| spec.as_str().into()).collect();
+    let index = repo.index_or_empty()?;
+    let mut collect = gix::dir::walk::delegate::Collect::default();
+    repo.dirwalk(
+        &index,
+        &patterns,
+        &Default::default(),
+        options,
+        &mut collect,
+    )?;
+
+    let excludes = Excludes::new(&opts.excludes);
+    // Without pathspecs, untracked directories are only removed with -d.
+    let directories = opts.directories || !pathspecs.is_empty();
+    for (entry, _) in collect.into_entries_by_path() {
+        if entry
+            .pathspec_match
+            .is_none_or(|m| m == gix::dir::entry::PathspecMatch::Excluded)
+        {
+            continue;
+        }
+        let Some(kind) = entry.disk_kind else {
+            continue;
+        };
+        match kind {
+            Kind::Directory if !directories => continue,
+            Kind::Repository if !directories || opts.force < 2 => continue,
+            _ => {}
+        }
+        let is_dir = matches!(kind, Kind::Directory | Kind::Repository);
+        let excluded = excludes.matches(entry.rela_path.as_bstr(), is_dir);
+        let remove = match entry.status {
+            Status::Untracked if kind == Kind::Directory && !opts.excludes.is_empty() => {
+                // The extra patterns may match only some of what's in the directory.
+                clean_directory(&workdir, entry.rela_path.as_bstr(), &excludes, &opts)?;
+                continue;
+            }
+            Status::Untracked => excluded == opts.only_ignored,
+            Status::Ignored(gix::ignore::Kind::Expendable) => {
+                opts.only_ignored || (opts.ignored && !excluded)
+            }
+            Status::Ignored(gix::ignore::Kind::Precious) | Status::Tracked | Status::Pruned => {
+                false
+            }
+        };
+        if remove {
+            let display = if is_dir {
+                format!("{}/", entry.rela_path)
+            } else {
+                entry.rela_path.to_string()
+            };
+            remove_path(
+                &workdir.join(gix::path::from_bstr(entry.rela_path.as_bstr())),
+                &display,
+                &opts,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The patterns given with `-e`, which add to the ignore rules
+struct Excludes(gix::ignore::Search);
+
+impl Excludes {
+    fn new(patterns: &[String]) -> Self {
+        Excludes(gix::ignore::Search::from_overrides(patterns))
+    }
+
+    fn matches(&self, rela_path: &BStr, is_dir: bool) -> bool {
+        self.0
+            .pattern_matching_relative_path(
+                rela_path,
+                Some(is_dir),
+                gix::glob::pattern::Case::Sensitive,
+            )
+            .is_some_and(|m| !m.pattern.is_negative())
+    }
+}
+
+/// Remove what's in the untracked directory `rela_dir` according to `excludes`, all of it if nothing in it matches them.
+fn clean_directory(
+    workdir: &Path,
+    rela_dir: &BStr,
+    excludes: &Excludes,
+    opts: &Options,
+) -> Result<()> {
+    let dir = workdir.join(gix::path::from_bstr(rela_dir));
+    // Files match if they or any of their parent directories do, with everything they contain.
+    let mut files = Vec::new();
+    let mut walk = walkdir::WalkDir::new(&dir).min_depth(1).into_iter();
+    while let Some(entry) = walk.next() {
+        let entry = entry?;
+        let rela_path = gix::path::to_unix_separators_on_windows(gix::path::into_bstr(
+            entry.path().strip_prefix(workdir)?,
+        ))
+        .into_owned();
+        let is_dir = entry.file_type().is_dir();
+        let excluded = excludes.matches(rela_path.as_bstr(), is_dir);
+        if is_dir && excluded {
+            walk.skip_current_dir();
+        }
+        if !is_dir || excluded {
+            files.push((entry.path().to_owned(), rela_path, excluded, is_dir));
+        }
+    }
+
+    if !files.iter().any(|(_, _, excluded, _)| *excluded) {
+        if !opts.only_ignored {
+            remove_path(&dir, &format!("{}/", rela_dir), opts);
+        }
+        return Ok(());
+    }
+    for (path, rela_path, excluded, is_dir) in &files {
+        if *excluded == opts.only_ignored {
+            let display = if *is_dir {
+                format!("{}/", rela_path)
+            } else {
+                rela_path.to_string()
+            };
+            remove_path(path, &display, opts);
+        }
+    }
+    if !opts.dry_run {
+        remove_empty_dirs(&dir);
+    }
+    Ok(())
+}
+
+/// Remove the file or directory at `path`, reporting it as `display` unless quiet.
+fn remove_path(path: &Path, display: &str, opts: &Options) {
+    if opts.dry_run {
+        if !opts.quiet {
+            println!("Would remove {}", display);
+        }
+        return;
+    }
+    let removed = if path.is_dir() && !path.is_symlink() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    };
+    match removed {
+        Ok(()) if !opts.quiet => println!("Removing {}", display),
+        Ok(()) => {}
+        Err(err) => println!("warning: failed to remove {}: {}", display, err),
+    }
+}
+
+/// Remove the directories below and including `dir` that are empty, deepest first.
+fn remove_empty_dirs(dir: &Path) {
+    for entry in walkdir::WalkDir::new(dir)
+        .contents_first(true)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_dir())
+    {
+        std::fs::remove_dir(entry.path()).ok();
+    }
+}
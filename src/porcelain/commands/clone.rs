@@ -1,149 +1,357 @@
 use anyhow::Result;
-use gitoxide_core as core;
-use std::path::PathBuf;
-
-pub fn run(
-    repository: String,
-    directory: Option<PathBuf>,
-    bare: bool,
-    depth: Option<u32>,
-    recurse_submodules: bool,
-) -> Result<()> {
-    run_inner(repository, directory, bare, depth, recurse_submodules, 10)
+use gix::bstr::ByteSlice;
+use std::path::{Path, PathBuf};
+
+use super::config_utils::{open_local_config, write_local_config};
+use super::hook_utils::run_hook;
+use super::index_utils::{checkout_index, get_current_branch};
+use super::progress;
+use super::ref_utils::{detach_head, set_symbolic_ref};
+use super::submodule_utils::{absorb_git_dir, register, resolve_url};
+
+const REMOTE_NAME: &str = "origin";
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub bare: bool,
+    pub recurse_submodules: bool,
+    pub shallow: gix::remote::fetch::Shallow,
+    pub branch: Option<String>,
+    pub single_branch: bool,
+    pub filter: Option<super::promisor::Filter>,
+    /// Suppress progress and informational messages
+    pub quiet: bool,
+    /// Force progress on or off, instead of showing it only if stderr is a terminal
+    pub progress: Option<bool>,
+    /// Don't check out `HEAD` after cloning
+    pub no_checkout: bool,
 }
 
-fn run_inner(
-    repository: String,
-    directory: Option<PathBuf>,
-    bare: bool,
-    depth: Option<u32>,
-    recurse_submodules: bool,
-    ttl: usize,
-) -> Result<()> {
-    if ttl == 0 {
-        println!("Warning: Maximum submodule depth reached, skipping further submodules");
-        return Ok(());
-    }
+/// How deep submodules of submodules are cloned before giving up
+const MAX_SUBMODULE_DEPTH: usize = 10;
 
+pub fn run(repository: String, directory: Option<PathBuf>, opts: Options) -> Result<()> {
     let target_dir = directory.unwrap_or_else(|| {
         let name = repository.split('/').last().unwrap_or("repo");
         let name = name.strip_suffix(".git").unwrap_or(name);
         PathBuf::from(name)
     });
 
-    let is_submodule = ttl < 10;
-
     if target_dir.exists() {
-        if is_submodule {
-            let git_dir = target_dir.join(".git");
-            if git_dir.exists() {
-                println!("Submodule already cloned, skipping");
-                return Ok(());
-            } else {
-                std::fs::remove_dir_all(&target_dir)
-                    .map_err(|e| anyhow::anyhow!("Failed to remove empty directory: {}", e))?;
-            }
-        } else {
-            println!(
-                "fatal: destination path '{}' already exists and is not an empty directory.",
-                target_dir.display()
-            );
-            return Ok(());
+        println!(
+            "fatal: destination path '{}' already exists and is not an empty directory.",
+            target_dir.display()
+        );
+        return Ok(());
+    }
+
+    if !opts.quiet {
+        println!("Cloning into '{}'...", target_dir.display());
+    }
+
+    progress::run(
+        "clone",
+        progress::enabled(opts.progress, opts.quiet),
+        |progress| clone_repository(&repository, &target_dir, &opts, progress),
+    )?;
+
+    if !opts.bare && !opts.no_checkout {
+        let repo = gix::open(&target_dir)?;
+        if let Ok(head) = repo.head_id() {
+            let null = gix::ObjectId::null(repo.object_hash());
+            run_hook(
+                &repo,
+                "post-checkout",
+                &[&null.to_string(), &head.to_string(), "1"],
+                None,
+            )?;
         }
     }
 
-    // TODO: Add proper shallow/depth support by using the correct Shallow enum
-    if depth.is_some() && !is_submodule {
-        println!("fatal: --depth option not yet implemented");
+    if opts.recurse_submodules && !opts.bare {
+        let repo = gix::open(&target_dir)
+            .map_err(|e| anyhow::anyhow!("Failed to open repository: {}", e))?;
+        clone_submodules(&repo, &opts, MAX_SUBMODULE_DEPTH)?;
+    }
+
+    Ok(())
+}
+
+/// Clone and check out all submodules of `repo` that aren't cloned yet, recursing `depth` levels deep.
+pub(super) fn clone_submodules(repo: &gix::Repository, opts: &Options, depth: usize) -> Result<()> {
+    if depth == 0 {
+        println!("Warning: Maximum submodule depth reached, skipping further submodules");
         return Ok(());
     }
 
-    let opts = core::repository::clone::Options {
-        format: core::OutputFormat::Human,
-        bare,
-        handshake_info: false,
-        no_tags: false,
-        ref_name: None,
-        shallow: Default::default(),
+    let Some(submodules) = repo
+        .submodules()
+        .map_err(|e| anyhow::anyhow!("Failed to read submodules: {}", e))?
+    else {
+        return Ok(());
     };
 
-    let config: Vec<gix::bstr::BString> = vec![];
+    for submodule in submodules {
+        let Some(worktree) = clone_submodule(repo, &submodule, opts)? else {
+            continue;
+        };
+        if opts.recurse_submodules {
+            let submodule_repo = gix::open(&worktree)?;
+            clone_submodules(&submodule_repo, opts, depth - 1)?;
+        }
+    }
 
-    if !is_submodule {
-        println!("Cloning into '{}'...", target_dir.display());
+    Ok(())
+}
+
+/// Clone `submodule` of `repo` and check out the commit recorded in the superproject, honoring
+/// `submodule.<name>.update` and `submodule.<name>.branch`.
+///
+/// Returns the worktree of the submodule, or `None` if it wasn't cloned.
+pub(super) fn clone_submodule(
+    repo: &gix::Repository,
+    submodule: &gix::Submodule<'_>,
+    opts: &Options,
+) -> Result<Option<PathBuf>> {
+    use gix::submodule::config::{Branch, Update};
+
+    let name = submodule.name().to_owned();
+    let path = submodule.path()?.to_string();
+    let update = submodule.update()?.unwrap_or_default();
+    if update == Update::None {
+        if !opts.quiet {
+            println!("Skipping submodule '{}'", path);
+        }
+        return Ok(None);
+    }
+    let Some(commit) = submodule.index_id()? else {
+        return Ok(None);
+    };
+    if submodule.git_dir().exists() {
+        if !opts.quiet {
+            println!("Submodule '{}' is already cloned, skipping", path);
+        }
+        return Ok(None);
     }
 
-    if is_submodule {
-        // Had issues with fancy progress bars in submodules
-        use gix::progress::Discard;
-        let mut progress = Discard;
-
-        core::repository::clone(
-            repository,
-            Some(target_dir.clone()),
-            config,
-            &mut progress,
-            &mut std::io::sink(),
-            &mut std::io::sink(),
-            opts,
-        )
-        .map_err(|e| anyhow::anyhow!("clone failed: {}", e))?;
+    let url = resolve_url(repo, &submodule.url()?.to_bstring().to_string());
+    register(repo, name.as_bstr(), &url)?;
+    let worktree = submodule.work_dir()?;
+    if !opts.quiet {
+        println!(
+            "Submodule '{}' ({}) registered for path '{}'",
+            name, url, path
+        );
+        println!("Cloning into '{}'...", worktree.display());
+    }
+
+    // The checkout of the superproject leaves an empty directory in place of the submodule.
+    if worktree.is_dir() {
+        std::fs::remove_dir(&worktree).map_err(|e| {
+            anyhow::anyhow!(
+                "destination path '{}' already exists and is not an empty directory: {}",
+                worktree.display(),
+                e
+            )
+        })?;
+    }
+
+    let branch = match submodule.branch()? {
+        Some(Branch::Name(branch)) if branch != "HEAD" => Some(
+            branch
+                .strip_prefix(b"refs/heads/")
+                .unwrap_or(branch.as_slice())
+                .to_str_lossy()
+                .into_owned(),
+        ),
+        Some(Branch::CurrentInSuperproject) => Some(get_current_branch(repo)?),
+        _ => None,
+    };
+    let submodule_opts = Options {
+        bare: false,
+        recurse_submodules: false,
+        shallow: opts.shallow.clone(),
+        branch: branch.clone(),
+        single_branch: opts.single_branch,
+        filter: opts.filter.clone(),
+        quiet: opts.quiet,
+        progress: opts.progress,
+        no_checkout: true,
+    };
+    progress::run(
+        "clone",
+        progress::enabled(opts.progress, opts.quiet),
+        |progress| clone_repository(&url, &worktree, &submodule_opts, progress),
+    )?;
+
+    let submodule_repo = gix::open(&worktree)?;
+    fetch_commit(&submodule_repo, commit, &opts.shallow)?;
+    let message = format!("checkout: moving to {}", commit);
+    match (&update, &branch) {
+        (Update::Rebase | Update::Merge, Some(branch)) => {
+            // Keep the configured branch checked out so later rebases or merges have something to work on.
+            let branch_ref = format!("refs/heads/{}", branch);
+            submodule_repo.reference(
+                branch_ref.as_str(),
+                commit,
+                gix::refs::transaction::PreviousValue::Any,
+                message.as_str(),
+            )?;
+            set_symbolic_ref(
+                &submodule_repo,
+                "HEAD",
+                branch_ref.as_str().into(),
+                &message,
+            )?;
+        }
+        _ => detach_head(&submodule_repo, commit, &message)?,
+    }
+    let tree_id = submodule_repo.find_commit(commit)?.tree_id()?.detach();
+    let mut index = super::promisor::index_from_tree(&submodule_repo, &tree_id)?;
+    checkout_index(&submodule_repo, &mut index)?;
+    index.write(Default::default())?;
+
+    if let Update::Command(command) = &update {
+        let status = std::process::Command::new(if cfg!(windows) { "cmd" } else { "sh" })
+            .arg(if cfg!(windows) { "/C" } else { "-c" })
+            .arg(format!("{} {}", command, commit))
+            .current_dir(&worktree)
+            .status()?;
+        if !status.success() {
+            anyhow::bail!(
+                "Execution of '{} {}' failed in submodule path '{}'",
+                command,
+                commit,
+                path
+            );
+        }
+    }
+
+    absorb_git_dir(repo, name.as_bstr(), &worktree)?;
+    if !opts.quiet {
+        println!("Submodule path '{}': checked out '{}'", path, commit);
+    }
+
+    Ok(Some(worktree))
+}
+
+/// Make sure `commit` is available in `repo`, fetching it by id from the default remote if the
+/// initial clone didn't include it.
+fn fetch_commit(
+    repo: &gix::Repository,
+    commit: gix::ObjectId,
+    shallow: &gix::remote::fetch::Shallow,
+) -> Result<()> {
+    if repo.has_object(commit) {
+        return Ok(());
+    }
+    if super::promisor::promisor_remote(repo).is_some() {
+        return super::promisor::fetch_missing(repo, [commit]);
+    }
+
+    let mut remote = repo.find_remote(REMOTE_NAME)?;
+    let spec = commit.to_string();
+    remote.replace_refspecs([spec.as_str()], gix::remote::Direction::Fetch)?;
+    remote
+        .connect(gix::remote::Direction::Fetch)?
+        .prepare_fetch(gix::progress::Discard, Default::default())?
+        .with_shallow(shallow.clone())
+        .receive(gix::progress::Discard, &gix::interrupt::IS_INTERRUPTED)?;
+
+    if !repo.has_object(commit) {
+        anyhow::bail!(
+            "Fetched in submodule path '{}', but it did not contain {}",
+            repo.workdir().unwrap_or(repo.path()).display(),
+            commit
+        );
+    }
+    Ok(())
+}
+
+fn clone_repository<P>(url: &str, target_dir: &Path, opts: &Options, mut progress: P) -> Result<()>
+where
+    P: gix::NestedProgress,
+    P::SubProgress: gix::NestedProgress + 'static,
+{
+    if let Some(filter) = &opts.filter {
+        return super::promisor::clone(url, target_dir, opts, REMOTE_NAME, filter, &mut progress);
+    }
+
+    let mut prepare = if opts.bare {
+        gix::prepare_clone_bare(url, target_dir)
     } else {
-        let target_dir_clone = target_dir.clone();
-        crate::shared::pretty::prepare_and_run(
-            "clone",
-            false,
-            true,
-            false,
-            false,
-            None,
-            move |progress, _out, _err| {
-                core::repository::clone(
-                    repository,
-                    Some(target_dir_clone),
-                    config,
-                    progress,
-                    &mut std::io::sink(),
-                    &mut std::io::sink(),
-                    opts,
-                )
-                .map_err(|e| anyhow::anyhow!("clone failed: {}", e))
-            },
-        )?;
+        gix::prepare_clone(url, target_dir)
     }
+    .map_err(|e| anyhow::anyhow!("clone failed: {}", e))?
+    .with_remote_name(REMOTE_NAME)?
+    .with_shallow(opts.shallow.clone())
+    .with_ref_name(opts.branch.as_deref())?;
 
-    if recurse_submodules {
-        let repo = gix::discover(&target_dir)
-            .map_err(|e| anyhow::anyhow!("Failed to open repository: {}", e))?;
+    if opts.single_branch {
+        let branch = opts.branch.clone();
+        prepare = prepare.configure_remote(move |mut remote| {
+            // Without a branch name only the remote HEAD is fetched, and the refspec
+            // is filled in once we know which branch it points to.
+            let specs: Vec<String> = branch
+                .iter()
+                .map(|branch| format!("+refs/heads/{branch}:refs/remotes/{REMOTE_NAME}/{branch}"))
+                .collect();
+            remote.replace_refspecs(
+                specs.iter().map(String::as_str),
+                gix::remote::Direction::Fetch,
+            )?;
+            Ok(remote.with_fetch_tags(gix::remote::fetch::Tags::Included))
+        });
+    }
 
-        let Some(submodules) = repo
-            .submodules()
-            .map_err(|e| anyhow::anyhow!("Failed to read submodules: {}", e))?
-        else {
-            return Ok(());
-        };
+    let (mut checkout, _outcome) = prepare
+        .fetch_then_checkout(&mut progress, &gix::interrupt::IS_INTERRUPTED)
+        .map_err(|e| anyhow::anyhow!("clone failed: {}", e))?;
 
-        let submodules: Vec<_> = submodules.collect();
+    let repo = if opts.bare || opts.no_checkout {
+        checkout.persist()
+    } else {
+        let (repo, _outcome) = checkout
+            .main_worktree(&mut progress, &gix::interrupt::IS_INTERRUPTED)
+            .map_err(|e| anyhow::anyhow!("clone failed: {}", e))?;
+        repo
+    };
 
-        if !submodules.is_empty() {
-            println!("Cloning submodules...");
+    if opts.single_branch && opts.branch.is_none() {
+        track_head_branch_only(&repo)?;
+    }
 
-            for submodule in submodules {
-                let path = submodule
-                    .path()
-                    .map_err(|e| anyhow::anyhow!("Failed to get submodule path: {}", e))?
-                    .to_string();
-                let url = submodule
-                    .url()
-                    .map_err(|e| anyhow::anyhow!("Failed to get submodule URL: {}", e))?
-                    .to_string();
+    Ok(())
+}
+
+/// Configure the fetch refspec of a single-branch clone to track the branch the remote `HEAD` pointed to.
+pub(super) fn track_head_branch_only(repo: &gix::Repository) -> Result<()> {
+    let head = repo.head()?;
+    let Some(head_name) = head.referent_name() else {
+        return Ok(());
+    };
+    let Some(branch) = head_name.as_bstr().strip_prefix(b"refs/heads/") else {
+        return Ok(());
+    };
+    let branch = branch.to_str()?;
+    let tracking_ref = format!("refs/remotes/{REMOTE_NAME}/{branch}");
 
-                let submodule_dir = target_dir.join(&path);
-                println!("Cloning submodule '{}' from '{}'...", path, url);
+    let mut config = open_local_config(repo)?;
+    config.set_raw_value_by(
+        "remote",
+        Some(REMOTE_NAME.into()),
+        "fetch",
+        format!("+refs/heads/{branch}:{tracking_ref}").as_str(),
+    )?;
+    write_local_config(repo, &config)?;
 
-                run_inner(url, Some(submodule_dir), false, None, true, ttl - 1)?;
-            }
-        }
+    if let Some(id) = head.id() {
+        repo.reference(
+            tracking_ref.as_str(),
+            id,
+            gix::refs::transaction::PreviousValue::Any,
+            "clone: single branch",
+        )?;
     }
 
     Ok(())
@@ -1,14 +1,56 @@
 use anyhow::Result;
-use std::path::Path;
+use gix::bstr::{BString, ByteSlice};
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
-use super::index_utils::{add_modified_files_to_index, get_current_branch, is_initial_commit};
+use super::commit_utils::{configured_author, configured_committer};
+use super::diff_utils::tree_changes;
+use super::editor_utils::{cleanup, cleanup_mode, edit};
+use super::gc;
+use super::hook_utils::run_hook;
+use super::index_utils::{
+    add_modified_files_to_index, get_current_branch, is_initial_commit, write_tree,
+};
+use super::ref_utils::move_head;
+use super::sequencer::strip_comments;
+use super::signing_utils::{sign, sign_by_default, signing_key};
 
-pub fn run(
-    _repository: &Path,
-    message: Option<String>,
-    all: bool,
-    allow_empty: bool,
-) -> Result<()> {
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Paragraphs of the commit message, one per `-m`
+    pub messages: Vec<String>,
+    /// Read the message from this file, or from stdin if it's `-`
+    pub file: Option<PathBuf>,
+    /// Add all modified files before committing
+    pub all: bool,
+    pub allow_empty: bool,
+    /// Replace the `HEAD` commit instead of adding a new one on top of it
+    pub amend: bool,
+    /// Override the author, given as `Name <email>`
+    pub author: Option<String>,
+    /// Override the author date
+    pub date: Option<String>,
+    /// Make the committer the author when amending, instead of keeping the original author
+    pub reset_author: bool,
+    /// Add a `Signed-off-by` trailer for the committer
+    pub signoff: bool,
+    /// Trailers to add, as `key=value` or `key: value`
+    pub trailers: Vec<String>,
+    /// Skip the `pre-commit` and `commit-msg` hooks
+    pub no_verify: bool,
+    /// Edit the message even though it was given with `-m` or `-F`
+    pub edit: bool,
+    /// Use the amended commit's message without editing it
+    pub no_edit: bool,
+    /// How to clean up the message, overriding `commit.cleanup`
+    pub cleanup: Option<String>,
+    /// Sign the commit, with the given key or the default one if it's empty
+    pub gpg_sign: Option<String>,
+    /// Don't sign the commit even if `commit.gpgSign` is set
+    pub no_gpg_sign: bool,
+}
+
+pub fn run(_repository: &Path, opts: Options) -> Result<()> {
     let repo = match gix::discover(".") {
         Ok(repo) => repo,
         Err(_) => {
@@ -17,23 +59,35 @@ pub fn run(
         }
     };
 
-    if all {
+    let initial = is_initial_commit(&repo)?;
+    let head = if initial {
+        None
+    } else {
+        Some(repo.head_commit()?)
+    };
+    if opts.amend && head.is_none() {
+        println!("fatal: You have nothing to amend.");
+        return Ok(());
+    }
+
+    if opts.all {
         add_modified_files_to_index(&repo)?;
     }
 
-    let commit_message = match message {
-        Some(msg) => msg,
-        None => {
-            eprintln!("error: no commit message provided");
-            eprintln!("hint: use -m <message> to provide a commit message");
-            return Ok(());
-        }
-    };
+    if !opts.no_verify && !run_hook(&repo, "pre-commit", &[], None)? {
+        return Ok(());
+    }
 
-    if !allow_empty && !has_staged_changes(&repo)? {
+    // A merge that stopped is concluded with its other side as second parent, even if nothing changed.
+    let merge_head = std::fs::read_to_string(repo.path().join("MERGE_HEAD"))
+        .ok()
+        .and_then(|id| gix::ObjectId::from_hex(id.trim().as_bytes()).ok())
+        .filter(|_| !opts.amend);
+
+    if !opts.amend && !opts.allow_empty && merge_head.is_none() && !has_staged_changes(&repo)? {
         println!("On branch {}", get_current_branch(&repo)?);
 
-        if is_initial_commit(&repo)? {
+        if initial {
             println!("\nNo commits yet\n");
             println!("nothing to commit (create/copy files and use \"git add\" to track)");
         } else {
@@ -42,12 +96,234 @@ pub fn run(
         return Ok(());
     }
 
-    let commit_id = create_commit(&repo, &commit_message)?;
+    // A merge, cherry-pick or revert that stopped leaves the message it would have used in `MERGE_MSG`.
+    let merge_message = std::fs::read_to_string(repo.path().join("MERGE_MSG"))
+        .ok()
+        .filter(|_| !opts.amend)
+        .map(|message| strip_comments(&message));
+    let cherry_picked = std::fs::read_to_string(repo.path().join("CHERRY_PICK_HEAD"))
+        .ok()
+        .and_then(|id| gix::ObjectId::from_hex(id.trim().as_bytes()).ok());
+
+    // Without -m or -F the message is edited, starting from the amended message, `MERGE_MSG` or `commit.template`.
+    let (message, from_user) = match (&opts.file, opts.messages.is_empty(), &head) {
+        (Some(file), _, _) => {
+            let mut content = String::new();
+            let read = if file.as_os_str() == "-" {
+                std::io::stdin().read_to_string(&mut content).map(|_| ())
+            } else {
+                std::fs::read_to_string(file).map(|file_content| content = file_content)
+            };
+            if let Err(err) = read {
+                println!(
+                    "fatal: could not read log file '{}': {}",
+                    file.display(),
+                    err
+                );
+                return Ok(());
+            }
+            (content, true)
+        }
+        (None, false, _) => (opts.messages.join("\n\n"), true),
+        (None, true, Some(head)) if opts.amend => {
+            (head.message_raw_sloppy().to_str_lossy().into_owned(), false)
+        }
+        (None, true, _) => match &merge_message {
+            Some(merge_message) => (merge_message.clone(), false),
+            None => (read_template(&repo)?.unwrap_or_default(), false),
+        },
+    };
+    let template = (!from_user && !opts.amend && merge_message.is_none()).then(|| message.clone());
+    let use_editor = opts.edit || (!from_user && !opts.no_edit);
+    let Some(cleanup_mode) = cleanup_mode(&repo, opts.cleanup.as_deref(), use_editor) else {
+        return Ok(());
+    };
+
+    let message_file = repo.path().join("COMMIT_EDITMSG");
+    if use_editor {
+        let base_tree = match &head {
+            Some(head) if opts.amend => match head.parent_ids().next() {
+                Some(parent) => repo.find_commit(parent)?.tree_id()?.detach(),
+                None => gix::ObjectId::empty_tree(repo.object_hash()),
+            },
+            Some(head) => head.tree_id()?.detach(),
+            None => gix::ObjectId::empty_tree(repo.object_hash()),
+        };
+        let message = message.trim_end();
+        let content = format!(
+            "{}\n{}#\n{}",
+            if message.is_empty() {
+                String::new()
+            } else {
+                format!("{}\n", message)
+            },
+            cleanup_mode.instructions("commit"),
+            status_comment(&repo, base_tree, initial)?
+        );
+        std::fs::write(&message_file, content)?;
+    } else {
+        std::fs::write(&message_file, &message)?;
+    }
+
+    let message_path = message_file.to_string_lossy();
+    let mut hook_args = vec![message_path.as_ref()];
+    if from_user {
+        hook_args.push("message");
+    } else if opts.amend {
+        hook_args.extend(["commit", "HEAD"]);
+    } else if merge_message.is_some() {
+        hook_args.push("merge");
+    } else if template
+        .as_ref()
+        .is_some_and(|template| !template.is_empty())
+    {
+        hook_args.push("template");
+    }
+    if !run_hook(&repo, "prepare-commit-msg", &hook_args, None)? {
+        return Ok(());
+    }
+    let message = if use_editor {
+        let Some(edited) = edit(&repo, &message_file)? else {
+            println!("Please supply the message using either -m or -F option.");
+            return Ok(());
+        };
+        edited
+    } else {
+        std::fs::read_to_string(&message_file)?
+    };
+    let message = cleanup(&message, cleanup_mode);
+    if message.trim().is_empty() {
+        println!("Aborting commit due to empty commit message.");
+        return Ok(());
+    }
+    if let Some(template) = template.filter(|template| !template.trim().is_empty()) {
+        if message == cleanup(&template, cleanup_mode) {
+            println!("Aborting commit; you did not edit the message.");
+            return Ok(());
+        }
+    }
+
+    let committer = configured_committer(&repo)?;
+    let mut trailers = Vec::new();
+    for trailer in &opts.trailers {
+        match trailer.split_once(['=', ':']) {
+            Some((key, value)) => trailers.push(format!("{}: {}", key.trim(), value.trim())),
+            None => trailers.push(format!("{}: ", trailer.trim())),
+        }
+    }
+    if opts.signoff {
+        trailers.push(format!(
+            "Signed-off-by: {} <{}>",
+            committer.name, committer.email
+        ));
+    }
+    let mut commit_message = add_trailers(message.trim_end(), &trailers);
+
+    // The commit-msg hook sees the final message and may still change it.
+    if !opts.no_verify {
+        std::fs::write(&message_file, &commit_message)?;
+        if !run_hook(&repo, "commit-msg", &[message_path.as_ref()], None)? {
+            return Ok(());
+        }
+        commit_message = cleanup(&std::fs::read_to_string(&message_file)?, cleanup_mode);
+    }
+
+    // When amending, the original author is kept unless asked otherwise, and so is the author of a cherry-picked commit.
+    let mut author = match (&head, cherry_picked) {
+        (Some(head), _) if opts.amend && !opts.reset_author => head.author()?.to_owned(),
+        (_, Some(picked)) if !opts.amend && !opts.reset_author => {
+            repo.find_commit(picked)?.author()?.to_owned()
+        }
+        _ => configured_author(&repo)?,
+    };
+    if let Some(spec) = &opts.author {
+        let found = match parse_author(spec) {
+            Some((name, email)) => Some((name.into(), email.into())),
+            None => find_author(&repo, spec)?,
+        };
+        match found {
+            Some((name, email)) => {
+                author.name = name;
+                author.email = email;
+            }
+            None => {
+                println!(
+                    "fatal: --author '{}' is not 'Name <email>' and matches no existing author",
+                    spec
+                );
+                return Ok(());
+            }
+        }
+    }
+    if let Some(date) = &opts.date {
+        match gix::date::parse(date, Some(std::time::SystemTime::now())) {
+            Ok(time) => author.time = time,
+            Err(_) => {
+                println!("fatal: invalid date format: {}", date);
+                return Ok(());
+            }
+        }
+    }
+
+    let parents: Vec<gix::ObjectId> = match &head {
+        Some(head) if opts.amend => head.parent_ids().map(|id| id.detach()).collect(),
+        Some(head) => std::iter::once(head.id).chain(merge_head).collect(),
+        None => Vec::new(),
+    };
+    let reflog_message = format!(
+        "commit{}: {}",
+        if opts.amend {
+            " (amend)"
+        } else if merge_head.is_some() {
+            " (merge)"
+        } else if initial {
+            " (initial)"
+        } else {
+            ""
+        },
+        get_first_line(&commit_message)
+    );
+    let signing_key = if opts.gpg_sign.is_some()
+        || (!opts.no_gpg_sign && sign_by_default(&repo, "commit.gpgSign"))
+    {
+        match signing_key(&repo, opts.gpg_sign.as_deref())? {
+            Some(key) => Some(key),
+            None => return Ok(()),
+        }
+    } else {
+        None
+    };
+    let Some(commit_id) = create_commit(
+        &repo,
+        &commit_message,
+        parents,
+        author,
+        committer,
+        signing_key.as_deref(),
+        &reflog_message,
+    )?
+    else {
+        println!("fatal: failed to write commit object");
+        return Ok(());
+    };
+    // The stopped merge, cherry-pick or revert is committed now, so `--continue` moves on to the next commit.
+    if !opts.amend {
+        for file in [
+            "MERGE_HEAD",
+            "MERGE_MODE",
+            "CHERRY_PICK_HEAD",
+            "REVERT_HEAD",
+            "MERGE_MSG",
+        ] {
+            std::fs::remove_file(repo.path().join(file)).ok();
+        }
+    }
+    run_hook(&repo, "post-commit", &[], None)?;
 
     let branch_name = get_current_branch(&repo)?;
     let short_id = commit_id.to_hex_with_len(7);
 
-    if is_initial_commit(&repo)? {
+    if initial {
         println!(
             "[{} (root-commit) {}] {}",
             branch_name,
@@ -86,6 +362,7 @@ pub fn run(
         }
         println!();
     }
+    gc::auto(&repo, false)?;
 
     Ok(())
 }
@@ -99,7 +376,7 @@ fn has_staged_changes(repo: &gix::Repository) -> Result<bool> {
 
     let head_commit = repo.head_commit()?;
     let head_tree = head_commit.tree()?;
-    let head_index = repo.index_from_tree(&head_tree.id)?;
+    let head_index = super::promisor::index_from_tree(repo, &head_tree.id)?;
 
     if index.entries().len() != head_index.entries().len() {
         return Ok(true);
@@ -116,31 +393,217 @@ fn has_staged_changes(repo: &gix::Repository) -> Result<bool> {
     Ok(false)
 }
 
-fn create_commit(repo: &gix::Repository, message: &str) -> Result<gix::ObjectId> {
-    repo.index()?;
-    let tree_id = write_tree_from_index(repo)?;
+/// Write a commit of the index on top of `parents` and move `HEAD`, and the branch it refers to, to it.
+///
+/// With `signing_key`, the commit is signed in its `gpgsig` header, and `None` is returned if that failed.
+fn create_commit(
+    repo: &gix::Repository,
+    message: &str,
+    parents: Vec<gix::ObjectId>,
+    author: gix::actor::Signature,
+    committer: gix::actor::Signature,
+    signing_key: Option<&str>,
+    reflog_message: &str,
+) -> Result<Option<gix::ObjectId>> {
+    let tree = write_tree(repo, &repo.index_or_empty()?)?;
+    let mut commit = gix::objs::Commit {
+        message: message.into(),
+        tree,
+        author,
+        committer,
+        encoding: None,
+        parents: parents.into(),
+        extra_headers: Default::default(),
+    };
+    if let Some(key) = signing_key {
+        // The signature covers the commit as it would be written without it.
+        let mut payload = Vec::new();
+        gix::objs::WriteTo::write_to(&commit, &mut payload)?;
+        let Some(signature) = sign(repo, &payload, key)? else {
+            return Ok(None);
+        };
+        commit
+            .extra_headers
+            .push(("gpgsig".into(), signature.trim_end().into()));
+    }
+    let commit_id = repo.write_object(&commit)?.detach();
+    move_head(repo, commit_id, reflog_message)?;
+    Ok(Some(commit_id))
+}
 
-    let parents: Vec<gix::ObjectId> = if is_initial_commit(repo)? {
-        Vec::new()
-    } else {
-        vec![repo.head_commit()?.id]
+/// The content of the file configured in `commit.template`, if there is one.
+fn read_template(repo: &gix::Repository) -> Result<Option<String>> {
+    let Some(path) = repo.config_snapshot().trusted_path("commit.template") else {
+        return Ok(None);
     };
+    let path = path?;
+    match std::fs::read_to_string(&path) {
+        Ok(template) => Ok(Some(template)),
+        Err(err) => {
+            println!("fatal: could not read '{}': {}", path.display(), err);
+            Ok(None)
+        }
+    }
+}
+
+/// The status as shown in the commented part of the message template, with staged changes relative to `base_tree`.
+fn status_comment(
+    repo: &gix::Repository,
+    base_tree: gix::ObjectId,
+    initial: bool,
+) -> Result<String> {
+    use gix::status::plumbing::index_as_worktree::{Change, EntryStatus};
 
-    let commit_id = repo.commit("HEAD", message, tree_id, parents)?;
+    let mut comment = String::new();
+    if repo.head()?.is_detached() {
+        comment.push_str("# Not currently on any branch.\n");
+    } else {
+        comment.push_str(&format!("# On branch {}\n", get_current_branch(repo)?));
+    }
+    if initial {
+        comment.push_str("#\n# Initial commit\n#\n");
+    }
 
-    Ok(commit_id.detach())
+    let index_tree = write_tree(repo, &repo.index_or_empty()?)?;
+    let staged = tree_changes(repo, base_tree, index_tree)?;
+    let (mut unstaged, mut untracked) = (Vec::new(), Vec::new());
+    for item in repo
+        .status(gix::progress::Discard)?
+        .untracked_files(gix::status::UntrackedFiles::Files)
+        .index_worktree_submodules(None)
+        .into_index_worktree_iter(Vec::new())?
+    {
+        match item? {
+            gix::status::index_worktree::Item::Modification {
+                rela_path, status, ..
+            } => {
+                let label = match status {
+                    EntryStatus::Change(Change::Removed) => "deleted",
+                    _ => "modified",
+                };
+                unstaged.push((label, rela_path));
+            }
+            gix::status::index_worktree::Item::DirectoryContents { entry, .. }
+                if entry.status == gix::dir::entry::Status::Untracked =>
+            {
+                untracked.push(entry.rela_path);
+            }
+            _ => {}
+        }
+    }
+
+    let line = |label: &str, path: &dyn std::fmt::Display| {
+        format!("#\t{:<12}{}\n", format!("{}:", label), path)
+    };
+    if !staged.is_empty() {
+        comment.push_str("# Changes to be committed:\n");
+        for change in &staged {
+            let label = match (&change.old, &change.new) {
+                (None, _) => "new file",
+                (_, None) => "deleted",
+                _ => "modified",
+            };
+            comment.push_str(&line(label, &change.path));
+        }
+        comment.push_str("#\n");
+    }
+    if !unstaged.is_empty() {
+        comment.push_str("# Changes not staged for commit:\n");
+        for (label, path) in &unstaged {
+            comment.push_str(&line(*label, path));
+        }
+        comment.push_str("#\n");
+    }
+    if !untracked.is_empty() {
+        comment.push_str("# Untracked files:\n");
+        for path in &untracked {
+            comment.push_str(&format!("#\t{}\n", path));
+        }
+        comment.push_str("#\n");
+    }
+    Ok(comment)
 }
 
-fn write_tree_from_index(repo: &gix::Repository) -> Result<gix::ObjectId> {
-    if !is_initial_commit(repo)? {
-        let head_commit = repo.head_commit()?;
-        let head_tree_id = head_commit.tree_id()?;
+/// Split `Name <email>` into its parts.
+fn parse_author(spec: &str) -> Option<(&str, &str)> {
+    let (name, rest) = spec.split_once('<')?;
+    let email = rest.strip_suffix('>')?;
+    Some((name.trim(), email.trim()))
+}
 
-        return Ok(head_tree_id.detach());
+/// The name and email of the most recent author in any branch whose `Name <email>` matches the regular expression
+/// `pattern`, ignoring case, which is how git resolves an `--author` that isn't `Name <email>`.
+fn find_author(repo: &gix::Repository, pattern: &str) -> Result<Option<(BString, BString)>> {
+    let Ok(pattern) = regex::RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+    else {
+        return Ok(None);
+    };
+    let mut tips = Vec::new();
+    if let Ok(head) = repo.head_id() {
+        tips.push(head.detach());
     }
+    for reference in repo.references()?.all()? {
+        let Ok(mut reference) = reference else {
+            continue;
+        };
+        let commit = reference
+            .peel_to_id_in_place()
+            .ok()
+            .and_then(|id| id.object().ok())
+            .and_then(|object| object.peel_to_commit().ok());
+        if let Some(commit) = commit {
+            tips.push(commit.id);
+        }
+    }
+
+    let walk = repo
+        .rev_walk(tips)
+        .sorting(gix::revision::walk::Sorting::ByCommitTime(
+            gix::traverse::commit::simple::CommitTimeOrder::NewestFirst,
+        ))
+        .all()?;
+    for info in walk {
+        let commit = info?.object()?;
+        let author = commit.author()?;
+        if pattern.is_match(&format!("{} <{}>", author.name, author.email)) {
+            return Ok(Some((author.name.to_owned(), author.email.to_owned())));
+        }
+    }
+    Ok(None)
+}
 
-    let empty_tree_id = gix::ObjectId::empty_tree(repo.object_hash());
-    Ok(empty_tree_id)
+/// Append `trailers` to `message`, as a new paragraph unless its last paragraph consists of trailers already.
+///
+/// A trailer that is already the last line isn't added again, like git does for `--signoff`.
+fn add_trailers(message: &str, trailers: &[String]) -> String {
+    let mut message = message.to_string();
+    if trailers.is_empty() {
+        message.push('\n');
+        return message;
+    }
+    let last_paragraph = message.rsplit("\n\n").next().unwrap_or_default();
+    let is_trailer = |line: &str| {
+        line.split_once(": ").is_some_and(|(key, _)| {
+            !key.is_empty() && key.chars().all(|c| c.is_alphanumeric() || c == '-')
+        })
+    };
+    let has_trailers = message.contains("\n\n") && last_paragraph.lines().all(is_trailer);
+    if !has_trailers && !message.is_empty() {
+        message.push('\n');
+    }
+    for trailer in trailers {
+        if message.lines().last() == Some(trailer.as_str()) {
+            continue;
+        }
+        if !message.is_empty() {
+            message.push('\n');
+        }
+        message.push_str(trailer);
+    }
+    message.push('\n');
+    message
 }
 
 fn get_first_line(message: &str) -> &str {
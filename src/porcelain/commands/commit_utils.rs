@@ -0,0 +1,64 @@
+use anyhow::{anyhow, Result};
+
+/// Write a commit of `tree` with `parents` using the configured author and committer, without updating any reference.
+pub fn write_commit(
+    repo: &gix::Repository,
+    message: &str,
+    tree: gix::ObjectId,
+    parents: impl IntoIterator<Item = gix::ObjectId>,
+) -> Result<gix::ObjectId> {
+    write_commit_as(repo, None, message, tree, parents)
+}
+
+/// Like [`write_commit()`], but keep `author` if set, as when a commit is replayed.
+pub fn write_commit_as(
+    repo: &gix::Repository,
+    author: Option<gix::actor::Signature>,
+    message: &str,
+    tree: gix::ObjectId,
+    parents: impl IntoIterator<Item = gix::ObjectId>,
+) -> Result<gix::ObjectId> {
+    let author = match author {
+        Some(author) => author,
+        None => configured_author(repo)?,
+    };
+    let committer = configured_committer(repo)?;
+    let commit = gix::objs::Commit {
+        message: message.into(),
+        tree,
+        author,
+        committer,
+        encoding: None,
+        parents: parents.into_iter().collect(),
+        extra_headers: Default::default(),
+    };
+    Ok(repo.write_object(&commit)?.detach())
+}
+
+/// The author of new commits, with `GIT_AUTHOR_NAME` and `GIT_AUTHOR_EMAIL` taking precedence over the configuration like in git.
+pub fn configured_author(repo: &gix::Repository) -> Result<gix::actor::Signature> {
+    let author = repo
+        .author()
+        .ok_or_else(|| anyhow!("Author identity unknown"))??;
+    Ok(with_environment(author.into(), "AUTHOR"))
+}
+
+/// The committer of new commits, with `GIT_COMMITTER_NAME` and `GIT_COMMITTER_EMAIL` taking precedence over the configuration.
+pub fn configured_committer(repo: &gix::Repository) -> Result<gix::actor::Signature> {
+    let committer = repo
+        .committer()
+        .ok_or_else(|| anyhow!("Committer identity unknown"))??;
+    Ok(with_environment(committer.into(), "COMMITTER"))
+}
+
+/// `gix` only uses the environment when nothing is configured, but git lets it override `user.name` and `user.email`.
+/// Dates from `GIT_AUTHOR_DATE` and `GIT_COMMITTER_DATE` are already picked up by `gix`.
+fn with_environment(mut signature: gix::actor::Signature, role: &str) -> gix::actor::Signature {
+    if let Ok(name) = std::env::var(format!("GIT_{}_NAME", role)) {
+        signature.name = name.into();
+    }
+    if let Ok(email) = std::env::var(format!("GIT_{}_EMAIL", role)) {
+        signature.email = email.into();
+    }
+    signature
+}
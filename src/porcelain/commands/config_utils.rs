@@ -0,0 +1,42 @@
+use anyhow::Result;
+use std::path::PathBuf;
+
+/// The path to the repository-local configuration file, shared by all worktrees
+pub fn local_config_path(repo: &gix::Repository) -> PathBuf {
+    repo.common_dir().join("config")
+}
+
+/// Load the repository-local configuration file for editing, without resolving includes
+pub fn open_local_config(repo: &gix::Repository) -> Result<gix::config::File<'static>> {
+    Ok(gix::config::File::from_path_no_includes(
+        local_config_path(repo),
+        gix::config::Source::Local,
+    )?)
+}
+
+/// Write an edited configuration file back to the repository-local configuration
+pub fn write_local_config(
+    repo: &gix::Repository,
+    config: &gix::config::File<'static>,
+) -> Result<()> {
+    let mut buf = Vec::new();
+    config.write_to(&mut buf)?;
+    std::fs::write(local_config_path(repo), buf)?;
+    Ok(())
+}
+
+/// Replace all values of `key` in the last section `name.subsection` with `values`, creating the section if needed
+pub fn set_values(
+    config: &mut gix::config::File<'static>,
+    name: &str,
+    subsection: Option<&gix::bstr::BStr>,
+    key: &'static str,
+    values: &[gix::bstr::BString],
+) -> Result<()> {
+    let mut section = config.section_mut_or_create_new(name, subsection)?;
+    while section.remove(key).is_some() {}
+    for value in values {
+        section.push(key.try_into()?, Some(value.as_ref()));
+    }
+    Ok(())
+}
@@ -2,6 +2,8 @@
 use anyhow::{Context, Result};
 use std::path::Path;
 
+use super::revision_utils::rev_parse_single;
+
 pub mod describe {
     #[derive(Debug, Clone)]
     pub struct Options {
@@ -50,7 +52,7 @@ pub fn describe(
 ) -> Result<()> {
     repo.object_cache_size_if_unset(4 * 1024 * 1024);
     let commit = match rev_spec {
-        Some(spec) => repo.rev_parse_single(spec)?.object()?.try_into_commit()?,
+        Some(spec) => rev_parse_single(&repo, spec)?.object()?.try_into_commit()?,
         None => repo.head_commit()?,
     };
     use gix::commit::describe::SelectRef::*;
@@ -1,5 +1,6 @@
 use anyhow::Result;
 use gitoxide_core as core;
+use std::io::Write;
 use std::path::PathBuf;
 
 pub fn run(pathspec: Vec<PathBuf>, cached: bool) -> Result<()> {
@@ -15,17 +16,32 @@ pub fn run(pathspec: Vec<PathBuf>, cached: bool) -> Result<()> {
 
     if cached {
         let head_tree = match repo.head_tree_id() {
-            Ok(tree_id) => format!("{}", tree_id),
+            Ok(tree_id) => tree_id.detach(),
             Err(_) => {
                 println!("fatal: ambiguous argument 'HEAD': unknown revision or path not in the working tree");
                 return Ok(());
             }
         };
+        // Everything in `HEAD` shows up in the diff against the empty tree.
+        let head_index = super::promisor::index_from_tree(&repo, &head_tree)?;
+        super::promisor::fetch_missing_blobs(&repo, &head_index)?;
+        let head_tree = head_tree.to_string();
 
         // TODO: Hmmm... this is a bit of a hack, we should ideally use the index tree
         let empty_tree = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
 
-        core::repository::diff::tree(repo, &mut out, head_tree.into(), empty_tree.into())?;
+        // Buffered, so that nothing is printed twice when the diff is retried after fetching a missing object.
+        let diff = super::promisor::on_demand(&repo, || {
+            let mut diff = Vec::new();
+            core::repository::diff::tree(
+                repo.clone(),
+                &mut diff,
+                head_tree.as_str().into(),
+                empty_tree.into(),
+            )?;
+            Ok(diff)
+        })?;
+        out.write_all(&diff)?;
     } else if pathspec.is_empty() {
         println!("Working tree diff not yet fully implemented");
         println!("Use 'git status' to see changed files");
@@ -0,0 +1,228 @@
+use anyhow::Result;
+use gix::bstr::{BString, ByteSlice};
+use gix::diff::blob::unified_diff::{ContextSize, NewlineSeparator};
+use std::io::Write;
+
+use super::index_utils::{differing_paths, entries_by_path};
+
+/// A path whose id or mode differs between two trees, with `None` on the side that doesn't have it
+pub struct FileChange {
+    pub path: BString,
+    pub old: Option<(gix::ObjectId, gix::index::entry::Mode)>,
+    pub new: Option<(gix::ObjectId, gix::index::entry::Mode)>,
+}
+
+/// The files that differ between `old_tree` and `new_tree`, sorted by path.
+pub fn tree_changes(
+    repo: &gix::Repository,
+    old_tree: gix::ObjectId,
+    new_tree: gix::ObjectId,
+) -> Result<Vec<FileChange>> {
+    let old = entries_by_path(Some(&super::promisor::index_from_tree(repo, &old_tree)?));
+    let new = entries_by_path(Some(&super::promisor::index_from_tree(repo, &new_tree)?));
+    Ok(differing_paths(&old, &new)
+        .into_iter()
+        .map(|path| FileChange {
+            old: old.get(&path).copied(),
+            new: new.get(&path).copied(),
+            path,
+        })
+        .collect())
+}
+
+/// Write `changes` as a patch in git's format.
+pub fn write_patch(
+    repo: &gix::Repository,
+    changes: &[FileChange],
+    mut out: impl Write,
+) -> Result<()> {
+    for change in changes {
+        let path = &change.path;
+        writeln!(out, "diff --git a/{} b/{}", path, path)?;
+        match (change.old, change.new) {
+            (None, Some((_, mode))) => writeln!(out, "new file mode {:06o}", mode.bits())?,
+            (Some((_, mode)), None) => writeln!(out, "deleted file mode {:06o}", mode.bits())?,
+            (Some((_, old_mode)), Some((_, new_mode))) if old_mode != new_mode => {
+                writeln!(out, "old mode {:06o}", old_mode.bits())?;
+                writeln!(out, "new mode {:06o}", new_mode.bits())?;
+            }
+            _ => {}
+        }
+        let null = gix::ObjectId::null(repo.object_hash());
+        let old_id = change.old.map_or(null, |(id, _)| id);
+        let new_id = change.new.map_or(null, |(id, _)| id);
+        write!(
+            out,
+            "index {}..{}",
+            old_id.to_hex_with_len(7),
+            new_id.to_hex_with_len(7)
+        )?;
+        match (change.old, change.new) {
+            (Some((_, old_mode)), Some((_, new_mode))) if old_mode == new_mode => {
+                writeln!(out, " {:06o}", new_mode.bits())?
+            }
+            _ => writeln!(out)?,
+        }
+
+        let old = content(repo, change.old)?;
+        let new = content(repo, change.new)?;
+        if is_binary(&old) || is_binary(&new) {
+            writeln!(
+                out,
+                "Binary files {} and {} differ",
+                change
+                    .old
+                    .map_or("/dev/null".into(), |_| format!("a/{}", path)),
+                change
+                    .new
+                    .map_or("/dev/null".into(), |_| format!("b/{}", path))
+            )?;
+            continue;
+        }
+        match change.old {
+            Some(_) => writeln!(out, "--- a/{}", path)?,
+            None => writeln!(out, "--- /dev/null")?,
+        }
+        match change.new {
+            Some(_) => writeln!(out, "+++ b/{}", path)?,
+            None => writeln!(out, "+++ /dev/null")?,
+        }
+        out.write_all(&unified_diff(&old, &new)?)?;
+    }
+    Ok(())
+}
+
+/// Write a summary of `changes` like `git diff --stat` does.
+pub fn write_stat(
+    repo: &gix::Repository,
+    changes: &[FileChange],
+    mut out: impl Write,
+) -> Result<()> {
+    let mut stats = Vec::new();
+    for change in changes {
+        let old = content(repo, change.old)?;
+        let new = content(repo, change.new)?;
+        if is_binary(&old) || is_binary(&new) {
+            stats.push((change.path.to_string(), None, (old.len(), new.len())));
+            continue;
+        }
+        let (mut insertions, mut deletions) = (0, 0);
+        for line in unified_diff(&old, &new)?.lines() {
+            match line.first() {
+                Some(b'+') => insertions += 1,
+                Some(b'-') => deletions += 1,
+                _ => {}
+            }
+        }
+        stats.push((
+            change.path.to_string(),
+            Some((insertions, deletions)),
+            (0, 0),
+        ));
+    }
+
+    let name_width = stats.iter().map(|(name, ..)| name.len()).max().unwrap_or(0);
+    let max_changes = stats
+        .iter()
+        .filter_map(|(_, lines, _)| lines.map(|(added, removed)| added + removed))
+        .max()
+        .unwrap_or(0);
+    let count_width = max_changes.to_string().len();
+    // Keep the bars within a terminal's width, like git does for large changes.
+    let scale = |n: usize| {
+        if max_changes > 50 {
+            (n * 50).div_ceil(max_changes)
+        } else {
+            n
+        }
+    };
+
+    let (mut total_insertions, mut total_deletions) = (0, 0);
+    for (name, lines, sizes) in &stats {
+        match lines {
+            Some((insertions, deletions)) => {
+                total_insertions += insertions;
+                total_deletions += deletions;
+                writeln!(
+                    out,
+                    " {:name_width$} | {:>count_width$} {}{}",
+                    name,
+                    insertions + deletions,
+                    "+".repeat(scale(*insertions)),
+                    "-".repeat(scale(*deletions)),
+                )?;
+            }
+            None => writeln!(
+                out,
+                " {:name_width$} | Bin {} -> {} bytes",
+                name, sizes.0, sizes.1
+            )?,
+        }
+    }
+
+    let plural = |count: usize, one: &'static str, many: &'static str| {
+        if count == 1 {
+            one
+        } else {
+            many
+        }
+    };
+    write!(
+        out,
+        " {} {} changed",
+        stats.len(),
+        plural(stats.len(), "file", "files")
+    )?;
+    if total_insertions > 0 || total_deletions == 0 {
+        write!(
+            out,
+            ", {} {}(+)",
+            total_insertions,
+            plural(total_insertions, "insertion", "insertions")
+        )?;
+    }
+    if total_deletions > 0 || total_insertions == 0 {
+        write!(
+            out,
+            ", {} {}(-)",
+            total_deletions,
+            plural(total_deletions, "deletion", "deletions")
+        )?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// The content of the object `entry` refers to, with submodules shown as the commit they point to.
+fn content(
+    repo: &gix::Repository,
+    entry: Option<(gix::ObjectId, gix::index::entry::Mode)>,
+) -> Result<Vec<u8>> {
+    Ok(match entry {
+        None => Vec::new(),
+        Some((id, mode)) if mode.is_submodule() => format!("Subproject commit {}\n", id).into(),
+        Some((id, _)) => repo.find_object(id)?.detach().data,
+    })
+}
+
+fn is_binary(data: &[u8]) -> bool {
+    data[..data.len().min(8000)].contains(&0)
+}
+
+/// The hunks of a unified diff between `old` and `new`, with three lines of context.
+fn unified_diff(old: &[u8], new: &[u8]) -> Result<Vec<u8>> {
+    let input = gix::diff::blob::intern::InternedInput::new(
+        gix::diff::blob::sources::byte_lines(old),
+        gix::diff::blob::sources::byte_lines(new),
+    );
+    Ok(gix::diff::blob::diff(
+        gix::diff::blob::Algorithm::Histogram,
+        &input,
+        gix::diff::blob::UnifiedDiff::new(
+            &input,
+            Vec::new(),
+            NewlineSeparator::AfterHeaderAndWhenNeeded("\n"),
+            ContextSize::symmetrical(3),
+        ),
+    )?)
+}
@@ -0,0 +1,227 @@
+use anyhow::Result;
+use gix::bstr::ByteSlice;
+use std::path::Path;
+
+/// The line below which everything is ignored with the `scissors` cleanup mode, after the comment character
+pub const SCISSORS: &str = "------------------------ >8 ------------------------";
+
+/// How a message is cleaned up before it's used, as configured with `commit.cleanup` or `--cleanup`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cleanup {
+    /// Remove comments as well as surrounding and repeated blank lines
+    Strip,
+    /// Remove surrounding and repeated blank lines, but keep comments
+    Whitespace,
+    /// Leave the message as it is
+    Verbatim,
+    /// Like `Whitespace`, but drop everything from the scissors line on
+    Scissors,
+}
+
+impl Cleanup {
+    /// Parse `mode`, with `default` depending on whether the message was `edited` in an editor, like in git.
+    pub fn parse(mode: &str, edited: bool) -> Option<Self> {
+        Some(match mode {
+            "strip" => Cleanup::Strip,
+            "whitespace" => Cleanup::Whitespace,
+            "verbatim" => Cleanup::Verbatim,
+            "scissors" if edited => Cleanup::Scissors,
+            "scissors" => Cleanup::Whitespace,
+            "default" if edited => Cleanup::Strip,
+            "default" => Cleanup::Whitespace,
+            _ => return None,
+        })
+    }
+
+    /// The comment explaining what happens to comments, for the end of a message template.
+    pub fn instructions(&self, action: &str) -> String {
+        match self {
+            Cleanup::Strip => format!(
+                "# Please enter the {} message for your changes. Lines starting\n\
+                 # with '#' will be ignored, and an empty message aborts the {}.\n",
+                action, action
+            ),
+            Cleanup::Scissors => format!(
+                "# {}\n\
+                 # Do not modify or remove the line above.\n\
+                 # Everything below it will be ignored.\n",
+                SCISSORS
+            ),
+            Cleanup::Whitespace | Cleanup::Verbatim => format!(
+                "# Please enter the {} message for your changes. Lines starting\n\
+                 # with '#' will be kept; you may remove them yourself if you want to.\n\
+                 # An empty message aborts the {}.\n",
+                action, action
+            ),
+        }
+    }
+}
+
+/// The cleanup mode from `mode`, or `commit.cleanup` if unset, or `None` after reporting an invalid mode.
+pub fn cleanup_mode(repo: &gix::Repository, mode: Option<&str>, edited: bool) -> Option<Cleanup> {
+    let configured = repo
+        .config_snapshot()
+        .string("commit.cleanup")
+        .map(|mode| mode.to_str_lossy().into_owned());
+    let mode = mode
+        .map(str::to_string)
+        .or(configured)
+        .unwrap_or_else(|| "default".into());
+    let cleanup = Cleanup::parse(&mode, edited);
+    if cleanup.is_none() {
+        println!("fatal: Invalid cleanup mode {}", mode);
+    }
+    cleanup
+}
+
+/// Clean up `message` according to `mode`, always ending it with a newline unless it's empty.
+pub fn cleanup(message: &str, mode: Cleanup) -> String {
+    if mode == Cleanup::Verbatim {
+        return message.to_string();
+    }
+    let mut lines: Vec<&str> = Vec::new();
+    for line in message.lines() {
+        if mode == Cleanup::Scissors && line.starts_with('#') && line.contains(SCISSORS) {
+            break;
+        }
+        if mode == Cleanup::Strip && line.starts_with('#') {
+            continue;
+        }
+        let line = line.trim_end();
+        if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    if lines.is_empty() {
+        return String::new();
+    }
+    format!("{}\n", lines.join("\n"))
+}
+
+/// The editor to use, from `GIT_EDITOR`, `core.editor`, `VISUAL` or `EDITOR`, in that order.
+pub fn editor(repo: &gix::Repository) -> String {
+    let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+    var("GIT_EDITOR")
+        .or_else(|| {
+            repo.config_snapshot()
+                .string("core.editor")
+                .map(|editor| editor.to_str_lossy().into_owned())
+        })
+        .or_else(|| var("VISUAL"))
+        .or_else(|| var("EDITOR"))
+        .unwrap_or_else(|| "vi".into())
+}
+
+/// Let the user edit the file at `path` and return what they saved, or `None` after reporting that the editor failed.
+pub fn edit(repo: &gix::Repository, path: &Path) -> Result<Option<String>> {
+    let editor = editor(repo);
+    // `:` is what git's tests use to accept a message as it is, so it's a no-op here too.
+    if editor != ":" {
+        let status = if cfg!(windows) {
+            std::process::Command::new("cmd")
+                .arg("/C")
+                .arg(format!("{} \"{}\"", editor, path.display()))
+                .status()?
+        } else {
+            std::process::Command::new("sh")
+                .arg("-c")
+                .arg(format!("{} \"$@\"", editor))
+                .arg(&editor)
+                .arg(path)
+                .status()?
+        };
+        if !status.success() {
+            println!("error: There was a problem with the editor '{}'.", editor);
+            return Ok(None);
+        }
+    }
+    Ok(Some(std::fs::read_to_string(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cleanup, cleanup_mode, edit, Cleanup, SCISSORS};
+
+    /// A repository whose `core.editor` runs `script` on the message file, passed as `$1`.
+    fn repo_with_editor(root: &std::path::Path, script: &str) -> gix::Repository {
+        let repo = gix::init(root.join("repo")).unwrap();
+        let editor = root.join("editor.sh");
+        std::fs::write(&editor, script).unwrap();
+        let config = repo.path().join("config");
+        let mut content = std::fs::read_to_string(&config).unwrap();
+        content.push_str(&format!("[core]\n\teditor = sh '{}'\n", editor.display()));
+        std::fs::write(&config, content).unwrap();
+        gix::open(repo.path()).unwrap()
+    }
+
+    #[test]
+    fn edit_returns_what_the_editor_saved() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with_editor(
+            root.path(),
+            "grep -q '^template$' \"$1\" || exit 1\nprintf 'edited\\n' >\"$1\"\n",
+        );
+        let path = repo.path().join("COMMIT_EDITMSG");
+        std::fs::write(&path, "template\n").unwrap();
+        assert_eq!(edit(&repo, &path).unwrap().as_deref(), Some("edited\n"));
+    }
+
+    #[test]
+    fn failing_editor_returns_none() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with_editor(root.path(), "exit 1\n");
+        let path = repo.path().join("COMMIT_EDITMSG");
+        std::fs::write(&path, "template\n").unwrap();
+        assert_eq!(edit(&repo, &path).unwrap(), None);
+    }
+
+    #[test]
+    fn cleanup_modes() {
+        let message = format!(
+            "\n\nsubject  \n\n\n# comment\nbody\n# {}\nbelow\n\n",
+            SCISSORS
+        );
+        assert_eq!(cleanup(&message, Cleanup::Verbatim), message);
+        assert_eq!(
+            cleanup(&message, Cleanup::Strip),
+            "subject\n\nbody\nbelow\n"
+        );
+        assert_eq!(
+            cleanup(&message, Cleanup::Whitespace),
+            format!("subject\n\n# comment\nbody\n# {}\nbelow\n", SCISSORS)
+        );
+        assert_eq!(
+            cleanup(&message, Cleanup::Scissors),
+            "subject\n\n# comment\nbody\n"
+        );
+        assert_eq!(cleanup("# only a comment\n", Cleanup::Strip), "");
+    }
+
+    #[test]
+    fn cleanup_mode_depends_on_config_and_editing() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with_editor(root.path(), "exit 0\n");
+        assert_eq!(cleanup_mode(&repo, None, true), Some(Cleanup::Strip));
+        assert_eq!(cleanup_mode(&repo, None, false), Some(Cleanup::Whitespace));
+        assert_eq!(
+            cleanup_mode(&repo, Some("scissors"), false),
+            Some(Cleanup::Whitespace)
+        );
+        assert_eq!(cleanup_mode(&repo, Some("bogus"), true), None);
+
+        let config = repo.path().join("config");
+        let mut content = std::fs::read_to_string(&config).unwrap();
+        content.push_str("[commit]\n\tcleanup = verbatim\n");
+        std::fs::write(&config, content).unwrap();
+        let repo = gix::open(repo.path()).unwrap();
+        assert_eq!(cleanup_mode(&repo, None, true), Some(Cleanup::Verbatim));
+        assert_eq!(
+            cleanup_mode(&repo, Some("strip"), true),
+            Some(Cleanup::Strip)
+        );
+    }
+}
@@ -1,88 +1,395 @@
-use anyhow::Result;
-use gix::bstr::BString;
+use anyhow::{anyhow, Result};
+use gix::bstr::{BStr, BString, ByteSlice};
+use gix::refs::transaction::{Change, PreviousValue, RefEdit, RefLog};
+use gix::remote::fetch::refs::update::Mode;
+use gix::remote::fetch::{Shallow, Tags};
 use std::path::Path;
 
-#[cfg(feature = "gitoxide-core-blocking-client")]
-use gitoxide_core as core;
+use super::gc;
+use super::progress;
+use super::promisor;
+
+/// The width of the summary column in the ref update table, enough for `abcdef0...1234567`
+const SUMMARY_WIDTH: usize = 2 * 7 + 3;
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub dry_run: bool,
+    pub verbose: bool,
+    /// Fetch from all configured remotes
+    pub all: bool,
+    /// Treat all positional arguments as remote names
+    pub multiple: bool,
+    /// Delete stale remote-tracking refs, or use `remote.<name>.prune` and `fetch.prune` if unset
+    pub prune: Option<bool>,
+    /// Fetch all tags with `Some(true)`, or none at all with `Some(false)`
+    pub tags: Option<bool>,
+    pub shallow: Shallow,
+    pub unshallow: bool,
+    pub filter: Option<promisor::Filter>,
+    /// Suppress progress and the ref update table
+    pub quiet: bool,
+    /// Force progress on or off, instead of showing it only if stderr is a terminal
+    pub progress: Option<bool>,
+}
 
 pub fn run(
     _repository: &Path,
     remote: Option<String>,
     refspecs: Vec<BString>,
-    dry_run: bool,
-    verbose: bool,
-    all: bool,
+    opts: Options,
 ) -> Result<()> {
-    #[cfg(not(feature = "gitoxide-core-blocking-client"))]
-    {
-        println!("fatal: fetch command requires 'gitoxide-core-blocking-client' feature");
-        if let Some(r) = remote {
-            println!("Remote: {}", r);
-        }
-        if !refspecs.is_empty() {
-            println!("Refspecs: {:?}", refspecs);
-        }
-        if dry_run {
-            println!("Dry run: true");
+    let repo = match gix::discover(".") {
+        Ok(repo) => repo,
+        Err(_) => {
+            println!("fatal: not a git repository (or any of the parent directories): .git");
+            return Ok(());
         }
-        if verbose {
-            println!("Verbose: true");
+    };
+
+    let mut opts = opts;
+    if opts.unshallow {
+        if !repo.is_shallow() {
+            println!("fatal: --unshallow on a complete repository does not make sense");
+            return Ok(());
         }
-        if all {
-            println!("All: true");
+        opts.shallow = Shallow::undo();
+    }
+
+    let remotes: Vec<String> = if opts.all {
+        if remote.is_some() || !refspecs.is_empty() {
+            println!("fatal: fetch --all does not take a repository argument");
+            return Ok(());
         }
+        repo.remote_names()
+            .into_iter()
+            .map(|name| name.to_string())
+            .collect()
+    } else if opts.multiple {
+        remote
+            .into_iter()
+            .chain(refspecs.iter().map(|name| name.to_string()))
+            .collect()
+    } else {
+        let name = remote
+            .or_else(|| {
+                repo.remote_default_name(gix::remote::Direction::Fetch)
+                    .map(|name| name.to_string())
+            })
+            .unwrap_or_else(|| "origin".into());
+        vec![name]
+    };
+    let refspecs = if opts.all || opts.multiple {
+        Vec::new()
+    } else {
+        refspecs
+    };
+
+    if remotes.len() > 1 && opts.filter.is_some() {
+        println!("fatal: --filter can only be used with a single remote");
         return Ok(());
     }
 
-    #[cfg(feature = "gitoxide-core-blocking-client")]
-    {
-        let repo = match gix::discover(".") {
-            Ok(repo) => repo,
-            Err(_) => {
-                println!("fatal: not a git repository (or any of the parent directories): .git");
-                return Ok(());
+    let mut failed = Vec::new();
+    for name in &remotes {
+        if remotes.len() > 1 && !opts.quiet {
+            println!("Fetching {}", name);
+        }
+        if let Err(e) = fetch_remote(&repo, name, &refspecs, &opts) {
+            if remotes.len() > 1 {
+                println!("error: could not fetch {}: {}", name, e);
+                failed.push(name.as_str());
+            } else {
+                println!("fatal: {}", e);
             }
-        };
-
-        if all {
-            println!("fatal: --all flag not yet implemented");
-            return Ok(());
         }
+    }
+    if !failed.is_empty() {
+        println!("error: could not fetch {}", failed.join(", "));
+    }
+    if !opts.dry_run {
+        gc::auto(&repo, opts.quiet)?;
+    }
 
-        let opts = core::repository::fetch::Options {
-            format: core::OutputFormat::Human,
-            dry_run,
-            remote,
-            handshake_info: verbose,
-            negotiation_info: verbose,
-            open_negotiation_graph: None,
-            shallow: Default::default(),
-            ref_specs: refspecs,
-        };
+    Ok(())
+}
 
-        let mut out = Vec::new();
-        let mut err = Vec::new();
+fn fetch_remote(
+    repo: &gix::Repository,
+    name: &str,
+    refspecs: &[BString],
+    opts: &Options,
+) -> Result<()> {
+    let mut remote = repo.find_fetch_remote(Some(name.into()))?;
+    if !refspecs.is_empty() {
+        remote.replace_refspecs(refspecs.iter(), gix::remote::Direction::Fetch)?;
+        remote = remote.with_fetch_tags(Tags::Included);
+    }
+    match opts.tags {
+        Some(true) => remote = remote.with_fetch_tags(Tags::All),
+        Some(false) => remote = remote.with_fetch_tags(Tags::None),
+        None => {}
+    }
 
-        let result =
-            core::repository::fetch(repo, gix::progress::Discard, &mut out, &mut err, opts);
+    let url = remote
+        .url(gix::remote::Direction::Fetch)
+        .map(|url| url.to_bstring())
+        .unwrap_or_else(|| name.into());
 
-        match result {
-            Ok(_) => {
-                let stdout_output = String::from_utf8_lossy(&out);
-                if !stdout_output.is_empty() {
-                    print!("{}", stdout_output);
-                }
+    let filter = opts
+        .filter
+        .clone()
+        .or_else(|| promisor::configured_filter(repo, name));
+    let (ref_map, updates) = progress::run(
+        "fetch",
+        progress::enabled(opts.progress, opts.quiet),
+        |mut progress| match &filter {
+            Some(filter) => promisor::fetch(
+                repo,
+                &remote,
+                filter,
+                &opts.shallow,
+                opts.dry_run,
+                &mut progress,
+            ),
+            None => {
+                let outcome = remote
+                    .connect(gix::remote::Direction::Fetch)?
+                    .prepare_fetch(&mut progress, Default::default())?
+                    .with_dry_run(opts.dry_run)
+                    .with_shallow(opts.shallow.clone())
+                    .receive(&mut progress, &gix::interrupt::IS_INTERRUPTED)?;
+                let updates = ref_updates(&outcome);
+                Ok((outcome.ref_map, updates))
+            }
+        },
+    )?;
 
-                let stderr_output = String::from_utf8_lossy(&err);
-                if !stderr_output.is_empty() {
-                    eprint!("{}", stderr_output);
+    let mut lines = Vec::new();
+    for update in updates {
+        let is_tag = update.local.starts_with(b"refs/tags/");
+        let (flag, summary, note) = match &update.mode {
+            Mode::NoChangeNeeded => {
+                if !opts.verbose {
+                    continue;
                 }
+                ('=', "[up to date]".to_string(), None)
             }
-            Err(e) => {
-                println!("fatal: {}", e);
+            Mode::New => {
+                let summary = if is_tag {
+                    "[new tag]"
+                } else if update.local.starts_with(b"refs/remotes/")
+                    || update.local.starts_with(b"refs/heads/")
+                {
+                    "[new branch]"
+                } else {
+                    "[new ref]"
+                };
+                ('*', summary.to_string(), None)
             }
+            Mode::FastForward => match (update.old, update.new) {
+                (Some(old), Some(new)) => (
+                    ' ',
+                    format!("{}..{}", old.to_hex_with_len(7), new.to_hex_with_len(7)),
+                    None,
+                ),
+                _ => (' ', "[updated]".to_string(), None),
+            },
+            Mode::Forced => match (update.old, update.new) {
+                (Some(old), Some(new)) if !is_tag => (
+                    '+',
+                    format!("{}...{}", old.to_hex_with_len(7), new.to_hex_with_len(7)),
+                    Some("forced update".to_string()),
+                ),
+                _ => ('t', "[tag update]".to_string(), None),
+            },
+            Mode::ImplicitTagNotSentByRemote => continue,
+            Mode::RejectedTagUpdate => (
+                '!',
+                "[rejected]".to_string(),
+                Some("would clobber existing tag".to_string()),
+            ),
+            Mode::RejectedNonFastForward => (
+                '!',
+                "[rejected]".to_string(),
+                Some("non-fast-forward".to_string()),
+            ),
+            rejected => (
+                '!',
+                "[rejected]".to_string(),
+                Some(
+                    rejected
+                        .to_string()
+                        .trim_start_matches("rejected (")
+                        .trim_end_matches(')')
+                        .to_string(),
+                ),
+            ),
+        };
+        lines.push((
+            flag,
+            summary,
+            short_name(update.remote.as_ref()).to_owned(),
+            short_name(update.local.as_ref()).to_owned(),
+            note,
+        ));
+    }
+
+    let prune = opts.prune.unwrap_or_else(|| {
+        let config = repo.config_snapshot();
+        config
+            .boolean(format!("remote.{}.prune", name).as_str())
+            .or_else(|| config.boolean("fetch.prune"))
+            .unwrap_or(false)
+    });
+    if prune {
+        for stale in prune_stale_refs(repo, &remote, &ref_map, opts.dry_run)? {
+            lines.push((
+                '-',
+                "[deleted]".to_string(),
+                "(none)".into(),
+                short_name(stale.as_ref()).to_owned(),
+                None,
+            ));
+        }
+    }
+
+    if !lines.is_empty() && !opts.quiet {
+        eprintln!("From {}", url);
+        let width = lines
+            .iter()
+            .map(|(_, _, remote_name, _, _)| remote_name.len())
+            .max()
+            .unwrap_or(0);
+        for (flag, summary, remote_name, local, note) in lines {
+            let note = note.map(|note| format!("  ({})", note)).unwrap_or_default();
+            eprintln!(
+                " {} {:<summary_width$} {:<width$} -> {}{}",
+                flag,
+                summary,
+                remote_name.to_str_lossy(),
+                local.to_str_lossy(),
+                note,
+                summary_width = SUMMARY_WIDTH,
+                width = width,
+            );
         }
+    }
 
-        Ok(())
+    Ok(())
+}
+
+/// How a fetch changed a local ref, or left it alone.
+pub(super) struct RefUpdate {
+    pub mode: Mode,
+    /// The name of the ref on the remote, or the id that was fetched if it was asked for by id
+    pub remote: BString,
+    /// The full name of the local ref
+    pub local: BString,
+    pub old: Option<gix::ObjectId>,
+    pub new: Option<gix::ObjectId>,
+}
+
+/// The updates gix made to the local refs of the mappings in `outcome`.
+fn ref_updates(outcome: &gix::remote::fetch::Outcome) -> Vec<RefUpdate> {
+    let update_refs = match &outcome.status {
+        gix::remote::fetch::Status::NoPackReceived { update_refs, .. }
+        | gix::remote::fetch::Status::Change { update_refs, .. } => update_refs,
+    };
+    let mut updates = Vec::new();
+    for (update, mapping, _spec, edit) in update_refs.iter_mapping_updates(
+        &outcome.ref_map.mappings,
+        &outcome.ref_map.refspecs,
+        &outcome.ref_map.extra_refspecs,
+    ) {
+        let (Some(local), Some(edit)) = (&mapping.local, edit) else {
+            continue;
+        };
+        let remote = match &mapping.remote {
+            gix::remote::fetch::refmap::Source::Ref(r) => r.unpack().0.to_owned(),
+            gix::remote::fetch::refmap::Source::ObjectId(id) => id.to_string().into(),
+        };
+        updates.push(RefUpdate {
+            mode: update.mode.clone(),
+            remote,
+            local: local.clone(),
+            old: edit
+                .change
+                .previous_value()
+                .and_then(|target| target.try_id().map(ToOwned::to_owned)),
+            new: mapping.remote.as_id().map(ToOwned::to_owned),
+        });
+    }
+    updates
+}
+
+/// Delete remote-tracking refs matched by the remote's refspecs whose counterpart vanished on the remote.
+///
+/// Returns the names of the deleted refs, which are only reported but kept in `dry_run` mode.
+pub(super) fn prune_stale_refs(
+    repo: &gix::Repository,
+    remote: &gix::Remote<'_>,
+    ref_map: &gix::remote::fetch::RefMap,
+    dry_run: bool,
+) -> Result<Vec<BString>> {
+    let prefixes: Vec<BString> = remote
+        .refspecs(gix::remote::Direction::Fetch)
+        .iter()
+        .filter_map(|spec| {
+            let destination = spec.to_ref().destination()?;
+            destination
+                .strip_suffix(b"*")
+                .map(|prefix| prefix.as_bstr().to_owned())
+        })
+        .collect();
+    if prefixes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut edits = Vec::new();
+    for reference in repo.references()?.all()? {
+        let reference = reference.map_err(|e| anyhow!("{}", e))?;
+        let name = reference.name().as_bstr();
+        if !prefixes.iter().any(|prefix| name.starts_with(prefix)) {
+            continue;
+        }
+        // Symbolic refs like `refs/remotes/origin/HEAD` aren't advertised by name and are kept.
+        if reference.target().try_id().is_none() {
+            continue;
+        }
+        let is_mapped = ref_map
+            .mappings
+            .iter()
+            .any(|mapping| mapping.local.as_ref().is_some_and(|local| local == name));
+        if is_mapped {
+            continue;
+        }
+        edits.push(RefEdit {
+            change: Change::Delete {
+                expected: PreviousValue::MustExistAndMatch(reference.target().into_owned()),
+                log: RefLog::AndReference,
+            },
+            name: reference.name().to_owned(),
+            deref: false,
+        });
+    }
+
+    let names = edits
+        .iter()
+        .map(|edit| edit.name.as_bstr().to_owned())
+        .collect();
+    if !dry_run {
+        repo.edit_references(edits)?;
+    }
+    Ok(names)
+}
+
+/// Shorten a full reference name the way git displays it in the ref update table.
+fn short_name(name: &BStr) -> &BStr {
+    for prefix in ["refs/heads/", "refs/tags/", "refs/remotes/"] {
+        if let Some(short) = name.strip_prefix(prefix.as_bytes()) {
+            return short.as_bstr();
+        }
     }
+    name
 }
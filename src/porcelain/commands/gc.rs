@@ -0,0 +1,891 @@
+use anyhow::{anyhow, Result};
+use gitoxide_core as core;
+use gix::bstr::ByteSlice;
+use gix::objs::Write;
+use gix::refs::file::transaction::PackedRefs;
+use gix::refs::transaction::{Change, LogChange, PreviousValue, RefEdit, RefLog};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::promisor;
+use super::reflog;
+use super::revision_utils::parse_date;
+
+/// The generation a commit-graph can store at most, which all later generations are capped at
+const GENERATION_MAX: u32 = 0x3fff_ffff;
+/// The parent position in the commit-graph of a commit without this parent
+const NO_PARENT: u32 = 0x7000_0000;
+/// Marks the second parent position as the start of the parents in the extra edge list, and the last of them there
+const EXTRA_EDGES: u32 = 0x8000_0000;
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Only run if there are too many loose objects or packs, as configured with `gc.auto` and `gc.autoPackLimit`
+    pub auto: bool,
+    /// Prune unreachable objects older than this date instead of `gc.pruneExpire`
+    pub prune: Option<String>,
+    /// Keep all unreachable objects
+    pub no_prune: bool,
+    /// Don't say that the repository is packed with `auto`
+    pub quiet: bool,
+}
+
+/// Clean up the repository by packing references into `packed-refs`, expiring reflogs, packing all reachable objects
+/// into a single pack and writing a multi-pack-index and commit-graph for it.
+///
+/// Unreachable objects stay around as loose objects until they are older than `gc.pruneExpire`, two weeks by default.
+pub fn run(opts: Options) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    if opts.auto {
+        return auto(&repo, opts.quiet);
+    }
+    let prune = if opts.no_prune {
+        None
+    } else {
+        let Some(prune) = prune_expiry(&repo, opts.prune.as_deref()) else {
+            return Ok(());
+        };
+        prune
+    };
+    gc(&repo, prune)
+}
+
+/// Run `gc` like `gc --auto` does if there are too many loose objects or packs, for commands that add objects.
+pub(super) fn auto(repo: &gix::Repository, quiet: bool) -> Result<()> {
+    if !needs_gc(repo) {
+        return Ok(());
+    }
+    if !quiet {
+        eprintln!("Auto packing the repository for optimum performance.");
+        eprintln!("See \"git help gc\" for manual housekeeping.");
+    }
+    let Some(prune) = prune_expiry(repo, None) else {
+        return Ok(());
+    };
+    gc(repo, prune)
+}
+
+fn gc(repo: &gix::Repository, prune: Option<i64>) -> Result<()> {
+    pack_refs(repo)?;
+    reflog::expire_all(repo)?;
+    let (commits, others) = roots(repo)?;
+    if promisor::promisor_remote(repo).is_none() {
+        let mut objects = others;
+        if !commits.is_empty() {
+            for info in repo.rev_walk(commits.iter().copied()).all()? {
+                objects.push(info?.id);
+            }
+        }
+        repack(
+            repo,
+            &objects,
+            core::pack::create::ObjectExpansion::TreeTraversal,
+            prune,
+        )?;
+    } else {
+        // Partial clones lack objects on purpose, so only what isn't in promisor packs is traversed and packed.
+        let objects = local_objects(repo, commits.iter().chain(&others).copied())?;
+        repack(
+            repo,
+            &objects,
+            core::pack::create::ObjectExpansion::None,
+            prune,
+        )?;
+    }
+    write_multi_pack_index(repo)?;
+    // The history of shallow repositories ends early, which the commit-graph can't express.
+    if !repo.is_shallow() {
+        write_commit_graph(repo, &commits)?;
+    }
+    Ok(())
+}
+
+/// Whether there are more loose objects than `gc.auto`, 6700 by default, or more packs than `gc.autoPackLimit`, 50 by default.
+fn needs_gc(repo: &gix::Repository) -> bool {
+    let config = repo.config_snapshot();
+    let auto = config.integer("gc.auto").unwrap_or(6700);
+    if auto <= 0 {
+        return false;
+    }
+    let objects_dir = repo.objects.store_ref().path();
+    // Like git, estimate the number of loose objects from one of the 256 directories they are spread across.
+    let name_len = repo.object_hash().len_in_hex() - 2;
+    let loose = std::fs::read_dir(objects_dir.join("17")).map_or(0, |entries| {
+        entries
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name().len() == name_len)
+            .count()
+    });
+    if loose as i64 > (auto + 255) / 256 {
+        return true;
+    }
+    let pack_limit = config.integer("gc.autoPackLimit").unwrap_or(50);
+    pack_limit > 0 && repackable_packs(&objects_dir.join("pack")).len() as i64 > pack_limit
+}
+
+/// The time before which unreachable objects are pruned, given as `prune` or configured with `gc.pruneExpire`,
+/// with `None` meaning never.
+///
+/// Returns `None` after reporting a date that can't be parsed.
+fn prune_expiry(repo: &gix::Repository, prune: Option<&str>) -> Option<Option<i64>> {
+    let value = match prune {
+        Some(value) => value.to_string(),
+        None => repo
+            .config_snapshot()
+            .string("gc.pruneExpire")
+            .map_or_else(|| "2.weeks.ago".to_string(), |value| value.to_string()),
+    };
+    match value.as_str() {
+        "never" | "false" => Some(None),
+        "all" | "now" => Some(Some(i64::MAX)),
+        date => match parse_date(date) {
+            Some(time) => Some(Some(time.seconds)),
+            None => {
+                println!("fatal: failed to parse prune expiry value {}", value);
+                None
+            }
+        },
+    }
+}
+
+/// Move all loose references into `packed-refs`, except for those that belong to a single worktree.
+fn pack_refs(repo: &gix::Repository) -> Result<()> {
+    let mut edits = Vec::new();
+    for reference in repo.refs.loose_iter()? {
+        let reference = reference?;
+        let name = reference.name.as_bstr();
+        if !name.starts_with(b"refs/")
+            || ["refs/worktree/", "refs/bisect/", "refs/rewritten/"]
+                .iter()
+                .any(|prefix| name.starts_with(prefix.as_bytes()))
+        {
+            continue;
+        }
+        // Symbolic references can't be packed.
+        let gix::refs::Target::Object(id) = reference.target else {
+            continue;
+        };
+        // The reference keeps its value, so the reflog doesn't get an entry.
+        edits.push(RefEdit {
+            change: Change::Update {
+                log: LogChange {
+                    mode: RefLog::AndReference,
+                    force_create_reflog: false,
+                    message: "pack-refs".into(),
+                },
+                expected: PreviousValue::MustExistAndMatch(gix::refs::Target::Object(id)),
+                new: gix::refs::Target::Object(id),
+            },
+            name: reference.name,
+            deref: false,
+        });
+    }
+    if edits.is_empty() {
+        return Ok(());
+    }
+    repo.refs
+        .transaction()
+        .packed_refs(
+            PackedRefs::DeletionsAndNonSymbolicUpdatesRemoveLooseSourceReference(Box::new(
+                repo.objects.clone(),
+            )),
+        )
+        .prepare(
+            edits,
+            gix::lock::acquire::Fail::Immediately,
+            gix::lock::acquire::Fail::Immediately,
+        )?
+        .commit(None::<gix::actor::SignatureRef<'_>>)?;
+    Ok(())
+}
+
+/// Everything that keeps objects alive, which is what references, the `HEAD`s and reflogs of all worktrees point to,
+/// along with the blobs in their indices. Returns the commits among them, and all other objects.
+fn roots(repo: &gix::Repository) -> Result<(Vec<gix::ObjectId>, Vec<gix::ObjectId>)> {
+    let mut roots = Vec::new();
+    for reference in repo.references()?.all()? {
+        let reference = reference.map_err(|e| anyhow!("{}", e))?;
+        if let Some(id) = reference.target().try_id() {
+            roots.push(id.to_owned());
+        }
+    }
+    let mut git_dirs = vec![repo.common_dir().to_owned()];
+    for proxy in repo.worktrees()? {
+        git_dirs.push(proxy.git_dir().to_owned());
+    }
+    for git_dir in &git_dirs {
+        // A detached `HEAD` isn't a reference that's listed.
+        if let Ok(head) = std::fs::read_to_string(git_dir.join("HEAD")) {
+            roots.extend(gix::ObjectId::from_hex(head.trim().as_bytes()).ok());
+        }
+        if let Ok(index) = gix::index::File::at(
+            git_dir.join("index"),
+            repo.object_hash(),
+            false,
+            Default::default(),
+        ) {
+            roots.extend(
+                index
+                    .entries()
+                    .iter()
+                    .filter(|entry| entry.mode != gix::index::entry::Mode::COMMIT)
+                    .map(|entry| entry.id),
+            );
+        }
+        for entry in walkdir::WalkDir::new(git_dir.join("logs"))
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_file())
+        {
+            let Ok(log) = std::fs::read(entry.path()) else {
+                continue;
+            };
+            for line in log.lines_with_terminator() {
+                if let Ok(line) = gix::refs::file::log::LineRef::from_bytes(line) {
+                    roots.extend([line.previous_oid(), line.new_oid()]);
+                }
+            }
+        }
+    }
+    roots.retain(|id| !id.is_null());
+    roots.sort();
+    roots.dedup();
+
+    let mut commits = Vec::new();
+    let mut others = Vec::new();
+    for id in roots {
+        // Reflogs may still mention objects that were pruned.
+        match repo.try_find_header(id)? {
+            Some(header) if header.kind() == gix::objs::Kind::Commit => commits.push(id),
+            Some(_) => others.push(id),
+            None => {}
+        }
+    }
+    Ok((commits, others))
+}
+
+/// The objects reachable from `roots` that aren't in packs of the promisor remote, stopping at those that are
+/// or that are missing, as the promisor remote can provide them.
+fn local_objects(
+    repo: &gix::Repository,
+    roots: impl IntoIterator<Item = gix::ObjectId>,
+) -> Result<Vec<gix::ObjectId>> {
+    let pack_dir = repo.objects.store_ref().path().join("pack");
+    let promised = files_with_extension(&pack_dir, "promisor")
+        .into_iter()
+        .map(|marker| {
+            gix::odb::pack::index::File::at(marker.with_extension("idx"), repo.object_hash())
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut seen = HashSet::new();
+    let mut objects = Vec::new();
+    let mut stack: Vec<_> = roots.into_iter().collect();
+    while let Some(id) = stack.pop() {
+        if !seen.insert(id)
+            || promised.iter().any(|index| index.lookup(id).is_some())
+            || !repo.has_object(id)
+        {
+            continue;
+        }
+        objects.push(id);
+        let object = repo.find_object(id)?;
+        match object.kind {
+            gix::objs::Kind::Commit => {
+                let commit = object.into_commit();
+                stack.push(commit.tree_id()?.detach());
+                stack.extend(commit.parent_ids().map(|id| id.detach()));
+            }
+            gix::objs::Kind::Tree => {
+                let tree = object.into_tree();
+                stack.extend(
+                    tree.decode()?
+                        .entries
+                        .iter()
+                        .filter(|entry| !entry.mode.is_commit())
+                        .map(|entry| entry.oid.to_owned()),
+                );
+            }
+            gix::objs::Kind::Tag => stack.push(object.into_tag().target_id()?.detach()),
+            gix::objs::Kind::Blob => {}
+        }
+    }
+    Ok(objects)
+}
+
+/// Pack `objects`, along with everything they reference with `expansion`, into a single new pack replacing
+/// all others but those that are kept or from promisor remotes, and remove the loose objects that are now packed
+/// or unreachable and older than `prune`.
+///
+/// Unreachable objects of the replaced packs are written as loose objects with the time of their pack,
+/// unless they would be pruned right away, so they expire like all others.
+fn repack(
+    repo: &gix::Repository,
+    objects: &[gix::ObjectId],
+    expansion: core::pack::create::ObjectExpansion,
+    prune: Option<i64>,
+) -> Result<()> {
+    let objects_dir = repo.objects.store_ref().path().to_owned();
+    let pack_dir = objects_dir.join("pack");
+    let loose = gix::odb::loose::Store::at(&objects_dir, repo.object_hash());
+
+    let packed = if objects.is_empty() {
+        None
+    } else {
+        let index_path = create_pack(repo, &pack_dir, objects, expansion)?;
+        let packed = gix::odb::pack::index::File::at(&index_path, repo.object_hash())?;
+
+        let old_packs: Vec<_> = repackable_packs(&pack_dir)
+            .into_iter()
+            .filter(|pack| *pack != index_path.with_extension("pack"))
+            .collect();
+        for pack in &old_packs {
+            loosen_unreachable(repo, &loose, pack, &packed, prune)?;
+        }
+        // The multi-pack-index still lists the old packs, and is written anew once they are gone.
+        remove_if_exists(&pack_dir.join("multi-pack-index"))?;
+        for pack in &old_packs {
+            for extension in ["idx", "rev", "bitmap", "mtimes", "pack"] {
+                // A pack that's still in use on some platforms is left for the next run.
+                std::fs::remove_file(pack.with_extension(extension)).ok();
+            }
+        }
+        Some(packed)
+    };
+
+    for id in loose.iter() {
+        let Ok(id) = id else {
+            continue;
+        };
+        let path = loose.object_path(&id);
+        let is_packed = packed
+            .as_ref()
+            .is_some_and(|packed| packed.lookup(id).is_some());
+        let expired = prune.is_some_and(|expire| modified_seconds(&path) < expire);
+        if is_packed || expired {
+            std::fs::remove_file(&path)?;
+            if let Some(dir) = path.parent() {
+                // Only succeeds once the directory is empty.
+                std::fs::remove_dir(dir).ok();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write a pack of `objects` and what `expansion` adds into `pack_dir` like `free pack create` does,
+/// returning the path to its index.
+fn create_pack(
+    repo: &gix::Repository,
+    pack_dir: &Path,
+    objects: &[gix::ObjectId],
+    expansion: core::pack::create::ObjectExpansion,
+) -> Result<PathBuf> {
+    // Packs are written to a directory of their own first, so they're easy to tell apart from existing ones.
+    let staging = pack_dir.join("tmp_gc");
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)?;
+    }
+    std::fs::create_dir_all(&staging)?;
+    let input: Vec<u8> = objects
+        .iter()
+        .flat_map(|id| format!("{}\n", id).into_bytes())
+        .collect();
+    core::pack::create(
+        repo.path(),
+        Vec::<std::ffi::OsString>::new(),
+        Some(std::io::Cursor::new(input)),
+        Some(&staging),
+        gix::progress::Discard,
+        core::pack::create::Context {
+            thread_limit: None,
+            thin: false,
+            nondeterministic_thread_count: None,
+            pack_cache_size_in_bytes: 0,
+            object_cache_size_in_bytes: 0,
+            statistics: None,
+            out: std::io::sink(),
+            expansion,
+        },
+    )?;
+    let Some(pack) = files_with_extension(&staging, "pack").into_iter().next() else {
+        return Err(anyhow!("no pack was written to {}", staging.display()));
+    };
+    core::pack::index::from_pack(
+        core::pack::index::PathOrRead::Path(pack),
+        Some(staging.clone()),
+        gix::progress::Discard,
+        core::pack::index::Context {
+            thread_limit: None,
+            iteration_mode: core::pack::index::IterationMode::Verify,
+            format: core::OutputFormat::Human,
+            out: std::io::sink(),
+            object_hash: repo.object_hash(),
+            should_interrupt: &gix::interrupt::IS_INTERRUPTED,
+        },
+    )?;
+    let Some(index) = files_with_extension(&staging, "idx").into_iter().next() else {
+        return Err(anyhow!(
+            "no pack index was written to {}",
+            staging.display()
+        ));
+    };
+
+    // The pack is moved first, as the index is what makes it visible.
+    let stem = index
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = if stem.starts_with("pack-") {
+        stem
+    } else {
+        format!("pack-{}", stem)
+    };
+    let index_path = pack_dir.join(&name).with_extension("idx");
+    std::fs::rename(
+        index.with_extension("pack"),
+        index_path.with_extension("pack"),
+    )?;
+    std::fs::rename(&index, &index_path)?;
+    std::fs::remove_dir_all(&staging)?;
+    Ok(index_path)
+}
+
+/// Write the objects of `pack` that aren't in `packed` as loose objects, with the time of the pack so they expire
+/// as if they had been loose all along, unless they are older than `prune` already.
+fn loosen_unreachable(
+    repo: &gix::Repository,
+    loose: &gix::odb::loose::Store,
+    pack: &Path,
+    packed: &gix::odb::pack::index::File,
+    prune: Option<i64>,
+) -> Result<()> {
+    if prune.is_some_and(|expire| modified_seconds(pack) < expire) {
+        return Ok(());
+    }
+    let modified = std::fs::metadata(pack)?.modified()?;
+    let index = gix::odb::pack::index::File::at(pack.with_extension("idx"), repo.object_hash())?;
+    for entry in index.iter() {
+        if packed.lookup(entry.oid).is_some() || loose.contains(&entry.oid) {
+            continue;
+        }
+        let object = repo.find_object(entry.oid)?;
+        loose
+            .write_buf(object.kind, &object.data)
+            .map_err(|err| anyhow!(err))?;
+        // Without the time of the pack, the object is only kept for longer.
+        if let Ok(file) = std::fs::File::open(loose.object_path(&entry.oid)) {
+            file.set_modified(modified).ok();
+        }
+    }
+    Ok(())
+}
+
+/// Write `objects/pack/multi-pack-index` for all packs like `free pack multi-index create` does.
+fn write_multi_pack_index(repo: &gix::Repository) -> Result<()> {
+    let pack_dir = repo.objects.store_ref().path().join("pack");
+    let multi_index_path = pack_dir.join("multi-pack-index");
+    let indices = files_with_extension(&pack_dir, "idx");
+    if indices.is_empty() {
+        return remove_if_exists(&multi_index_path);
+    }
+    core::pack::multi_index::create(
+        indices,
+        multi_index_path,
+        gix::progress::Discard,
+        &gix::interrupt::IS_INTERRUPTED,
+        repo.object_hash(),
+    )
+}
+
+/// Write `objects/info/commit-graph` for all commits reachable from `commits`, replacing a split commit-graph.
+///
+/// It holds the chunks git writes by default: the fan-out table, the sorted commit ids, the tree, parents,
+/// generation and commit time of each commit, and the parents of octopus merges beyond the first.
+fn write_commit_graph(repo: &gix::Repository, commits: &[gix::ObjectId]) -> Result<()> {
+    if commits.is_empty() {
+        return Ok(());
+    }
+    // Tree, parents and commit time of each commit, sorted by id like the commit-graph is.
+    let mut graph = BTreeMap::new();
+    for info in repo.rev_walk(commits.iter().copied()).all()? {
+        let commit = repo.find_commit(info?.id)?;
+        let parents: Vec<gix::ObjectId> = commit.parent_ids().map(|id| id.detach()).collect();
+        let time = commit.time()?.seconds.max(0) as u64;
+        graph.insert(commit.id, (commit.tree_id()?.detach(), parents, time));
+    }
+    let ids: Vec<gix::ObjectId> = graph.keys().copied().collect();
+    let position = |id: &gix::ObjectId| ids.binary_search(id).map_or(NO_PARENT, |pos| pos as u32);
+
+    // The generation of a commit is one more than the highest of its parents, which are resolved first.
+    let mut generations: HashMap<gix::ObjectId, u32> = HashMap::new();
+    for id in &ids {
+        let mut stack = vec![*id];
+        while let Some(&top) = stack.last() {
+            if generations.contains_key(&top) {
+                stack.pop();
+                continue;
+            }
+            let parents = &graph[&top].1;
+            let unresolved: Vec<_> = parents
+                .iter()
+                .filter(|parent| graph.contains_key(*parent) && !generations.contains_key(*parent))
+                .copied()
+                .collect();
+            if unresolved.is_empty() {
+                let generation = parents
+                    .iter()
+                    .filter_map(|parent| generations.get(parent))
+                    .max()
+                    .map_or(1, |generation| (generation + 1).min(GENERATION_MAX));
+                generations.insert(top, generation);
+                stack.pop();
+            } else {
+                stack.extend(unresolved);
+            }
+        }
+    }
+
+    let mut fanout = Vec::with_capacity(256 * 4);
+    for byte in 0..=255u8 {
+        let count = ids.partition_point(|id| id.as_bytes()[0] <= byte) as u32;
+        fanout.extend_from_slice(&count.to_be_bytes());
+    }
+    let mut lookup = Vec::new();
+    let mut data = Vec::new();
+    let mut edges = Vec::new();
+    for id in &ids {
+        let (tree, parents, time) = &graph[id];
+        lookup.extend_from_slice(id.as_bytes());
+        data.extend_from_slice(tree.as_bytes());
+        let first = parents.first().map_or(NO_PARENT, position);
+        let second = match parents.len() {
+            0 | 1 => NO_PARENT,
+            2 => position(&parents[1]),
+            _ => {
+                let start = (edges.len() / 4) as u32;
+                for (i, parent) in parents[1..].iter().enumerate() {
+                    let mut edge = position(parent);
+                    if i == parents.len() - 2 {
+                        edge |= EXTRA_EDGES;
+                    }
+                    edges.extend_from_slice(&edge.to_be_bytes());
+                }
+                EXTRA_EDGES | start
+            }
+        };
+        data.extend_from_slice(&first.to_be_bytes());
+        data.extend_from_slice(&second.to_be_bytes());
+        // 30 bits of generation and 34 bits of commit time.
+        let generation = generations[id];
+        data.extend_from_slice(&((generation << 2) | ((time >> 32) as u32 & 0b11)).to_be_bytes());
+        data.extend_from_slice(&(*time as u32).to_be_bytes());
+    }
+    let mut chunks: Vec<(&[u8; 4], Vec<u8>)> =
+        vec![(b"OIDF", fanout), (b"OIDL", lookup), (b"CDAT", data)];
+    if !edges.is_empty() {
+        chunks.push((b"EDGE", edges));
+    }
+
+    let mut out = Vec::new();
+    out.extend_from_slice(b"CGPH");
+    // Version 1, the hash version of the object ids, numbered like gix numbers its hash kinds, and no base graphs.
+    out.extend_from_slice(&[1, repo.object_hash() as u8, chunks.len() as u8, 0]);
+    let mut offset = (out.len() + (chunks.len() + 1) * 12) as u64;
+    for (id, chunk) in &chunks {
+        out.extend_from_slice(*id);
+        out.extend_from_slice(&offset.to_be_bytes());
+        offset += chunk.len() as u64;
+    }
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(&offset.to_be_bytes());
+    for (_, chunk) in &chunks {
+        out.extend_from_slice(chunk);
+    }
+    let mut hasher = gix::hash::hasher(repo.object_hash());
+    hasher.update(&out);
+    out.extend_from_slice(hasher.try_finalize()?.as_bytes());
+
+    let info_dir = repo.objects.store_ref().path().join("info");
+    std::fs::create_dir_all(&info_dir)?;
+    let lock_path = info_dir.join("commit-graph.lock");
+    std::fs::write(&lock_path, &out)?;
+    std::fs::rename(&lock_path, info_dir.join("commit-graph"))?;
+    let chain_dir = info_dir.join("commit-graphs");
+    if chain_dir.is_dir() {
+        std::fs::remove_dir_all(chain_dir)?;
+    }
+    Ok(())
+}
+
+/// The packs in `pack_dir` that may be replaced, which are all but those marked to be kept and those of promisor remotes.
+fn repackable_packs(pack_dir: &Path) -> Vec<PathBuf> {
+    files_with_extension(pack_dir, "pack")
+        .into_iter()
+        .filter(|pack| {
+            !pack.with_extension("keep").exists() && !pack.with_extension("promisor").exists()
+        })
+        .collect()
+}
+
+/// The files in `dir` with `extension`, sorted by name.
+fn files_with_extension(dir: &Path, extension: &str) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == extension))
+        .collect();
+    files.sort();
+    files
+}
+
+/// When the file at `path` was last modified, in seconds since the epoch, or the current time if unknown.
+fn modified_seconds(path: &Path) -> i64 {
+    let modified = std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .unwrap_or_else(|_| SystemTime::now());
+    modified
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn open_repo() -> Option<gix::Repository> {
+    match gix::discover(".") {
+        Ok(repo) => Some(repo),
+        Err(_) => {
+            println!("fatal: not a git repository (or any of the parent directories): .git");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::process::Command;
+
+    use super::{gc, write_commit_graph};
+
+    /// Run `git` in `dir`, returning its trimmed output.
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args([
+                "-c",
+                "user.name=A U Thor",
+                "-c",
+                "user.email=author@example.com",
+            ])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .expect("git can be run");
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8(output.stdout).unwrap().trim().to_owned()
+    }
+
+    /// A repository with a commit on `main`, or `None` if git isn't installed.
+    fn repo(root: &Path) -> Option<std::path::PathBuf> {
+        Command::new("git").arg("--version").output().ok()?;
+        let dir = root.join("repo");
+        std::fs::create_dir_all(dir.join("dir")).unwrap();
+        git(&dir, &["init", "--quiet", "--initial-branch=main"]);
+        std::fs::write(dir.join("top"), "top\n").unwrap();
+        std::fs::write(dir.join("dir/file"), "file\n").unwrap();
+        git(&dir, &["add", "."]);
+        git(&dir, &["commit", "--quiet", "-m", "initial"]);
+        Some(dir)
+    }
+
+    fn id(hex: &str) -> gix::ObjectId {
+        gix::ObjectId::from_hex(hex.as_bytes()).unwrap()
+    }
+
+    /// All objects git considers reachable.
+    fn reachable(dir: &Path) -> Vec<gix::ObjectId> {
+        git(
+            dir,
+            &[
+                "rev-list",
+                "--objects",
+                "--all",
+                "--reflog",
+                "--indexed-objects",
+            ],
+        )
+        .lines()
+        .map(|line| id(line.split(' ').next().unwrap()))
+        .collect()
+    }
+
+    #[test]
+    fn commit_graph_reads_back() {
+        let root = tempfile::tempdir().unwrap();
+        let Some(dir) = repo(root.path()) else {
+            return;
+        };
+        let first = git(&dir, &["rev-parse", "HEAD"]);
+        let tree = git(&dir, &["rev-parse", "HEAD^{tree}"]);
+        let second = git(&dir, &["commit-tree", &tree, "-p", &first, "-m", "second"]);
+        let third = git(&dir, &["commit-tree", &tree, "-p", &first, "-m", "third"]);
+        let octopus = git(
+            &dir,
+            &[
+                "commit-tree",
+                &tree,
+                "-p",
+                &first,
+                "-p",
+                &second,
+                "-p",
+                &third,
+                "-m",
+                "octopus",
+            ],
+        );
+        git(&dir, &["update-ref", "refs/heads/main", &octopus]);
+
+        let repo = gix::open(&dir).unwrap();
+        write_commit_graph(&repo, &[id(&octopus)]).unwrap();
+        let path = repo.objects.store_ref().path().join("info/commit-graph");
+        let graph = gix::commitgraph::File::at(&path).unwrap();
+        graph.verify_checksum().unwrap();
+        assert_eq!(graph.object_hash(), repo.object_hash());
+        assert_eq!(graph.num_commits(), 4);
+
+        let expected = [
+            (&first, vec![], 1),
+            (&second, vec![&first], 2),
+            (&third, vec![&first], 2),
+            // Octopus merges keep their parents beyond the first in the extra edge list.
+            (&octopus, vec![&first, &second, &third], 3),
+        ];
+        for (commit, parents, generation) in expected {
+            let position = graph
+                .lookup(id(commit))
+                .expect("all commits are in the graph");
+            let entry = graph.commit_at(position);
+            let object = repo.find_commit(id(commit)).unwrap();
+            assert_eq!(
+                entry.root_tree_id().to_owned(),
+                object.tree_id().unwrap().detach()
+            );
+            assert_eq!(
+                entry.committer_timestamp(),
+                object.time().unwrap().seconds as u64
+            );
+            assert_eq!(entry.generation(), generation);
+            let actual: Vec<gix::ObjectId> = entry
+                .iter_parents()
+                .map(|parent| graph.id_at(parent.unwrap()).to_owned())
+                .collect();
+            let parents: Vec<gix::ObjectId> =
+                parents.into_iter().map(|parent| id(parent)).collect();
+            assert_eq!(actual, parents);
+        }
+        git(&dir, &["commit-graph", "verify"]);
+    }
+
+    #[test]
+    fn gc_keeps_reachable_and_prunes_unreachable() {
+        let root = tempfile::tempdir().unwrap();
+        let Some(dir) = repo(root.path()) else {
+            return;
+        };
+        // An unreachable commit in a pack, and an unreachable loose blob.
+        let tree = git(&dir, &["rev-parse", "HEAD^{tree}"]);
+        let dropped = git(&dir, &["commit-tree", &tree, "-m", "dropped"]);
+        git(&dir, &["update-ref", "refs/heads/dropped", &dropped]);
+        git(&dir, &["repack", "-a", "-d", "--quiet"]);
+        git(&dir, &["branch", "--quiet", "-D", "dropped"]);
+        std::fs::write(dir.join("loose"), "loose\n").unwrap();
+        let loose = git(&dir, &["hash-object", "-w", "loose"]);
+        std::fs::remove_file(dir.join("loose")).unwrap();
+        let expected = reachable(&dir);
+
+        gc(&gix::open(&dir).unwrap(), Some(i64::MAX)).unwrap();
+        let repo = gix::open(&dir).unwrap();
+        for object in &expected {
+            assert!(repo.has_object(object), "{} is reachable", object);
+        }
+        assert!(!repo.has_object(id(&dropped)));
+        assert!(!repo.has_object(id(&loose)));
+        git(&dir, &["fsck", "--strict", "--no-dangling"]);
+    }
+
+    #[test]
+    fn gc_packs_local_objects_of_partial_clones() {
+        let root = tempfile::tempdir().unwrap();
+        let Some(upstream) = repo(root.path()) else {
+            return;
+        };
+        git(&upstream, &["config", "uploadpack.allowFilter", "true"]);
+        let url = format!("file://{}", upstream.display());
+        git(
+            root.path(),
+            &["clone", "--quiet", "--filter=blob:none", &url, "clone"],
+        );
+        let dir = root.path().join("clone");
+        std::fs::write(dir.join("local"), "local\n").unwrap();
+        git(&dir, &["add", "local"]);
+        git(&dir, &["commit", "--quiet", "-m", "local"]);
+        let local = id(&git(&dir, &["rev-parse", "HEAD"]));
+
+        let repo = gix::open(&dir).unwrap();
+        let pack_dir = repo.objects.store_ref().path().join("pack");
+        let promisor_packs = super::files_with_extension(&pack_dir, "promisor");
+        assert!(!promisor_packs.is_empty());
+        gc(&repo, Some(i64::MAX)).unwrap();
+
+        let repo = gix::open(&dir).unwrap();
+        let loose = gix::odb::loose::Store::at(repo.objects.store_ref().path(), repo.object_hash());
+        assert_eq!(loose.iter().count(), 0, "all local objects are packed");
+        assert!(repo.has_object(local));
+        assert!(repo.has_object(id(&git(&dir, &["rev-parse", "HEAD:local"]))));
+        assert_eq!(
+            super::files_with_extension(&pack_dir, "promisor"),
+            promisor_packs,
+            "promisor packs are left alone"
+        );
+        assert!(!super::needs_gc(&repo));
+        git(&dir, &["fsck", "--connectivity-only"]);
+    }
+
+    #[test]
+    fn gc_without_prune_keeps_unreachable() {
+        let root = tempfile::tempdir().unwrap();
+        let Some(dir) = repo(root.path()) else {
+            return;
+        };
+        let tree = git(&dir, &["rev-parse", "HEAD^{tree}"]);
+        let dropped = git(&dir, &["commit-tree", &tree, "-m", "dropped"]);
+        git(&dir, &["update-ref", "refs/heads/dropped", &dropped]);
+        git(&dir, &["repack", "-a", "-d", "--quiet"]);
+        git(&dir, &["branch", "--quiet", "-D", "dropped"]);
+        let expected = reachable(&dir);
+
+        gc(&gix::open(&dir).unwrap(), None).unwrap();
+        let repo = gix::open(&dir).unwrap();
+        for object in &expected {
+            assert!(repo.has_object(object), "{} is reachable", object);
+        }
+        // The pack it was in is gone, so it's kept as a loose object.
+        assert!(repo.has_object(id(&dropped)));
+        let pack_dir = repo.objects.store_ref().path().join("pack");
+        assert_eq!(super::files_with_extension(&pack_dir, "pack").len(), 1);
+        git(&dir, &["fsck", "--strict"]);
+    }
+}
@@ -0,0 +1,495 @@
+use anyhow::Result;
+use gix::bstr::{BStr, BString, ByteSlice};
+use gix::index::entry::Mode;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::index_utils::{matches_pathspec, normalize_pathspecs};
+use super::revision_utils::rev_parse_single;
+
+/// How the pattern is interpreted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PatternType {
+    /// A POSIX basic regular expression, like git does by default
+    #[default]
+    Basic,
+    /// A POSIX extended regular expression
+    Extended,
+    /// A literal string
+    Fixed,
+    /// A Perl-compatible regular expression, without lookaround and backreferences
+    Perl,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Match case-insensitively
+    pub ignore_case: bool,
+    /// Only match at word boundaries
+    pub word_regexp: bool,
+    /// Print the number of each matching line
+    pub line_number: bool,
+    /// Print only the names of files with matches
+    pub files_with_matches: bool,
+    /// Print the number of matching lines of each file instead of the lines
+    pub count: bool,
+    /// How the pattern is interpreted
+    pub pattern_type: PatternType,
+    /// Search the blobs in the index instead of the files in the worktree
+    pub cached: bool,
+}
+
+/// A file to search and where its content comes from
+struct File {
+    /// The name to print, the path prefixed by the revision for trees
+    name: BString,
+    source: Source,
+    /// Whether the attributes say the file is binary, or `None` to look at its content
+    binary: Option<bool>,
+}
+
+enum Source {
+    Worktree(PathBuf),
+    Blob(gix::ObjectId),
+}
+
+/// Search for `pattern` in the tracked files of the worktree, the index with `cached`, or the trees of `args`.
+///
+/// Arguments that aren't revisions are taken as pathspecs, as are all of `pathspecs`. Binary files are skipped,
+/// and files are searched in parallel with the output streamed in the order of their paths.
+pub fn run(
+    pattern: String,
+    args: Vec<String>,
+    pathspecs: Vec<PathBuf>,
+    opts: Options,
+) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    let Some(matcher) = Matcher::new(&pattern, &opts) else {
+        return Ok(());
+    };
+
+    let mut revisions = Vec::new();
+    let mut paths = Vec::new();
+    for arg in args {
+        // Like git, the first argument that isn't a revision starts the pathspecs.
+        match rev_parse_single(&repo, &arg) {
+            Ok(id) if paths.is_empty() => revisions.push((arg, id.detach())),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    paths.extend(pathspecs);
+    if opts.cached && !revisions.is_empty() {
+        println!("fatal: both --cached and trees are given");
+        return Ok(());
+    }
+    let pathspecs = normalize_pathspecs(&paths);
+
+    let mut files = Vec::new();
+    if !revisions.is_empty() {
+        for (name, id) in &revisions {
+            let Ok(tree) = repo.find_object(*id)?.peel_to_tree() else {
+                println!("fatal: unable to read tree ({})", id);
+                return Ok(());
+            };
+            let index = super::promisor::index_from_tree(&repo, &tree.id)?;
+            collect_files(
+                &repo,
+                &index,
+                &pathspecs,
+                Some(name),
+                false,
+                gix::worktree::stack::state::attributes::Source::IdMapping,
+                &mut files,
+            )?;
+        }
+    } else if opts.cached {
+        let index = repo.index_or_empty()?;
+        collect_files(
+            &repo,
+            &index,
+            &pathspecs,
+            None,
+            false,
+            gix::worktree::stack::state::attributes::Source::IdMapping,
+            &mut files,
+        )?;
+    } else {
+        if repo.workdir().is_none() {
+            println!("fatal: this operation must be run in a work tree");
+            return Ok(());
+        }
+        let index = repo.index_or_empty()?;
+        collect_files(
+            &repo,
+            &index,
+            &pathspecs,
+            None,
+            true,
+            gix::worktree::stack::state::attributes::Source::WorktreeThenIdMapping,
+            &mut files,
+        )?;
+    }
+
+    if !search(&repo, &files, &matcher, &opts)? {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Add the regular files of `index` matching `pathspecs` to `files`, named after `revision` if there is one,
+/// and read from the worktree if `from_worktree` is set.
+fn collect_files(
+    repo: &gix::Repository,
+    index: &gix::index::File,
+    pathspecs: &[BString],
+    revision: Option<&str>,
+    from_worktree: bool,
+    attributes_source: gix::worktree::stack::state::attributes::Source,
+    files: &mut Vec<File>,
+) -> Result<()> {
+    let mut attributes = repo.attributes_only(index, attributes_source)?;
+    let mut outcome = attributes.selected_attribute_matches(["diff"]);
+    let mut previous: Option<&BStr> = None;
+    for entry in index.entries() {
+        let path = entry.path(index);
+        // Unmerged paths have an entry per stage, but are only searched once.
+        if previous == Some(path) {
+            continue;
+        }
+        previous = Some(path);
+        if !(entry.mode == Mode::FILE || entry.mode == Mode::FILE_EXECUTABLE)
+            || !matches_pathspec(pathspecs, path)
+        {
+            continue;
+        }
+        attributes
+            .at_entry(path, Some(entry.mode))?
+            .matching_attributes(&mut outcome);
+        // `binary` files have `-diff`, and files with `diff` set are text no matter what's in them.
+        let binary = outcome
+            .iter_selected()
+            .next()
+            .and_then(|m| match m.assignment.state {
+                gix::attrs::StateRef::Unset => Some(true),
+                gix::attrs::StateRef::Set | gix::attrs::StateRef::Value(_) => Some(false),
+                gix::attrs::StateRef::Unspecified => None,
+            });
+        let source = if from_worktree {
+            Source::Worktree(
+                repo.workdir()
+                    .expect("only called with a worktree")
+                    .join(gix::path::from_bstr(path)),
+            )
+        } else {
+            Source::Blob(entry.id)
+        };
+        let name = match revision {
+            Some(revision) => format!("{}:{}", revision, path).into(),
+            None => path.to_owned(),
+        };
+        files.push(File {
+            name,
+            source,
+            binary,
+        });
+    }
+    Ok(())
+}
+
+/// Search all `files` on as many threads as there are cores, printing the output of each file with matches
+/// as soon as the files before it are done, so it appears in order. Returns whether anything matched.
+fn search(
+    repo: &gix::Repository,
+    files: &[File],
+    matcher: &Matcher,
+    opts: &Options,
+) -> Result<bool> {
+    // A partial clone fetches all blobs it's missing at once, instead of failing on the first one.
+    super::promisor::fetch_missing(
+        repo,
+        files.iter().filter_map(|file| match file.source {
+            Source::Blob(id) => Some(id),
+            Source::Worktree(_) => None,
+        }),
+    )?;
+    let threads = std::thread::available_parallelism()
+        .map_or(1, usize::from)
+        .min(files.len())
+        .max(1);
+    let repo = &repo.clone().into_sync();
+    let next = &AtomicUsize::new(0);
+    let (sender, receiver) = std::sync::mpsc::channel::<(usize, Option<Vec<u8>>)>();
+    std::thread::scope(|scope| -> Result<bool> {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                let sender = sender.clone();
+                scope.spawn(move || -> Result<()> {
+                    let repo = repo.to_thread_local();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(file) = files.get(index) else {
+                            break;
+                        };
+                        let output = match &file.source {
+                            // Files deleted from the worktree have nothing to search.
+                            Source::Worktree(path) => match std::fs::read(path) {
+                                Ok(data) => grep_file(file, &data, matcher, opts),
+                                Err(_) => None,
+                            },
+                            Source::Blob(id) => {
+                                grep_file(file, &repo.find_object(*id)?.data, matcher, opts)
+                            }
+                        };
+                        // Nobody is listening anymore once printing failed.
+                        if sender.send((index, output)).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        drop(sender);
+
+        let mut stdout = std::io::stdout().lock();
+        let mut done = BTreeMap::new();
+        let mut printed = 0;
+        let mut found = false;
+        for (index, output) in receiver {
+            done.insert(index, output);
+            while let Some(output) = done.remove(&printed) {
+                if let Some(output) = output {
+                    stdout.write_all(&output)?;
+                    found = true;
+                }
+                printed += 1;
+            }
+        }
+        for worker in workers {
+            worker.join().expect("searching doesn't panic")?;
+        }
+        Ok(found)
+    })
+}
+
+/// Search `data`, the content of `file`, returning what to print if anything matched.
+fn grep_file(file: &File, data: &[u8], matcher: &Matcher, opts: &Options) -> Option<Vec<u8>> {
+    // Like git, content with a NUL byte early on is binary.
+    let binary = file
+        .binary
+        .unwrap_or_else(|| data[..data.len().min(8000)].contains(&0));
+    if binary {
+        return None;
+    }
+    let mut out = Vec::new();
+    let mut count = 0;
+    for (number, line) in data.lines_with_terminator().enumerate() {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        if !matcher.is_match(line) {
+            continue;
+        }
+        count += 1;
+        if opts.files_with_matches {
+            break;
+        }
+        if !opts.count {
+            out.extend_from_slice(&file.name);
+            out.push(b':');
+            if opts.line_number {
+                out.extend_from_slice(format!("{}:", number + 1).as_bytes());
+            }
+            out.extend_from_slice(line);
+            out.push(b'\n');
+        }
+    }
+    if count == 0 {
+        return None;
+    }
+    if opts.files_with_matches {
+        out.extend_from_slice(&file.name);
+        out.push(b'\n');
+    } else if opts.count {
+        out.extend_from_slice(&file.name);
+        out.extend_from_slice(format!(":{}\n", count).as_bytes());
+    }
+    Some(out)
+}
+
+/// The compiled pattern, matched against one line at a time
+struct Matcher {
+    compiled: regex::bytes::Regex,
+    word_regexp: bool,
+}
+
+impl Matcher {
+    /// Compile `pattern` as `opts` say, or return `None` after reporting that it's invalid.
+    fn new(pattern: &str, opts: &Options) -> Option<Self> {
+        let regex = match opts.pattern_type {
+            PatternType::Basic => basic_to_extended(pattern),
+            PatternType::Extended | PatternType::Perl => pattern.to_string(),
+            PatternType::Fixed => regex::escape(pattern),
+        };
+        match regex::bytes::RegexBuilder::new(&regex)
+            .case_insensitive(opts.ignore_case)
+            .build()
+        {
+            Ok(compiled) => Some(Matcher {
+                compiled,
+                word_regexp: opts.word_regexp,
+            }),
+            // Perl patterns are only as good as the `regex` crate, which lacks lookaround and backreferences.
+            Err(err) if opts.pattern_type == PatternType::Perl => {
+                println!(
+                    "fatal: -P doesn't support lookaround or backreferences, '{}': {}",
+                    pattern, err
+                );
+                None
+            }
+            Err(err) => {
+                println!("fatal: command line, '{}': {}", pattern, err);
+                None
+            }
+        }
+    }
+
+    /// Whether `line` matches, which with `-w` means a match that isn't part of a larger word.
+    fn is_match(&self, line: &[u8]) -> bool {
+        if !self.word_regexp {
+            return self.compiled.is_match(line);
+        }
+        let is_word = |byte: u8| byte.is_ascii_alphanumeric() || byte == b'_';
+        let mut start = 0;
+        while let Some(m) = self.compiled.find_at(line, start) {
+            let before = m.start() == 0 || !is_word(line[m.start() - 1]);
+            let after = m.end() == line.len() || !is_word(line[m.end()]);
+            if before && after {
+                return true;
+            }
+            // Try again with a match starting later, which may end elsewhere too.
+            start = m.start() + 1;
+            if start > line.len() {
+                break;
+            }
+        }
+        false
+    }
+}
+
+/// Turn a POSIX basic regular expression into the extended syntax the `regex` crate understands,
+/// which means swapping the meaning of escaped and unescaped grouping and repetition characters.
+fn basic_to_extended(pattern: &str) -> String {
+    let mut out = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c @ ('(' | ')' | '{' | '}' | '|' | '+' | '?')) => out.push(c),
+                Some(c) => {
+                    out.push('\\');
+                    out.push(c);
+                }
+                None => out.push_str("\\\\"),
+            },
+            '(' | ')' | '{' | '}' | '|' | '+' | '?' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn open_repo() -> Option<gix::Repository> {
+    match gix::discover(".") {
+        Ok(repo) => Some(repo),
+        Err(_) => {
+            println!("fatal: not a git repository (or any of the parent directories): .git");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::process::Command;
+
+    use super::{collect_files, search, Matcher, Options};
+
+    /// Run `git` in `dir`, returning its trimmed output.
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args([
+                "-c",
+                "user.name=A U Thor",
+                "-c",
+                "user.email=author@example.com",
+            ])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .expect("git can be run");
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8(output.stdout).unwrap().trim().to_owned()
+    }
+
+    #[test]
+    fn trees_of_partial_clones_are_searched() {
+        if Command::new("git").arg("--version").output().is_err() {
+            return;
+        }
+        let root = tempfile::tempdir().unwrap();
+        let upstream = root.path().join("upstream");
+        std::fs::create_dir_all(&upstream).unwrap();
+        git(&upstream, &["init", "--quiet", "--initial-branch=main"]);
+        std::fs::write(upstream.join("a"), "needle\n").unwrap();
+        std::fs::write(upstream.join("b"), "hay\n").unwrap();
+        git(&upstream, &["add", "."]);
+        git(&upstream, &["commit", "--quiet", "-m", "initial"]);
+        git(&upstream, &["config", "uploadpack.allowFilter", "true"]);
+        let url = format!("file://{}", upstream.display());
+        git(
+            root.path(),
+            &[
+                "clone",
+                "--quiet",
+                "--no-checkout",
+                "--filter=blob:none",
+                &url,
+                "clone",
+            ],
+        );
+
+        let repo = gix::open(root.path().join("clone")).unwrap();
+        let tree = repo.head_commit().unwrap().tree_id().unwrap().detach();
+        let index = super::super::promisor::index_from_tree(&repo, &tree).unwrap();
+        assert!(index
+            .entries()
+            .iter()
+            .all(|entry| !repo.has_object(entry.id)));
+
+        let mut files = Vec::new();
+        collect_files(
+            &repo,
+            &index,
+            &[],
+            Some("HEAD"),
+            false,
+            gix::worktree::stack::state::attributes::Source::IdMapping,
+            &mut files,
+        )
+        .unwrap();
+        let opts = Options::default();
+        let matcher = Matcher::new("needle", &opts).unwrap();
+        assert!(search(&repo, &files, &matcher, &opts).unwrap());
+        assert!(index
+            .entries()
+            .iter()
+            .all(|entry| repo.has_object(entry.id)));
+    }
+}
@@ -0,0 +1,139 @@
+use anyhow::Result;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// The hook `name` in `core.hooksPath`, or the `hooks` directory of the repository, if it exists.
+fn find_hook(repo: &gix::Repository, name: &str) -> Option<PathBuf> {
+    let dir = match repo.config_snapshot().trusted_path("core.hooksPath") {
+        // Relative paths are relative to where hooks run, which is the top of the worktree.
+        Some(Ok(path)) if path.is_relative() => repo.workdir().unwrap_or(repo.path()).join(path),
+        Some(Ok(path)) => path.into_owned(),
+        _ => repo.common_dir().join("hooks"),
+    };
+    dir.join(name)
+        .canonicalize()
+        .ok()
+        .filter(|path| path.is_file())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &std::path::Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(_path: &std::path::Path) -> bool {
+    true
+}
+
+/// Run the hook `name` with `args`, feeding it `stdin` if set, from the top of the worktree like git does.
+///
+/// Returns `false` if the hook exists and failed, so the caller can abort for hooks that may block.
+/// Hooks that don't exist or aren't executable count as success.
+pub fn run_hook(
+    repo: &gix::Repository,
+    name: &str,
+    args: &[&str],
+    stdin: Option<&[u8]>,
+) -> Result<bool> {
+    let Some(path) = find_hook(repo, name) else {
+        return Ok(true);
+    };
+    if !is_executable(&path) {
+        println!(
+            "hint: The '{}' hook was ignored because it's not set as executable.",
+            path.display()
+        );
+        println!("hint: You can disable this warning with `git config advice.ignoredHook false`.");
+        return Ok(true);
+    }
+
+    // Hooks are usually scripts, which only sh knows how to run on Windows.
+    let mut command = if cfg!(windows) {
+        let mut command = std::process::Command::new("sh");
+        command.arg(&path);
+        command
+    } else {
+        std::process::Command::new(&path)
+    };
+    command
+        .args(args)
+        .current_dir(repo.workdir().unwrap_or(repo.path()))
+        .env(
+            "GIT_INDEX_FILE",
+            repo.index_path()
+                .canonicalize()
+                .unwrap_or_else(|_| repo.index_path()),
+        );
+    if stdin.is_some() {
+        command.stdin(std::process::Stdio::piped());
+    }
+    let mut child = command.spawn()?;
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        // The hook may exit without reading everything, which isn't an error.
+        if let Err(err) = pipe.write_all(input) {
+            if err.kind() != std::io::ErrorKind::BrokenPipe {
+                return Err(err.into());
+            }
+        }
+    }
+    Ok(child.wait()?.success())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::run_hook;
+
+    /// A repository with the hook `name` running `script`.
+    fn repo_with_hook(root: &std::path::Path, name: &str, script: &str) -> gix::Repository {
+        let repo = gix::init(root.join("repo")).unwrap();
+        let hook = repo.path().join("hooks").join(name);
+        std::fs::create_dir_all(hook.parent().unwrap()).unwrap();
+        std::fs::write(&hook, format!("#!/bin/sh\n{}", script)).unwrap();
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+        repo
+    }
+
+    #[test]
+    fn hook_gets_args_and_stdin() {
+        let root = tempfile::tempdir().unwrap();
+        let out = root.path().join("out");
+        let repo = repo_with_hook(
+            root.path(),
+            "pre-push",
+            &format!("{{ echo \"$1 $2\"; cat; }} >'{}'\n", out.display()),
+        );
+        let line = "refs/heads/main 1111 refs/heads/main 0000\n";
+        let ok = run_hook(
+            &repo,
+            "pre-push",
+            &["origin", "https://example.com/repo"],
+            Some(line.as_bytes()),
+        )
+        .unwrap();
+        assert!(ok);
+        assert_eq!(
+            std::fs::read_to_string(out).unwrap(),
+            format!("origin https://example.com/repo\n{}", line)
+        );
+    }
+
+    #[test]
+    fn failing_hook_that_ignores_stdin_blocks() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with_hook(root.path(), "pre-push", "exit 1\n");
+        let input = vec![b'x'; 1 << 20];
+        assert!(!run_hook(&repo, "pre-push", &[], Some(&input)).unwrap());
+    }
+
+    #[test]
+    fn missing_hook_succeeds() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = gix::init(root.path().join("repo")).unwrap();
+        assert!(run_hook(&repo, "pre-push", &[], Some(b"")).unwrap());
+    }
+}
@@ -1,10 +1,18 @@
 use anyhow::Result;
-use gix::bstr::ByteSlice;
+use gix::bstr::{BStr, BString, ByteSlice};
+use std::collections::{BTreeSet, HashMap};
 use std::path::Path;
 
-/// Add a file to the index with proper metadata and blob creation
+/// The id and mode of index entries by their path
+pub type EntriesByPath = HashMap<BString, (gix::ObjectId, gix::index::entry::Mode)>;
+
+/// Add a file to the index with proper metadata and blob creation.
+///
+/// The file content is routed through `pipeline` so clean filters, `ident` and eol conversion
+/// configured via `.gitattributes` and `core.autocrlf` are applied before hashing.
 pub fn add_file_to_index(
     repo: &gix::Repository,
+    pipeline: &mut gix::filter::Pipeline<'_>,
     file_path: &Path,
     index: &mut gix::index::File,
 ) -> Result<bool> {
@@ -22,17 +30,19 @@ pub fn add_file_to_index(
         return Ok(false);
     }
 
-    let file_content = match std::fs::read(file_path) {
-        Ok(content) => content,
-        Err(e) => {
-            eprintln!("error: unable to read '{}': {}", file_path.display(), e);
-            return Ok(false);
-        }
-    };
+    let path_bstr =
+        gix::path::to_unix_separators_on_windows(gix::path::into_bstr(relative_path)).into_owned();
 
-    let blob_id = repo.write_blob(&file_content)?;
+    let (blob_id, kind, metadata) =
+        match pipeline.worktree_file_to_object(path_bstr.as_ref(), index) {
+            Ok(Some(object)) => object,
+            Ok(None) => return Ok(false),
+            Err(e) => {
+                eprintln!("error: unable to read '{}': {}", file_path.display(), e);
+                return Ok(false);
+            }
+        };
 
-    let metadata = std::fs::metadata(file_path)?;
     let stat = gix::index::entry::Stat {
         mtime: gix::index::entry::stat::Time {
             secs: metadata
@@ -55,32 +65,206 @@ pub fn add_file_to_index(
         size: metadata.len() as u32,
     };
 
-    let mode = if metadata.is_file() {
-        gix::index::entry::Mode::FILE
-    } else if metadata.is_symlink() {
-        gix::index::entry::Mode::SYMLINK
-    } else {
-        gix::index::entry::Mode::FILE
-    };
+    let mode = gix::index::entry::Mode::from(gix::objs::tree::EntryMode::from(kind));
+
+    match index.entry_index_by_path(path_bstr.as_ref()) {
+        Ok(existing) => {
+            let entry = &mut index.entries_mut()[existing];
+            entry.stat = stat;
+            entry.id = blob_id;
+            entry.mode = mode;
+        }
+        Err(_) => {
+            index.dangerously_push_entry(
+                stat,
+                blob_id,
+                gix::index::entry::Flags::empty(),
+                mode,
+                path_bstr.as_ref(),
+            );
+            index.sort_entries();
+        }
+    }
+    index.remove_tree();
+
+    Ok(true)
+}
 
-    let path_bstr = gix::path::into_bstr(relative_path);
+/// Write all entries of `index` into the worktree, overwriting what's there.
+///
+/// Attributes are read from the index, so smudge filters, `ident` expansion and eol conversion
+/// apply just like they do for `git checkout`. Stat information in `index` is refreshed as files are written.
+pub fn checkout_index(repo: &gix::Repository, index: &mut gix::index::File) -> Result<()> {
+    let worktree = repo
+        .workdir()
+        .ok_or_else(|| anyhow::anyhow!("No worktree found"))?;
 
-    index.dangerously_push_entry(
-        stat,
-        blob_id.detach(),
-        gix::index::entry::Flags::empty(),
-        mode,
-        &path_bstr,
-    );
+    // In a partial clone, get all blobs we are about to write in one go instead of fetching them one by one.
+    super::promisor::fetch_missing_blobs(repo, index)?;
+
+    let mut opts =
+        repo.checkout_options(gix::worktree::stack::state::attributes::Source::IdMapping)?;
+    opts.overwrite_existing = true;
+
+    let outcome = gix::worktree::state::checkout(
+        index,
+        worktree,
+        super::promisor::Objects::new(repo)?,
+        &gix::progress::Discard,
+        &gix::progress::Discard,
+        &std::sync::atomic::AtomicBool::new(false),
+        opts,
+    )?;
+
+    for collision in outcome.collisions {
+        eprintln!(
+            "warning: '{}' collided with an existing path and was not checked out",
+            collision.path
+        );
+    }
+    for record in outcome.errors {
+        eprintln!(
+            "error: unable to check out '{}': {}",
+            record.path, record.error
+        );
+    }
+
+    Ok(())
+}
+
+/// Make the index and worktree match `tree_id`, removing files that were tracked before but aren't part of the tree.
+pub fn checkout_tree(repo: &gix::Repository, tree_id: gix::ObjectId) -> Result<()> {
+    let worktree = repo
+        .workdir()
+        .ok_or_else(|| anyhow::anyhow!("No worktree found"))?;
+    let old_index = repo.index_or_empty()?;
+    let mut index = super::promisor::index_from_tree(repo, &tree_id)?;
+
+    for entry in old_index.entries() {
+        let path = entry.path(&old_index);
+        if entry.mode.is_submodule() || index.entry_by_path(path).is_some() {
+            continue;
+        }
+        let file = worktree.join(gix::path::from_bstr(path));
+        if std::fs::remove_file(&file).is_ok() {
+            remove_empty_parents(worktree, &file);
+        }
+    }
+
+    checkout_index(repo, &mut index)?;
+    index.write(Default::default())?;
+    Ok(())
+}
 
+/// Remove the directories leading to `path` as long as they are empty, stopping at `worktree`.
+pub fn remove_empty_parents(worktree: &Path, path: &Path) {
+    let mut dir = path.parent();
+    while let Some(current) = dir {
+        if current == worktree || std::fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
+
+/// Turn `paths` relative to the worktree root into the form used by index entries, like `dir/file`.
+pub fn normalize_pathspecs(paths: &[std::path::PathBuf]) -> Vec<BString> {
+    paths
+        .iter()
+        .map(|path| {
+            let path =
+                gix::path::to_unix_separators_on_windows(gix::path::into_bstr(path.as_path()));
+            let path = path.to_str_lossy();
+            let path = path.trim_start_matches("./").trim_end_matches('/');
+            (if path == "." { "" } else { path }).into()
+        })
+        .collect()
+}
+
+/// Whether the index entry at `path` is `pathspecs` itself or below one of them.
+pub fn matches_pathspec(pathspecs: &[BString], path: &BStr) -> bool {
+    pathspecs.iter().any(|spec| {
+        spec.is_empty()
+            || path == spec.as_bstr()
+            || (path.starts_with(spec.as_slice()) && path.get(spec.len()) == Some(&b'/'))
+    })
+}
+
+/// Reuse the stat information of entries in `old` for the entries of `index` that didn't change,
+/// so they aren't considered modified until their content is compared again.
+pub fn keep_stat(index: &mut gix::index::File, old: &gix::index::State) {
+    for (entry, path) in index.entries_mut_with_paths() {
+        if let Some(old_entry) = old.entry_by_path(path) {
+            if old_entry.id == entry.id && old_entry.mode == entry.mode {
+                entry.stat = old_entry.stat;
+            }
+        }
+    }
+}
+
+/// Replace the entries of `index` matching `pathspecs` with those of `source`, removing them if `source` doesn't have them.
+pub fn replace_index_entries(
+    index: &mut gix::index::File,
+    source: Option<&gix::index::File>,
+    pathspecs: &[BString],
+) {
+    index.remove_entries(|_, path, _| matches_pathspec(pathspecs, path));
+    if let Some(source) = source {
+        for entry in source.entries() {
+            let path = entry.path(source);
+            if matches_pathspec(pathspecs, path) {
+                index.dangerously_push_entry(entry.stat, entry.id, entry.flags, entry.mode, path);
+            }
+        }
+    }
     index.sort_entries();
+    index.remove_tree();
+}
 
-    Ok(true)
+/// The id and mode of each path in `index`, with unmerged entries recorded as the null id so they never match.
+pub fn entries_by_path(index: Option<&gix::index::File>) -> EntriesByPath {
+    let mut entries = HashMap::new();
+    let Some(index) = index else {
+        return entries;
+    };
+    for entry in index.entries() {
+        let id = if entry.stage_raw() == 0 {
+            entry.id
+        } else {
+            gix::ObjectId::null(entry.id.kind())
+        };
+        entries.insert(entry.path(index).to_owned(), (id, entry.mode));
+    }
+    entries
+}
+
+/// The paths whose id or mode differ between `a` and `b`, including those only one of them has.
+pub fn differing_paths(a: &EntriesByPath, b: &EntriesByPath) -> BTreeSet<BString> {
+    a.keys()
+        .chain(b.keys())
+        .filter(|path| a.get(*path) != b.get(*path))
+        .cloned()
+        .collect()
+}
+
+/// Write the tree recorded by the unconflicted entries of `index` to the object database, like `git write-tree`.
+pub fn write_tree(repo: &gix::Repository, index: &gix::index::State) -> Result<gix::ObjectId> {
+    let mut editor = repo.edit_tree(gix::ObjectId::empty_tree(repo.object_hash()))?;
+    for entry in index.entries() {
+        if entry.stage_raw() != 0 {
+            continue;
+        }
+        if let Some(mode) = entry.mode.to_tree_entry_mode() {
+            editor.upsert(entry.path(index), mode.kind(), entry.id)?;
+        }
+    }
+    Ok(editor.write()?.detach())
 }
 
 /// Add all modified files to the index (for --update and commit -a)
 pub fn add_modified_files_to_index(repo: &gix::Repository) -> Result<usize> {
     let mut index = repo.index_or_load_from_head_or_empty()?.into_owned();
+    let (mut pipeline, _) = repo.filter_pipeline(None)?;
     let mut added_files = 0;
 
     let status_iter = repo
@@ -94,7 +278,7 @@ pub fn add_modified_files_to_index(repo: &gix::Repository) -> Result<usize> {
                     .workdir()
                     .unwrap()
                     .join(gix::path::from_bstr(&rela_path));
-                if add_file_to_index(repo, &full_path, &mut index)? {
+                if add_file_to_index(repo, &mut pipeline, &full_path, &mut index)? {
                     added_files += 1;
                 }
             }
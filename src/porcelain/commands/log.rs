@@ -1,7 +1,16 @@
 use anyhow::Result;
 use gitoxide_core as core;
+use gix::bstr::ByteSlice;
+use std::io::Write;
 
-pub fn run(max_count: Option<usize>, oneline: bool, graph: bool) -> Result<()> {
+use super::signing_utils::verify_commit;
+
+pub fn run(
+    max_count: Option<usize>,
+    oneline: bool,
+    graph: bool,
+    show_signature: bool,
+) -> Result<()> {
     let repo = match gix::discover(".") {
         Ok(repo) => repo,
         Err(_) => {
@@ -39,10 +48,21 @@ pub fn run(max_count: Option<usize>, oneline: bool, graph: bool) -> Result<()> {
             let short_hash = format!("{:.7}", hash);
             let message = commit.message()?.title.to_string();
 
+            let mut signature_check = String::new();
+            if show_signature {
+                if let Some(verification) = verify_commit(&repo, &commit)? {
+                    signature_check = verification.output;
+                }
+            }
+
             if oneline {
+                print!("{}", signature_check);
                 println!("{} {}", short_hash, message);
             } else if graph {
                 println!("* {} {}", short_hash, message);
+                for line in signature_check.lines() {
+                    println!("| {}", line);
+                }
             }
 
             let mut parent_ids = commit.parent_ids();
@@ -53,20 +73,86 @@ pub fn run(max_count: Option<usize>, oneline: bool, graph: bool) -> Result<()> {
             };
             count += 1;
         }
+    } else if show_signature {
+        let mut out = WithSignatures {
+            repo: &repo,
+            out,
+            line: Vec::new(),
+        };
+        list(repo.clone(), &mut out, max_count, spec, format)?;
+        out.flush()?;
     } else {
-        core::repository::revision::list(
-            repo,
-            gix::progress::Discard,
-            &mut out,
-            core::repository::revision::list::Context {
-                limit: max_count,
-                spec,
-                format,
-                long_hashes: false,
-                text: core::repository::revision::list::Format::Text,
-            },
-        )?;
+        list(repo, &mut out, max_count, spec, format)?;
     }
 
     Ok(())
 }
+
+fn list(
+    repo: gix::Repository,
+    out: &mut impl Write,
+    limit: Option<usize>,
+    spec: std::ffi::OsString,
+    format: core::OutputFormat,
+) -> Result<()> {
+    core::repository::revision::list(
+        repo,
+        gix::progress::Discard,
+        out,
+        core::repository::revision::list::Context {
+            limit,
+            spec,
+            format,
+            long_hashes: false,
+            text: core::repository::revision::list::Format::Text,
+        },
+    )
+}
+
+/// Passes `log` output through to `out`, preceding each line that starts with a commit hash with the check of its signature.
+struct WithSignatures<'repo, W> {
+    repo: &'repo gix::Repository,
+    out: W,
+    /// Output received since the last complete line
+    line: Vec<u8>,
+}
+
+impl<W: Write> WithSignatures<'_, W> {
+    fn write_line(&mut self, line: &[u8]) -> std::io::Result<()> {
+        let commit = line
+            .split(|byte| byte.is_ascii_whitespace())
+            .next()
+            .and_then(|hash| hash.to_str().ok())
+            .filter(|hash| !hash.is_empty() && hash.bytes().all(|byte| byte.is_ascii_hexdigit()))
+            .and_then(|hash| self.repo.rev_parse_single(hash).ok())
+            .and_then(|id| id.object().ok())
+            .and_then(|object| object.try_into_commit().ok());
+        if let Some(commit) = commit {
+            if let Some(verification) =
+                verify_commit(self.repo, &commit).map_err(std::io::Error::other)?
+            {
+                self.out.write_all(verification.output.as_bytes())?;
+            }
+        }
+        self.out.write_all(line)
+    }
+}
+
+impl<W: Write> Write for WithSignatures<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        while let Some(end) = self.line.find_byte(b'\n') {
+            let line: Vec<u8> = self.line.drain(..=end).collect();
+            self.write_line(&line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            self.write_line(&line)?;
+        }
+        self.out.flush()
+    }
+}
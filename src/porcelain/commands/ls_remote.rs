@@ -0,0 +1,168 @@
+use anyhow::{anyhow, Result};
+use gix::bstr::{BStr, ByteSlice};
+use gix::protocol::handshake::Ref;
+use gix::protocol::transport::client::Transport;
+use std::borrow::Cow;
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Only show branches
+    pub heads: bool,
+    /// Only show tags, or tags and branches together with `heads`
+    pub tags: bool,
+    /// Don't show peeled tags or pseudo-refs like `HEAD`
+    pub refs: bool,
+    /// Show what symbolic refs point to
+    pub symref: bool,
+    /// Exit with status 2 if no ref matched
+    pub exit_code: bool,
+}
+
+/// List the refs advertised by `repository`, a remote name or URL, or by the default remote if unset.
+///
+/// URLs can be listed outside of a repository, in which case no remote configuration applies.
+pub fn run(repository: Option<String>, patterns: Vec<String>, opts: Options) -> Result<()> {
+    let mut prefixes = Vec::new();
+    if opts.heads {
+        prefixes.push("refs/heads/");
+    }
+    if opts.tags {
+        prefixes.push("refs/tags/");
+    }
+
+    let refs = match gix::discover(".") {
+        Ok(repo) => list_remote(&repo, repository.as_deref(), &prefixes),
+        Err(_) => match &repository {
+            Some(url) => list_url(url, &prefixes),
+            None => {
+                println!("fatal: No remote configured to list refs from.");
+                std::process::exit(128);
+            }
+        },
+    };
+    let refs = match refs {
+        Ok(refs) => refs,
+        Err(e) => {
+            println!("fatal: {}", e);
+            std::process::exit(128);
+        }
+    };
+
+    // Patterns match the peeled `^{}` line of a tag by the name of the tag, like git does.
+    let is_shown = |name: &BStr, is_peeled: bool| {
+        if opts.refs && (is_peeled || !name.starts_with(b"refs/")) {
+            return false;
+        }
+        if !prefixes.is_empty()
+            && !prefixes
+                .iter()
+                .any(|prefix| name.starts_with(prefix.as_bytes()))
+        {
+            return false;
+        }
+        patterns.is_empty() || patterns.iter().any(|pattern| tail_matches(pattern, name))
+    };
+
+    let mut matched = false;
+    for r in &refs {
+        let (name, id, peeled) = r.unpack();
+        if !is_shown(name, false) {
+            continue;
+        }
+        matched = true;
+        if opts.symref {
+            if let Ref::Symbolic { target, .. } | Ref::Unborn { target, .. } = r {
+                println!("ref: {}\t{}", target, name);
+            }
+        }
+        if let Some(id) = id {
+            println!("{}\t{}", id, name);
+        }
+        if let Some(peeled) = peeled {
+            if is_shown(name, true) {
+                println!("{}\t{}^{{}}", peeled, name);
+            }
+        }
+    }
+
+    if opts.exit_code && !matched {
+        std::process::exit(2);
+    }
+    Ok(())
+}
+
+/// List the refs of the remote `name`, which may also be a URL, using its configuration in `repo`.
+fn list_remote(repo: &gix::Repository, name: Option<&str>, prefixes: &[&str]) -> Result<Vec<Ref>> {
+    let remote = repo.find_fetch_remote(name.map(Into::into))?;
+    let url = remote
+        .url(gix::remote::Direction::Fetch)
+        .ok_or_else(|| anyhow!("remote has no fetch URL"))?
+        .to_owned();
+
+    let mut connection = remote.connect(gix::remote::Direction::Fetch)?;
+    if let Some(config) = repo.transport_options(
+        url.to_bstring().as_bstr(),
+        remote.name().map(|name| name.as_bstr()),
+    )? {
+        connection.transport_mut().configure(&*config)?;
+    }
+    let mut authenticate = connection.configured_credentials(url)?;
+    list_refs(connection.transport_mut(), &mut authenticate, prefixes)
+}
+
+/// List the refs at `url` without a repository, using the credential helpers configured globally.
+fn list_url(url: &str, prefixes: &[&str]) -> Result<Vec<Ref>> {
+    let mut transport = gix::protocol::transport::client::connect(url, Default::default())?;
+    list_refs(
+        &mut transport,
+        gix::protocol::credentials::builtin,
+        prefixes,
+    )
+}
+
+fn list_refs<T: Transport>(
+    mut transport: T,
+    authenticate: impl FnMut(
+        gix::protocol::credentials::helper::Action,
+    ) -> gix::protocol::credentials::protocol::Result,
+    prefixes: &[&str],
+) -> Result<Vec<Ref>> {
+    let mut handshake = gix::protocol::fetch::handshake(
+        &mut transport,
+        authenticate,
+        Vec::new(),
+        &mut gix::progress::Discard,
+    )?;
+
+    // Protocol V1 advertises all refs as part of the handshake, V2 needs to be asked for them.
+    let refs = match handshake.refs.take() {
+        Some(refs) => refs,
+        None => gix::protocol::ls_refs(
+            &mut transport,
+            &handshake.capabilities,
+            |_capabilities, arguments, features| {
+                features.push(("agent", Some(Cow::Borrowed(super::promisor::USER_AGENT))));
+                for prefix in prefixes {
+                    arguments.push(format!("ref-prefix {}", prefix).into());
+                }
+                Ok(gix::protocol::ls_refs::Action::Continue)
+            },
+            &mut gix::progress::Discard,
+            false,
+        )?,
+    };
+    gix::protocol::indicate_end_of_interaction(&mut transport, false).ok();
+    Ok(refs)
+}
+
+/// Match `pattern` against the trailing path components of the ref `name`, like git does,
+/// so `v1.0` matches `refs/tags/v1.0` and `heads/*` matches all branches.
+fn tail_matches(pattern: &str, name: &BStr) -> bool {
+    let tail_pattern = format!("*/{}", pattern);
+    name == pattern.as_bytes()
+        || gix::glob::wildmatch(
+            tail_pattern.as_bytes().as_bstr(),
+            name,
+            gix::glob::wildmatch::Mode::empty(),
+        )
+}
@@ -1,14 +1,31 @@
 use anyhow::Result;
+use std::io::IsTerminal;
 use std::path::Path;
 
-pub fn run(
-    _repository: &Path,
-    commits: Vec<String>,
-    message: Option<String>,
-    no_commit: bool,
-    ff_only: bool,
-) -> Result<()> {
-    let _repo = match gix::discover(".") {
+use super::commit_utils::write_commit;
+use super::diff_utils::tree_changes;
+use super::editor_utils::{cleanup, cleanup_mode, edit};
+use super::hook_utils::run_hook;
+use super::index_utils::{get_current_branch, write_tree};
+use super::merge_utils::{merge_into_worktree, Labels};
+use super::ref_utils::move_head;
+use super::revision_utils::rev_parse_single;
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// The message of the merge commit, instead of the generated one
+    pub message: Option<String>,
+    /// Perform the merge but don't commit
+    pub no_commit: bool,
+    /// Refuse to merge unless it's a fast-forward
+    pub ff_only: bool,
+    /// Whether to edit the merge message, or only if it wasn't given and we are on a terminal if unset
+    pub edit: Option<bool>,
+}
+
+/// Merge `commits` into the current branch, fast-forwarding if possible.
+pub fn run(_repository: &Path, commits: Vec<String>, opts: Options) -> Result<()> {
+    let repo = match gix::discover(".") {
         Ok(repo) => repo,
         Err(_) => {
             println!("fatal: not a git repository (or any of the parent directories): .git");
@@ -16,22 +33,281 @@ pub fn run(
         }
     };
 
-    if commits.is_empty() {
-        println!("fatal: no commits specified to merge");
+    let spec = match commits.as_slice() {
+        [] => {
+            println!("fatal: no commits specified to merge");
+            return Ok(());
+        }
+        [spec] => spec,
+        _ => {
+            println!("fatal: merging more than one commit at once is not supported");
+            return Ok(());
+        }
+    };
+    if repo.path().join("MERGE_HEAD").exists() {
+        println!("fatal: You have not concluded your merge (MERGE_HEAD exists).");
+        println!("Please, commit your changes before you merge.");
+        return Ok(());
+    }
+    let Some(theirs) = rev_parse_single(&repo, spec)
+        .ok()
+        .and_then(|id| id.object().ok())
+        .and_then(|object| object.peel_to_commit().ok())
+    else {
+        println!("merge: {} - not something we can merge", spec);
+        return Ok(());
+    };
+    let theirs_tree = theirs.tree_id()?.detach();
+    let theirs = theirs.id;
+
+    let index = repo.index_or_load_from_head_or_empty()?.into_owned();
+    if index.entries().iter().any(|entry| entry.stage_raw() != 0) {
+        println!("error: Merging is not possible because you have unmerged files.");
+        println!("fatal: Exiting because of an unresolved conflict.");
+        return Ok(());
+    }
+    let ours = write_tree(&repo, &index)?;
+    let (head, head_tree) = match repo.head_commit() {
+        Ok(head) => (Some(head.id), head.tree_id()?.detach()),
+        Err(_) => (None, gix::ObjectId::empty_tree(repo.object_hash())),
+    };
+    let staged = tree_changes(&repo, head_tree, ours)?;
+    if !staged.is_empty() {
+        println!("error: Your local changes to the following files would be overwritten by merge:");
+        for change in &staged {
+            println!("\t{}", change.path);
+        }
+        println!("Please commit your changes or stash them before you merge.");
+        println!("Aborting");
         return Ok(());
     }
 
-    println!("fatal: merge functionality not yet fully implemented");
-    println!("Would merge: {:?}", commits);
-    if let Some(msg) = message {
-        println!("With message: {}", msg);
+    let base = match head {
+        Some(head) => match repo.merge_base(head, theirs) {
+            Ok(base) => Some(base.detach()),
+            Err(_) => {
+                println!("fatal: refusing to merge unrelated histories");
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+    if base == Some(theirs) {
+        println!("Already up to date.");
+        return Ok(());
+    }
+    let fast_forward = base == head;
+    if opts.ff_only && !fast_forward {
+        println!("fatal: Not possible to fast-forward, aborting.");
+        return Ok(());
     }
-    if no_commit {
-        println!("With --no-commit flag");
+
+    let base_tree = match base {
+        Some(base) => repo.find_commit(base)?.tree_id()?.detach(),
+        None => head_tree,
+    };
+    let Some(merged) = merge_into_worktree(
+        &repo,
+        base_tree,
+        ours,
+        theirs_tree,
+        Labels {
+            base: "merged common ancestors",
+            ours: "HEAD",
+            theirs: spec,
+        },
+    )?
+    else {
+        return Ok(());
+    };
+    merged.index.write(Default::default())?;
+
+    if fast_forward {
+        if let Some(head) = head {
+            println!(
+                "Updating {}..{}",
+                head.to_hex_with_len(7),
+                theirs.to_hex_with_len(7)
+            );
+        }
+        println!("Fast-forward");
+        move_head(&repo, theirs, &format!("merge {}: Fast-forward", spec))?;
+        run_hook(&repo, "post-merge", &["0"], None)?;
+        return Ok(());
     }
-    if ff_only {
-        println!("With --ff-only flag");
+
+    let default_message = default_message(&repo, spec)?;
+    if !merged.conflicts.is_empty() || opts.no_commit {
+        let mut message = format!("{}\n", opts.message.as_deref().unwrap_or(&default_message));
+        if !merged.conflicts.is_empty() {
+            message.push_str("\n# Conflicts:\n");
+            for path in &merged.conflicts {
+                message.push_str(&format!("#\t{}\n", path));
+            }
+        }
+        std::fs::write(repo.path().join("MERGE_HEAD"), format!("{}\n", theirs))?;
+        std::fs::write(repo.path().join("MERGE_MODE"), "")?;
+        std::fs::write(repo.path().join("MERGE_MSG"), message)?;
+        if merged.conflicts.is_empty() {
+            println!("Automatic merge went well; stopped before committing as requested");
+        } else {
+            println!("Automatic merge failed; fix conflicts and then commit the result.");
+        }
+        return Ok(());
     }
 
+    std::fs::write(repo.path().join("MERGE_HEAD"), format!("{}\n", theirs))?;
+    std::fs::write(repo.path().join("MERGE_MODE"), "")?;
+    let Some(message) = merge_message(&repo, &default_message, &opts)? else {
+        println!("Not committing merge; use 'git commit' to complete the merge.");
+        return Ok(());
+    };
+    let head = head.expect("merges that aren't fast-forwards have a HEAD");
+    let commit_id = write_commit(&repo, &message, merged.tree_id, [head, theirs])?;
+    move_head(
+        &repo,
+        commit_id,
+        &format!("merge {}: Merge made by the 'ort' strategy.", spec),
+    )?;
+    for file in ["MERGE_HEAD", "MERGE_MODE", "MERGE_MSG"] {
+        std::fs::remove_file(repo.path().join(file)).ok();
+    }
+    println!("Merge made by the 'ort' strategy.");
+    // The argument says whether this was a squash merge, which isn't supported.
+    run_hook(&repo, "post-merge", &["0"], None)?;
+
     Ok(())
 }
+
+/// The message git generates for merging `spec`, like `Merge branch 'topic' into next`.
+fn default_message(repo: &gix::Repository, spec: &str) -> Result<String> {
+    let kind = if repo
+        .try_find_reference(format!("refs/heads/{}", spec).as_str())?
+        .is_some()
+    {
+        "branch"
+    } else if repo
+        .try_find_reference(format!("refs/remotes/{}", spec).as_str())?
+        .is_some()
+    {
+        "remote-tracking branch"
+    } else if repo
+        .try_find_reference(format!("refs/tags/{}", spec).as_str())?
+        .is_some()
+    {
+        "tag"
+    } else {
+        "commit"
+    };
+    let mut message = format!("Merge {} '{}'", kind, spec);
+    if !repo.head()?.is_detached() {
+        let branch = get_current_branch(repo)?;
+        if !matches!(branch.as_str(), "main" | "master") {
+            message.push_str(&format!(" into {}", branch));
+        }
+    }
+    Ok(message)
+}
+
+/// The message of the merge commit, from `-m` or `default`, which is edited in `MERGE_MSG` unless asked otherwise.
+///
+/// Returns `None` after reporting that the editor failed or the message is empty.
+fn merge_message(repo: &gix::Repository, default: &str, opts: &Options) -> Result<Option<String>> {
+    let message = opts.message.as_deref().unwrap_or(default);
+    let use_editor = opts.edit.unwrap_or_else(|| {
+        opts.message.is_none() && std::io::stdin().is_terminal() && std::io::stdout().is_terminal()
+    });
+    let Some(cleanup_mode) = cleanup_mode(repo, None, use_editor) else {
+        return Ok(None);
+    };
+    if !use_editor {
+        return Ok(Some(cleanup(message, cleanup_mode)));
+    }
+
+    let path = repo.path().join("MERGE_MSG");
+    std::fs::write(
+        &path,
+        format!(
+            "{}\n\
+             # Please enter a commit message to explain why this merge is necessary,\n\
+             # especially if it merges an updated upstream into a topic branch.\n\
+             #\n\
+             {}",
+            message.trim_end(),
+            cleanup_mode.instructions("commit")
+        ),
+    )?;
+    let Some(edited) = edit(repo, &path)? else {
+        return Ok(None);
+    };
+    let message = cleanup(&edited, cleanup_mode);
+    if message.trim().is_empty() {
+        println!("Aborting commit due to empty commit message.");
+        return Ok(None);
+    }
+    Ok(Some(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge_message, Options};
+
+    /// A repository whose `core.editor` runs `script` on the message file, passed as `$1`.
+    fn repo_with_editor(root: &std::path::Path, script: &str) -> gix::Repository {
+        let repo = gix::init(root.join("repo")).unwrap();
+        let editor = root.join("editor.sh");
+        std::fs::write(&editor, script).unwrap();
+        let config = repo.path().join("config");
+        let mut content = std::fs::read_to_string(&config).unwrap();
+        content.push_str(&format!("[core]\n\teditor = sh '{}'\n", editor.display()));
+        std::fs::write(&config, content).unwrap();
+        gix::open(repo.path()).unwrap()
+    }
+
+    fn editing() -> Options {
+        Options {
+            edit: Some(true),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn edited_message_is_cleaned_up() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with_editor(
+            root.path(),
+            "grep -q \"^Merge branch 'topic'$\" \"$1\" || exit 1\n\
+             printf 'Merge topic\\n\\n\\nBecause reasons  \\n# a comment\\n\\n' >\"$1\"\n",
+        );
+        let message = merge_message(&repo, "Merge branch 'topic'", &editing()).unwrap();
+        assert_eq!(message.as_deref(), Some("Merge topic\n\nBecause reasons\n"));
+    }
+
+    #[test]
+    fn empty_message_aborts() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with_editor(root.path(), "printf '# only a comment\\n' >\"$1\"\n");
+        let message = merge_message(&repo, "Merge branch 'topic'", &editing()).unwrap();
+        assert_eq!(message, None);
+    }
+
+    #[test]
+    fn failing_editor_aborts() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with_editor(root.path(), "exit 1\n");
+        let message = merge_message(&repo, "Merge branch 'topic'", &editing()).unwrap();
+        assert_eq!(message, None);
+    }
+
+    #[test]
+    fn given_message_is_not_edited() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with_editor(root.path(), "exit 1\n");
+        let opts = Options {
+            message: Some("Merge it\n\n".into()),
+            ..Default::default()
+        };
+        let message = merge_message(&repo, "Merge branch 'topic'", &opts).unwrap();
+        assert_eq!(message.as_deref(), Some("Merge it\n"));
+    }
+}
@@ -0,0 +1,154 @@
+use anyhow::Result;
+use gix::bstr::{BString, ByteSlice};
+use gix::merge::tree::{apply_index_entries::RemovalMode, TreatAsUnresolved};
+use std::collections::BTreeSet;
+
+use super::index_utils::{
+    checkout_index, differing_paths, entries_by_path, keep_stat, remove_empty_parents,
+};
+
+/// How the sides of a merge are called in conflict markers
+pub struct Labels<'a> {
+    pub base: &'a str,
+    pub ours: &'a str,
+    pub theirs: &'a str,
+}
+
+/// The result of merging a tree into the worktree
+pub struct Merged {
+    /// The merged tree, with conflict markers in files that couldn't be merged cleanly
+    pub tree_id: gix::ObjectId,
+    /// The new index, with conflicting paths recorded in stages 1 to 3. It isn't written yet.
+    pub index: gix::index::File,
+    /// Paths that were left with conflicts
+    pub conflicts: Vec<BString>,
+}
+
+/// Merge the changes between `base` and `theirs` into `ours`, the tree of the current index,
+/// and update the worktree files that changed.
+///
+/// Returns `None` after listing the files in the way if local changes or untracked files would be overwritten,
+/// in which case nothing was changed.
+pub fn merge_into_worktree(
+    repo: &gix::Repository,
+    base: gix::ObjectId,
+    ours: gix::ObjectId,
+    theirs: gix::ObjectId,
+    labels: Labels<'_>,
+) -> Result<Option<Merged>> {
+    let worktree = repo
+        .workdir()
+        .ok_or_else(|| anyhow::anyhow!("No worktree found"))?;
+    super::promisor::fetch_changed_blobs(repo, base, &[ours, theirs])?;
+    let mut outcome = super::promisor::on_demand(repo, || {
+        Ok(repo.merge_trees(
+            base,
+            ours,
+            theirs,
+            gix::merge::blob::builtin_driver::text::Labels {
+                ancestor: Some(labels.base.into()),
+                current: Some(labels.ours.into()),
+                other: Some(labels.theirs.into()),
+            },
+            repo.tree_merge_options()?,
+        )?)
+    })?;
+    let tree_id = outcome.tree.write()?.detach();
+    let mut index = super::promisor::index_from_tree(repo, &tree_id)?;
+    outcome.index_changed_after_applying_conflicts(
+        &mut index,
+        TreatAsUnresolved::git(),
+        RemovalMode::Prune,
+    );
+
+    let ours_index = super::promisor::index_from_tree(repo, &ours)?;
+    let ours_entries = entries_by_path(Some(&ours_index));
+    let changed = differing_paths(&ours_entries, &entries_by_path(Some(&index)));
+
+    let mut modified = BTreeSet::new();
+    for item in repo
+        .status(gix::progress::Discard)?
+        .into_index_worktree_iter(Vec::new())?
+    {
+        if let gix::status::index_worktree::Item::Modification { rela_path, .. } = item? {
+            modified.insert(rela_path);
+        }
+    }
+    let (mut overwritten, mut untracked) = (Vec::new(), Vec::new());
+    for path in &changed {
+        if modified.contains(path) {
+            overwritten.push(path);
+        } else if !ours_entries.contains_key(path)
+            && worktree.join(gix::path::from_bstr(path.as_bstr())).exists()
+        {
+            untracked.push(path);
+        }
+    }
+    if !overwritten.is_empty() || !untracked.is_empty() {
+        if !overwritten.is_empty() {
+            println!(
+                "error: Your local changes to the following files would be overwritten by merge:"
+            );
+            for path in &overwritten {
+                println!("\t{}", path);
+            }
+            println!("Please commit your changes or stash them before you merge.");
+        }
+        if !untracked.is_empty() {
+            println!(
+                "error: The following untracked working tree files would be overwritten by merge:"
+            );
+            for path in &untracked {
+                println!("\t{}", path);
+            }
+            println!("Please move or remove them before you merge.");
+        }
+        println!("Aborting");
+        return Ok(None);
+    }
+
+    let mut conflicts = BTreeSet::new();
+    for entry in index.entries() {
+        if entry.stage_raw() != 0 {
+            conflicts.insert(entry.path(&index).to_owned());
+        }
+    }
+    for path in &conflicts {
+        let has_stage = |stage: u32| {
+            index
+                .entries()
+                .iter()
+                .any(|entry| entry.stage_raw() == stage && entry.path(&index) == path.as_bstr())
+        };
+        if has_stage(2) && has_stage(3) {
+            println!("CONFLICT (content): Merge conflict in {}", path);
+        } else {
+            println!(
+                "CONFLICT (modify/delete): {} deleted in one side and modified in the other.",
+                path
+            );
+        }
+    }
+
+    // The merged tree has the files with conflict markers, which is what the worktree should show.
+    let mut updates = super::promisor::index_from_tree(repo, &tree_id)?;
+    updates.remove_entries(|_, path, _| !changed.contains(path));
+    checkout_index(repo, &mut updates)?;
+    for path in &changed {
+        if updates.entry_by_path(path.as_bstr()).is_some() || conflicts.contains(path) {
+            continue;
+        }
+        let file = worktree.join(gix::path::from_bstr(path.as_bstr()));
+        if std::fs::remove_file(&file).is_ok() {
+            remove_empty_parents(worktree, &file);
+        }
+    }
+
+    keep_stat(&mut index, &repo.index_or_empty()?);
+    keep_stat(&mut index, &updates);
+    Ok(Some(Merged {
+        tree_id,
+        index,
+        conflicts: conflicts.into_iter().collect(),
+    }))
+}
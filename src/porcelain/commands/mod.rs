@@ -1,16 +1,45 @@
 pub mod add;
+pub mod archive;
 pub mod blame;
+pub mod clean;
 pub mod clone;
 pub mod commit;
+mod commit_utils;
 pub mod config;
+mod config_utils;
 pub mod describe;
 pub mod diff;
+mod diff_utils;
+mod editor_utils;
 pub mod fetch;
+pub mod gc;
+pub mod grep;
+mod hook_utils;
 mod index_utils;
 pub mod init;
 pub mod log;
+pub mod ls_remote;
 pub mod merge;
+mod merge_utils;
+pub mod mv;
+mod progress;
+pub mod promisor;
+pub mod rebase;
+mod ref_utils;
+pub mod reflog;
 pub mod remote;
 pub mod reset;
+pub mod restore;
+mod revision_utils;
+pub mod rm;
+pub mod sequencer;
+pub mod shortlog;
 pub mod show;
+mod signing_utils;
+pub mod stash;
 pub mod status;
+pub mod submodule;
+mod submodule_utils;
+pub mod tag;
+pub mod verify;
+pub mod worktree;
@@ -0,0 +1,156 @@
+use anyhow::Result;
+use gix::bstr::{BString, ByteSlice};
+use std::path::PathBuf;
+
+use super::index_utils::{matches_pathspec, normalize_pathspecs};
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Overwrite existing destination files
+    pub force: bool,
+    /// Only show what would be moved
+    pub dry_run: bool,
+    /// Skip sources that can't be moved instead of failing
+    pub skip_errors: bool,
+    pub verbose: bool,
+}
+
+/// Move or rename the tracked files or directories in `paths` to the last of them, updating the index to match.
+pub fn run(paths: Vec<PathBuf>, opts: Options) -> Result<()> {
+    let repo = match gix::discover(".") {
+        Ok(repo) => repo,
+        Err(_) => {
+            println!("fatal: not a git repository (or any of the parent directories): .git");
+            return Ok(());
+        }
+    };
+    let Some(workdir) = repo.workdir() else {
+        println!("fatal: this operation must be run in a work tree");
+        return Ok(());
+    };
+    let Some((destination, sources)) = paths
+        .split_last()
+        .filter(|(_, sources)| !sources.is_empty())
+    else {
+        println!("usage: git mv [<options>] <source>... <destination>");
+        return Ok(());
+    };
+
+    let destination_spec = normalize_pathspecs(std::slice::from_ref(destination)).remove(0);
+    let into_directory = workdir
+        .join(gix::path::from_bstr(destination_spec.as_bstr()))
+        .is_dir();
+    if sources.len() > 1 && !into_directory {
+        println!(
+            "fatal: destination '{}' is not a directory",
+            destination.display()
+        );
+        return Ok(());
+    }
+
+    let mut index = repo.index_or_load_from_head_or_empty()?.into_owned();
+    let mut moves: Vec<(BString, BString)> = Vec::new();
+    for (source, source_spec) in sources.iter().zip(normalize_pathspecs(sources)) {
+        let target: BString = if into_directory {
+            let name = source_spec
+                .rsplit_str("/")
+                .next()
+                .unwrap_or(source_spec.as_slice());
+            if destination_spec.is_empty() {
+                name.into()
+            } else {
+                format!("{}/{}", destination_spec, name.as_bstr()).into()
+            }
+        } else {
+            destination_spec.clone()
+        };
+
+        let source_file = workdir.join(gix::path::from_bstr(source_spec.as_bstr()));
+        let target_file = workdir.join(gix::path::from_bstr(target.as_bstr()));
+        let is_tracked = index
+            .entries()
+            .iter()
+            .any(|entry| matches_pathspec(std::slice::from_ref(&source_spec), entry.path(&index)));
+        let error = if !source_file.exists() {
+            Some("bad source")
+        } else if source_spec.is_empty() || target == source_spec {
+            Some("can not move directory into itself")
+        } else if target.starts_with(source_spec.as_slice())
+            && target.get(source_spec.len()) == Some(&b'/')
+        {
+            Some("can not move directory into itself")
+        } else if !is_tracked {
+            Some("not under version control")
+        } else if target_file.exists() && (!opts.force || source_file.is_dir()) {
+            Some("destination exists")
+        } else if target_file.parent().is_some_and(|parent| !parent.is_dir()) {
+            Some("destination directory does not exist")
+        } else if moves.iter().any(|(_, other)| *other == target) {
+            Some("multiple sources for the same target")
+        } else {
+            None
+        };
+        if let Some(error) = error {
+            if opts.skip_errors {
+                continue;
+            }
+            println!(
+                "fatal: {}, source={}, destination={}",
+                error,
+                source.display(),
+                gix::path::from_bstr(target.as_bstr()).display()
+            );
+            return Ok(());
+        }
+        moves.push((source_spec, target));
+    }
+
+    for (source, target) in &moves {
+        if opts.verbose || opts.dry_run {
+            println!("Renaming {} to {}", source, target);
+        }
+        if opts.dry_run {
+            continue;
+        }
+        let target_file = workdir.join(gix::path::from_bstr(target.as_bstr()));
+        if opts.force && target_file.is_file() {
+            std::fs::remove_file(&target_file)?;
+        }
+        std::fs::rename(
+            workdir.join(gix::path::from_bstr(source.as_bstr())),
+            target_file,
+        )?;
+    }
+    if opts.dry_run {
+        return Ok(());
+    }
+
+    // Entries below moved directories keep their content and stat, only their path changes.
+    let mut moved_entries = Vec::new();
+    index.remove_entries(|_, path, entry| {
+        for (source, target) in &moves {
+            if matches_pathspec(std::slice::from_ref(source), path) {
+                let mut new_path = target.clone();
+                new_path.extend_from_slice(&path[source.len()..]);
+                moved_entries.push((entry.clone(), new_path));
+                return true;
+            }
+        }
+        // Forcefully overwritten files.
+        moves.iter().any(|(_, target)| path == target.as_bstr())
+    });
+    for (entry, path) in moved_entries {
+        index.dangerously_push_entry(
+            entry.stat,
+            entry.id,
+            entry.flags,
+            entry.mode,
+            path.as_bstr(),
+        );
+    }
+    index.sort_entries();
+    index.remove_tree();
+    index.write(Default::default())?;
+
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use anyhow::Result;
+use gix::progress::DoOrDiscard;
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const RENDER_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Decide whether to show progress, which is forced with `--progress`, suppressed with `--no-progress`
+/// or `--quiet`, and otherwise only shown if stderr is a terminal.
+pub fn enabled(progress: Option<bool>, quiet: bool) -> bool {
+    progress.unwrap_or_else(|| !quiet && std::io::stderr().is_terminal())
+}
+
+/// Run `f` with a progress tree whose tasks are rendered on stderr like git does, if `enabled`.
+pub fn run<T>(
+    name: &str,
+    enabled: bool,
+    f: impl FnOnce(DoOrDiscard<prodash::tree::Item>) -> Result<T>,
+) -> Result<T> {
+    if !enabled {
+        return f(DoOrDiscard::from(None));
+    }
+
+    let root: Arc<prodash::tree::Root> = prodash::tree::root::Options::default().into();
+    let item = root.add_child(name);
+    let stop = Arc::new(AtomicBool::new(false));
+    let renderer = std::thread::spawn({
+        let root = root.clone();
+        let stop = stop.clone();
+        move || {
+            let mut state = RenderState::default();
+            let mut snapshot = Vec::new();
+            loop {
+                let is_last = stop.load(Ordering::Relaxed);
+                root.sorted_snapshot(&mut snapshot);
+                state.update(&snapshot);
+                if is_last {
+                    break;
+                }
+                std::thread::sleep(RENDER_INTERVAL);
+            }
+            state.finish();
+        }
+    });
+
+    let res = f(DoOrDiscard::from(Some(item)));
+    stop.store(true, Ordering::Relaxed);
+    renderer.join().ok();
+    res
+}
+
+/// A phase of a transfer as git names it, along with the progress task it's derived from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Receiving,
+    Resolving,
+    Checkout,
+}
+
+impl Phase {
+    fn from_task(name: &str) -> Option<Self> {
+        Some(match name {
+            "indexing" => Phase::Receiving,
+            "Resolving" => Phase::Resolving,
+            "checkout" => Phase::Checkout,
+            _ => return None,
+        })
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Phase::Receiving => "Receiving objects",
+            Phase::Resolving => "Resolving deltas",
+            Phase::Checkout => "Updating files",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Counts {
+    step: usize,
+    max: Option<usize>,
+    bytes: Option<usize>,
+}
+
+#[derive(Default)]
+struct RenderState {
+    current: Option<(Phase, Counts)>,
+    started: Option<Instant>,
+}
+
+impl RenderState {
+    fn update(&mut self, snapshot: &[(prodash::progress::Key, prodash::progress::Task)]) {
+        let mut bytes = None;
+        let mut latest = None;
+        for (_key, task) in snapshot {
+            let Some(value) = &task.progress else {
+                continue;
+            };
+            let step = value.step.load(Ordering::Relaxed);
+            if task.name == "read pack" {
+                bytes = Some(step);
+                continue;
+            }
+            if let Some(phase) = Phase::from_task(&task.name) {
+                latest = Some((
+                    phase,
+                    Counts {
+                        step,
+                        max: value.done_at,
+                        bytes: None,
+                    },
+                ));
+            }
+        }
+
+        let Some((phase, mut counts)) = latest else {
+            return;
+        };
+        if phase == Phase::Receiving {
+            counts.bytes = bytes;
+        }
+        match self.current {
+            Some((current, _)) if current == phase => {}
+            _ => {
+                self.finish();
+                self.started = Some(Instant::now());
+            }
+        }
+        self.current = Some((phase, counts));
+        self.print("");
+    }
+
+    /// Print the final state of the current phase, if any.
+    fn finish(&mut self) {
+        if self.current.is_some() {
+            self.print(", done.\n");
+        }
+        self.current = None;
+    }
+
+    fn print(&self, end: &str) {
+        let Some((phase, counts)) = self.current else {
+            return;
+        };
+        let mut line = format!("{}: ", phase.label());
+        match counts.max {
+            Some(max) if max > 0 => line.push_str(&format!(
+                "{:3}% ({}/{})",
+                counts.step * 100 / max,
+                counts.step,
+                max
+            )),
+            _ => line.push_str(&counts.step.to_string()),
+        }
+        if let Some(bytes) = counts.bytes {
+            line.push_str(&format!(", {}", human_bytes(bytes as u64)));
+            let elapsed = self.started.map_or(0.0, |t| t.elapsed().as_secs_f64());
+            if elapsed > 0.0 {
+                line.push_str(&format!(
+                    " | {}/s",
+                    human_bytes((bytes as f64 / elapsed) as u64)
+                ));
+            }
+        }
+
+        let mut err = std::io::stderr().lock();
+        write!(err, "\r{}{}", line, end).ok();
+        err.flush().ok();
+    }
+}
+
+/// Format `bytes` like git does in its progress output, e.g. `12.30 MiB`.
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 3] = ["GiB", "MiB", "KiB"];
+    for (idx, unit) in UNITS.iter().enumerate() {
+        let scale = 1u64 << (10 * (UNITS.len() - idx));
+        if bytes >= scale {
+            let whole = bytes / scale;
+            let frac = (bytes % scale) * 100 / scale;
+            return format!("{}.{:02} {}", whole, frac, unit);
+        }
+    }
+    format!("{} bytes", bytes)
+}
@@ -0,0 +1,1052 @@
+use anyhow::{anyhow, bail, Result};
+use gix::bstr::{BString, ByteSlice};
+use gix::objs::{Exists, Find};
+use gix::protocol::fetch::{refmap, RefMap};
+use gix::protocol::handshake;
+use gix::refs::transaction::{Change, LogChange, PreviousValue, RefEdit, RefLog};
+use gix::remote::fetch::refs::update::Mode;
+use gix::remote::fetch::{Shallow, Tags};
+use std::borrow::Cow;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use super::config_utils::{open_local_config, write_local_config};
+use super::diff_utils::tree_changes;
+use super::fetch::RefUpdate;
+use super::index_utils::checkout_index;
+use super::ref_utils::set_symbolic_ref;
+
+pub(super) const USER_AGENT: &str = concat!("git/portable-git-", env!("CARGO_PKG_VERSION"));
+
+/// An object filter as understood by `upload-pack`, like `blob:none` or `tree:0`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    BlobNone,
+    BlobLimit(u64),
+    Tree(u64),
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        if spec == "blob:none" {
+            return Ok(Filter::BlobNone);
+        }
+        if let Some(limit) = spec.strip_prefix("blob:limit=") {
+            let (digits, unit) = match limit.char_indices().last() {
+                Some((idx, 'k' | 'K')) => (&limit[..idx], 1024),
+                Some((idx, 'm' | 'M')) => (&limit[..idx], 1024 * 1024),
+                Some((idx, 'g' | 'G')) => (&limit[..idx], 1024 * 1024 * 1024),
+                _ => (limit, 1),
+            };
+            return digits
+                .parse::<u64>()
+                .map(|n| Filter::BlobLimit(n * unit))
+                .map_err(|_| format!("invalid filter-spec '{}'", spec));
+        }
+        if let Some(depth) = spec.strip_prefix("tree:") {
+            return depth
+                .parse::<u64>()
+                .map(Filter::Tree)
+                .map_err(|_| format!("invalid filter-spec '{}'", spec));
+        }
+        Err(format!("invalid filter-spec '{}'", spec))
+    }
+}
+
+impl std::fmt::Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Filter::BlobNone => write!(f, "blob:none"),
+            Filter::BlobLimit(limit) => write!(f, "blob:limit={}", limit),
+            Filter::Tree(depth) => write!(f, "tree:{}", depth),
+        }
+    }
+}
+
+/// What to request from a remote.
+enum Request<'a> {
+    /// The objects with the given ids, as needed to fill in what a filter left out.
+    Objects(&'a [gix::ObjectId]),
+    /// Everything reachable from the references matched by the remote's refspecs.
+    Refs {
+        filter: Option<&'a Filter>,
+        shallow: &'a Shallow,
+        with_head: bool,
+        /// Only list the refs, without fetching what they point to
+        dry_run: bool,
+    },
+}
+
+/// The name of the remote that promised to provide objects omitted by a partial clone, if any
+pub fn promisor_remote(repo: &gix::Repository) -> Option<String> {
+    let config = repo.config_snapshot();
+    if let Some(name) = config.string("extensions.partialClone") {
+        return Some(name.to_string());
+    }
+    repo.remote_names()
+        .into_iter()
+        .find(|name| {
+            config
+                .boolean(format!("remote.{}.promisor", name).as_str())
+                .unwrap_or(false)
+        })
+        .map(|name| name.to_string())
+}
+
+/// The filter that was used to create a partial clone from `remote_name`, if any
+pub fn configured_filter(repo: &gix::Repository, remote_name: &str) -> Option<Filter> {
+    repo.config_snapshot()
+        .string(format!("remote.{}.partialclonefilter", remote_name).as_str())
+        .and_then(|spec| spec.to_str().ok()?.parse().ok())
+}
+
+/// Mark `remote_name` as a promisor remote and remember `filter` for future fetches from it
+pub fn record_promisor_remote(
+    repo: &gix::Repository,
+    remote_name: &str,
+    filter: &Filter,
+) -> Result<()> {
+    let mut config = open_local_config(repo)?;
+    config.set_raw_value_by("core", None, "repositoryformatversion", "1")?;
+    config.set_raw_value_by("extensions", None, "partialClone", remote_name)?;
+    config.set_raw_value_by("remote", Some(remote_name.into()), "promisor", "true")?;
+    config.set_raw_value_by(
+        "remote",
+        Some(remote_name.into()),
+        "partialclonefilter",
+        filter.to_string().as_str(),
+    )?;
+    write_local_config(repo, &config)
+}
+
+/// Fetch all objects in `ids` that are missing locally from the promisor remote, in a single request.
+///
+/// This is the one place objects left out by a filter are fetched, and only the objects asked for are sent,
+/// as trees come without their blobs. It's a no-op in repositories that aren't partial clones.
+pub fn fetch_missing(
+    repo: &gix::Repository,
+    ids: impl IntoIterator<Item = gix::ObjectId>,
+) -> Result<()> {
+    let Some(remote_name) = promisor_remote(repo) else {
+        return Ok(());
+    };
+    let mut missing: Vec<_> = ids.into_iter().filter(|id| !repo.has_object(id)).collect();
+    missing.sort();
+    missing.dedup();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let remote = repo.find_remote(remote_name.as_str())?;
+    fetch_from(
+        repo,
+        &remote,
+        Request::Objects(&missing),
+        &mut gix::progress::Discard,
+    )?;
+    Ok(())
+}
+
+/// Objects of a repository that are fetched from the promisor remote the moment they are looked up but missing,
+/// for use wherever gix takes an object database, like checkouts, tree traversals and blame.
+#[derive(Clone)]
+pub struct Objects {
+    inner: gix::OdbHandleArc,
+    repo: gix::ThreadSafeRepository,
+    /// The promisor remote, or `None` if the repository isn't a partial clone and nothing is ever fetched
+    remote: Option<String>,
+    /// Makes threads that miss the same objects wait for one fetch instead of each starting their own
+    fetching: Arc<Mutex<()>>,
+}
+
+impl Objects {
+    pub fn new(repo: &gix::Repository) -> Result<Self> {
+        Ok(Objects {
+            inner: repo.objects.clone().into_arc()?,
+            repo: repo.clone().into_sync(),
+            remote: promisor_remote(repo),
+            fetching: Arc::new(Mutex::new(())),
+        })
+    }
+
+    /// Fetch `id` if this is a partial clone that doesn't have it yet.
+    fn fetch_if_missing(&self, id: &gix::oid) -> Result<(), gix::objs::find::Error> {
+        if self.remote.is_none() || self.inner.exists(id) {
+            return Ok(());
+        }
+        let _fetching = self.fetching.lock().unwrap_or_else(|err| err.into_inner());
+        fetch_missing(&self.repo.to_thread_local(), [id.to_owned()]).map_err(Into::into)
+    }
+}
+
+impl gix::objs::Find for Objects {
+    fn try_find<'a>(
+        &self,
+        id: &gix::oid,
+        buffer: &'a mut Vec<u8>,
+    ) -> Result<Option<gix::objs::Data<'a>>, gix::objs::find::Error> {
+        self.fetch_if_missing(id)?;
+        self.inner.try_find(id, buffer)
+    }
+}
+
+impl gix::objs::FindHeader for Objects {
+    fn try_header(
+        &self,
+        id: &gix::oid,
+    ) -> Result<Option<gix::objs::Header>, gix::objs::find::Error> {
+        self.fetch_if_missing(id)?;
+        gix::objs::FindHeader::try_header(&self.inner, id)
+    }
+}
+
+impl gix::objs::Exists for Objects {
+    fn exists(&self, id: &gix::oid) -> bool {
+        self.fetch_if_missing(id).is_ok() && self.inner.exists(id)
+    }
+}
+
+/// Like `Repository::find_object()`, but fetching the object first if a filter left it out.
+pub fn find_object(repo: &gix::Repository, id: gix::ObjectId) -> Result<gix::Object<'_>> {
+    fetch_missing(repo, [id])?;
+    Ok(repo.find_object(id)?)
+}
+
+/// Like `Repository::index_from_tree()`, but fetching the trees a `tree:<depth>` filter left out as they are traversed.
+pub fn index_from_tree(repo: &gix::Repository, tree: &gix::oid) -> Result<gix::index::File> {
+    let state = gix::index::State::from_tree(tree, Objects::new(repo)?, Default::default())?;
+    Ok(gix::index::File::from_state(state, repo.index_path()))
+}
+
+/// Run `f`, which looks up objects through the repository itself, fetching each object it fails to find because
+/// a filter left it out and running it again, until it succeeds or fails for another reason.
+///
+/// This is a fallback for objects that weren't fetched up front, as with [`fetch_changed_blobs()`],
+/// as each of them costs a round-trip and another run of `f`.
+pub fn on_demand<T>(repo: &gix::Repository, mut f: impl FnMut() -> Result<T>) -> Result<T> {
+    if promisor_remote(repo).is_none() {
+        return f();
+    }
+    let mut fetched = Vec::new();
+    loop {
+        let err = match f() {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+        match missing_object(&err) {
+            // An object that is still missing after fetching it won't appear by trying again.
+            Some(id) if !fetched.contains(&id) && !repo.has_object(id) => {
+                fetch_missing(repo, [id])?;
+                fetched.push(id);
+            }
+            _ => return Err(err),
+        }
+    }
+}
+
+/// The id of the object whose absence caused `err`, if that's why it failed.
+fn missing_object(err: &anyhow::Error) -> Option<gix::ObjectId> {
+    use gix::objs::find::{existing, existing_iter, existing_object};
+
+    err.chain().find_map(|cause| {
+        if let Some(existing::Error::NotFound { oid }) = cause.downcast_ref() {
+            return Some(*oid);
+        }
+        if let Some(existing_object::Error::NotFound { oid }) = cause.downcast_ref() {
+            return Some(*oid);
+        }
+        if let Some(existing_iter::Error::NotFound { oid }) = cause.downcast_ref() {
+            return Some(*oid);
+        }
+        None
+    })
+}
+
+/// Fetch the blobs referenced by `index` that a filter left out, all in one request before they are written
+/// to the worktree, like git does, instead of one at a time as each is looked up.
+pub fn fetch_missing_blobs(repo: &gix::Repository, index: &gix::index::State) -> Result<()> {
+    if promisor_remote(repo).is_none() {
+        return Ok(());
+    }
+    fetch_missing(
+        repo,
+        index
+            .entries()
+            .iter()
+            .filter(|entry| !entry.mode.is_submodule())
+            .map(|entry| entry.id),
+    )
+}
+
+/// Fetch the blobs of the files that differ between `base` and any of `trees` that a filter left out,
+/// all in one request before a diff or merge reads them, like git does.
+pub fn fetch_changed_blobs(
+    repo: &gix::Repository,
+    base: gix::ObjectId,
+    trees: &[gix::ObjectId],
+) -> Result<()> {
+    if promisor_remote(repo).is_none() {
+        return Ok(());
+    }
+    let mut ids = Vec::new();
+    for tree in trees {
+        for change in tree_changes(repo, base, *tree)? {
+            ids.extend(
+                [change.old, change.new]
+                    .into_iter()
+                    .flatten()
+                    .filter(|(id, mode)| !mode.is_submodule() && !id.is_null())
+                    .map(|(id, _)| id),
+            );
+        }
+    }
+    fetch_missing(repo, ids)
+}
+
+/// Fetch from `remote` using `filter`, updating local refs with the same checks as a regular fetch.
+///
+/// Returns the ref map along with how each ref was updated, or would be in `dry_run` mode, where nothing is
+/// downloaded or written.
+pub fn fetch(
+    repo: &gix::Repository,
+    remote: &gix::Remote<'_>,
+    filter: &Filter,
+    shallow: &Shallow,
+    dry_run: bool,
+    progress: &mut dyn gix::progress::DynNestedProgress,
+) -> Result<(RefMap, Vec<RefUpdate>)> {
+    let remote_name = remote
+        .name()
+        .map(|name| name.as_bstr().to_string())
+        .ok_or_else(|| anyhow!("--filter can only be used with a configured remote"))?;
+    if !dry_run && promisor_remote(repo).is_none() {
+        record_promisor_remote(repo, &remote_name, filter)?;
+    }
+    let ref_map = fetch_from(
+        repo,
+        remote,
+        Request::Refs {
+            filter: Some(filter),
+            shallow,
+            with_head: false,
+            dry_run,
+        },
+        progress,
+    )?
+    .expect("ref map is returned for ref requests");
+
+    let message = format!("fetch {}", remote_name);
+    let updates = update_refs(repo, &ref_map, remote.fetch_tags(), &message, dry_run)?;
+    Ok((ref_map, updates))
+}
+
+/// Clone `url` into `target_dir` as a partial clone that omits the objects matched by `filter`
+pub fn clone(
+    url: &str,
+    target_dir: &Path,
+    opts: &super::clone::Options,
+    remote_name: &str,
+    filter: &Filter,
+    progress: &mut dyn gix::progress::DynNestedProgress,
+) -> Result<()> {
+    let repo = if opts.bare {
+        gix::init_bare(target_dir)?
+    } else {
+        gix::init(target_dir)?
+    };
+
+    let url = match std::fs::canonicalize(url) {
+        Ok(path) if Path::new(url).exists() => path.display().to_string(),
+        _ => url.to_string(),
+    };
+
+    let mut config = open_local_config(&repo)?;
+    config.set_raw_value_by("remote", Some(remote_name.into()), "url", url.as_str())?;
+    match (&opts.branch, opts.single_branch) {
+        (_, false) => {
+            config.set_raw_value_by(
+                "remote",
+                Some(remote_name.into()),
+                "fetch",
+                format!("+refs/heads/*:refs/remotes/{remote_name}/*").as_str(),
+            )?;
+        }
+        (Some(branch), true) => {
+            config.set_raw_value_by(
+                "remote",
+                Some(remote_name.into()),
+                "fetch",
+                format!("+refs/heads/{branch}:refs/remotes/{remote_name}/{branch}").as_str(),
+            )?;
+        }
+        // The refspec is filled in once we know which branch the remote HEAD points to.
+        (None, true) => {}
+    }
+    write_local_config(&repo, &config)?;
+    record_promisor_remote(&repo, remote_name, filter)?;
+
+    let repo = gix::open(repo.path())?;
+    let remote = repo.find_remote(remote_name)?;
+    let ref_map = fetch_from(
+        &repo,
+        &remote,
+        Request::Refs {
+            filter: Some(filter),
+            shallow: &opts.shallow,
+            with_head: true,
+            dry_run: false,
+        },
+        progress,
+    )?
+    .expect("ref map is returned for ref requests");
+
+    let message = format!("clone: from {}", url);
+    create_tracking_refs(&repo, &ref_map, remote_name, &message)?;
+
+    let head = match &opts.branch {
+        Some(branch) => ref_map.remote_refs.iter().find_map(|r| {
+            let (name, target, peeled) = r.unpack();
+            let object = peeled.or(target)?;
+            let is_match = name == format!("refs/heads/{branch}").as_str()
+                || name == format!("refs/tags/{branch}").as_str();
+            is_match.then(|| (name.to_owned(), object.to_owned()))
+        }),
+        None => ref_map.remote_refs.iter().find_map(|r| match r {
+            handshake::Ref::Symbolic {
+                full_ref_name,
+                target,
+                object,
+                ..
+            } if full_ref_name == "HEAD" => Some((target.clone(), *object)),
+            _ => None,
+        }),
+    };
+
+    let Some((head_ref, head_id)) = head else {
+        if let Some(branch) = &opts.branch {
+            bail!(
+                "Remote branch {} not found in upstream {}",
+                branch,
+                remote_name
+            );
+        }
+        println!("warning: You appear to have cloned an empty repository.");
+        return Ok(());
+    };
+
+    if head_ref.starts_with(b"refs/heads/") {
+        let branch = head_ref[b"refs/heads/".len()..].to_str()?.to_owned();
+        repo.reference(
+            head_ref.to_str()?,
+            head_id,
+            PreviousValue::Any,
+            message.as_str(),
+        )?;
+        set_symbolic_ref(&repo, "HEAD", head_ref.as_ref(), &message)?;
+
+        let mut config = open_local_config(&repo)?;
+        config.set_raw_value_by(
+            "branch",
+            Some(branch.as_str().into()),
+            "remote",
+            remote_name,
+        )?;
+        config.set_raw_value_by(
+            "branch",
+            Some(branch.as_str().into()),
+            "merge",
+            head_ref.as_bstr(),
+        )?;
+        write_local_config(&repo, &config)?;
+
+        if opts.single_branch && opts.branch.is_none() {
+            super::clone::track_head_branch_only(&repo)?;
+        }
+    } else {
+        repo.reference("HEAD", head_id, PreviousValue::Any, message.as_str())?;
+    }
+
+    if !opts.bare && !opts.no_checkout {
+        let tree_id = repo.find_commit(head_id)?.tree_id()?.detach();
+        let mut index = index_from_tree(&repo, &tree_id)?;
+        checkout_index(&repo, &mut index)?;
+        index.write(Default::default())?;
+    }
+
+    Ok(())
+}
+
+fn fetch_from(
+    repo: &gix::Repository,
+    remote: &gix::Remote<'_>,
+    request: Request<'_>,
+    progress: &mut dyn gix::progress::DynNestedProgress,
+) -> Result<Option<RefMap>> {
+    use gix::protocol::transport::client::TransportWithoutIO;
+
+    let mut connection = remote.connect(gix::remote::Direction::Fetch)?;
+    let url = remote
+        .url(gix::remote::Direction::Fetch)
+        .ok_or_else(|| anyhow!("remote has no fetch URL"))?
+        .to_owned();
+    if let Some(config) = repo.transport_options(
+        url.to_bstring().as_bstr(),
+        remote.name().map(|name| name.as_bstr()),
+    )? {
+        connection.transport_mut().configure(&*config)?;
+    }
+    let mut authenticate = connection.configured_credentials(url)?;
+    let mut handshake = gix::protocol::fetch::handshake(
+        connection.transport_mut(),
+        &mut authenticate,
+        Vec::new(),
+        &mut gix::progress::Discard,
+    )?;
+
+    let (wants, haves, filter, shallow, ref_map) = match request {
+        // Like git, leave out the blobs of requested trees, which are fetched once they are needed.
+        Request::Objects(ids) => (
+            ids.to_vec(),
+            Vec::new(),
+            Some(&Filter::BlobNone),
+            &Shallow::NoChange,
+            None,
+        ),
+        Request::Refs {
+            filter,
+            shallow,
+            with_head,
+            dry_run,
+        } => {
+            let mut extra_refspecs = Vec::new();
+            if let Some(spec) = remote.fetch_tags().to_refspec() {
+                extra_refspecs.push(spec.to_owned());
+            }
+            if with_head {
+                let head_spec = format!(
+                    "HEAD:refs/remotes/{}/HEAD",
+                    remote.name().map_or("origin".into(), |name| name.as_bstr())
+                );
+                extra_refspecs.push(
+                    gix::refspec::parse(
+                        head_spec.as_str().into(),
+                        gix::refspec::parse::Operation::Fetch,
+                    )?
+                    .to_owned(),
+                );
+            }
+
+            let ref_map = RefMap::new(
+                gix::progress::Discard,
+                remote.refspecs(gix::remote::Direction::Fetch),
+                gix::protocol::fetch::Context {
+                    handshake: &mut handshake,
+                    transport: connection.transport_mut(),
+                    user_agent: ("agent", Some(Cow::Borrowed(USER_AGENT))),
+                    trace_packetlines: false,
+                },
+                refmap::init::Options {
+                    prefix_from_spec_as_filter_on_remote: true,
+                    extra_refspecs,
+                },
+            )?;
+
+            let is_shallow = !matches!(shallow, Shallow::NoChange);
+            // Tags that are only included if they point into what's fetched anyway are sent with `include-tag`.
+            let tags_included = matches!(remote.fetch_tags(), Tags::Included);
+            let mut wants: Vec<_> = ref_map
+                .mappings
+                .iter()
+                .filter(|mapping| !(tags_included && is_implicit_tag(mapping)))
+                .filter_map(|mapping| mapping.remote.as_id())
+                .map(ToOwned::to_owned)
+                .filter(|id| is_shallow || !repo.has_object(id))
+                .filter(|_| !dry_run)
+                .collect();
+            wants.sort();
+            wants.dedup();
+
+            let mut haves = Vec::new();
+            for reference in repo.references()?.all()? {
+                let mut reference = reference.map_err(|e| anyhow!("{}", e))?;
+                if let Ok(id) = reference.peel_to_id_in_place() {
+                    haves.push(id.detach());
+                }
+            }
+
+            (wants, haves, filter, shallow, Some(ref_map))
+        }
+    };
+
+    if wants.is_empty() {
+        return Ok(ref_map);
+    }
+
+    let version = handshake.server_protocol_version;
+    let mut features =
+        gix::protocol::Command::Fetch.default_features(version, &handshake.capabilities);
+    features.push(("agent", Some(Cow::Borrowed(USER_AGENT))));
+    let mut arguments = gix::protocol::fetch::Arguments::new(version, features, false);
+
+    for id in &wants {
+        arguments.want(id);
+    }
+    for id in &haves {
+        arguments.have(id);
+    }
+    if ref_map.is_some()
+        && !matches!(remote.fetch_tags(), Tags::None)
+        && arguments.can_use_include_tag()
+    {
+        arguments.use_include_tag();
+    }
+    if let Some(filter) = filter {
+        if arguments.can_use_filter() {
+            arguments.filter(&filter.to_string());
+        } else {
+            eprintln!("warning: filtering not recognized by server, ignoring");
+        }
+    }
+    match shallow {
+        Shallow::NoChange => {}
+        Shallow::DepthAtRemote(depth) => arguments.deepen(depth.get() as usize),
+        Shallow::Deepen(depth) => {
+            arguments.deepen(*depth as usize);
+            arguments.deepen_relative();
+        }
+        Shallow::Since { cutoff } => arguments.deepen_since(cutoff.seconds),
+        Shallow::Exclude {
+            remote_refs,
+            since_cutoff,
+        } => {
+            if let Some(cutoff) = since_cutoff {
+                arguments.deepen_since(cutoff.seconds);
+            }
+            for name in remote_refs {
+                arguments.deepen_not(name.as_ref().as_bstr());
+            }
+        }
+    }
+
+    let shallow_updates = {
+        let mut reader = arguments.send(connection.transport_mut(), true)?;
+        let response =
+            gix::protocol::fetch::Response::from_line_reader(version, &mut reader, true, false)?;
+        if response.has_pack() {
+            let outcome = gix::odb::pack::Bundle::write_to_directory(
+                &mut reader,
+                Some(&repo.objects.store_ref().path().join("pack")),
+                progress,
+                &gix::interrupt::IS_INTERRUPTED,
+                Some(repo.objects.clone()),
+                gix::odb::pack::bundle::write::Options {
+                    thread_limit: None,
+                    iteration_mode: gix::odb::pack::data::input::Mode::Verify,
+                    index_version: gix::odb::pack::index::Version::default(),
+                    object_hash: repo.object_hash(),
+                },
+            )?;
+            if let Some(data_path) = &outcome.data_path {
+                std::fs::write(data_path.with_extension("promisor"), b"")?;
+            }
+            if let Some(keep_path) = &outcome.keep_path {
+                std::fs::remove_file(keep_path)?;
+            }
+        }
+        response.shallow_updates().to_vec()
+    };
+
+    if matches!(version, gix::protocol::transport::Protocol::V2) {
+        gix::protocol::indicate_end_of_interaction(connection.transport_mut(), false).ok();
+    }
+
+    if !shallow_updates.is_empty() {
+        write_shallow_file(repo, &shallow_updates)?;
+    }
+
+    Ok(ref_map)
+}
+
+/// Apply the shallow boundary changes sent by the server to `.git/shallow`.
+fn write_shallow_file(
+    repo: &gix::Repository,
+    updates: &[gix::protocol::fetch::response::ShallowUpdate],
+) -> Result<()> {
+    use gix::protocol::fetch::response::ShallowUpdate;
+
+    let mut commits: Vec<gix::ObjectId> = repo
+        .shallow_commits()?
+        .map(|commits| commits.iter().copied().collect())
+        .unwrap_or_default();
+    for update in updates {
+        match update {
+            ShallowUpdate::Shallow(id) => commits.push(*id),
+            ShallowUpdate::Unshallow(id) => commits.retain(|c| c != id),
+        }
+    }
+    commits.sort();
+    commits.dedup();
+
+    let mut content = String::new();
+    for id in commits {
+        content.push_str(&id.to_string());
+        content.push('\n');
+    }
+    std::fs::write(repo.shallow_file(), content)?;
+    Ok(())
+}
+
+/// Whether `mapping` is for a tag that is fetched because of `remote.<name>.tagOpt`, not the remote's refspecs.
+fn is_implicit_tag(mapping: &refmap::Mapping) -> bool {
+    mapping.spec_index.implicit_index().is_some()
+        && mapping
+            .local
+            .as_ref()
+            .is_some_and(|local| local.starts_with(b"refs/tags/"))
+}
+
+/// Update the local refs of all mappings in `ref_map` to what the remote advertised, like a regular fetch:
+/// tags are only replaced and branches only rewound by forcing refspecs, tags that are merely included are
+/// only stored if they were sent, and the branch checked out in the worktree is left alone.
+///
+/// Returns how each ref was updated, or would be with `dry_run`, in which case nothing is written.
+fn update_refs(
+    repo: &gix::Repository,
+    ref_map: &RefMap,
+    tags: Tags,
+    message: &str,
+    dry_run: bool,
+) -> Result<Vec<RefUpdate>> {
+    let checked_out = match repo.workdir() {
+        Some(_) => repo.head_name()?,
+        None => None,
+    };
+    let mut updates = Vec::new();
+    let mut edits = Vec::new();
+    for mapping in &ref_map.mappings {
+        let (Some(local), Some(new)) = (&mapping.local, mapping.remote.as_id()) else {
+            continue;
+        };
+        let new = new.to_owned();
+        let remote = match &mapping.remote {
+            refmap::Source::Ref(r) => r.unpack().0.to_owned(),
+            refmap::Source::ObjectId(id) => id.to_string().into(),
+        };
+        let old = repo
+            .try_find_reference(local.as_bstr())?
+            .and_then(|reference| reference.target().try_id().map(ToOwned::to_owned));
+        let is_tag = local.starts_with(b"refs/tags/");
+        let is_forced = mapping
+            .spec_index
+            .get(&ref_map.refspecs, &ref_map.extra_refspecs)
+            .is_some_and(|spec| spec.allow_non_fast_forward());
+
+        let mode = match old {
+            _ if matches!(tags, Tags::Included)
+                && is_implicit_tag(mapping)
+                && !repo.has_object(new) =>
+            {
+                Mode::ImplicitTagNotSentByRemote
+            }
+            Some(old) if old == new => Mode::NoChangeNeeded,
+            _ if checked_out
+                .as_ref()
+                .is_some_and(|head| head.as_bstr() == local.as_bstr()) =>
+            {
+                Mode::RejectedCurrentlyCheckedOut {
+                    worktree_dirs: repo.workdir().map(ToOwned::to_owned).into_iter().collect(),
+                }
+            }
+            None => Mode::New,
+            Some(_) if is_tag && !is_forced => Mode::RejectedTagUpdate,
+            Some(old) if !is_tag && is_fast_forward(repo, old, new) => Mode::FastForward,
+            Some(_) if is_forced => Mode::Forced,
+            Some(_) => Mode::RejectedNonFastForward,
+        };
+
+        let reason = match mode {
+            Mode::New if is_tag => Some("storing tag"),
+            Mode::New => Some("storing head"),
+            Mode::FastForward => Some("fast-forward"),
+            Mode::Forced => Some("forced-update"),
+            _ => None,
+        };
+        if let Some(reason) = reason {
+            edits.push(RefEdit {
+                change: Change::Update {
+                    log: LogChange {
+                        mode: RefLog::AndReference,
+                        force_create_reflog: false,
+                        message: format!("{}: {}", message, reason).into(),
+                    },
+                    expected: match old {
+                        Some(old) => {
+                            PreviousValue::MustExistAndMatch(gix::refs::Target::Object(old))
+                        }
+                        None => PreviousValue::MustNotExist,
+                    },
+                    new: gix::refs::Target::Object(new),
+                },
+                name: local.clone().try_into()?,
+                deref: false,
+            });
+        }
+        updates.push(RefUpdate {
+            mode,
+            remote,
+            local: local.clone(),
+            old,
+            new: Some(new),
+        });
+    }
+    if !dry_run && !edits.is_empty() {
+        repo.edit_references(edits)?;
+    }
+    Ok(updates)
+}
+
+/// Whether `new` has `old` in its history, which is assumed if `new` wasn't fetched as in a dry run.
+fn is_fast_forward(repo: &gix::Repository, old: gix::ObjectId, new: gix::ObjectId) -> bool {
+    if !repo.has_object(new) {
+        return true;
+    }
+    repo.merge_base(old, new)
+        .is_ok_and(|base| base.detach() == old)
+}
+
+/// Create the local refs of all mappings in `ref_map` of a new clone, pointing them at what the remote advertised.
+fn create_tracking_refs(
+    repo: &gix::Repository,
+    ref_map: &RefMap,
+    remote_name: &str,
+    message: &str,
+) -> Result<()> {
+    let mut edits = Vec::new();
+    for mapping in &ref_map.mappings {
+        let Some(local) = &mapping.local else {
+            continue;
+        };
+        let new = match &mapping.remote {
+            refmap::Source::Ref(handshake::Ref::Symbolic {
+                full_ref_name,
+                target,
+                ..
+            }) if full_ref_name == "HEAD" && target.starts_with(b"refs/heads/") => {
+                let branch = &target[b"refs/heads/".len()..];
+                let mut name = BString::from(format!("refs/remotes/{}/", remote_name));
+                name.extend_from_slice(branch);
+                gix::refs::Target::Symbolic(name.try_into()?)
+            }
+            remote => match remote.as_id() {
+                Some(id) => gix::refs::Target::Object(id.to_owned()),
+                None => continue,
+            },
+        };
+        edits.push(RefEdit {
+            change: Change::Update {
+                log: LogChange {
+                    mode: RefLog::AndReference,
+                    force_create_reflog: false,
+                    message: message.into(),
+                },
+                expected: PreviousValue::MustNotExist,
+                new,
+            },
+            name: local.clone().try_into()?,
+            deref: false,
+        });
+    }
+    repo.edit_references(edits)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    use super::{fetch_changed_blobs, find_object, index_from_tree, Filter, Mode, Objects};
+    use gix::objs::FindExt;
+    use gix::remote::fetch::Shallow;
+
+    /// Run `git` in `dir`, returning its trimmed output.
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args([
+                "-c",
+                "user.name=A U Thor",
+                "-c",
+                "user.email=author@example.com",
+            ])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .expect("git can be run");
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8(output.stdout).unwrap().trim().to_owned()
+    }
+
+    /// A repository with `top` and `dir/file`, served by the system's `git upload-pack` the way a server
+    /// supporting partial clones would, or `None` if git isn't installed.
+    fn upstream(root: &Path) -> Option<PathBuf> {
+        Command::new("git").arg("--version").output().ok()?;
+        let dir = root.join("upstream");
+        std::fs::create_dir_all(dir.join("dir")).unwrap();
+        git(&dir, &["init", "--quiet", "--initial-branch=main"]);
+        git(&dir, &["config", "uploadpack.allowFilter", "true"]);
+        git(&dir, &["config", "uploadpack.allowAnySHA1InWant", "true"]);
+        std::fs::write(dir.join("top"), "top\n").unwrap();
+        std::fs::write(dir.join("dir/file"), "file\n").unwrap();
+        git(&dir, &["add", "."]);
+        git(&dir, &["commit", "--quiet", "-m", "initial"]);
+        Some(dir)
+    }
+
+    fn partial_clone(root: &Path, upstream: &Path, filter: &Filter) -> gix::Repository {
+        let target = root.join("clone");
+        super::clone(
+            upstream.to_str().unwrap(),
+            &target,
+            &super::super::clone::Options {
+                no_checkout: true,
+                ..Default::default()
+            },
+            "origin",
+            filter,
+            &mut gix::progress::Discard,
+        )
+        .unwrap();
+        gix::open(target).unwrap()
+    }
+
+    fn object_id(upstream: &Path, spec: &str) -> gix::ObjectId {
+        gix::ObjectId::from_hex(git(upstream, &["rev-parse", spec]).as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn blob_none_fetches_only_the_blobs_that_are_looked_up() {
+        let root = tempfile::tempdir().unwrap();
+        let Some(upstream) = upstream(root.path()) else {
+            return;
+        };
+        let repo = partial_clone(root.path(), &upstream, &Filter::BlobNone);
+        let top = object_id(&upstream, "HEAD:top");
+        let file = object_id(&upstream, "HEAD:dir/file");
+        assert!(
+            !repo.has_object(top) && !repo.has_object(file),
+            "blobs are left out"
+        );
+
+        let tree = repo.head_commit().unwrap().tree_id().unwrap().detach();
+        let index = index_from_tree(&repo, &tree).unwrap();
+        assert_eq!(index.entries().len(), 2);
+        assert!(
+            !repo.has_object(top),
+            "building an index doesn't need blobs"
+        );
+
+        assert_eq!(find_object(&repo, top).unwrap().data, b"top\n");
+        assert!(
+            !repo.has_object(file),
+            "only the blob that was looked up is fetched"
+        );
+
+        let mut buf = Vec::new();
+        let objects = Objects::new(&repo).unwrap();
+        assert_eq!(objects.find_blob(&file, &mut buf).unwrap().data, b"file\n");
+    }
+
+    #[test]
+    fn tree_0_fetches_trees_on_lookup_without_their_blobs() {
+        let root = tempfile::tempdir().unwrap();
+        let Some(upstream) = upstream(root.path()) else {
+            return;
+        };
+        let repo = partial_clone(root.path(), &upstream, &Filter::Tree(0));
+        let tree = repo.head_commit().unwrap().tree_id().unwrap().detach();
+        assert!(!repo.has_object(tree), "trees are left out");
+
+        let index = index_from_tree(&repo, &tree).unwrap();
+        let paths: Vec<_> = index
+            .entries()
+            .iter()
+            .map(|entry| entry.path(&index).to_string())
+            .collect();
+        assert_eq!(paths, ["dir/file", "top"]);
+        assert!(repo.has_object(object_id(&upstream, "HEAD:dir")));
+        assert!(
+            !repo.has_object(object_id(&upstream, "HEAD:top")),
+            "fetching trees doesn't bring their blobs"
+        );
+    }
+
+    #[test]
+    fn filtered_fetch_updates_refs_like_a_regular_fetch() {
+        let root = tempfile::tempdir().unwrap();
+        let Some(upstream) = upstream(root.path()) else {
+            return;
+        };
+        git(&upstream, &["tag", "v1"]);
+        let repo = partial_clone(root.path(), &upstream, &Filter::BlobNone);
+        let tagged = object_id(&upstream, "v1");
+
+        std::fs::write(upstream.join("top"), "changed\n").unwrap();
+        git(&upstream, &["commit", "--quiet", "-am", "change top"]);
+        git(&upstream, &["tag", "--force", "v1"]);
+        let head = object_id(&upstream, "HEAD");
+
+        let remote = repo.find_remote("origin").unwrap();
+        let (_, updates) = super::fetch(
+            &repo,
+            &remote,
+            &Filter::BlobNone,
+            &Shallow::NoChange,
+            false,
+            &mut gix::progress::Discard,
+        )
+        .unwrap();
+        let mode = |local: &str| {
+            updates
+                .iter()
+                .find(|update| update.local == local)
+                .map(|update| update.mode.clone())
+        };
+        assert_eq!(mode("refs/remotes/origin/main"), Some(Mode::FastForward));
+        assert_eq!(mode("refs/tags/v1"), Some(Mode::RejectedTagUpdate));
+
+        let id = |name: &str| repo.find_reference(name).unwrap().id().detach();
+        assert_eq!(id("refs/remotes/origin/main"), head);
+        assert_eq!(
+            id("refs/tags/v1"),
+            tagged,
+            "tags are only replaced by force"
+        );
+        assert!(
+            !repo.has_object(object_id(&upstream, "HEAD:top")),
+            "the filter still applies"
+        );
+    }
+
+    #[test]
+    fn changed_blobs_are_fetched_together() {
+        let root = tempfile::tempdir().unwrap();
+        let Some(upstream) = upstream(root.path()) else {
+            return;
+        };
+        std::fs::write(upstream.join("top"), "changed\n").unwrap();
+        git(&upstream, &["commit", "--quiet", "-am", "change top"]);
+        let repo = partial_clone(root.path(), &upstream, &Filter::BlobNone);
+
+        let tree = |spec: &str| object_id(&upstream, &format!("{}^{{tree}}", spec));
+        fetch_changed_blobs(&repo, tree("HEAD~1"), &[tree("HEAD")]).unwrap();
+        assert!(repo.has_object(object_id(&upstream, "HEAD~1:top")));
+        assert!(repo.has_object(object_id(&upstream, "HEAD:top")));
+        assert!(
+            !repo.has_object(object_id(&upstream, "HEAD:dir/file")),
+            "unchanged blobs aren't needed"
+        );
+    }
+}
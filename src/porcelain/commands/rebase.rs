@@ -0,0 +1,652 @@
+use anyhow::Result;
+use gix::bstr::{BString, ByteSlice};
+use gix::refs::transaction::PreviousValue;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use super::commit_utils::write_commit_as;
+use super::hook_utils::run_hook;
+use super::index_utils::{checkout_tree, write_tree};
+use super::merge_utils::{merge_into_worktree, Labels};
+use super::ref_utils::{detach_head, set_symbolic_ref};
+use super::revision_utils::rev_parse_single;
+use super::sequencer::strip_comments;
+use super::stash::{apply_commit, create, reset_stashed, update_stash_ref, Applied};
+
+/// Files of the state directory that only exist while stopped at a commit that didn't apply cleanly
+const STOP_FILES: &[&str] = &["stopped-sha", "message", "author-script"];
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// The branch to compare against, the upstream of the current branch if unset
+    pub upstream: Option<String>,
+    /// Where to replay the commits, `upstream` if unset
+    pub onto: Option<String>,
+    /// Stash local changes before starting and apply them again when done
+    pub autostash: bool,
+    /// How merge commits are rebased, with `None` or `no` dropping them and replaying a linear history
+    pub rebase_merges: Option<String>,
+    /// Skip the `pre-rebase` hook
+    pub no_verify: bool,
+}
+
+/// A line of `git-rebase-todo`
+enum Instruction {
+    Pick(gix::ObjectId),
+    /// Leave the commit out, also used for `noop`
+    Drop,
+    /// Stop, so the user can look around before continuing
+    Break,
+}
+
+enum Outcome {
+    Done,
+    /// Stopped with the state saved for `--continue`
+    Stopped,
+}
+
+/// Replay the commits of the current branch that aren't in `upstream` on top of `onto`, and move the branch there.
+pub fn run(opts: Options) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    if repo.workdir().is_none() {
+        println!("fatal: this operation must be run in a work tree");
+        return Ok(());
+    }
+    for name in ["rebase-merge", "rebase-apply"] {
+        if repo.path().join(name).is_dir() {
+            println!(
+                "fatal: It seems that there is already a {} directory, and",
+                name
+            );
+            println!("I wonder if you are in the middle of another rebase.  If that is the");
+            println!("case, please try");
+            println!("\tgit rebase (--continue | --abort | --skip)");
+            println!("If that is not the case, please");
+            println!("\trm -fr \"{}\"", repo.path().join(name).display());
+            println!("and run me again.  I am stopping in case you still have something");
+            println!("valuable there.");
+            return Ok(());
+        }
+    }
+    if let Some(mode) = opts.rebase_merges.as_deref().filter(|mode| *mode != "no") {
+        println!("fatal: --rebase-merges={} is not supported yet", mode);
+        return Ok(());
+    }
+
+    let Ok(head) = repo.head_id().map(|id| id.detach()) else {
+        println!("fatal: no such branch/commit 'HEAD'");
+        return Ok(());
+    };
+    let head_name = repo.head_name()?;
+
+    let upstream = match (&opts.upstream, &head_name) {
+        (Some(spec), _) => match resolve_commit(&repo, spec) {
+            Some(id) => id,
+            None => {
+                println!("fatal: invalid upstream '{}'", spec);
+                return Ok(());
+            }
+        },
+        (None, Some(name)) => {
+            let tracking = repo
+                .branch_remote_tracking_ref_name(name.as_ref(), gix::remote::Direction::Fetch)
+                .transpose()?;
+            match tracking
+                .and_then(|tracking| resolve_commit(&repo, &tracking.as_bstr().to_str_lossy()))
+            {
+                Some(id) => id,
+                None => {
+                    print_no_upstream();
+                    return Ok(());
+                }
+            }
+        }
+        (None, None) => {
+            print_no_upstream();
+            return Ok(());
+        }
+    };
+    let onto = match &opts.onto {
+        Some(spec) => match resolve_commit(&repo, spec) {
+            Some(id) => id,
+            None => {
+                println!("fatal: Does not point to a valid commit '{}'", spec);
+                return Ok(());
+            }
+        },
+        None => upstream,
+    };
+
+    let index = repo.index_or_load_from_head_or_empty()?.into_owned();
+    let head_tree = repo.find_commit(head)?.tree_id()?.detach();
+    let staged = index.entries().iter().any(|entry| entry.stage_raw() != 0)
+        || write_tree(&repo, &index)? != head_tree;
+    let mut unstaged = false;
+    for item in repo
+        .status(gix::progress::Discard)?
+        .untracked_files(gix::status::UntrackedFiles::None)
+        .index_worktree_submodules(None)
+        .into_index_worktree_iter(Vec::new())?
+    {
+        if let gix::status::index_worktree::Item::Modification { .. } = item? {
+            unstaged = true;
+            break;
+        }
+    }
+    if (staged || unstaged) && !opts.autostash {
+        if unstaged {
+            println!("error: cannot rebase: You have unstaged changes.");
+        } else {
+            println!("error: cannot rebase: Your index contains uncommitted changes.");
+        }
+        println!("error: Please commit or stash them.");
+        return Ok(());
+    }
+
+    // Commits that are in `upstream` already are left out, as are merges since the history is made linear.
+    let mut excluded = HashSet::new();
+    for info in repo.rev_walk([upstream]).all()? {
+        excluded.insert(info?.id);
+    }
+    let mut commits = Vec::new();
+    let mut has_merges = false;
+    for info in repo.rev_walk([head]).all()? {
+        let info = info?;
+        if excluded.contains(&info.id) {
+            continue;
+        }
+        if info.parent_ids.len() > 1 {
+            has_merges = true;
+            continue;
+        }
+        commits.push(info.id);
+    }
+    commits.reverse();
+
+    let first_parent = match commits.first() {
+        Some(id) => repo
+            .find_commit(*id)?
+            .parent_ids()
+            .next()
+            .map(|id| id.detach()),
+        None => Some(head),
+    };
+    if !has_merges && first_parent == Some(onto) {
+        match &head_name {
+            Some(name) => println!("Current branch {} is up to date.", name.shorten()),
+            None => println!("HEAD is up to date."),
+        }
+        return Ok(());
+    }
+
+    if !opts.no_verify {
+        let args: Vec<&str> = opts.upstream.as_deref().into_iter().collect();
+        if !run_hook(&repo, "pre-rebase", &args, None)? {
+            println!("fatal: The pre-rebase hook refused to rebase.");
+            return Ok(());
+        }
+    }
+
+    let dir = state_dir(&repo);
+    std::fs::create_dir_all(&dir)?;
+    if staged || unstaged {
+        if let Some(stash) = create(&repo, &[], Some("autostash"), false)? {
+            reset_stashed(&repo, &[], &stash)?;
+            std::fs::write(dir.join("autostash"), format!("{}\n", stash.id))?;
+            println!("Created autostash: {}", stash.id.to_hex_with_len(7));
+        }
+    }
+
+    let mut todo = String::new();
+    for id in &commits {
+        todo.push_str(&format!("pick {} {}\n", id, summary(&repo, *id)?));
+    }
+    if commits.is_empty() {
+        todo.push_str("noop\n");
+    }
+    std::fs::write(
+        dir.join("head-name"),
+        match &head_name {
+            Some(name) => format!("{}\n", name.as_bstr()),
+            None => "detached HEAD\n".to_string(),
+        },
+    )?;
+    std::fs::write(dir.join("onto"), format!("{}\n", onto))?;
+    std::fs::write(dir.join("orig-head"), format!("{}\n", head))?;
+    std::fs::write(dir.join("git-rebase-todo"), todo)?;
+    std::fs::write(dir.join("done"), "")?;
+    std::fs::write(dir.join("end"), format!("{}\n", commits.len().max(1)))?;
+    std::fs::write(dir.join("msgnum"), "0\n")?;
+    std::fs::write(repo.path().join("ORIG_HEAD"), format!("{}\n", head))?;
+
+    checkout_tree(&repo, repo.find_commit(onto)?.tree_id()?.detach())?;
+    let onto_name = opts.onto.as_deref().or(opts.upstream.as_deref());
+    detach_head(
+        &repo,
+        onto,
+        &format!(
+            "rebase (start): checkout {}",
+            onto_name.map_or(onto.to_string(), str::to_string)
+        ),
+    )?;
+    run_hook(
+        &repo,
+        "post-checkout",
+        &[&head.to_string(), &onto.to_string(), "1"],
+        None,
+    )?;
+
+    run_todo(&repo)
+}
+
+/// Commit the resolved conflicts of the commit that stopped and carry on with the rest of the todo list.
+pub fn resume() -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    if !in_progress(&repo) {
+        println!("fatal: No rebase in progress?");
+        return Ok(());
+    }
+
+    let index = repo.index_or_empty()?;
+    if index.entries().iter().any(|entry| entry.stage_raw() != 0) {
+        println!("error: Committing is not possible because you have unmerged files.");
+        println!("hint: Fix them up in the work tree, and then use 'git add/rm <file>'");
+        println!("hint: as appropriate to mark resolution and make a commit.");
+        println!("fatal: Exiting because of an unresolved conflict.");
+        return Ok(());
+    }
+
+    // The commit that stopped is committed with its original author, and the message as the user may have edited it.
+    let dir = state_dir(&repo);
+    let stopped = std::fs::read_to_string(dir.join("stopped-sha"))
+        .ok()
+        .and_then(|stopped| resolve_commit(&repo, stopped.trim()));
+    if let Some(stopped) = stopped {
+        let head = repo.head_id()?.detach();
+        let head_tree = repo.find_commit(head)?.tree_id()?.detach();
+        let tree = write_tree(&repo, &index)?;
+        if tree != head_tree {
+            let message = std::fs::read_to_string(repo.path().join("MERGE_MSG"))
+                .or_else(|_| std::fs::read_to_string(dir.join("message")))
+                .map(|message| strip_comments(&message))
+                .unwrap_or_default();
+            let author = repo.find_commit(stopped)?.author()?.to_owned();
+            let commit_id = write_commit_as(&repo, Some(author), &message, tree, [head])?;
+            detach_head(
+                &repo,
+                commit_id,
+                &format!(
+                    "rebase (continue): {}",
+                    message.lines().next().unwrap_or_default()
+                ),
+            )?;
+        }
+    }
+    remove_stop_files(&repo);
+
+    run_todo(&repo)
+}
+
+/// Drop the changes of the commit that stopped and carry on with the rest of the todo list.
+pub fn skip() -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    if !in_progress(&repo) {
+        println!("fatal: No rebase in progress?");
+        return Ok(());
+    }
+
+    checkout_tree(&repo, repo.head_tree_id()?.detach())?;
+    remove_stop_files(&repo);
+    run_todo(&repo)
+}
+
+/// Go back to the branch and commit the rebase started from, leaving the branch untouched.
+pub fn abort() -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    if !in_progress(&repo) {
+        println!("fatal: No rebase in progress?");
+        return Ok(());
+    }
+
+    let dir = state_dir(&repo);
+    let head_name = read_state(&repo, "head-name").unwrap_or_default();
+    let orig_head = read_state(&repo, "orig-head")
+        .and_then(|orig_head| gix::ObjectId::from_hex(orig_head.as_bytes()).ok());
+    if let Some(orig_head) = orig_head {
+        checkout_tree(&repo, repo.find_commit(orig_head)?.tree_id()?.detach())?;
+        if head_name.starts_with("refs/") {
+            set_symbolic_ref(
+                &repo,
+                "HEAD",
+                head_name.as_str().into(),
+                &format!("rebase (abort): returning to {}", head_name),
+            )?;
+        } else {
+            detach_head(
+                &repo,
+                orig_head,
+                &format!("rebase (abort): returning to {}", orig_head),
+            )?;
+        }
+    }
+
+    let autostash = read_state(&repo, "autostash");
+    remove_stop_files(&repo);
+    std::fs::remove_dir_all(dir)?;
+    if let Some(autostash) = autostash {
+        apply_autostash(&repo, &autostash)?;
+    }
+    Ok(())
+}
+
+fn open_repo() -> Option<gix::Repository> {
+    match gix::discover(".") {
+        Ok(repo) => Some(repo),
+        Err(_) => {
+            println!("fatal: not a git repository (or any of the parent directories): .git");
+            None
+        }
+    }
+}
+
+fn state_dir(repo: &gix::Repository) -> PathBuf {
+    repo.path().join("rebase-merge")
+}
+
+fn in_progress(repo: &gix::Repository) -> bool {
+    state_dir(repo).is_dir()
+}
+
+/// The trimmed content of the state file `name`, if it exists.
+fn read_state(repo: &gix::Repository, name: &str) -> Option<String> {
+    std::fs::read_to_string(state_dir(repo).join(name))
+        .ok()
+        .map(|content| content.trim().to_string())
+}
+
+fn remove_stop_files(repo: &gix::Repository) {
+    for file in STOP_FILES {
+        std::fs::remove_file(state_dir(repo).join(file)).ok();
+    }
+    for file in ["REBASE_HEAD", "MERGE_MSG"] {
+        std::fs::remove_file(repo.path().join(file)).ok();
+    }
+}
+
+/// The commit `spec` refers to, peeled from tags.
+fn resolve_commit(repo: &gix::Repository, spec: &str) -> Option<gix::ObjectId> {
+    let object = rev_parse_single(repo, spec).ok()?.object().ok()?;
+    object.peel_to_commit().ok().map(|commit| commit.id)
+}
+
+fn summary(repo: &gix::Repository, id: gix::ObjectId) -> Result<BString> {
+    let commit = repo.find_commit(id)?;
+    Ok(commit
+        .message_raw_sloppy()
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .into())
+}
+
+fn print_no_upstream() {
+    println!("There is no tracking information for the current branch.");
+    println!("Please specify which branch you want to rebase against.");
+    println!("See git-rebase(1) for details.");
+    println!();
+    println!("    git rebase '<branch>'");
+    println!();
+    println!("If you wish to set tracking information for this branch you can do so with:");
+    println!();
+    println!("    git branch --set-upstream-to=<remote>/<branch> <branch>");
+    println!();
+}
+
+/// Parse a line of the todo list, returning `None` for empty lines and comments.
+fn parse_instruction(repo: &gix::Repository, line: &str) -> Option<Result<Instruction, String>> {
+    let mut fields = line.split_whitespace();
+    let command = fields.next().filter(|command| !command.starts_with('#'))?;
+    Some(match command {
+        "pick" | "p" => match fields.next().and_then(|rev| resolve_commit(repo, rev)) {
+            Some(id) => Ok(Instruction::Pick(id)),
+            None => Err(format!("invalid line: {}", line)),
+        },
+        "drop" | "d" | "noop" => Ok(Instruction::Drop),
+        "break" | "b" => Ok(Instruction::Break),
+        "reword" | "r" | "edit" | "e" | "squash" | "s" | "fixup" | "f" | "exec" | "x" | "label"
+        | "l" | "reset" | "t" | "merge" | "m" | "update-ref" | "u" => {
+            Err(format!("'{}' is not supported yet: {}", command, line))
+        }
+        _ => Err(format!("invalid command '{}': {}", command, line)),
+    })
+}
+
+/// Execute the remaining instructions of `git-rebase-todo`, moving each to `done` as it starts, and finish when none are left.
+fn run_todo(repo: &gix::Repository) -> Result<()> {
+    let dir = state_dir(repo);
+    let todo = std::fs::read_to_string(dir.join("git-rebase-todo")).unwrap_or_default();
+    let mut lines: Vec<String> = todo.lines().map(str::to_string).collect();
+
+    // The whole list is checked first so a typo doesn't stop the rebase halfway through.
+    for line in &lines {
+        if let Some(Err(err)) = parse_instruction(repo, line) {
+            println!("error: {}", err);
+            println!("You can fix this with 'git rebase --edit-todo' and then run 'git rebase --continue'.");
+            println!("Or you can abort the rebase with 'git rebase --abort'.");
+            return Ok(());
+        }
+    }
+
+    let mut msgnum: usize = read_state(repo, "msgnum")
+        .and_then(|msgnum| msgnum.parse().ok())
+        .unwrap_or_default();
+    while !lines.is_empty() {
+        let line = lines.remove(0);
+        let Some(Ok(instruction)) = parse_instruction(repo, &line) else {
+            continue;
+        };
+        msgnum += 1;
+        let mut done = std::fs::read_to_string(dir.join("done")).unwrap_or_default();
+        done.push_str(&format!("{}\n", line));
+        std::fs::write(dir.join("done"), done)?;
+        std::fs::write(dir.join("git-rebase-todo"), join_lines(&lines))?;
+        std::fs::write(dir.join("msgnum"), format!("{}\n", msgnum))?;
+
+        let outcome = match instruction {
+            Instruction::Pick(id) => pick(repo, id, &line)?,
+            Instruction::Drop => Outcome::Done,
+            Instruction::Break => {
+                let head = repo.head_id()?.detach();
+                println!(
+                    "Stopped at {}...  {}",
+                    head.to_hex_with_len(7),
+                    summary(repo, head)?
+                );
+                Outcome::Stopped
+            }
+        };
+        if let Outcome::Stopped = outcome {
+            return Ok(());
+        }
+    }
+    finish(repo)
+}
+
+fn join_lines(lines: &[String]) -> String {
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+/// Apply the changes of `id` on top of `HEAD` and commit them with the original author and message.
+fn pick(repo: &gix::Repository, id: gix::ObjectId, line: &str) -> Result<Outcome> {
+    let commit = repo.find_commit(id)?;
+    let subject = summary(repo, id)?;
+    let parents: Vec<_> = commit.parent_ids().map(|id| id.detach()).collect();
+    let head = repo.head_id()?.detach();
+    let head_tree = repo.find_commit(head)?.tree_id()?.detach();
+    let commit_tree = commit.tree_id()?.detach();
+
+    // A commit that is already on top of `HEAD` is kept as it is, like git does without `--force-rebase`.
+    if parents.first() == Some(&head) {
+        checkout_tree(repo, commit_tree)?;
+        detach_head(repo, id, &format!("rebase (pick): {}", subject))?;
+        return Ok(Outcome::Done);
+    }
+    let parent_tree = match parents.as_slice() {
+        [] => gix::ObjectId::empty_tree(repo.object_hash()),
+        [parent] => repo.find_commit(*parent)?.tree_id()?.detach(),
+        _ => {
+            println!(
+                "error: commit {} is a merge but no -m option was given.",
+                id
+            );
+            println!(
+                "error: could not apply {}... {}",
+                id.to_hex_with_len(7),
+                subject
+            );
+            return Ok(Outcome::Stopped);
+        }
+    };
+
+    let label = format!("{} ({})", id.to_hex_with_len(7), subject);
+    let parent_label = format!("parent of {}", label);
+    let Some(merged) = merge_into_worktree(
+        repo,
+        parent_tree,
+        head_tree,
+        commit_tree,
+        Labels {
+            base: &parent_label,
+            ours: "HEAD",
+            theirs: &label,
+        },
+    )?
+    else {
+        // Nothing was changed, so the instruction is put back to be tried again.
+        let dir = state_dir(repo);
+        let todo = std::fs::read_to_string(dir.join("git-rebase-todo")).unwrap_or_default();
+        std::fs::write(dir.join("git-rebase-todo"), format!("{}\n{}", line, todo))?;
+        let done = std::fs::read_to_string(dir.join("done")).unwrap_or_default();
+        let done: Vec<String> = done.lines().map(str::to_string).collect();
+        std::fs::write(
+            dir.join("done"),
+            join_lines(&done[..done.len().saturating_sub(1)]),
+        )?;
+        println!("Could not execute the todo command");
+        println!();
+        println!("    {}", line);
+        println!();
+        println!("It has been rescheduled; To edit the command before continuing, please");
+        println!("edit the todo list first:");
+        println!();
+        println!("    git rebase --edit-todo");
+        println!("    git rebase --continue");
+        return Ok(Outcome::Stopped);
+    };
+    merged.index.write(Default::default())?;
+
+    let message = commit.message_raw_sloppy().to_str_lossy().into_owned();
+    let author = commit.author()?.to_owned();
+    if !merged.conflicts.is_empty() {
+        let dir = state_dir(repo);
+        let mut merge_message = format!("{}\n# Conflicts:\n", message.trim_end());
+        for path in &merged.conflicts {
+            merge_message.push_str(&format!("#\t{}\n", path));
+        }
+        std::fs::write(repo.path().join("MERGE_MSG"), merge_message)?;
+        std::fs::write(repo.path().join("REBASE_HEAD"), format!("{}\n", id))?;
+        std::fs::write(dir.join("stopped-sha"), format!("{}\n", id))?;
+        std::fs::write(dir.join("message"), &message)?;
+        std::fs::write(dir.join("author-script"), author_script(&author))?;
+        println!(
+            "error: could not apply {}... {}",
+            id.to_hex_with_len(7),
+            subject
+        );
+        println!("hint: Resolve all conflicts manually, mark them as resolved with");
+        println!("hint: \"git add/rm <conflicted_files>\", then run \"git rebase --continue\".");
+        println!("hint: You can instead skip this commit: run \"git rebase --skip\".");
+        println!("hint: To abort and get back to the state before \"git rebase\", run \"git rebase --abort\".");
+        println!("Could not apply {}... {}", id.to_hex_with_len(7), subject);
+        return Ok(Outcome::Stopped);
+    }
+
+    if merged.tree_id == head_tree {
+        println!(
+            "dropping {} {} -- patch contents already upstream",
+            id, subject
+        );
+        return Ok(Outcome::Done);
+    }
+    let commit_id = write_commit_as(repo, Some(author), &message, merged.tree_id, [head])?;
+    detach_head(repo, commit_id, &format!("rebase (pick): {}", subject))?;
+    Ok(Outcome::Done)
+}
+
+/// The author of a stopped commit the way git records it in `author-script`, as shell variable assignments.
+fn author_script(author: &gix::actor::Signature) -> String {
+    let quote = |value: &str| format!("'{}'", value.replace('\'', "'\\''"));
+    format!(
+        "GIT_AUTHOR_NAME={}\nGIT_AUTHOR_EMAIL={}\nGIT_AUTHOR_DATE={}\n",
+        quote(&author.name.to_str_lossy()),
+        quote(&author.email.to_str_lossy()),
+        quote(&format!("@{}", author.time.to_bstring())),
+    )
+}
+
+/// Point the branch that was rebased at the result, check it out again and clean up.
+fn finish(repo: &gix::Repository) -> Result<()> {
+    let head_name = read_state(repo, "head-name").unwrap_or_default();
+    let onto = read_state(repo, "onto").unwrap_or_default();
+    let head = repo.head_id()?.detach();
+    if head_name.starts_with("refs/") {
+        repo.reference(
+            head_name.as_str(),
+            head,
+            PreviousValue::Any,
+            format!("rebase (finish): {} onto {}", head_name, onto),
+        )?;
+        set_symbolic_ref(
+            repo,
+            "HEAD",
+            head_name.as_str().into(),
+            &format!("rebase (finish): returning to {}", head_name),
+        )?;
+    }
+
+    let autostash = read_state(repo, "autostash");
+    remove_stop_files(repo);
+    std::fs::remove_dir_all(state_dir(repo))?;
+    if let Some(autostash) = autostash {
+        apply_autostash(repo, &autostash)?;
+    }
+    if head_name.starts_with("refs/") {
+        println!("Successfully rebased and updated {}.", head_name);
+    } else {
+        println!("Successfully rebased and updated detached HEAD.");
+    }
+    Ok(())
+}
+
+/// Apply the changes stashed when the rebase started, keeping them in the stash if they don't apply cleanly.
+fn apply_autostash(repo: &gix::Repository, autostash: &str) -> Result<()> {
+    let id = gix::ObjectId::from_hex(autostash.as_bytes())?;
+    match apply_commit(repo, id, false, true)? {
+        Applied::Clean => println!("Applied autostash."),
+        Applied::Conflicts | Applied::Refused => {
+            update_stash_ref(repo, id, "autostash")?;
+            println!("Applying autostash resulted in conflicts.");
+            println!("Your changes are safe in the stash.");
+            println!("You can run \"git stash pop\" or \"git stash drop\" at any time.");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use anyhow::Result;
+use gix::bstr::BStr;
+use gix::refs::transaction::{Change, LogChange, PreviousValue, RefEdit, RefLog};
+
+/// Make the reference `name` point to `target` symbolically, like `git symbolic-ref` does
+pub fn set_symbolic_ref(
+    repo: &gix::Repository,
+    name: &str,
+    target: &BStr,
+    message: &str,
+) -> Result<()> {
+    repo.edit_reference(RefEdit {
+        change: Change::Update {
+            log: LogChange {
+                mode: RefLog::AndReference,
+                force_create_reflog: false,
+                message: message.into(),
+            },
+            expected: PreviousValue::Any,
+            new: gix::refs::Target::Symbolic(target.to_owned().try_into()?),
+        },
+        name: name.try_into()?,
+        deref: false,
+    })?;
+    Ok(())
+}
+
+/// Point `HEAD` directly at `id`, detaching it from any branch
+pub fn detach_head(repo: &gix::Repository, id: gix::ObjectId, message: &str) -> Result<()> {
+    repo.reference("HEAD", id, PreviousValue::Any, message)?;
+    Ok(())
+}
+
+/// Point `HEAD` at `id`, moving the branch it refers to along unless it's detached
+pub fn move_head(repo: &gix::Repository, id: gix::ObjectId, message: &str) -> Result<()> {
+    repo.edit_reference(RefEdit {
+        change: Change::Update {
+            log: LogChange {
+                mode: RefLog::AndReference,
+                force_create_reflog: false,
+                message: message.into(),
+            },
+            expected: PreviousValue::Any,
+            new: gix::refs::Target::Object(id),
+        },
+        name: "HEAD".try_into()?,
+        deref: true,
+    })?;
+    Ok(())
+}
@@ -0,0 +1,276 @@
+use anyhow::Result;
+use gix::bstr::ByteSlice;
+use std::collections::{BTreeSet, HashSet};
+use std::path::PathBuf;
+
+use super::revision_utils::parse_date;
+
+/// Print the reflog of `reference`, or of `HEAD`, most recent entry first as `<name>@{<n>}` like it's looked up.
+pub fn show(reference: Option<String>) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    let name = reference.as_deref().unwrap_or("HEAD");
+    let Ok(found) = repo.find_reference(name) else {
+        println!(
+            "fatal: ambiguous argument '{}': unknown revision or path not in the working tree.",
+            name
+        );
+        return Ok(());
+    };
+    let mut log = found.log_iter();
+    let Some(lines) = log.rev()? else {
+        return Ok(());
+    };
+    for (position, line) in lines.enumerate() {
+        let line = line?;
+        println!(
+            "{} {}@{{{}}}: {}",
+            line.new_oid.to_hex_with_len(7),
+            name,
+            position,
+            line.message
+        );
+    }
+    Ok(())
+}
+
+/// Remove the entries older than `expire` from the reflogs of `references`, or of all references with `all`,
+/// and those older than `expire_unreachable` if their commit can't be reached from the reference anymore.
+///
+/// Unset times are read from `gc.reflogExpire` and `gc.reflogExpireUnreachable`, defaulting to 90 and 30 days.
+pub fn expire(
+    references: Vec<String>,
+    all: bool,
+    expire: Option<String>,
+    expire_unreachable: Option<String>,
+) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    let Some((expire, expire_unreachable)) =
+        expiry_times(&repo, expire.as_deref(), expire_unreachable.as_deref())
+    else {
+        return Ok(());
+    };
+    let mut names = Vec::new();
+    for reference in &references {
+        match repo.find_reference(reference.as_str()) {
+            Ok(found) => names.push(found.name().as_bstr().to_string()),
+            Err(_) => {
+                println!("error: reflog could not be found: '{}'", reference);
+                std::process::exit(1);
+            }
+        }
+    }
+    if all {
+        names.extend(all_reflogs(&repo));
+    }
+    for name in names {
+        expire_reflog(&repo, &name, expire, expire_unreachable)?;
+    }
+    Ok(())
+}
+
+/// Expire the reflogs of all references like `reflog expire --all` does with the configured times, for `gc`.
+pub(super) fn expire_all(repo: &gix::Repository) -> Result<()> {
+    let Some((expire, expire_unreachable)) = expiry_times(repo, None, None) else {
+        return Ok(());
+    };
+    for name in all_reflogs(repo) {
+        expire_reflog(repo, &name, expire, expire_unreachable)?;
+    }
+    Ok(())
+}
+
+/// Remove the reflog entries given like `HEAD@{2}`, keeping the references where they are.
+pub fn delete(entries: Vec<String>) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    let mut failed = false;
+    for entry in &entries {
+        let parsed = entry.strip_suffix('}').and_then(|entry| {
+            let (name, position) = entry.rsplit_once("@{")?;
+            Some((name, position.parse::<usize>().ok()?))
+        });
+        let Some((name, position)) = parsed else {
+            println!("error: '{}' is not a reflog", entry);
+            failed = true;
+            continue;
+        };
+        let name = if name.is_empty() { "HEAD" } else { name };
+        let path = match repo.find_reference(name) {
+            Ok(found) => reflog_path(&repo, &found.name().as_bstr().to_string()),
+            Err(_) => {
+                println!("error: no reflog for '{}'", entry);
+                failed = true;
+                continue;
+            }
+        };
+        let Ok(log) = std::fs::read(&path) else {
+            println!("error: no reflog for '{}'", entry);
+            failed = true;
+            continue;
+        };
+        let mut lines: Vec<&[u8]> = log.lines_with_terminator().collect();
+        // The reflog is oldest first, while positions count from the most recent entry.
+        if position < lines.len() {
+            lines.remove(lines.len() - 1 - position);
+            std::fs::write(&path, lines.concat())?;
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// The times before which entries expire, and before which unreachable entries do, with `None` meaning never.
+///
+/// Returns `None` after reporting a time that can't be parsed.
+fn expiry_times(
+    repo: &gix::Repository,
+    expire: Option<&str>,
+    expire_unreachable: Option<&str>,
+) -> Option<(Option<i64>, Option<i64>)> {
+    let config = repo.config_snapshot();
+    let mut times = Vec::new();
+    for (given, key, default, option) in [
+        (expire, "gc.reflogExpire", "90.days.ago", "--expire"),
+        (
+            expire_unreachable,
+            "gc.reflogExpireUnreachable",
+            "30.days.ago",
+            "--expire-unreachable",
+        ),
+    ] {
+        let value = match given {
+            Some(value) => value.to_string(),
+            None => config
+                .string(key)
+                .map_or_else(|| default.to_string(), |value| value.to_string()),
+        };
+        let time = match value.as_str() {
+            "never" | "false" => None,
+            "all" | "now" => Some(i64::MAX),
+            date => match parse_date(date) {
+                Some(time) => Some(time.seconds),
+                None => {
+                    println!("fatal: invalid timestamp '{}' given to '{}'", value, option);
+                    return None;
+                }
+            },
+        };
+        times.push(time);
+    }
+    Some((times[0], times[1]))
+}
+
+/// Rewrite the reflog of the reference `name` without the entries that expired.
+fn expire_reflog(
+    repo: &gix::Repository,
+    name: &str,
+    expire: Option<i64>,
+    expire_unreachable: Option<i64>,
+) -> Result<()> {
+    let path = reflog_path(repo, name);
+    let Ok(log) = std::fs::read(&path) else {
+        return Ok(());
+    };
+    // The commits reachable from the reference, only walked once an entry could expire for not being one of them.
+    let mut reachable: Option<HashSet<gix::ObjectId>> = None;
+    let mut kept = Vec::new();
+    let mut expired = false;
+    for line in log.lines_with_terminator() {
+        let Ok(entry) = gix::refs::file::log::LineRef::from_bytes(line) else {
+            kept.push(line);
+            continue;
+        };
+        let time = entry.signature.time.seconds;
+        let mut expires = expire.is_some_and(|expire| time < expire);
+        // Stash entries aren't ancestors of each other, so they would all be unreachable.
+        if !expires
+            && name != "refs/stash"
+            && expire_unreachable.is_some_and(|expire| time < expire)
+        {
+            if reachable.is_none() {
+                reachable = Some(reachable_commits(repo, name)?);
+            }
+            expires = reachable
+                .as_ref()
+                .is_some_and(|reachable| !reachable.contains(&entry.new_oid()));
+        }
+        if expires {
+            expired = true;
+        } else {
+            kept.push(line);
+        }
+    }
+    if expired {
+        std::fs::write(&path, kept.concat())?;
+    }
+    Ok(())
+}
+
+/// All commits reachable from where the reference `name` points now.
+fn reachable_commits(repo: &gix::Repository, name: &str) -> Result<HashSet<gix::ObjectId>> {
+    let mut reachable = HashSet::new();
+    let Some(tip) = repo
+        .try_find_reference(name)?
+        .and_then(|mut reference| reference.peel_to_id_in_place().ok())
+    else {
+        return Ok(reachable);
+    };
+    if let Ok(walk) = repo.rev_walk([tip]).all() {
+        for info in walk {
+            reachable.insert(info?.id);
+        }
+    }
+    Ok(reachable)
+}
+
+/// The full names of all references that have a reflog.
+fn all_reflogs(repo: &gix::Repository) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    for dir in [repo.common_dir(), repo.path()] {
+        let logs = dir.join("logs");
+        for entry in walkdir::WalkDir::new(&logs)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_file())
+        {
+            if let Ok(name) = entry.path().strip_prefix(&logs) {
+                names.insert(
+                    gix::path::to_unix_separators_on_windows(gix::path::into_bstr(name))
+                        .to_string(),
+                );
+            }
+        }
+    }
+    names
+}
+
+/// The reflog of the reference `name`, which is in the directory of the worktree for `HEAD` and other per-worktree references.
+fn reflog_path(repo: &gix::Repository, name: &str) -> PathBuf {
+    let per_worktree = !name.starts_with("refs/")
+        || ["refs/worktree/", "refs/bisect/", "refs/rewritten/"]
+            .iter()
+            .any(|prefix| name.starts_with(prefix));
+    let dir = if per_worktree {
+        repo.path()
+    } else {
+        repo.common_dir()
+    };
+    dir.join("logs").join(name)
+}
+
+fn open_repo() -> Option<gix::Repository> {
+    match gix::discover(".") {
+        Ok(repo) => Some(repo),
+        Err(_) => {
+            println!("fatal: not a git repository (or any of the parent directories): .git");
+            None
+        }
+    }
+}
@@ -1,6 +1,45 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use gix::bstr::{BStr, BString, ByteSlice};
+use gix::refs::transaction::{Change, LogChange, PreviousValue, RefEdit, RefLog};
+use gix::remote::Direction;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::path::Path;
 
+use super::config_utils::{open_local_config, set_values, write_local_config};
+
+/// Which direction of a remote mirrors the local repository, as set up with `remote add --mirror`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirror {
+    /// Fetch all refs of the remote into the same refs locally
+    Fetch,
+    /// Push all local refs to the remote, deleting those that don't exist locally
+    Push,
+    /// Both of the above, which is what a plain `--mirror` does
+    Both,
+}
+
+impl std::str::FromStr for Mirror {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fetch" => Ok(Mirror::Fetch),
+            "push" => Ok(Mirror::Push),
+            _ => Err(format!("unknown mirror argument: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AddOptions {
+    /// Fetch from the remote right after adding it
+    pub fetch: bool,
+    /// Only track these branches instead of all of them
+    pub track: Vec<String>,
+    pub mirror: Option<Mirror>,
+}
+
 pub fn run(_repository: &Path, verbose: bool) -> Result<()> {
     let repo = match gix::discover(".") {
         Ok(repo) => repo,
@@ -43,3 +82,694 @@ pub fn run(_repository: &Path, verbose: bool) -> Result<()> {
 
     Ok(())
 }
+
+/// Add the remote `name` pointing to `url`, tracking all of its branches unless told otherwise.
+pub fn add(name: String, url: String, opts: AddOptions) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    if !is_valid_name(&name) {
+        println!("fatal: '{}' is not a valid remote name", name);
+        return Ok(());
+    }
+    if remote_exists(&repo, &name) {
+        println!("error: remote {} already exists.", name);
+        return Ok(());
+    }
+    // A push mirror fetches nothing, so there are no branches it could track.
+    if opts.mirror == Some(Mirror::Push) && !opts.track.is_empty() {
+        println!("fatal: specifying branches to track makes sense only with fetch mirrors");
+        return Ok(());
+    }
+
+    let fetch_specs: Vec<BString> = match opts.mirror {
+        Some(Mirror::Push) => Vec::new(),
+        Some(Mirror::Fetch | Mirror::Both) if opts.track.is_empty() => {
+            vec!["+refs/*:refs/*".into()]
+        }
+        Some(Mirror::Fetch | Mirror::Both) => opts
+            .track
+            .iter()
+            .map(|branch| format!("+refs/heads/{branch}:refs/heads/{branch}").into())
+            .collect(),
+        None if opts.track.is_empty() => {
+            vec![format!("+refs/heads/*:refs/remotes/{name}/*").into()]
+        }
+        None => opts
+            .track
+            .iter()
+            .map(|branch| format!("+refs/heads/{branch}:refs/remotes/{name}/{branch}").into())
+            .collect(),
+    };
+
+    let subsection: &BStr = name.as_str().into();
+    let mut config = open_local_config(&repo)?;
+    config.set_raw_value_by("remote", Some(subsection), "url", url.as_str())?;
+    set_values(
+        &mut config,
+        "remote",
+        Some(subsection),
+        "fetch",
+        &fetch_specs,
+    )?;
+    if matches!(opts.mirror, Some(Mirror::Push | Mirror::Both)) {
+        config.set_raw_value_by("remote", Some(subsection), "mirror", "true")?;
+    }
+    write_local_config(&repo, &config)?;
+
+    if opts.fetch {
+        println!("Updating {}", name);
+        super::fetch::run(Path::new("."), Some(name), Vec::new(), Default::default())?;
+    }
+    Ok(())
+}
+
+/// Remove the remote `name` along with its remote-tracking refs and the configuration of branches tracking it.
+pub fn remove(name: String) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    if !remote_exists(&repo, &name) {
+        println!("error: No such remote: '{}'", name);
+        return Ok(());
+    }
+    let remote = repo.find_remote(name.as_str())?;
+
+    let edits: Vec<RefEdit> = tracking_refs(&repo, &remote)?
+        .into_iter()
+        .map(|reference| RefEdit {
+            change: Change::Delete {
+                expected: PreviousValue::MustExistAndMatch(reference.target),
+                log: RefLog::AndReference,
+            },
+            name: reference.name,
+            deref: false,
+        })
+        .collect();
+
+    let subsection: &BStr = name.as_str().into();
+    let mut config = open_local_config(&repo)?;
+    while config.remove_section("remote", Some(subsection)).is_some() {}
+    for (branch, _merge) in branches_tracking(&config, &name) {
+        if let Ok(mut section) = config.section_mut("branch", Some(branch.as_bstr())) {
+            section.remove("remote");
+            section.remove("merge");
+        }
+    }
+    write_local_config(&repo, &config)?;
+
+    repo.edit_references(edits)?;
+    Ok(())
+}
+
+/// Rename the remote `old` to `new`, moving its remote-tracking refs and rewriting the refspecs and
+/// branch configuration that refer to it.
+pub fn rename(old: String, new: String) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    if !remote_exists(&repo, &old) {
+        println!("error: No such remote: '{}'", old);
+        return Ok(());
+    }
+    if !is_valid_name(&new) {
+        println!("fatal: '{}' is not a valid remote name", new);
+        return Ok(());
+    }
+    if remote_exists(&repo, &new) {
+        println!("error: remote {} already exists.", new);
+        return Ok(());
+    }
+
+    let old_subsection: &BStr = old.as_str().into();
+    let new_subsection: &BStr = new.as_str().into();
+    let mut config = open_local_config(&repo)?;
+    if config.section_mut("remote", Some(old_subsection)).is_err() {
+        println!(
+            "error: Could not rename config section 'remote.{}' to 'remote.{}'",
+            old, new
+        );
+        return Ok(());
+    }
+    while config
+        .rename_section(
+            "remote",
+            Some(old_subsection),
+            "remote",
+            Some(Cow::Owned(new_subsection.to_owned())),
+        )
+        .is_ok()
+    {}
+
+    let old_tracking = format!(":refs/remotes/{old}/");
+    let new_tracking = format!(":refs/remotes/{new}/");
+    let fetch_specs: Vec<BString> = config
+        .raw_values_by("remote", Some(new_subsection), "fetch")
+        .unwrap_or_default()
+        .into_iter()
+        .map(|spec| spec.replace(&old_tracking, &new_tracking).into())
+        .collect();
+    if !fetch_specs.is_empty() {
+        set_values(
+            &mut config,
+            "remote",
+            Some(new_subsection),
+            "fetch",
+            &fetch_specs,
+        )?;
+    }
+    for (branch, _merge) in branches_tracking(&config, &old) {
+        config.set_raw_value_by("branch", Some(branch.as_bstr()), "remote", new.as_str())?;
+    }
+    if config
+        .raw_value("remote.pushDefault")
+        .is_ok_and(|remote| remote.as_bytes() == old.as_bytes())
+    {
+        config.set_raw_value_by("remote", None, "pushDefault", new.as_str())?;
+    }
+    write_local_config(&repo, &config)?;
+
+    let old_prefix = format!("refs/remotes/{old}/");
+    let new_prefix = format!("refs/remotes/{new}/");
+    let renamed = |name: &BStr| -> Result<Option<gix::refs::FullName>> {
+        Ok(match name.strip_prefix(old_prefix.as_bytes()) {
+            Some(rest) => Some(format!("{}{}", new_prefix, rest.as_bstr()).try_into()?),
+            None => None,
+        })
+    };
+    let mut edits = Vec::new();
+    for reference in repo.references()?.all()? {
+        let reference = reference.map_err(|e| anyhow!("{}", e))?.detach();
+        let Some(new_name) = renamed(reference.name.as_bstr())? else {
+            continue;
+        };
+        // Symbolic refs like `refs/remotes/<old>/HEAD` have to follow the refs they point to.
+        let target = match &reference.target {
+            gix::refs::Target::Symbolic(target) => match renamed(target.as_bstr())? {
+                Some(target) => gix::refs::Target::Symbolic(target),
+                None => reference.target.clone(),
+            },
+            gix::refs::Target::Object(_) => reference.target.clone(),
+        };
+        let message = format!(
+            "remote: renamed {} to {}",
+            reference.name.as_bstr(),
+            new_name.as_bstr()
+        );
+        edits.push(RefEdit {
+            change: Change::Delete {
+                expected: PreviousValue::MustExistAndMatch(reference.target),
+                log: RefLog::AndReference,
+            },
+            name: reference.name,
+            deref: false,
+        });
+        edits.push(RefEdit {
+            change: Change::Update {
+                log: LogChange {
+                    mode: RefLog::AndReference,
+                    force_create_reflog: false,
+                    message: message.into(),
+                },
+                expected: PreviousValue::MustNotExist,
+                new: target,
+            },
+            name: new_name,
+            deref: false,
+        });
+    }
+    repo.edit_references(edits)?;
+    Ok(())
+}
+
+/// Change the URLs of the remote `name`.
+///
+/// With `add` the URL is appended, with `delete` all URLs containing `new_url` are removed, and otherwise
+/// the URLs containing `old_url`, or all of them if it's unset, are replaced with `new_url`.
+pub fn set_url(
+    name: String,
+    new_url: String,
+    old_url: Option<String>,
+    push: bool,
+    add: bool,
+    delete: bool,
+) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    if !remote_exists(&repo, &name) {
+        println!("error: No such remote '{}'", name);
+        return Ok(());
+    }
+
+    let key = if push { "pushurl" } else { "url" };
+    let subsection: &BStr = name.as_str().into();
+    let mut config = open_local_config(&repo)?;
+    let mut urls: Vec<BString> = config
+        .raw_values_by("remote", Some(subsection), key)
+        .unwrap_or_default()
+        .into_iter()
+        .map(Cow::into_owned)
+        .collect();
+
+    if add {
+        urls.push(new_url.into());
+    } else if delete {
+        let before = urls.len();
+        urls.retain(|url| !url.contains_str(&new_url));
+        if urls.len() == before {
+            println!("fatal: No such URL found: {}", new_url);
+            return Ok(());
+        }
+        if urls.is_empty() && !push {
+            println!("fatal: Will not delete all non-push URLs");
+            return Ok(());
+        }
+    } else if let Some(old_url) = old_url {
+        let mut replaced = false;
+        for url in urls.iter_mut().filter(|url| url.contains_str(&old_url)) {
+            *url = new_url.as_str().into();
+            replaced = true;
+        }
+        if !replaced {
+            println!("fatal: No such URL found: {}", old_url);
+            return Ok(());
+        }
+    } else {
+        urls = vec![new_url.into()];
+    }
+
+    set_values(&mut config, "remote", Some(subsection), key, &urls)?;
+    write_local_config(&repo, &config)
+}
+
+/// Print the first URL of the remote `name`, or all of them with `all`.
+pub fn get_url(name: String, push: bool, all: bool) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    if !remote_exists(&repo, &name) {
+        println!("error: No such remote '{}'", name);
+        return Ok(());
+    }
+
+    let config = repo.config_snapshot();
+    let subsection: &BStr = name.as_str().into();
+    let values = |key: &str| {
+        config
+            .plumbing()
+            .raw_values_by("remote", Some(subsection), key)
+            .unwrap_or_default()
+    };
+    // Push URLs default to the fetch URLs if there are none.
+    let mut urls = if push { values("pushurl") } else { Vec::new() };
+    if urls.is_empty() {
+        urls = values("url");
+    }
+
+    let count = if all { urls.len() } else { 1 };
+    for url in urls.iter().take(count) {
+        println!("{}", url);
+    }
+    Ok(())
+}
+
+/// Show information about the remotes in `names`, querying them for their branches unless `no_query` is set.
+pub fn show(names: Vec<String>, no_query: bool) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    if names.is_empty() {
+        for name in repo.remote_names() {
+            println!("{}", name);
+        }
+        return Ok(());
+    }
+
+    for name in names {
+        if !remote_exists(&repo, &name) {
+            println!("error: No such remote '{}'", name);
+            continue;
+        }
+        if let Err(e) = show_remote(&repo, &name, no_query) {
+            println!("fatal: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn show_remote(repo: &gix::Repository, name: &str, no_query: bool) -> Result<()> {
+    let remote = repo.find_remote(name)?;
+    let url = |direction| {
+        remote
+            .url(direction)
+            .map(|url| url.to_bstring())
+            .unwrap_or_else(|| "(no URL)".into())
+    };
+    println!("* remote {}", name);
+    println!("  Fetch URL: {}", url(Direction::Fetch));
+    println!("  Push  URL: {}", url(Direction::Push));
+
+    let tracking: Vec<BString> = tracking_refs(repo, &remote)?
+        .into_iter()
+        .filter(|reference| reference.target.try_id().is_some())
+        .map(|reference| reference.name.as_bstr().to_owned())
+        .collect();
+    let tracking_prefix = format!("refs/remotes/{name}/");
+    let short = |full: &BStr| -> BString {
+        full.strip_prefix(tracking_prefix.as_bytes())
+            .or_else(|| full.strip_prefix(b"refs/heads/"))
+            .unwrap_or(full.as_bytes())
+            .into()
+    };
+
+    if no_query {
+        println!("  HEAD branch: (not queried)");
+        if !tracking.is_empty() {
+            println!(
+                "  Remote {}: (status not queried)",
+                plural(tracking.len(), "branch", "branches")
+            );
+            for name in &tracking {
+                println!("    {}", short(name.as_bstr()));
+            }
+        }
+        print_pull_config(repo, name);
+        println!("  Local refs configured for 'git push' (status not queried):");
+        println!("    (matching) pushes to (matching)");
+        return Ok(());
+    }
+
+    let (ref_map, _handshake) = remote.connect(Direction::Fetch)?.ref_map(
+        gix::progress::Discard,
+        gix::remote::ref_map::Options {
+            prefix_from_spec_as_filter_on_remote: false,
+            ..Default::default()
+        },
+    )?;
+
+    let head = ref_map.remote_refs.iter().find_map(|r| match r {
+        gix::protocol::handshake::Ref::Symbolic {
+            full_ref_name,
+            target,
+            ..
+        }
+        | gix::protocol::handshake::Ref::Unborn {
+            full_ref_name,
+            target,
+        } if full_ref_name == "HEAD" => Some(short(target.as_bstr())),
+        _ => None,
+    });
+    match head {
+        Some(head) => println!("  HEAD branch: {}", head),
+        None => println!("  HEAD branch: (unknown)"),
+    }
+
+    let mut branches = BTreeMap::new();
+    for mapping in &ref_map.mappings {
+        let Some(remote_name) = mapping.remote.as_name() else {
+            continue;
+        };
+        if !remote_name.starts_with(b"refs/heads/") {
+            continue;
+        }
+        let status = match &mapping.local {
+            Some(local) if tracking.contains(local) => "tracked".to_string(),
+            Some(_) => format!("new (next fetch will store in remotes/{})", name),
+            None => continue,
+        };
+        branches.insert(short(remote_name), status);
+    }
+    for local in &tracking {
+        let is_mapped = ref_map
+            .mappings
+            .iter()
+            .any(|mapping| mapping.local.as_ref() == Some(local));
+        if !is_mapped {
+            branches.insert(
+                short(local.as_bstr()),
+                "stale (use 'git remote prune' to remove)".into(),
+            );
+        }
+    }
+    if !branches.is_empty() {
+        println!("  Remote {}:", plural(branches.len(), "branch", "branches"));
+        let width = branches.keys().map(|name| name.len()).max().unwrap_or(0);
+        for (branch, status) in &branches {
+            println!(
+                "    {:<width$} {}",
+                branch.to_str_lossy(),
+                status,
+                width = width
+            );
+        }
+    }
+
+    print_pull_config(repo, name);
+
+    let remote_ids: BTreeMap<&BStr, gix::ObjectId> = ref_map
+        .remote_refs
+        .iter()
+        .filter_map(|r| {
+            let (name, target, peeled) = r.unpack();
+            Some((name, peeled.or(target)?.to_owned()))
+        })
+        .collect();
+    let mut pushes = Vec::new();
+    for reference in repo.references()?.local_branches()? {
+        let reference = reference.map_err(|e| anyhow!("{}", e))?;
+        let Some(local_id) = reference.target().try_id().map(ToOwned::to_owned) else {
+            continue;
+        };
+        let local_name = reference.name().as_bstr().to_owned();
+        let Some(destination) = push_destination(&remote, local_name.as_bstr()) else {
+            continue;
+        };
+        let status = match remote_ids.get(&destination.as_bstr()) {
+            None => "create",
+            Some(remote_id) if *remote_id == local_id => "up to date",
+            Some(remote_id)
+                if repo.has_object(remote_id)
+                    && repo
+                        .merge_base(local_id, *remote_id)
+                        .is_ok_and(|base| base == *remote_id) =>
+            {
+                "fast-forwardable"
+            }
+            Some(_) => "local out of date",
+        };
+        // Without push refspecs only branches that exist on both sides are pushed by default.
+        if status == "create" && remote.refspecs(Direction::Push).is_empty() {
+            continue;
+        }
+        pushes.push((
+            short(local_name.as_bstr()),
+            short(destination.as_bstr()),
+            status,
+        ));
+    }
+    if !pushes.is_empty() {
+        println!(
+            "  Local {} configured for 'git push':",
+            plural(pushes.len(), "ref", "refs")
+        );
+        let width = pushes
+            .iter()
+            .map(|(local, _, _)| local.len())
+            .max()
+            .unwrap_or(0);
+        let dest_width = pushes
+            .iter()
+            .map(|(_, dest, _)| dest.len())
+            .max()
+            .unwrap_or(0);
+        for (local, destination, status) in pushes {
+            println!(
+                "    {:<width$} pushes to {:<dest_width$} ({})",
+                local.to_str_lossy(),
+                destination.to_str_lossy(),
+                status,
+                width = width,
+                dest_width = dest_width,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Delete the remote-tracking refs of the remotes in `names` whose branches vanished on the remote.
+pub fn prune(names: Vec<String>, dry_run: bool) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+
+    for name in names {
+        if !remote_exists(&repo, &name) {
+            println!("error: No such remote '{}'", name);
+            continue;
+        }
+        let remote = repo.find_remote(name.as_str())?;
+        let (ref_map, _handshake) = remote
+            .connect(Direction::Fetch)?
+            .ref_map(gix::progress::Discard, Default::default())?;
+        let stale = super::fetch::prune_stale_refs(&repo, &remote, &ref_map, dry_run)?;
+        if stale.is_empty() {
+            continue;
+        }
+
+        println!("Pruning {}", name);
+        println!(
+            "URL: {}",
+            remote
+                .url(Direction::Fetch)
+                .map(|url| url.to_bstring())
+                .unwrap_or_default()
+        );
+        for reference in stale {
+            println!(
+                " * [{}] {}",
+                if dry_run { "would prune" } else { "pruned" },
+                reference
+                    .strip_prefix(b"refs/remotes/")
+                    .unwrap_or(reference.as_slice())
+                    .as_bstr()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn open_repo() -> Option<gix::Repository> {
+    match gix::discover(".") {
+        Ok(repo) => Some(repo),
+        Err(_) => {
+            println!("fatal: not a git repository (or any of the parent directories): .git");
+            None
+        }
+    }
+}
+
+fn remote_exists(repo: &gix::Repository, name: &str) -> bool {
+    repo.remote_names().contains(name.as_bytes().as_bstr())
+}
+
+/// Remote names have to be usable as part of remote-tracking ref names.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && gix::refs::FullName::try_from(format!("refs/remotes/{name}/test")).is_ok()
+}
+
+/// The remote-tracking refs of `remote`, i.e. the refs below `refs/remotes/` matched by the destinations of its fetch refspecs.
+fn tracking_refs(
+    repo: &gix::Repository,
+    remote: &gix::Remote<'_>,
+) -> Result<Vec<gix::refs::Reference>> {
+    let destinations: Vec<BString> = remote
+        .refspecs(Direction::Fetch)
+        .iter()
+        .filter_map(|spec| spec.to_ref().destination().map(ToOwned::to_owned))
+        .collect();
+
+    let mut refs = Vec::new();
+    for reference in repo.references()?.all()? {
+        let reference = reference.map_err(|e| anyhow!("{}", e))?.detach();
+        let name = reference.name.as_bstr();
+        if !name.starts_with(b"refs/remotes/") {
+            continue;
+        }
+        let is_tracked =
+            destinations
+                .iter()
+                .any(|destination| match destination.strip_suffix(b"*") {
+                    Some(prefix) => name.starts_with(prefix),
+                    None => name == destination.as_bstr(),
+                });
+        if is_tracked {
+            refs.push(reference);
+        }
+    }
+    Ok(refs)
+}
+
+/// The branches configured to pull from the remote `name`, along with the `branch.<name>.merge` ref they merge.
+fn branches_tracking(
+    config: &gix::config::File<'_>,
+    name: &str,
+) -> Vec<(BString, Option<BString>)> {
+    let Some(sections) = config.sections_by_name("branch") else {
+        return Vec::new();
+    };
+    sections
+        .filter(|section| {
+            section
+                .value("remote")
+                .is_some_and(|remote| remote.as_bytes() == name.as_bytes())
+        })
+        .filter_map(|section| {
+            let branch = section.header().subsection_name()?.to_owned();
+            let merge = section.value("merge").map(Cow::into_owned);
+            Some((branch, merge))
+        })
+        .collect()
+}
+
+fn print_pull_config(repo: &gix::Repository, name: &str) {
+    let config = repo.config_snapshot();
+    let branches = branches_tracking(config.plumbing(), name);
+    if branches.is_empty() {
+        return;
+    }
+    println!(
+        "  Local {} configured for 'git pull':",
+        plural(branches.len(), "branch", "branches")
+    );
+    let width = branches
+        .iter()
+        .map(|(branch, _)| branch.len())
+        .max()
+        .unwrap_or(0);
+    for (branch, merge) in &branches {
+        let merge = merge.as_ref().map_or(Cow::Borrowed(""), |merge| {
+            merge
+                .strip_prefix(b"refs/heads/")
+                .unwrap_or(merge.as_slice())
+                .to_str_lossy()
+        });
+        println!(
+            "    {:<width$} merges with remote {}",
+            branch.to_str_lossy(),
+            merge,
+            width = width
+        );
+    }
+}
+
+/// The ref on `remote` that pushing the local branch `local` updates, if any, following its push refspecs.
+fn push_destination(remote: &gix::Remote<'_>, local: &BStr) -> Option<BString> {
+    let specs = remote.refspecs(Direction::Push);
+    if specs.is_empty() {
+        return Some(local.to_owned());
+    }
+    specs.iter().find_map(|spec| {
+        let spec = spec.to_ref();
+        let source = spec.source()?;
+        let destination = spec.destination().unwrap_or(source);
+        match (source.strip_suffix(b"*"), destination.strip_suffix(b"*")) {
+            (Some(source_prefix), Some(destination_prefix)) => {
+                let rest = local.strip_prefix(source_prefix)?;
+                let mut name = BString::from(destination_prefix);
+                name.extend_from_slice(rest);
+                Some(name)
+            }
+            _ if source == local => Some(destination.to_owned()),
+            _ => None,
+        }
+    })
+}
+
+fn plural<'a>(count: usize, one: &'a str, many: &'a str) -> &'a str {
+    if count == 1 {
+        one
+    } else {
+        many
+    }
+}
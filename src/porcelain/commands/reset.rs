@@ -1,14 +1,49 @@
 use anyhow::Result;
 use gix::bstr::ByteSlice;
+use gix::refs::transaction::PreviousValue;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
 
-#[derive(Debug, Clone, Copy)]
+use super::index_utils::{
+    checkout_index, checkout_tree, differing_paths, entries_by_path, keep_stat, matches_pathspec,
+    normalize_pathspecs, remove_empty_parents, replace_index_entries,
+};
+use super::ref_utils::move_head;
+use super::revision_utils::rev_parse_single;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ResetMode {
     Soft,
     Mixed,
     Hard,
+    /// Like `Hard`, but keep local changes to files that don't differ between `HEAD` and the target
+    Keep,
+    /// Like `Keep`, but also discard staged changes and only refuse if unstaged changes are in the way
+    Merge,
+}
+
+impl ResetMode {
+    fn name(&self) -> &'static str {
+        match self {
+            ResetMode::Soft => "soft",
+            ResetMode::Mixed => "mixed",
+            ResetMode::Hard => "hard",
+            ResetMode::Keep => "keep",
+            ResetMode::Merge => "merge",
+        }
+    }
 }
 
-pub fn run(mode: ResetMode, commit: Option<String>) -> Result<()> {
+/// Files recording an operation in progress, which is over once `HEAD` was reset.
+const BRANCH_STATE_FILES: &[&str] = &[
+    "MERGE_HEAD",
+    "MERGE_MSG",
+    "MERGE_MODE",
+    "CHERRY_PICK_HEAD",
+    "REVERT_HEAD",
+];
+
+pub fn run(mode: ResetMode, commit: Option<String>, paths: Vec<PathBuf>) -> Result<()> {
     let repo = match gix::discover(".") {
         Ok(repo) => repo,
         Err(_) => {
@@ -17,11 +52,32 @@ pub fn run(mode: ResetMode, commit: Option<String>) -> Result<()> {
         }
     };
 
+    let mut commit = commit;
+    let mut paths = paths;
+    // Like git, accept `reset <path>` without `--` if the argument can't be a revision.
+    if let Some(arg) = &commit {
+        if rev_parse_single(&repo, arg).is_err() && is_known_path(&repo, arg)? {
+            paths.insert(0, PathBuf::from(arg));
+            commit = None;
+        }
+    }
     let target = commit.as_deref().unwrap_or("HEAD");
 
-    let commit_id = match repo.rev_parse_single(target) {
-        Ok(spec) => spec.detach(),
-        Err(_) => {
+    if !paths.is_empty() {
+        if mode != ResetMode::Mixed {
+            println!("fatal: Cannot do {} reset with paths.", mode.name());
+            return Ok(());
+        }
+        return reset_paths(&repo, target, commit.is_none(), &paths);
+    }
+
+    let commit_id = match rev_parse_single(&repo, target)
+        .ok()
+        .and_then(|id| id.object().ok())
+        .and_then(|object| object.peel_to_commit().ok())
+    {
+        Some(commit) => commit.id,
+        None => {
             println!(
                 "fatal: ambiguous argument '{}': unknown revision or path not in the working tree.",
                 target
@@ -29,56 +85,43 @@ pub fn run(mode: ResetMode, commit: Option<String>) -> Result<()> {
             return Ok(());
         }
     };
+    if mode == ResetMode::Soft && repo.path().join("MERGE_HEAD").exists() {
+        println!("fatal: Cannot do a soft reset in the middle of a merge.");
+        return Ok(());
+    }
 
-    let mut head = match repo.head_ref() {
-        Ok(Some(head)) => head,
-        Ok(None) => {
-            println!("fatal: Failed to get HEAD reference");
-            return Ok(());
-        }
-        Err(e) => {
-            println!("fatal: {}", e);
-            return Ok(());
-        }
-    };
-
+    let old_head = repo.head_id().ok().map(|id| id.detach());
+    let tree_id = repo.find_commit(commit_id)?.tree_id()?.detach();
     match mode {
-        ResetMode::Soft => {
-            head.set_target_id(commit_id, "reset: moving to HEAD")?;
-            println!(
-                "HEAD is now at {} {}",
-                commit_id.to_hex_with_len(7),
-                get_commit_summary(&repo, &commit_id)?
-            );
+        ResetMode::Soft => {}
+        ResetMode::Mixed => reset_index(&repo, tree_id)?,
+        ResetMode::Hard => checkout_tree(&repo, tree_id)?,
+        ResetMode::Keep | ResetMode::Merge => {
+            let head_tree = match old_head {
+                Some(id) => Some(repo.find_commit(id)?.tree_id()?.detach()),
+                None => None,
+            };
+            if !reset_merging(&repo, mode, head_tree, tree_id, target)? {
+                return Ok(());
+            }
         }
-        ResetMode::Mixed => {
-            head.set_target_id(commit_id, "reset: moving to HEAD")?;
-
-            let tree_id = repo.find_commit(commit_id)?.tree_id()?;
-            reset_index(&repo, tree_id.into())?;
-
-            println!(
-                "HEAD is now at {} {}",
-                commit_id.to_hex_with_len(7),
-                get_commit_summary(&repo, &commit_id)?
-            );
-        }
-        ResetMode::Hard => {
-            head.set_target_id(commit_id, "reset: moving to HEAD")?;
-
-            let tree_id = repo.find_commit(commit_id)?.tree_id()?;
-            reset_index(&repo, tree_id.into())?;
-
-            reset_worktree(&repo, tree_id.into())?;
+    }
 
-            println!(
-                "HEAD is now at {} {}",
-                commit_id.to_hex_with_len(7),
-                get_commit_summary(&repo, &commit_id)?
-            );
-        }
+    let message = format!("reset: moving to {}", target);
+    if let Some(old_head) = old_head {
+        repo.reference("ORIG_HEAD", old_head, PreviousValue::Any, message.as_str())?;
+    }
+    move_head(&repo, commit_id, &message)?;
+    for file in BRANCH_STATE_FILES {
+        std::fs::remove_file(repo.path().join(file)).ok();
     }
 
+    println!(
+        "HEAD is now at {} {}",
+        commit_id.to_hex_with_len(7),
+        get_commit_summary(&repo, &commit_id)?
+    );
+
     Ok(())
 }
 
@@ -90,36 +133,157 @@ fn get_commit_summary(repo: &gix::Repository, commit_id: &gix::ObjectId) -> Resu
 }
 
 fn reset_index(repo: &gix::Repository, tree_id: gix::ObjectId) -> Result<()> {
-    let mut index = repo.index_from_tree(&tree_id)?;
+    let old_index = repo.index_or_empty()?;
+    let mut index = super::promisor::index_from_tree(repo, &tree_id)?;
+    keep_stat(&mut index, &old_index);
 
     index.write(Default::default())?;
 
     Ok(())
 }
 
-fn reset_worktree(repo: &gix::Repository, tree_id: gix::ObjectId) -> Result<()> {
-    let _tree = repo.find_tree(tree_id)?;
+/// Whether `arg` names a file in the worktree or an entry in the index.
+fn is_known_path(repo: &gix::Repository, arg: &str) -> Result<bool> {
+    if repo
+        .workdir()
+        .is_some_and(|worktree| worktree.join(arg).exists())
+    {
+        return Ok(true);
+    }
+    let index = repo.index_or_empty()?;
+    let specs = normalize_pathspecs(&[PathBuf::from(arg)]);
+    Ok(index
+        .entries()
+        .iter()
+        .any(|entry| matches_pathspec(&specs, entry.path(&index))))
+}
 
+/// Make the index entries at `paths` match those in `target`, removing the ones it doesn't have.
+///
+/// `HEAD` and the worktree are left alone, so this unstages changes.
+fn reset_paths(
+    repo: &gix::Repository,
+    target: &str,
+    is_head: bool,
+    paths: &[PathBuf],
+) -> Result<()> {
+    let tree_id = match rev_parse_single(repo, target)
+        .ok()
+        .and_then(|id| id.object().ok())
+        .and_then(|object| object.peel_to_tree().ok())
+    {
+        Some(tree) => Some(tree.id),
+        // Before the first commit, resetting paths to `HEAD` removes them from the index.
+        None if is_head && repo.head()?.is_unborn() => None,
+        None => {
+            println!(
+                "fatal: ambiguous argument '{}': unknown revision or path not in the working tree.",
+                target
+            );
+            return Ok(());
+        }
+    };
+    let target_index = match tree_id {
+        Some(tree_id) => Some(super::promisor::index_from_tree(repo, &tree_id)?),
+        None => None,
+    };
+
+    let specs = normalize_pathspecs(paths);
+    let old_index = repo.index_or_load_from_head_or_empty()?.into_owned();
+    let mut index = old_index.clone();
+    replace_index_entries(&mut index, target_index.as_ref(), &specs);
+    keep_stat(&mut index, &old_index);
+    index.write(Default::default())?;
+
+    Ok(())
+}
+
+/// Reset the index to `tree_id` and update the files in the worktree that differ between `head_tree` and `tree_id`,
+/// keeping all other local changes as `--keep` and `--merge` do. With `--keep`, only the index entries of those
+/// files are reset.
+///
+/// Returns `false` if local changes were in the way and nothing was changed.
+fn reset_merging(
+    repo: &gix::Repository,
+    mode: ResetMode,
+    head_tree: Option<gix::ObjectId>,
+    tree_id: gix::ObjectId,
+    target: &str,
+) -> Result<bool> {
     let worktree = repo
         .workdir()
         .ok_or_else(|| anyhow::anyhow!("No worktree found"))?;
 
-    let mut index = repo.index_from_tree(&tree_id)?;
-
-    let opts = gix::worktree::state::checkout::Options {
-        overwrite_existing: true,
-        ..Default::default()
+    let target_index = super::promisor::index_from_tree(repo, &tree_id)?;
+    let head_index = match head_tree {
+        Some(head_tree) => Some(super::promisor::index_from_tree(repo, &head_tree)?),
+        None => None,
     };
+    let old_index = repo.index_or_load_from_head_or_empty()?.into_owned();
 
-    gix::worktree::state::checkout(
-        &mut index,
-        worktree,
-        repo.objects.clone(),
-        &gix::progress::Discard,
-        &gix::progress::Discard,
-        &std::sync::atomic::AtomicBool::new(false),
-        opts,
-    )?;
+    let head_entries = entries_by_path(head_index.as_ref());
+    let target_entries = entries_by_path(Some(&target_index));
+    let index_entries = entries_by_path(Some(&old_index));
 
-    Ok(())
+    let changed = differing_paths(&head_entries, &target_entries);
+    let staged = differing_paths(&head_entries, &index_entries);
+    let mut unstaged = BTreeSet::new();
+    for item in repo
+        .status(gix::progress::Discard)?
+        .into_index_worktree_iter(Vec::new())?
+    {
+        if let gix::status::index_worktree::Item::Modification { rela_path, .. } = item? {
+            unstaged.insert(rela_path);
+        }
+    }
+
+    for path in &changed {
+        let error = if unstaged.contains(path) || (mode == ResetMode::Keep && staged.contains(path))
+        {
+            "not uptodate. Cannot merge."
+        } else if !head_entries.contains_key(path)
+            && !index_entries.contains_key(path)
+            && worktree.join(gix::path::from_bstr(path.as_bstr())).exists()
+        {
+            "would be overwritten by merge. Cannot merge."
+        } else {
+            continue;
+        };
+        println!("error: Entry '{}' {}", path, error);
+        println!(
+            "fatal: Could not reset index file to revision '{}'.",
+            target
+        );
+        return Ok(false);
+    }
+
+    let mut updates = target_index.clone();
+    updates.remove_entries(|_, path, _| !changed.contains(path));
+    checkout_index(repo, &mut updates)?;
+    for path in &changed {
+        if target_entries.contains_key(path) {
+            continue;
+        }
+        let file = worktree.join(gix::path::from_bstr(path.as_bstr()));
+        if std::fs::remove_file(&file).is_ok() {
+            remove_empty_parents(worktree, &file);
+        }
+    }
+
+    // `--merge` resets the whole index, while `--keep` only touches the paths that change between
+    // `HEAD` and the target and keeps everything else that was staged.
+    let mut index = match mode {
+        ResetMode::Keep => {
+            let mut index = old_index.clone();
+            let changed: Vec<_> = changed.into_iter().collect();
+            replace_index_entries(&mut index, Some(&target_index), &changed);
+            index
+        }
+        _ => target_index,
+    };
+    keep_stat(&mut index, &old_index);
+    keep_stat(&mut index, &updates);
+    index.write(Default::default())?;
+
+    Ok(true)
 }
@@ -0,0 +1,123 @@
+use anyhow::Result;
+use std::path::PathBuf;
+
+use super::index_utils::{
+    checkout_index, keep_stat, matches_pathspec, normalize_pathspecs, remove_empty_parents,
+    replace_index_entries,
+};
+use super::revision_utils::rev_parse_single;
+
+/// Restore `paths` in the worktree from the index, or in the index from `HEAD` with `staged`.
+///
+/// With `source`, both are restored from that tree instead, and files it doesn't have are removed.
+pub fn run(
+    paths: Vec<PathBuf>,
+    source: Option<String>,
+    staged: bool,
+    worktree: bool,
+) -> Result<()> {
+    let repo = match gix::discover(".") {
+        Ok(repo) => repo,
+        Err(_) => {
+            println!("fatal: not a git repository (or any of the parent directories): .git");
+            return Ok(());
+        }
+    };
+    if paths.is_empty() {
+        println!("fatal: you must specify path(s) to restore");
+        return Ok(());
+    }
+    let Some(workdir) = repo.workdir() else {
+        println!("fatal: this operation must be run in a work tree");
+        return Ok(());
+    };
+    let worktree = worktree || !staged;
+
+    // The worktree is restored from the index unless a source is given, the index always from a tree.
+    let source_rev = source.as_deref().or(staged.then_some("HEAD"));
+    let source_index = match source_rev {
+        Some(rev) => {
+            let tree_id = rev_parse_single(&repo, rev)
+                .ok()
+                .and_then(|id| id.object().ok())
+                .and_then(|object| object.peel_to_tree().ok())
+                .map(|tree| tree.id);
+            match tree_id {
+                Some(tree_id) => Some(super::promisor::index_from_tree(&repo, &tree_id)?),
+                // Before the first commit, restoring staged files from `HEAD` unstages them.
+                None if source.is_none() && repo.head()?.is_unborn() => {
+                    Some(gix::index::File::from_state(
+                        gix::index::State::new(repo.object_hash()),
+                        repo.index_path(),
+                    ))
+                }
+                None => {
+                    println!("fatal: could not resolve {}", rev);
+                    return Ok(());
+                }
+            }
+        }
+        None => None,
+    };
+
+    let specs = normalize_pathspecs(&paths);
+    let old_index = repo.index_or_load_from_head_or_empty()?.into_owned();
+    for (spec, path) in specs.iter().zip(&paths) {
+        let spec = std::slice::from_ref(spec);
+        let matches = |index: &gix::index::File| {
+            index
+                .entries()
+                .iter()
+                .any(|entry| matches_pathspec(spec, entry.path(index)))
+        };
+        if !matches(&old_index) && !source_index.as_ref().is_some_and(matches) {
+            println!(
+                "error: pathspec '{}' did not match any file(s) known to git",
+                path.display()
+            );
+            return Ok(());
+        }
+    }
+
+    let mut index = old_index.clone();
+    if staged {
+        replace_index_entries(&mut index, source_index.as_ref(), &specs);
+    }
+
+    if worktree {
+        let mut updates = match &source_index {
+            Some(source_index) => source_index.clone(),
+            None => old_index.clone(),
+        };
+        updates.remove_entries(|_, path, entry| {
+            if !matches_pathspec(&specs, path) {
+                return true;
+            }
+            if entry.stage_raw() != 0 {
+                println!("error: path '{}' is unmerged", path);
+                return true;
+            }
+            false
+        });
+        updates.sort_entries();
+
+        // Restoring from a tree doesn't overlay it, so tracked files it doesn't have are deleted.
+        if let Some(source_index) = &source_index {
+            for entry in old_index.entries() {
+                let path = entry.path(&old_index);
+                if matches_pathspec(&specs, path) && source_index.entry_by_path(path).is_none() {
+                    let file = workdir.join(gix::path::from_bstr(path));
+                    if std::fs::remove_file(&file).is_ok() {
+                        remove_empty_parents(workdir, &file);
+                    }
+                }
+            }
+        }
+
+        checkout_index(&repo, &mut updates)?;
+        keep_stat(&mut index, &updates);
+    }
+
+    index.write(Default::default())?;
+    Ok(())
+}
@@ -0,0 +1,59 @@
+/// Like `Repository::rev_parse_single()`, but also understanding the reflog dates git does, like `@{yesterday}`.
+pub fn rev_parse_single<'repo>(
+    repo: &'repo gix::Repository,
+    spec: &str,
+) -> Result<gix::Id<'repo>, gix::revision::spec::parse::single::Error> {
+    repo.rev_parse_single(expand_dates(spec).as_str())
+}
+
+/// Like `Repository::rev_parse()`, but also understanding the reflog dates git does, like `@{2.days.ago}`.
+pub fn rev_parse<'repo>(
+    repo: &'repo gix::Repository,
+    spec: &str,
+) -> Result<gix::revision::Spec<'repo>, gix::revision::spec::parse::Error> {
+    repo.rev_parse(expand_dates(spec).as_str())
+}
+
+/// Parse `date` relative to now, accepting `now`, `yesterday` and `2.weeks.ago` like git does besides absolute dates.
+pub fn parse_date(date: &str) -> Option<gix::date::Time> {
+    gix::date::parse(&normalize_date(date), Some(std::time::SystemTime::now())).ok()
+}
+
+/// Rewrite the dates in the `@{...}` parts of `spec` so gix understands them, leaving entry numbers and upstreams alone.
+fn expand_dates(spec: &str) -> String {
+    let mut out = String::with_capacity(spec.len());
+    let mut rest = spec;
+    while let Some(start) = rest.find("@{") {
+        let (before, after) = rest.split_at(start + 2);
+        out.push_str(before);
+        let Some(end) = after.find('}') else {
+            rest = after;
+            break;
+        };
+        let inner = &after[..end];
+        let is_date = inner.parse::<isize>().is_err()
+            && !matches!(
+                inner.to_ascii_lowercase().as_str(),
+                "u" | "upstream" | "push"
+            );
+        if is_date {
+            out.push_str(&normalize_date(inner));
+        } else {
+            out.push_str(inner);
+        }
+        rest = &after[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Turn the relative dates of git that gix doesn't know into ones it does.
+fn normalize_date(date: &str) -> String {
+    let date = date.trim();
+    match date {
+        "now" => "0 seconds ago".into(),
+        "yesterday" => "1 day ago".into(),
+        _ if date.ends_with(".ago") => date.replace('.', " "),
+        _ => date.into(),
+    }
+}
@@ -0,0 +1,304 @@
+use anyhow::Result;
+use gix::bstr::{BString, ByteSlice};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use super::index_utils::{
+    entries_by_path, matches_pathspec, normalize_pathspecs, remove_empty_parents,
+};
+use super::submodule_utils::absorb_git_dir;
+
+/// Remove the files at `paths` from the index, and from the worktree unless `cached` is set.
+///
+/// Files with changes that would be lost are only removed with `force`.
+pub fn run(
+    paths: Vec<PathBuf>,
+    cached: bool,
+    recursive: bool,
+    force: bool,
+    quiet: bool,
+) -> Result<()> {
+    let repo = match gix::discover(".") {
+        Ok(repo) => repo,
+        Err(_) => {
+            println!("fatal: not a git repository (or any of the parent directories): .git");
+            return Ok(());
+        }
+    };
+    if paths.is_empty() {
+        println!("fatal: No pathspec was given. Which files should I remove?");
+        return Ok(());
+    }
+    let Some(workdir) = repo.workdir() else {
+        println!("fatal: this operation must be run in a work tree");
+        return Ok(());
+    };
+
+    let specs = normalize_pathspecs(&paths);
+    let mut index = repo.index_or_load_from_head_or_empty()?.into_owned();
+
+    let mut selected = BTreeSet::new();
+    for (spec, path) in specs.iter().zip(&paths) {
+        let mut matched = false;
+        for entry in index.entries() {
+            let entry_path = entry.path(&index);
+            if !matches_pathspec(std::slice::from_ref(spec), entry_path) {
+                continue;
+            }
+            if entry_path != spec.as_slice() && !recursive {
+                println!(
+                    "fatal: not removing '{}' recursively without -r",
+                    path.display()
+                );
+                return Ok(());
+            }
+            selected.insert(entry_path.to_owned());
+            matched = true;
+        }
+        if !matched {
+            println!(
+                "fatal: pathspec '{}' did not match any files",
+                path.display()
+            );
+            return Ok(());
+        }
+    }
+
+    if !force && !check_local_changes(&repo, &index, &selected, cached)? {
+        return Ok(());
+    }
+    if !cached && !absorb_submodule_git_dirs(&repo, workdir, &selected)? {
+        return Ok(());
+    }
+
+    index.remove_entries(|_, path, _| selected.contains(path));
+    index.remove_tree();
+    for path in &selected {
+        if !quiet {
+            println!("rm '{}'", path);
+        }
+        if cached {
+            continue;
+        }
+        let file = workdir.join(gix::path::from_bstr(path.as_bstr()));
+        let removed = if file.is_dir() {
+            // Submodules are removed along with their worktree, with their repository absorbed above.
+            std::fs::remove_dir_all(&file)
+        } else {
+            std::fs::remove_file(&file)
+        };
+        if removed.is_ok() {
+            remove_empty_parents(workdir, &file);
+        }
+    }
+    index.write(Default::default())?;
+
+    Ok(())
+}
+
+/// Move the `.git` directories of submodules among `selected` into the superproject, so removing their
+/// worktrees doesn't remove their history.
+///
+/// Returns `false` after reporting submodules that can't be absorbed as they aren't listed in `.gitmodules`.
+fn absorb_submodule_git_dirs(
+    repo: &gix::Repository,
+    workdir: &Path,
+    selected: &BTreeSet<BString>,
+) -> Result<bool> {
+    let mut names = BTreeMap::new();
+    for submodule in repo.submodules()?.into_iter().flatten() {
+        names.insert(submodule.path()?.into_owned(), submodule.name().to_owned());
+    }
+
+    let (mut absorbable, mut unabsorbed) = (Vec::new(), Vec::new());
+    for path in selected {
+        let worktree = workdir.join(gix::path::from_bstr(path.as_bstr()));
+        if !worktree.join(".git").is_dir() {
+            continue;
+        }
+        match names.get(path) {
+            Some(name) => absorbable.push((name, worktree)),
+            None => unabsorbed.push(path),
+        }
+    }
+    if unabsorbed.is_empty() {
+        for (name, worktree) in absorbable {
+            absorb_git_dir(repo, name.as_bstr(), &worktree)?;
+        }
+        return Ok(true);
+    }
+    println!(
+        "error: the following submodule (or one of its nested submodules)\nuses a .git directory:"
+    );
+    for path in unabsorbed {
+        println!("    {}", path);
+    }
+    println!("(use 'rm -rf' if you really want to remove it including all of its history)");
+    Ok(false)
+}
+
+/// Make sure removing `selected` doesn't lose changes, printing the offending files like git if it would.
+fn check_local_changes(
+    repo: &gix::Repository,
+    index: &gix::index::File,
+    selected: &BTreeSet<BString>,
+    cached: bool,
+) -> Result<bool> {
+    let head_index = match repo.head_tree_id() {
+        Ok(tree_id) => Some(super::promisor::index_from_tree(repo, &tree_id)?),
+        Err(_) => None,
+    };
+    let head_entries = entries_by_path(head_index.as_ref());
+    let index_entries = entries_by_path(Some(index));
+
+    let mut modified = BTreeSet::new();
+    for item in repo
+        .status(gix::progress::Discard)?
+        .into_index_worktree_iter(Vec::new())?
+    {
+        if let gix::status::index_worktree::Item::Modification { rela_path, .. } = item? {
+            modified.insert(rela_path);
+        }
+    }
+
+    let (mut staged_and_modified, mut staged, mut local) = (Vec::new(), Vec::new(), Vec::new());
+    for path in selected {
+        let file_exists = repo
+            .workdir()
+            .is_some_and(|workdir| workdir.join(gix::path::from_bstr(path.as_bstr())).exists());
+        let is_staged = head_entries.get(path) != index_entries.get(path);
+        let is_modified = file_exists && modified.contains(path);
+        if is_staged && is_modified {
+            staged_and_modified.push(path);
+        } else if !cached && is_staged {
+            staged.push(path);
+        } else if !cached && is_modified {
+            local.push(path);
+        }
+    }
+
+    let report = |files: &[&BString], what: &str, hint: &str| {
+        if files.is_empty() {
+            return;
+        }
+        let noun = if files.len() == 1 {
+            "file has"
+        } else {
+            "files have"
+        };
+        println!("error: the following {} {}:", noun, what);
+        for file in files {
+            println!("    {}", file);
+        }
+        println!("({})", hint);
+    };
+    report(
+        &staged_and_modified,
+        "staged content different from both the\nfile and the HEAD",
+        "use -f to force removal",
+    );
+    report(
+        &staged,
+        "changes staged in the index",
+        "use --cached to keep the file, or -f to force removal",
+    );
+    report(
+        &local,
+        "local modifications",
+        "use --cached to keep the file, or -f to force removal",
+    );
+
+    Ok(staged_and_modified.is_empty() && staged.is_empty() && local.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    use super::absorb_submodule_git_dirs;
+
+    /// Run `git` in `dir`, returning its trimmed output.
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args([
+                "-c",
+                "user.name=A U Thor",
+                "-c",
+                "user.email=author@example.com",
+                "-c",
+                "protocol.file.allow=always",
+            ])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .expect("git can be run");
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8(output.stdout).unwrap().trim().to_owned()
+    }
+
+    /// A superproject with a repository cloned to `sub`, which still has its `.git` directory,
+    /// or `None` if git isn't installed.
+    fn superproject(root: &Path) -> Option<PathBuf> {
+        Command::new("git").arg("--version").output().ok()?;
+        let upstream = root.join("upstream");
+        std::fs::create_dir_all(&upstream).unwrap();
+        git(&upstream, &["init", "--quiet", "--initial-branch=main"]);
+        std::fs::write(upstream.join("file"), "file\n").unwrap();
+        git(&upstream, &["add", "file"]);
+        git(&upstream, &["commit", "--quiet", "-m", "initial"]);
+
+        let dir = root.join("super");
+        std::fs::create_dir_all(&dir).unwrap();
+        git(&dir, &["init", "--quiet", "--initial-branch=main"]);
+        git(
+            &dir,
+            &["clone", "--quiet", upstream.to_str().unwrap(), "sub"],
+        );
+        Some(dir)
+    }
+
+    fn sub() -> BTreeSet<gix::bstr::BString> {
+        BTreeSet::from(["sub".into()])
+    }
+
+    #[test]
+    fn submodule_git_dir_is_absorbed() {
+        let root = tempfile::tempdir().unwrap();
+        let Some(dir) = superproject(root.path()) else {
+            return;
+        };
+        let url = root.path().join("upstream");
+        git(
+            &dir,
+            &["submodule", "--quiet", "add", url.to_str().unwrap(), "sub"],
+        );
+        let head = git(&dir.join("sub"), &["rev-parse", "HEAD"]);
+
+        let repo = gix::open(&dir).unwrap();
+        assert!(absorb_submodule_git_dirs(&repo, &dir, &sub()).unwrap());
+        assert!(dir.join("sub/.git").is_file());
+        assert!(repo.path().join("modules/sub").is_dir());
+        // The history outlives the worktree.
+        std::fs::remove_dir_all(dir.join("sub")).unwrap();
+        assert_eq!(
+            git(&repo.path().join("modules/sub"), &["rev-parse", "HEAD"]),
+            head
+        );
+    }
+
+    #[test]
+    fn unlisted_submodule_with_git_dir_is_kept() {
+        let root = tempfile::tempdir().unwrap();
+        let Some(dir) = superproject(root.path()) else {
+            return;
+        };
+        // Without `.gitmodules`, there is no name to absorb it under.
+        git(&dir, &["add", "sub"]);
+
+        let repo = gix::open(&dir).unwrap();
+        assert!(!absorb_submodule_git_dirs(&repo, &dir, &sub()).unwrap());
+        assert!(dir.join("sub/.git").is_dir());
+    }
+}
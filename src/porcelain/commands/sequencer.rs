@@ -0,0 +1,589 @@
+use anyhow::Result;
+use gix::bstr::ByteSlice;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use super::commit_utils::write_commit_as;
+use super::index_utils::{checkout_tree, get_current_branch, write_tree};
+use super::merge_utils::{merge_into_worktree, Labels};
+use super::ref_utils::move_head;
+use super::revision_utils::{rev_parse, rev_parse_single};
+
+/// What is done with each commit of a sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Apply the changes a commit introduced
+    CherryPick,
+    /// Apply the inverse of the changes a commit introduced
+    Revert,
+}
+
+impl Action {
+    fn name(&self) -> &'static str {
+        match self {
+            Action::CherryPick => "cherry-pick",
+            Action::Revert => "revert",
+        }
+    }
+
+    /// The instruction used in `sequencer/todo`, which is what git uses too.
+    fn verb(&self) -> &'static str {
+        match self {
+            Action::CherryPick => "pick",
+            Action::Revert => "revert",
+        }
+    }
+
+    /// The file recording the commit that is being applied while stopped at a conflict.
+    fn head_file(&self) -> &'static str {
+        match self {
+            Action::CherryPick => "CHERRY_PICK_HEAD",
+            Action::Revert => "REVERT_HEAD",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Apply the changes to the index and worktree without committing them
+    pub no_commit: bool,
+    /// Append a line saying which commit was cherry-picked to the message
+    pub record_origin: bool,
+    /// The 1-based parent number of merge commits to diff against
+    pub mainline: Option<usize>,
+}
+
+/// A single step of the sequence
+type Step = (Action, gix::ObjectId);
+
+enum Outcome {
+    Done,
+    /// Stopped to let the user resolve conflicts, with the state saved for `--continue`
+    Stopped,
+    /// Nothing was changed for this commit
+    Failed,
+}
+
+/// Cherry-pick or revert `commits`, which may also be ranges like `a..b`, in order.
+pub fn run(action: Action, commits: Vec<String>, opts: Options) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    if in_progress(&repo) {
+        println!("error: a cherry-pick or revert is already in progress");
+        println!(
+            "hint: try \"git {} (--continue | --abort | --skip)\"",
+            action.name()
+        );
+        println!("fatal: {} failed", action.name());
+        return Ok(());
+    }
+    if commits.is_empty() {
+        println!("fatal: empty commit set passed");
+        return Ok(());
+    }
+
+    let mut steps = Vec::new();
+    for spec in &commits {
+        match resolve_commits(&repo, spec)? {
+            Some(ids) => steps.extend(ids.into_iter().map(|id| (action, id))),
+            None => {
+                println!("fatal: bad revision '{}'", spec);
+                return Ok(());
+            }
+        }
+    }
+
+    // Like git, a single commit is applied without a sequence, leaving only `CHERRY_PICK_HEAD` or `REVERT_HEAD`
+    // behind if it stops.
+    if let [(action, id)] = steps.as_slice() {
+        if let Outcome::Failed = apply_step(&repo, *action, *id, &opts)? {
+            println!("fatal: {} failed", action.name());
+        }
+        return Ok(());
+    }
+
+    let head = repo.head_id().ok().map(|id| id.detach());
+    if let Some(head) = head {
+        std::fs::create_dir_all(sequencer_dir(&repo))?;
+        std::fs::write(sequencer_dir(&repo).join("head"), format!("{}\n", head))?;
+    }
+    apply_steps(&repo, &steps, &opts)
+}
+
+/// Commit the resolved conflicts of the step that stopped and carry on with the rest of the sequence.
+pub fn resume(action: Action) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    if !in_progress(&repo) {
+        println!("error: no cherry-pick or revert in progress");
+        println!("fatal: {} failed", action.name());
+        return Ok(());
+    }
+    let (mut steps, opts) = load_state(&repo)?;
+
+    let index = repo.index_or_empty()?;
+    if index.entries().iter().any(|entry| entry.stage_raw() != 0) {
+        println!("error: Committing is not possible because you have unmerged files.");
+        println!("hint: Fix them up in the work tree, and then use 'git add/rm <file>'");
+        println!("hint: as appropriate to mark resolution and make a commit.");
+        println!("fatal: Exiting because of an unresolved conflict.");
+        return Ok(());
+    }
+
+    // The stopped step is committed with the message as the user may have edited it.
+    let stopped = [Action::CherryPick, Action::Revert]
+        .into_iter()
+        .find(|action| repo.path().join(action.head_file()).exists());
+    if let Some(stopped) = stopped {
+        let picked = std::fs::read_to_string(repo.path().join(stopped.head_file()))?;
+        let picked = repo.find_commit(gix::ObjectId::from_hex(picked.trim().as_bytes())?)?;
+        let message = std::fs::read_to_string(repo.path().join("MERGE_MSG"))
+            .map(|message| strip_comments(&message))
+            .unwrap_or_default();
+        let author = match stopped {
+            Action::CherryPick => Some(picked.author()?.to_owned()),
+            Action::Revert => None,
+        };
+        let tree = write_tree(&repo, &index)?;
+        commit_step(&repo, stopped, author, &message, tree)?;
+        std::fs::remove_file(repo.path().join(stopped.head_file())).ok();
+        std::fs::remove_file(repo.path().join("MERGE_MSG")).ok();
+        if !steps.is_empty() {
+            steps.remove(0);
+        }
+    } else if head_moved(&repo) || std::fs::remove_file(repo.path().join("MERGE_MSG")).is_ok() {
+        // The stopped step was committed with `git commit` already, or with `--no-commit` the resolved
+        // changes just stay in the index.
+        std::fs::remove_file(repo.path().join("MERGE_MSG")).ok();
+        if !steps.is_empty() {
+            steps.remove(0);
+        }
+    }
+
+    apply_steps(&repo, &steps, &opts)
+}
+
+/// Drop the changes of the step that stopped and carry on with the rest of the sequence.
+pub fn skip(action: Action) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    if !in_progress(&repo) {
+        println!("error: no cherry-pick or revert in progress");
+        println!("fatal: {} failed", action.name());
+        return Ok(());
+    }
+    let (mut steps, opts) = load_state(&repo)?;
+
+    if let Ok(head_tree) = repo.head_tree_id() {
+        checkout_tree(&repo, head_tree.detach())?;
+    }
+    for file in ["CHERRY_PICK_HEAD", "REVERT_HEAD", "MERGE_MSG"] {
+        std::fs::remove_file(repo.path().join(file)).ok();
+    }
+    if !steps.is_empty() {
+        steps.remove(0);
+    }
+    apply_steps(&repo, &steps, &opts)
+}
+
+/// Go back to the state before the sequence started, undoing all steps that were committed already.
+pub fn abort(action: Action) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    if !in_progress(&repo) {
+        println!("error: no cherry-pick or revert in progress");
+        println!("fatal: {} failed", action.name());
+        return Ok(());
+    }
+
+    let original_head = std::fs::read_to_string(sequencer_dir(&repo).join("head"))
+        .ok()
+        .and_then(|head| gix::ObjectId::from_hex(head.trim().as_bytes()).ok())
+        .or_else(|| repo.head_id().ok().map(|id| id.detach()));
+    if let Some(original_head) = original_head {
+        let tree = repo.find_commit(original_head)?.tree_id()?.detach();
+        checkout_tree(&repo, tree)?;
+        if repo.head_id().ok().map(|id| id.detach()) != Some(original_head) {
+            move_head(&repo, original_head, &format!("{}: abort", action.name()))?;
+        }
+    }
+    remove_state(&repo);
+    Ok(())
+}
+
+fn open_repo() -> Option<gix::Repository> {
+    match gix::discover(".") {
+        Ok(repo) => Some(repo),
+        Err(_) => {
+            println!("fatal: not a git repository (or any of the parent directories): .git");
+            None
+        }
+    }
+}
+
+fn sequencer_dir(repo: &gix::Repository) -> PathBuf {
+    repo.path().join("sequencer")
+}
+
+fn in_progress(repo: &gix::Repository) -> bool {
+    sequencer_dir(repo).is_dir()
+        || repo.path().join("CHERRY_PICK_HEAD").exists()
+        || repo.path().join("REVERT_HEAD").exists()
+}
+
+/// Whether `HEAD` moved since the sequence stopped, which means the stopped step was committed by hand.
+fn head_moved(repo: &gix::Repository) -> bool {
+    let Ok(stopped_at) = std::fs::read_to_string(sequencer_dir(repo).join("abort-safety")) else {
+        return false;
+    };
+    let head = repo.head_id().map(|id| id.to_string()).unwrap_or_default();
+    stopped_at.trim() != head
+}
+
+fn remove_state(repo: &gix::Repository) {
+    std::fs::remove_dir_all(sequencer_dir(repo)).ok();
+    for file in ["CHERRY_PICK_HEAD", "REVERT_HEAD", "MERGE_MSG"] {
+        std::fs::remove_file(repo.path().join(file)).ok();
+    }
+}
+
+/// The commits `spec` refers to, oldest first, or `None` if it doesn't resolve.
+fn resolve_commits(repo: &gix::Repository, spec: &str) -> Result<Option<Vec<gix::ObjectId>>> {
+    let Ok(resolved) = rev_parse(repo, spec) else {
+        return Ok(None);
+    };
+    let peel = |id: gix::ObjectId| -> Option<gix::ObjectId> {
+        let object = repo.find_object(id).ok()?;
+        object.peel_to_commit().ok().map(|commit| commit.id)
+    };
+    Ok(match resolved.detach() {
+        gix::revision::plumbing::Spec::Include(id) => peel(id).map(|id| vec![id]),
+        gix::revision::plumbing::Spec::Range { from, to } => {
+            let (Some(from), Some(to)) = (peel(from), peel(to)) else {
+                return Ok(None);
+            };
+            let mut excluded = HashSet::new();
+            for info in repo.rev_walk([from]).all()? {
+                excluded.insert(info?.id);
+            }
+            let mut ids = Vec::new();
+            for info in repo.rev_walk([to]).all()? {
+                let id = info?.id;
+                if !excluded.contains(&id) {
+                    ids.push(id);
+                }
+            }
+            ids.reverse();
+            Some(ids)
+        }
+        _ => None,
+    })
+}
+
+fn apply_steps(repo: &gix::Repository, steps: &[Step], opts: &Options) -> Result<()> {
+    for (position, (action, id)) in steps.iter().enumerate() {
+        match apply_step(repo, *action, *id, opts)? {
+            Outcome::Done => {}
+            Outcome::Stopped => {
+                save_state(repo, &steps[position..], opts)?;
+                return Ok(());
+            }
+            Outcome::Failed => {
+                if position == 0 {
+                    remove_state(repo);
+                } else {
+                    save_state(repo, &steps[position..], opts)?;
+                }
+                println!("fatal: {} failed", action.name());
+                return Ok(());
+            }
+        }
+    }
+    remove_state(repo);
+    Ok(())
+}
+
+fn apply_step(
+    repo: &gix::Repository,
+    action: Action,
+    id: gix::ObjectId,
+    opts: &Options,
+) -> Result<Outcome> {
+    let commit = repo.find_commit(id)?;
+    let parents: Vec<_> = commit.parent_ids().map(|id| id.detach()).collect();
+    let parent = match (parents.len(), opts.mainline) {
+        (0, None) => None,
+        (1, None) => Some(parents[0]),
+        (0 | 1, Some(_)) => {
+            println!(
+                "error: mainline was specified but commit {} is not a merge.",
+                id
+            );
+            return Ok(Outcome::Failed);
+        }
+        (_, None) => {
+            println!(
+                "error: commit {} is a merge but no -m option was given.",
+                id
+            );
+            return Ok(Outcome::Failed);
+        }
+        (count, Some(mainline)) if mainline == 0 || mainline > count => {
+            println!("error: commit {} does not have parent {}", id, mainline);
+            return Ok(Outcome::Failed);
+        }
+        (_, Some(mainline)) => Some(parents[mainline - 1]),
+    };
+    let parent_tree = match parent {
+        Some(parent) => repo.find_commit(parent)?.tree_id()?.detach(),
+        None => gix::ObjectId::empty_tree(repo.object_hash()),
+    };
+    let commit_tree = commit.tree_id()?.detach();
+    let (base, theirs) = match action {
+        Action::CherryPick => (parent_tree, commit_tree),
+        Action::Revert => (commit_tree, parent_tree),
+    };
+
+    let index = repo.index_or_load_from_head_or_empty()?.into_owned();
+    if index.entries().iter().any(|entry| entry.stage_raw() != 0) {
+        println!("error: Committing is not possible because you have unmerged files.");
+        return Ok(Outcome::Failed);
+    }
+    let ours = write_tree(repo, &index)?;
+    let head_tree = match repo.head_tree_id() {
+        Ok(tree) => tree.detach(),
+        Err(_) => gix::ObjectId::empty_tree(repo.object_hash()),
+    };
+    if !opts.no_commit && ours != head_tree {
+        println!(
+            "error: your local changes would be overwritten by {}.",
+            action.name()
+        );
+        println!("hint: commit your changes or stash them to proceed.");
+        return Ok(Outcome::Failed);
+    }
+
+    let summary = commit
+        .message_raw_sloppy()
+        .lines()
+        .next()
+        .unwrap_or_default();
+    let label = format!("{}... {}", id.to_hex_with_len(7), summary.as_bstr());
+    let parent_label = format!("parent of {}", label);
+    let labels = match action {
+        Action::CherryPick => Labels {
+            base: &parent_label,
+            ours: "HEAD",
+            theirs: &label,
+        },
+        Action::Revert => Labels {
+            base: &label,
+            ours: "HEAD",
+            theirs: &parent_label,
+        },
+    };
+    let Some(merged) = merge_into_worktree(repo, base, ours, theirs, labels)? else {
+        return Ok(Outcome::Failed);
+    };
+    merged.index.write(Default::default())?;
+
+    let message = match action {
+        Action::CherryPick => {
+            let mut message = commit.message_raw_sloppy().to_str_lossy().into_owned();
+            if opts.record_origin {
+                message = format!(
+                    "{}\n\n(cherry picked from commit {})\n",
+                    message.trim_end(),
+                    id
+                );
+            }
+            message
+        }
+        Action::Revert => {
+            let mut message = format!(
+                "Revert \"{}\"\n\nThis reverts commit {}",
+                summary.as_bstr(),
+                id
+            );
+            if let (Some(parent), true) = (parent, parents.len() > 1) {
+                message.push_str(&format!(",\nreversing changes made to {}", parent));
+            }
+            message.push_str(".\n");
+            message
+        }
+    };
+
+    if !merged.conflicts.is_empty() {
+        let mut merge_message = format!("{}\n# Conflicts:\n", message.trim_end());
+        for path in &merged.conflicts {
+            merge_message.push_str(&format!("#\t{}\n", path));
+        }
+        std::fs::write(repo.path().join("MERGE_MSG"), merge_message)?;
+        if !opts.no_commit {
+            std::fs::write(repo.path().join(action.head_file()), format!("{}\n", id))?;
+        }
+        println!(
+            "error: could not {} {}... {}",
+            if action == Action::CherryPick {
+                "apply"
+            } else {
+                "revert"
+            },
+            id.to_hex_with_len(7),
+            summary.as_bstr()
+        );
+        println!("hint: After resolving the conflicts, mark them with");
+        println!("hint: \"git add/rm <pathspec>\", then run");
+        println!("hint: \"git {} --continue\".", action.name());
+        println!(
+            "hint: You can instead skip this commit with \"git {} --skip\".",
+            action.name()
+        );
+        println!(
+            "hint: To abort and get back to the state before \"git {}\",",
+            action.name()
+        );
+        println!("hint: run \"git {} --abort\".", action.name());
+        return Ok(Outcome::Stopped);
+    }
+    if opts.no_commit {
+        return Ok(Outcome::Done);
+    }
+
+    if merged.tree_id == head_tree {
+        std::fs::write(repo.path().join("MERGE_MSG"), &message)?;
+        std::fs::write(repo.path().join(action.head_file()), format!("{}\n", id))?;
+        println!("On branch {}", get_current_branch(repo)?);
+        println!("nothing to commit, working tree clean");
+        println!(
+            "The previous {} is now empty, possibly due to conflict resolution.",
+            action.name()
+        );
+        println!("If you wish to commit it anyway, use:");
+        println!();
+        println!("    git commit --allow-empty");
+        println!();
+        println!("Otherwise, please use 'git {} --skip'", action.name());
+        return Ok(Outcome::Stopped);
+    }
+
+    let author = match action {
+        Action::CherryPick => Some(commit.author()?.to_owned()),
+        Action::Revert => None,
+    };
+    commit_step(repo, action, author, &message, merged.tree_id)?;
+    Ok(Outcome::Done)
+}
+
+/// Commit `tree` on top of `HEAD` and move `HEAD` to it, reporting the new commit like git does.
+fn commit_step(
+    repo: &gix::Repository,
+    action: Action,
+    author: Option<gix::actor::Signature>,
+    message: &str,
+    tree: gix::ObjectId,
+) -> Result<()> {
+    let head = repo.head_id().ok().map(|id| id.detach());
+    let commit_id = write_commit_as(repo, author, message, tree, head)?;
+    let summary = message.lines().next().unwrap_or_default();
+    move_head(repo, commit_id, &format!("{}: {}", action.name(), summary))?;
+
+    let branch = if repo.head()?.is_detached() {
+        "detached HEAD".to_string()
+    } else {
+        get_current_branch(repo)?
+    };
+    println!("[{} {}] {}", branch, commit_id.to_hex_with_len(7), summary);
+    Ok(())
+}
+
+/// Record `steps`, the first of which stopped, and `opts` the way git does, so either can continue the sequence.
+fn save_state(repo: &gix::Repository, steps: &[Step], opts: &Options) -> Result<()> {
+    let dir = sequencer_dir(repo);
+    std::fs::create_dir_all(&dir)?;
+
+    let mut todo = String::new();
+    for (action, id) in steps {
+        let commit = repo.find_commit(*id)?;
+        let summary = commit
+            .message_raw_sloppy()
+            .lines()
+            .next()
+            .unwrap_or_default();
+        todo.push_str(&format!("{} {} {}\n", action.verb(), id, summary.as_bstr()));
+    }
+    std::fs::write(dir.join("todo"), todo)?;
+    std::fs::write(
+        dir.join("abort-safety"),
+        repo.head_id()
+            .map(|id| format!("{}\n", id))
+            .unwrap_or_default(),
+    )?;
+
+    let mut options = String::from("[options]\n");
+    if opts.no_commit {
+        options.push_str("\tno-commit = true\n");
+    }
+    if opts.record_origin {
+        options.push_str("\trecord-origin = true\n");
+    }
+    if let Some(mainline) = opts.mainline {
+        options.push_str(&format!("\tmainline = {}\n", mainline));
+    }
+    std::fs::write(dir.join("opts"), options)?;
+    Ok(())
+}
+
+fn load_state(repo: &gix::Repository) -> Result<(Vec<Step>, Options)> {
+    let dir = sequencer_dir(repo);
+    let mut steps = Vec::new();
+    if let Ok(todo) = std::fs::read_to_string(dir.join("todo")) {
+        for line in todo.lines() {
+            let mut fields = line.split_whitespace();
+            let action = match fields.next() {
+                Some("pick" | "p") => Action::CherryPick,
+                Some("revert") => Action::Revert,
+                _ => continue,
+            };
+            let Some(id) = fields
+                .next()
+                .and_then(|rev| rev_parse_single(repo, rev).ok())
+            else {
+                continue;
+            };
+            steps.push((action, id.detach()));
+        }
+    }
+
+    let mut opts = Options::default();
+    if let Ok(config) =
+        gix::config::File::from_path_no_includes(dir.join("opts"), gix::config::Source::Local)
+    {
+        opts.no_commit = config
+            .boolean("options.no-commit")
+            .and_then(Result::ok)
+            .unwrap_or_default();
+        opts.record_origin = config
+            .boolean("options.record-origin")
+            .and_then(Result::ok)
+            .unwrap_or_default();
+        opts.mainline = config
+            .integer("options.mainline")
+            .and_then(Result::ok)
+            .and_then(|mainline| usize::try_from(mainline).ok());
+    }
+    Ok((steps, opts))
+}
+
+/// The message without the comment lines git adds to `MERGE_MSG`.
+pub(super) fn strip_comments(message: &str) -> String {
+    let lines: Vec<_> = message
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+    format!("{}\n", lines.join("\n").trim_end())
+}
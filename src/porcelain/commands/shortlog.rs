@@ -0,0 +1,189 @@
+use anyhow::Result;
+use gix::bstr::{BStr, ByteSlice};
+use std::collections::{BTreeMap, HashSet};
+
+use super::revision_utils::rev_parse;
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Only print the number of commits of each identity
+    pub summary: bool,
+    /// Sort by the number of commits instead of by name
+    pub numbered: bool,
+    /// Show the email address of each identity
+    pub email: bool,
+    /// What to group commits by, `author`, `committer` or `trailer:<key>`, by author if empty
+    pub groups: Vec<String>,
+}
+
+/// What commits are grouped by
+enum Group {
+    Author,
+    Committer,
+    /// The identities in the trailers with this key, like `Co-authored-by`
+    Trailer(String),
+}
+
+/// Summarize the commits of `revision`, or of `HEAD`, by printing their subjects grouped by identity.
+///
+/// Identities are canonicalized with the mailmap, so commits of the same person under different names or addresses end up together.
+pub fn run(revision: Option<String>, opts: Options) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    let mut groups = Vec::new();
+    for group in &opts.groups {
+        groups.push(match group.as_str() {
+            "author" => Group::Author,
+            "committer" => Group::Committer,
+            other => match other.strip_prefix("trailer:") {
+                Some(key) => Group::Trailer(key.to_string()),
+                None => {
+                    println!("fatal: unknown group type: {}", other);
+                    return Ok(());
+                }
+            },
+        });
+    }
+    if groups.is_empty() {
+        groups.push(Group::Author);
+    }
+    let spec = revision.as_deref().unwrap_or("HEAD");
+    let Some(commits) = resolve_commits(&repo, spec)? else {
+        println!(
+            "fatal: ambiguous argument '{}': unknown revision or path not in the working tree.",
+            spec
+        );
+        return Ok(());
+    };
+
+    let mailmap = repo.open_mailmap();
+    // Subjects per identity, newest first like the commits are walked.
+    let mut shortlog: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for id in commits {
+        let commit = repo.find_commit(id)?;
+        let mut identities = Vec::new();
+        for group in &groups {
+            match group {
+                Group::Author => identities.push(identity(&mailmap, commit.author()?, &opts)),
+                Group::Committer => identities.push(identity(&mailmap, commit.committer()?, &opts)),
+                Group::Trailer(key) => {
+                    let message = commit.message()?;
+                    let Some(body) = message.body() else {
+                        continue;
+                    };
+                    for trailer in body.trailers() {
+                        if trailer.token.eq_ignore_ascii_case(key.as_bytes()) {
+                            identities.push(trailer_identity(&mailmap, trailer.value, &opts));
+                        }
+                    }
+                }
+            }
+        }
+        // A commit counts once per identity, even if several groups name the same one.
+        let mut seen = HashSet::new();
+        let subject = commit.message()?.summary().to_string();
+        for identity in identities {
+            if seen.insert(identity.clone()) {
+                shortlog.entry(identity).or_default().push(subject.clone());
+            }
+        }
+    }
+
+    let mut shortlog: Vec<_> = shortlog.into_iter().collect();
+    if opts.numbered {
+        // The sort is stable, so identities with as many commits stay sorted by name.
+        shortlog.sort_by(|(_, a), (_, b)| b.len().cmp(&a.len()));
+    }
+    for (identity, subjects) in shortlog {
+        if opts.summary {
+            println!("{:>6}\t{}", subjects.len(), identity);
+            continue;
+        }
+        println!("{} ({}):", identity, subjects.len());
+        for subject in subjects.iter().rev() {
+            println!("      {}", subject);
+        }
+        println!();
+    }
+    Ok(())
+}
+
+/// The commits of `spec` newest first, which is either a single revision or a range like `A..B`,
+/// or `None` if it doesn't resolve.
+fn resolve_commits(repo: &gix::Repository, spec: &str) -> Result<Option<Vec<gix::ObjectId>>> {
+    let Ok(resolved) = rev_parse(repo, spec) else {
+        return Ok(None);
+    };
+    let peel = |id: gix::ObjectId| -> Option<gix::ObjectId> {
+        let object = repo.find_object(id).ok()?;
+        object.peel_to_commit().ok().map(|commit| commit.id)
+    };
+    let (tip, excluded) = match resolved.detach() {
+        gix::revision::plumbing::Spec::Include(id) => (peel(id), None),
+        gix::revision::plumbing::Spec::Range { from, to } => (peel(to), peel(from)),
+        _ => return Ok(None),
+    };
+    let Some(tip) = tip else {
+        return Ok(None);
+    };
+    let mut hidden = HashSet::new();
+    if let Some(excluded) = excluded {
+        for info in repo.rev_walk([excluded]).all()? {
+            hidden.insert(info?.id);
+        }
+    }
+    let mut ids = Vec::new();
+    for info in repo.rev_walk([tip]).all()? {
+        let id = info?.id;
+        if !hidden.contains(&id) {
+            ids.push(id);
+        }
+    }
+    Ok(Some(ids))
+}
+
+/// The canonical name of `signature` according to `mailmap`, with the email address if `opts` want it.
+fn identity(
+    mailmap: &gix::mailmap::Snapshot,
+    signature: gix::actor::SignatureRef<'_>,
+    opts: &Options,
+) -> String {
+    let resolved = mailmap.resolve(signature);
+    if opts.email {
+        format!("{} <{}>", resolved.name, resolved.email)
+    } else {
+        resolved.name.to_string()
+    }
+}
+
+/// The canonical name in a trailer `value`, which is used as is unless it looks like `Name <email>`.
+fn trailer_identity(mailmap: &gix::mailmap::Snapshot, value: &BStr, opts: &Options) -> String {
+    let parsed = value.strip_suffix(b">").and_then(|value| {
+        value
+            .rfind(b" <")
+            .map(|pos| (&value[..pos], &value[pos + 2..]))
+    });
+    match parsed {
+        Some((name, email)) => identity(
+            mailmap,
+            gix::actor::SignatureRef {
+                name: name.trim().as_bstr(),
+                email: email.as_bstr(),
+                time: Default::default(),
+            },
+            opts,
+        ),
+        None => value.to_string(),
+    }
+}
+
+fn open_repo() -> Option<gix::Repository> {
+    match gix::discover(".") {
+        Ok(repo) => Some(repo),
+        Err(_) => {
+            println!("fatal: not a git repository (or any of the parent directories): .git");
+            None
+        }
+    }
+}
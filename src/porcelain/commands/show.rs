@@ -2,6 +2,8 @@
 use anyhow::{Context, Result};
 use std::path::Path;
 
+use super::revision_utils::{rev_parse, rev_parse_single};
+
 pub enum TreeMode {
     Pretty,
 }
@@ -71,7 +73,7 @@ pub fn display_object(
 
 // COPIED FROM: https://github.com/Byron/gitoxide/blob/main/gitoxide-core/src/repository/cat.rs
 pub fn cat(repo: gix::Repository, revspec: &str, out: impl std::io::Write) -> anyhow::Result<()> {
-    let spec = repo.rev_parse(revspec)?;
+    let spec = rev_parse(&repo, revspec)?;
     display_object(&repo, spec, TreeMode::Pretty, out)?;
     Ok(())
 }
@@ -100,7 +102,7 @@ pub fn run(
     for object_spec in objects_to_show {
         if oneline {
             // For oneline, try to get it as commit and show abbreviated
-            match repo.rev_parse_single(object_spec.as_str()) {
+            match rev_parse_single(&repo, object_spec.as_str()) {
                 Ok(id) => {
                     if let Ok(object) = id.object() {
                         if let Ok(commit) = object.try_into_commit() {
@@ -0,0 +1,333 @@
+use anyhow::Result;
+use gix::bstr::ByteSlice;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use super::commit_utils::configured_committer;
+
+/// The kind of signature, as configured with `gpg.format` or recognized from the signature itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    OpenPgp,
+    X509,
+    Ssh,
+}
+
+impl Format {
+    /// The format configured in `gpg.format`, or `None` after reporting an unknown one.
+    pub fn configured(repo: &gix::Repository) -> Option<Self> {
+        let format = repo
+            .config_snapshot()
+            .string("gpg.format")
+            .map(|format| format.to_str_lossy().into_owned());
+        match format.as_deref() {
+            None | Some("openpgp") => Some(Format::OpenPgp),
+            Some("x509") => Some(Format::X509),
+            Some("ssh") => Some(Format::Ssh),
+            Some(other) => {
+                println!("error: unsupported value for gpg.format: {}", other);
+                None
+            }
+        }
+    }
+
+    /// The format of `signature`, judging by its first line.
+    fn of(signature: &[u8]) -> Option<Self> {
+        [
+            ("-----BEGIN PGP SIGNATURE-----", Format::OpenPgp),
+            ("-----BEGIN PGP MESSAGE-----", Format::OpenPgp),
+            ("-----BEGIN SIGNED MESSAGE-----", Format::X509),
+            ("-----BEGIN SSH SIGNATURE-----", Format::Ssh),
+        ]
+        .into_iter()
+        .find(|(start, _)| signature.starts_with(start.as_bytes()))
+        .map(|(_, format)| format)
+    }
+
+    /// The program that signs and verifies, from `gpg.<format>.program` or `gpg.program` for OpenPGP.
+    fn program(&self, repo: &gix::Repository) -> String {
+        let config = repo.config_snapshot();
+        let configured = |key: &str| {
+            config
+                .string(key)
+                .map(|program| program.to_str_lossy().into_owned())
+        };
+        match self {
+            Format::OpenPgp => configured("gpg.openpgp.program")
+                .or_else(|| configured("gpg.program"))
+                .unwrap_or_else(|| "gpg".into()),
+            Format::X509 => configured("gpg.x509.program").unwrap_or_else(|| "gpgsm".into()),
+            Format::Ssh => configured("gpg.ssh.program").unwrap_or_else(|| "ssh-keygen".into()),
+        }
+    }
+}
+
+/// The result of verifying a signature, with the output meant for humans and the machine-readable status
+#[derive(Debug, Clone, Default)]
+pub struct Verification {
+    pub good: bool,
+    pub output: String,
+    pub status: String,
+}
+
+/// Whether new commits or tags are signed by default, according to `key` like `commit.gpgSign`.
+pub fn sign_by_default(repo: &gix::Repository, key: &str) -> bool {
+    repo.config_snapshot().boolean(key).unwrap_or(false)
+}
+
+/// The key to sign with, which is `key` if given or `user.signingKey`, and the committer identity for OpenPGP otherwise.
+///
+/// Returns `None` after reporting that SSH signing has no key to use.
+pub fn signing_key(repo: &gix::Repository, key: Option<&str>) -> Result<Option<String>> {
+    if let Some(key) = key.filter(|key| !key.is_empty()) {
+        return Ok(Some(key.to_string()));
+    }
+    if let Some(key) = repo.config_snapshot().string("user.signingKey") {
+        return Ok(Some(key.to_str_lossy().into_owned()));
+    }
+    if Format::configured(repo) == Some(Format::Ssh) {
+        println!("error: user.signingKey needs to be set for ssh signing");
+        return Ok(None);
+    }
+    let committer = configured_committer(repo)?;
+    Ok(Some(format!("{} <{}>", committer.name, committer.email)))
+}
+
+/// Sign `payload` with `key` using the program for the configured format, returning the detached signature.
+///
+/// Returns `None` after reporting why signing failed.
+pub fn sign(repo: &gix::Repository, payload: &[u8], key: &str) -> Result<Option<String>> {
+    let Some(format) = Format::configured(repo) else {
+        return Ok(None);
+    };
+    let program = format.program(repo);
+    let signature = match format {
+        Format::OpenPgp | Format::X509 => {
+            let output = run(
+                Command::new(&program).args(["--status-fd=2", "-bsau", key]),
+                payload,
+            )?;
+            if !output.status.success() || output.stdout.is_empty() {
+                eprint!("{}", output.stderr.to_str_lossy());
+                println!("error: gpg failed to sign the data");
+                return Ok(None);
+            }
+            output.stdout
+        }
+        Format::Ssh => {
+            // The key may be given literally instead of as the path to a key file.
+            let literal = key
+                .strip_prefix("key::")
+                .or_else(|| key.starts_with("ssh-").then_some(key));
+            let literal_key_file = match literal {
+                Some(literal) => Some(temp_file(".git_signing_key_tmp", literal.as_bytes())?),
+                None => None,
+            };
+            let key_file = match &literal_key_file {
+                Some(file) => file.path(),
+                None => Path::new(key),
+            };
+            let buffer_file = temp_file(".git_signing_buffer_tmp", payload)?;
+            let mut command = Command::new(&program);
+            command
+                .args(["-Y", "sign", "-n", "git", "-f"])
+                .arg(key_file);
+            if literal.is_some() {
+                command.arg("-U");
+            }
+            let output = run(command.arg(buffer_file.path()), &[]);
+            let signature_file = PathBuf::from(format!("{}.sig", buffer_file.path().display()));
+            let signature = std::fs::read(&signature_file);
+            std::fs::remove_file(&signature_file).ok();
+            let output = output?;
+            match signature {
+                Ok(signature) if output.status.success() => signature,
+                _ => {
+                    let stderr = output.stderr.to_str_lossy();
+                    if stderr.contains("usage:") {
+                        println!("error: ssh-keygen -Y sign is needed for ssh signing (available in openssh version 8.2p1+)");
+                    }
+                    println!("error: {}", stderr.trim_end());
+                    return Ok(None);
+                }
+            }
+        }
+    };
+    Ok(Some(signature.to_str_lossy().replace("\r\n", "\n")))
+}
+
+/// Verify that `signature` was made over `payload`, with the program for the format of the signature.
+///
+/// Like in git, the signature is only good if the program succeeds and also reports a good signature in its output,
+/// as `GOODSIG` and `VALIDSIG` status lines for OpenPGP and X.509, or as `Good "git" signature` for SSH.
+pub fn verify(repo: &gix::Repository, payload: &[u8], signature: &[u8]) -> Result<Verification> {
+    let Some(format) = Format::of(signature) else {
+        return Ok(Verification {
+            output: "error: unknown signature format\n".into(),
+            ..Default::default()
+        });
+    };
+    let program = format.program(repo);
+    let signature_file = temp_file(".git_vtag_tmp", signature)?;
+    match format {
+        Format::OpenPgp | Format::X509 => {
+            let output = run(
+                Command::new(&program)
+                    .args(["--keyid-format=long", "--status-fd=1", "--verify"])
+                    .arg(signature_file.path())
+                    .arg("-"),
+                payload,
+            )?;
+            let status = output.stdout.to_str_lossy().into_owned();
+            let has_status = |keyword: &str| {
+                status.lines().any(|line| {
+                    line.strip_prefix("[GNUPG:] ")
+                        .and_then(|line| line.split_whitespace().next())
+                        == Some(keyword)
+                })
+            };
+            Ok(Verification {
+                good: output.status.success() && has_status("GOODSIG") && has_status("VALIDSIG"),
+                output: output.stderr.to_str_lossy().into_owned(),
+                status,
+            })
+        }
+        Format::Ssh => verify_ssh(repo, &program, payload, signature_file.path()),
+    }
+}
+
+/// Whether the output of `ssh-keygen -Y verify` or `-Y check-novalidate` says the signature is good.
+fn is_good_ssh_signature(output: &str) -> bool {
+    output
+        .lines()
+        .any(|line| line.starts_with("Good \"git\" signature"))
+}
+
+/// Verify an SSH signature against `gpg.ssh.allowedSignersFile`, trying each principal that may have made it.
+fn verify_ssh(
+    repo: &gix::Repository,
+    program: &str,
+    payload: &[u8],
+    signature_file: &Path,
+) -> Result<Verification> {
+    let allowed_signers = match repo
+        .config_snapshot()
+        .trusted_path("gpg.ssh.allowedSignersFile")
+    {
+        Some(Ok(path)) if path.exists() => path.into_owned(),
+        _ => {
+            return Ok(Verification {
+                output: "error: gpg.ssh.allowedSignersFile needs to be configured and exist for ssh signature verification\n".into(),
+                ..Default::default()
+            })
+        }
+    };
+
+    let principals = run(
+        Command::new(program)
+            .args(["-Y", "find-principals", "-f"])
+            .arg(&allowed_signers)
+            .arg("-s")
+            .arg(signature_file),
+        &[],
+    )?;
+    let principals = principals.stdout.to_str_lossy().into_owned();
+    let mut verification = Verification::default();
+    for principal in principals.lines().filter(|line| !line.is_empty()) {
+        let output = run(
+            Command::new(program)
+                .args(["-Y", "verify", "-n", "git", "-f"])
+                .arg(&allowed_signers)
+                .args(["-I", principal, "-s"])
+                .arg(signature_file),
+            payload,
+        )?;
+        let text = format!(
+            "{}{}",
+            output.stdout.to_str_lossy(),
+            output.stderr.to_str_lossy()
+        );
+        verification = Verification {
+            good: output.status.success() && is_good_ssh_signature(&text),
+            status: text.clone(),
+            output: text,
+        };
+        if verification.good {
+            return Ok(verification);
+        }
+    }
+    if !verification.output.is_empty() {
+        return Ok(verification);
+    }
+
+    // Without a matching principal the signature can only be checked for being intact.
+    let output = run(
+        Command::new(program)
+            .args(["-Y", "check-novalidate", "-n", "git", "-s"])
+            .arg(signature_file),
+        payload,
+    )?;
+    let text = format!(
+        "{}{}No principal matched.\n",
+        output.stdout.to_str_lossy(),
+        output.stderr.to_str_lossy()
+    );
+    Ok(Verification {
+        good: output.status.success() && is_good_ssh_signature(&text),
+        status: text.clone(),
+        output: text,
+    })
+}
+
+/// Split the raw data of a signed tag into what was signed and the signature that was appended to it.
+pub fn split_signature(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let mut start = None;
+    let mut offset = 0;
+    for line in data.lines_with_terminator() {
+        if Format::of(line).is_some() {
+            start = Some(offset);
+        }
+        offset += line.len();
+    }
+    start.map(|start| data.split_at(start))
+}
+
+/// Run `command` with `stdin` as its input, collecting its output.
+fn run(command: &mut Command, stdin: &[u8]) -> Result<std::process::Output> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut pipe = child.stdin.take().expect("stdin is piped");
+    // Writing from another thread keeps a program that answers before reading everything from blocking us.
+    std::thread::scope(|scope| {
+        scope.spawn(move || pipe.write_all(stdin).ok());
+        Ok(child.wait_with_output()?)
+    })
+}
+
+/// Write `content` to a new file in the temporary directory, named after `prefix` like git does.
+///
+/// The file is created exclusively under a random name, so nobody else can have prepared it, and is removed when dropped.
+fn temp_file(prefix: &str, content: &[u8]) -> Result<tempfile::NamedTempFile> {
+    let mut file = tempfile::Builder::new().prefix(prefix).tempfile()?;
+    file.write_all(content)?;
+    file.flush()?;
+    Ok(file)
+}
+
+/// Verify the signature in the `gpgsig` header of `commit`, or return `None` if it isn't signed.
+pub fn verify_commit(
+    repo: &gix::Repository,
+    commit: &gix::Commit<'_>,
+) -> Result<Option<Verification>> {
+    let Some((signature, signed_data)) = commit.signature()? else {
+        return Ok(None);
+    };
+    // The header value lost the newline that ended the signature when it was written.
+    let mut signature = signature.into_owned();
+    signature.push(b'\n');
+    Ok(Some(verify(repo, &signed_data.to_bstring(), &signature)?))
+}
@@ -0,0 +1,571 @@
+use anyhow::Result;
+use gix::bstr::{BString, ByteSlice};
+use gix::refs::transaction::{Change, LogChange, PreviousValue, RefEdit, RefLog};
+use std::path::{Path, PathBuf};
+
+use super::commit_utils::write_commit;
+use super::diff_utils::{tree_changes, write_patch, write_stat};
+use super::index_utils::{
+    add_file_to_index, checkout_index, checkout_tree, get_current_branch, keep_stat,
+    matches_pathspec, normalize_pathspecs, remove_empty_parents, replace_index_entries, write_tree,
+};
+use super::merge_utils::{merge_into_worktree, Labels};
+
+const STASH_REF: &str = "refs/stash";
+
+#[derive(Debug, Clone, Default)]
+pub struct PushOptions {
+    pub message: Option<String>,
+    /// Also stash untracked files and remove them from the worktree
+    pub include_untracked: bool,
+    pub quiet: bool,
+}
+
+/// A stash commit that was written, but isn't necessarily recorded in `refs/stash`
+pub(super) struct Created {
+    pub id: gix::ObjectId,
+    /// The message of the commit, also used for the reflog of `refs/stash`
+    pub message: String,
+    /// The untracked files that were stashed
+    untracked: Vec<BString>,
+}
+
+/// How applying a stash commit went
+pub(super) enum Applied {
+    Clean,
+    /// The changes were applied, but some of them conflicted and need to be resolved
+    Conflicts,
+    /// Nothing was applied, as explained by what was printed
+    Refused,
+}
+
+/// Save local changes to `paths`, or all of them, as a new stash entry and reset them to `HEAD`.
+pub fn push(paths: Vec<PathBuf>, opts: PushOptions) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    if repo.workdir().is_none() {
+        println!("fatal: this operation must be run in a work tree");
+        return Ok(());
+    }
+    if repo.head()?.is_unborn() {
+        println!("You do not have the initial commit yet");
+        return Ok(());
+    }
+    if !check_unmerged(&*repo.index_or_empty()?) {
+        return Ok(());
+    }
+
+    let specs = normalize_pathspecs(&paths);
+    let Some(stash) = create(
+        &repo,
+        &specs,
+        opts.message.as_deref(),
+        opts.include_untracked,
+    )?
+    else {
+        println!("No local changes to save");
+        return Ok(());
+    };
+    update_stash_ref(&repo, stash.id, &stash.message)?;
+    reset_stashed(&repo, &specs, &stash)?;
+
+    if !opts.quiet {
+        println!("Saved working directory and index state {}", stash.message);
+    }
+    Ok(())
+}
+
+/// Apply the changes recorded in `stash` on top of the current worktree, and drop it afterwards with `pop`.
+///
+/// With `restore_index`, changes that were staged are staged again, otherwise only new files are.
+pub fn apply(stash: Option<String>, restore_index: bool, pop: bool, quiet: bool) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    if repo.workdir().is_none() {
+        println!("fatal: this operation must be run in a work tree");
+        return Ok(());
+    }
+    let Some((position, stash_id)) = resolve(&repo, stash.as_deref())? else {
+        return Ok(());
+    };
+
+    match apply_commit(&repo, stash_id, restore_index, quiet)? {
+        Applied::Clean if pop => drop_entry(&repo, position, stash_id, quiet),
+        Applied::Clean => Ok(()),
+        Applied::Conflicts | Applied::Refused => {
+            if pop {
+                println!("The stash entry is kept in case you need it again.");
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Write a stash commit for the local changes to `specs`, or all of them, without changing anything else.
+///
+/// The commit is laid out like git does: a commit of the worktree whose parents are `HEAD`,
+/// a commit of the index, and optionally a commit of the untracked files. Returns `None` if there is nothing to stash.
+pub(super) fn create(
+    repo: &gix::Repository,
+    specs: &[BString],
+    message: Option<&str>,
+    include_untracked: bool,
+) -> Result<Option<Created>> {
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| anyhow::anyhow!("No worktree found"))?;
+    let head = repo.head_commit()?;
+    let head_tree = head.tree_id()?.detach();
+    let index = repo.index_or_load_from_head_or_empty()?.into_owned();
+
+    let mut worktree_index = index.clone();
+    let mut untracked = Vec::new();
+    let (mut pipeline, _) = repo.filter_pipeline(None)?;
+    let untracked_files = if include_untracked {
+        gix::status::UntrackedFiles::Files
+    } else {
+        gix::status::UntrackedFiles::None
+    };
+    for item in repo
+        .status(gix::progress::Discard)?
+        .untracked_files(untracked_files)
+        .index_worktree_submodules(None)
+        .into_index_worktree_iter(Vec::new())?
+    {
+        match item? {
+            gix::status::index_worktree::Item::Modification { rela_path, .. }
+                if matches_pathspec(specs, rela_path.as_bstr()) =>
+            {
+                let file = workdir.join(gix::path::from_bstr(rela_path.as_bstr()));
+                if file.symlink_metadata().is_ok() {
+                    add_file_to_index(repo, &mut pipeline, &file, &mut worktree_index)?;
+                } else {
+                    worktree_index.remove_entries(|_, path, _| path == rela_path.as_bstr());
+                    worktree_index.remove_tree();
+                }
+            }
+            gix::status::index_worktree::Item::DirectoryContents { entry, .. }
+                if entry.status == gix::dir::entry::Status::Untracked
+                    && matches_pathspec(specs, entry.rela_path.as_bstr()) =>
+            {
+                untracked.push(entry.rela_path);
+            }
+            _ => {}
+        }
+    }
+
+    // With paths, only their changes are stashed, everything else is recorded as it is in `HEAD`.
+    let head_index = super::promisor::index_from_tree(repo, &head_tree)?;
+    let limit_to_paths = |index: &gix::index::File| {
+        if specs.is_empty() {
+            return index.clone();
+        }
+        let mut limited = head_index.clone();
+        replace_index_entries(&mut limited, Some(index), specs);
+        limited
+    };
+    let index_tree = write_tree(repo, &limit_to_paths(&index))?;
+    let worktree_tree = write_tree(repo, &limit_to_paths(&worktree_index))?;
+    if index_tree == head_tree && worktree_tree == head_tree && untracked.is_empty() {
+        return Ok(None);
+    }
+
+    let branch = if repo.head()?.is_detached() {
+        "(no branch)".to_string()
+    } else {
+        get_current_branch(repo)?
+    };
+    let subject = format!(
+        "{}: {} {}",
+        branch,
+        head.id.to_hex_with_len(7),
+        head.message_raw_sloppy()
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_str_lossy()
+    );
+
+    let index_commit = write_commit(
+        repo,
+        &format!("index on {}\n", subject),
+        index_tree,
+        [head.id],
+    )?;
+    let mut parents = vec![head.id, index_commit];
+    if !untracked.is_empty() {
+        let mut untracked_index = gix::index::File::from_state(
+            gix::index::State::new(repo.object_hash()),
+            repo.index_path(),
+        );
+        for path in &untracked {
+            let file = workdir.join(gix::path::from_bstr(path.as_bstr()));
+            add_file_to_index(repo, &mut pipeline, &file, &mut untracked_index)?;
+        }
+        let untracked_tree = write_tree(repo, &untracked_index)?;
+        parents.push(write_commit(
+            repo,
+            &format!("untracked files on {}\n", subject),
+            untracked_tree,
+            None,
+        )?);
+    }
+    let message = match message {
+        Some(message) => format!("On {}: {}", branch, message),
+        None => format!("WIP on {}", subject),
+    };
+    let id = write_commit(repo, &format!("{}\n", message), worktree_tree, parents)?;
+    Ok(Some(Created {
+        id,
+        message,
+        untracked,
+    }))
+}
+
+/// Reset the changes recorded in `stash` to `HEAD`, like `git reset --hard` limited to `specs`, and remove stashed untracked files.
+pub(super) fn reset_stashed(
+    repo: &gix::Repository,
+    specs: &[BString],
+    stash: &Created,
+) -> Result<()> {
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| anyhow::anyhow!("No worktree found"))?;
+    let head_tree = repo.head_tree_id()?.detach();
+    if specs.is_empty() {
+        checkout_tree(repo, head_tree)?;
+    } else {
+        let head_index = super::promisor::index_from_tree(repo, &head_tree)?;
+        let old_index = repo.index_or_load_from_head_or_empty()?.into_owned();
+        let mut index = old_index.clone();
+        replace_index_entries(&mut index, Some(&head_index), specs);
+        for entry in old_index.entries() {
+            let path = entry.path(&old_index);
+            if matches_pathspec(specs, path) && head_index.entry_by_path(path).is_none() {
+                let file = workdir.join(gix::path::from_bstr(path));
+                if std::fs::remove_file(&file).is_ok() {
+                    remove_empty_parents(workdir, &file);
+                }
+            }
+        }
+        let mut updates = head_index.clone();
+        updates.remove_entries(|_, path, _| !matches_pathspec(specs, path));
+        checkout_index(repo, &mut updates)?;
+        keep_stat(&mut index, &old_index);
+        keep_stat(&mut index, &updates);
+        index.write(Default::default())?;
+    }
+    for path in &stash.untracked {
+        let file = workdir.join(gix::path::from_bstr(path.as_bstr()));
+        if std::fs::remove_file(&file).is_ok() {
+            remove_empty_parents(workdir, &file);
+        }
+    }
+    Ok(())
+}
+
+/// Apply the stash commit `stash_id` on top of the current index and worktree, showing the status afterwards unless `quiet`.
+pub(super) fn apply_commit(
+    repo: &gix::Repository,
+    stash_id: gix::ObjectId,
+    restore_index: bool,
+    quiet: bool,
+) -> Result<Applied> {
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| anyhow::anyhow!("No worktree found"))?;
+    let stash_commit = repo.find_commit(stash_id)?;
+    let parents: Vec<_> = stash_commit.parent_ids().map(|id| id.detach()).collect();
+    if parents.len() < 2 {
+        println!("fatal: '{}' is not a stash-like commit", stash_id);
+        return Ok(Applied::Refused);
+    }
+    let tree_of = |id: gix::ObjectId| -> Result<gix::ObjectId> {
+        Ok(repo.find_commit(id)?.tree_id()?.detach())
+    };
+    let base_tree = tree_of(parents[0])?;
+    let stashed_index_tree = tree_of(parents[1])?;
+    let stashed_tree = stash_commit.tree_id()?.detach();
+
+    let index = repo.index_or_load_from_head_or_empty()?.into_owned();
+    if !check_unmerged(&index) {
+        return Ok(Applied::Refused);
+    }
+    let current_tree = write_tree(repo, &index)?;
+
+    // Staged changes are applied to the current index first, and have to apply cleanly.
+    let index_tree = if restore_index && stashed_index_tree != base_tree {
+        super::promisor::fetch_changed_blobs(repo, base_tree, &[current_tree, stashed_index_tree])?;
+        let mut outcome = super::promisor::on_demand(repo, || {
+            Ok(repo.merge_trees(
+                base_tree,
+                current_tree,
+                stashed_index_tree,
+                gix::merge::blob::builtin_driver::text::Labels {
+                    ancestor: None,
+                    current: None,
+                    other: None,
+                },
+                repo.tree_merge_options()?,
+            )?)
+        })?;
+        if outcome.has_unresolved_conflicts(gix::merge::tree::TreatAsUnresolved::git()) {
+            println!("Conflicts in index. Try without --index.");
+            return Ok(Applied::Refused);
+        }
+        outcome.tree.write()?.detach()
+    } else {
+        current_tree
+    };
+
+    let untracked_index = match parents.get(2) {
+        Some(id) => Some(super::promisor::index_from_tree(repo, &tree_of(*id)?)?),
+        None => None,
+    };
+    if let Some(untracked_index) = &untracked_index {
+        let mut in_the_way = false;
+        for entry in untracked_index.entries() {
+            let path = entry.path(untracked_index);
+            if workdir.join(gix::path::from_bstr(path)).exists() {
+                println!("{} already exists, no checkout", path);
+                in_the_way = true;
+            }
+        }
+        if in_the_way {
+            println!("error: could not restore untracked files from stash");
+            return Ok(Applied::Refused);
+        }
+    }
+
+    let Some(merged) = merge_into_worktree(
+        repo,
+        base_tree,
+        current_tree,
+        stashed_tree,
+        Labels {
+            base: "Stash base",
+            ours: "Updated upstream",
+            theirs: "Stashed changes",
+        },
+    )?
+    else {
+        return Ok(Applied::Refused);
+    };
+
+    let mut new_index = merged.index;
+    if merged.conflicts.is_empty() {
+        // Only the changes that were staged stay staged, along with files the stash added.
+        let mut staged = super::promisor::index_from_tree(repo, &index_tree)?;
+        if !restore_index {
+            let base_index = super::promisor::index_from_tree(repo, &base_tree)?;
+            let added: Vec<BString> = new_index
+                .entries()
+                .iter()
+                .map(|entry| entry.path(&new_index).to_owned())
+                .filter(|path| {
+                    base_index.entry_by_path(path.as_bstr()).is_none()
+                        && staged.entry_by_path(path.as_bstr()).is_none()
+                })
+                .collect();
+            if !added.is_empty() {
+                replace_index_entries(&mut staged, Some(&new_index), &added);
+            }
+        }
+        keep_stat(&mut staged, &new_index);
+        new_index = staged;
+    }
+    new_index.write(Default::default())?;
+
+    if let Some(mut untracked_index) = untracked_index {
+        checkout_index(repo, &mut untracked_index)?;
+    }
+
+    if !quiet {
+        super::status::run(Path::new("."), false, false)?;
+    }
+    Ok(if merged.conflicts.is_empty() {
+        Applied::Clean
+    } else {
+        Applied::Conflicts
+    })
+}
+
+/// List all stash entries, most recent first.
+pub fn list() -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    for (position, (_, message)) in entries(&repo)?.iter().enumerate() {
+        println!("stash@{{{}}}: {}", position, message);
+    }
+    Ok(())
+}
+
+/// Show the changes recorded in `stash` as a diffstat, or as a patch.
+pub fn show(stash: Option<String>, patch: bool) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    let Some((_, stash_id)) = resolve(&repo, stash.as_deref())? else {
+        return Ok(());
+    };
+    let stash_commit = repo.find_commit(stash_id)?;
+    let Some(base) = stash_commit.parent_ids().next() else {
+        println!("fatal: '{}' is not a stash-like commit", stash_id);
+        return Ok(());
+    };
+    let base_tree = repo.find_commit(base)?.tree_id()?.detach();
+    let changes = tree_changes(&repo, base_tree, stash_commit.tree_id()?.detach())?;
+    let out = std::io::stdout();
+    if patch {
+        write_patch(&repo, &changes, out.lock())
+    } else {
+        write_stat(&repo, &changes, out.lock())
+    }
+}
+
+/// Remove a single stash entry.
+pub fn drop(stash: Option<String>, quiet: bool) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    let Some((position, stash_id)) = resolve(&repo, stash.as_deref())? else {
+        return Ok(());
+    };
+    drop_entry(&repo, position, stash_id, quiet)
+}
+
+/// Remove all stash entries.
+pub fn clear() -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    if let Some(stash) = repo.try_find_reference(STASH_REF)? {
+        stash.delete()?;
+    }
+    Ok(())
+}
+
+fn open_repo() -> Option<gix::Repository> {
+    match gix::discover(".") {
+        Ok(repo) => Some(repo),
+        Err(_) => {
+            println!("fatal: not a git repository (or any of the parent directories): .git");
+            None
+        }
+    }
+}
+
+/// Stash entries as commit and reflog message, most recent first, so their position is what `stash@{n}` refers to.
+fn entries(repo: &gix::Repository) -> Result<Vec<(gix::ObjectId, BString)>> {
+    let Some(stash) = repo.try_find_reference(STASH_REF)? else {
+        return Ok(Vec::new());
+    };
+    let mut log = stash.log_iter();
+    let Some(lines) = log.rev()? else {
+        return Ok(Vec::new());
+    };
+    let mut entries = Vec::new();
+    for line in lines {
+        let line = line?;
+        entries.push((line.new_oid, line.message));
+    }
+    Ok(entries)
+}
+
+/// Turn `stash`, like `stash@{1}` or just `1`, into the position and commit of the entry, printing an error if there is none.
+fn resolve(repo: &gix::Repository, stash: Option<&str>) -> Result<Option<(usize, gix::ObjectId)>> {
+    let entries = entries(repo)?;
+    if entries.is_empty() {
+        println!("No stash entries found.");
+        return Ok(None);
+    }
+    let spec = stash.unwrap_or("stash@{0}");
+    let position = spec
+        .strip_prefix("stash@{")
+        .or_else(|| spec.strip_prefix("refs/stash@{"))
+        .and_then(|rest| rest.strip_suffix('}'))
+        .unwrap_or(if spec == "stash" { "0" } else { spec })
+        .parse::<usize>()
+        .ok();
+    match position.and_then(|position| entries.get(position).map(|(id, _)| (position, *id))) {
+        Some(entry) => Ok(Some(entry)),
+        None => {
+            println!("error: {} is not a valid reference", spec);
+            Ok(None)
+        }
+    }
+}
+
+/// Point `refs/stash` at `id`, adding `message` to its reflog which holds all older entries.
+pub(super) fn update_stash_ref(
+    repo: &gix::Repository,
+    id: gix::ObjectId,
+    message: &str,
+) -> Result<()> {
+    repo.edit_reference(RefEdit {
+        change: Change::Update {
+            log: LogChange {
+                mode: RefLog::AndReference,
+                force_create_reflog: true,
+                message: message.into(),
+            },
+            expected: PreviousValue::Any,
+            new: gix::refs::Target::Object(id),
+        },
+        name: STASH_REF.try_into()?,
+        deref: false,
+    })?;
+    Ok(())
+}
+
+/// Remove the entry at `position` from the reflog of `refs/stash`, moving the ref to the next entry if it was the latest.
+fn drop_entry(
+    repo: &gix::Repository,
+    position: usize,
+    id: gix::ObjectId,
+    quiet: bool,
+) -> Result<()> {
+    let log_path = repo.common_dir().join("logs").join(STASH_REF);
+    let log = std::fs::read(&log_path)?;
+    let mut lines: Vec<&[u8]> = log.lines_with_terminator().collect();
+    // The reflog is oldest first, while positions count from the most recent entry.
+    let index = lines.len() - 1 - position;
+    lines.remove(index);
+
+    if lines.is_empty() {
+        repo.find_reference(STASH_REF)?.delete()?;
+    } else {
+        if position == 0 {
+            let latest = entries(repo)?[1].0;
+            // Moving the ref adds a reflog line, which is discarded when the log is rewritten below.
+            update_stash_ref(repo, latest, "drop")?;
+        }
+        std::fs::write(&log_path, lines.concat())?;
+    }
+
+    if !quiet {
+        println!("Dropped refs/stash@{{{}}} ({})", position, id);
+    }
+    Ok(())
+}
+
+/// Check that `index` has no conflicts, which can't be stashed or merged with, listing them like git if it has.
+fn check_unmerged(index: &gix::index::File) -> bool {
+    let mut unmerged = Vec::new();
+    for entry in index.entries() {
+        let path = entry.path(index);
+        if entry.stage_raw() != 0 && unmerged.last() != Some(&path) {
+            unmerged.push(path);
+        }
+    }
+    for path in &unmerged {
+        println!("{}: needs merge", path);
+    }
+    if !unmerged.is_empty() {
+        println!("error: could not write index");
+    }
+    unmerged.is_empty()
+}
@@ -0,0 +1,701 @@
+use anyhow::{anyhow, bail, Result};
+use gix::bstr::{BStr, ByteSlice};
+use gix::submodule::config::Update;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+
+use super::clone::{clone_submodule, Options as CloneOptions};
+use super::config_utils::{open_local_config, write_local_config};
+use super::index_utils::checkout_tree;
+use super::ref_utils::detach_head;
+use super::submodule_utils::{absorb_git_dir, register, resolve_url};
+
+/// Show the state of submodules, prefixed with `-` if not initialized, `+` if the checked out commit
+/// differs from the one recorded in the superproject, and `U` if it has merge conflicts.
+pub fn status(paths: Vec<PathBuf>, recursive: bool) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    status_inner(&repo, &paths, recursive, Path::new(""))
+}
+
+fn status_inner(
+    repo: &gix::Repository,
+    paths: &[PathBuf],
+    recursive: bool,
+    prefix: &Path,
+) -> Result<()> {
+    let index = repo.index_or_empty()?;
+    for submodule in selected(repo, paths)? {
+        let path = submodule.path()?.into_owned();
+        let display_path = prefix.join(gix::path::from_bstr(path.as_bstr()));
+        let display_path = display_path.display();
+
+        let is_conflicted = index
+            .entries()
+            .iter()
+            .any(|entry| entry.path(&index) == path && entry.stage_raw() != 0);
+        if is_conflicted {
+            println!("U{} {}", repo.object_hash().null(), display_path);
+            continue;
+        }
+
+        let Some(recorded) = submodule.index_id()? else {
+            continue;
+        };
+        let submodule_repo = match submodule.open()? {
+            Some(submodule_repo) if submodule.state()?.worktree_checkout => submodule_repo,
+            _ => {
+                println!("-{} {}", recorded, display_path);
+                continue;
+            }
+        };
+
+        let head = submodule_repo.head_id()?.detach();
+        let flag = if head == recorded { ' ' } else { '+' };
+        println!(
+            "{}{} {} ({})",
+            flag,
+            head,
+            display_path,
+            describe(&submodule_repo, head)?
+        );
+
+        if recursive {
+            status_inner(
+                &submodule_repo,
+                &[],
+                recursive,
+                &prefix.join(gix::path::from_bstr(path.as_bstr())),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Name `id` by the tag, branch or remote-tracking branch pointing at it, or by its abbreviated hash.
+fn describe(repo: &gix::Repository, id: gix::ObjectId) -> Result<String> {
+    let mut best: Option<(usize, String)> = None;
+    for reference in repo.references()?.all()? {
+        let mut reference = reference.map_err(|e| anyhow!("{}", e))?;
+        let name = reference.name().as_bstr().to_owned();
+        let (rank, short) = if let Some(tag) = name.strip_prefix(b"refs/tags/") {
+            (0, tag.to_str_lossy().into_owned())
+        } else if let Some(branch) = name.strip_prefix(b"refs/") {
+            let rank = if branch.starts_with(b"heads/") { 1 } else { 2 };
+            (rank, branch.to_str_lossy().into_owned())
+        } else {
+            continue;
+        };
+        if best
+            .as_ref()
+            .is_some_and(|(best_rank, _)| *best_rank <= rank)
+        {
+            continue;
+        }
+        if reference
+            .peel_to_id_in_place()
+            .ok()
+            .map(|peeled| peeled.detach())
+            == Some(id)
+        {
+            best = Some((rank, short));
+        }
+    }
+    Ok(best
+        .map(|(_, name)| name)
+        .unwrap_or_else(|| id.to_hex_with_len(7).to_string()))
+}
+
+/// Copy the URLs of submodules from `.gitmodules` into the repository configuration
+pub fn init(paths: Vec<PathBuf>) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    for submodule in selected(&repo, &paths)? {
+        init_submodule(&repo, &submodule)?;
+    }
+    Ok(())
+}
+
+fn init_submodule(repo: &gix::Repository, submodule: &gix::Submodule<'_>) -> Result<()> {
+    if is_initialized(repo, submodule.name()) {
+        return Ok(());
+    }
+    let url = resolve_url(repo, &submodule.url()?.to_bstring().to_string());
+    register(repo, submodule.name(), &url)?;
+    println!(
+        "Submodule '{}' ({}) registered for path '{}'",
+        submodule.name(),
+        url,
+        submodule.path()?
+    );
+    Ok(())
+}
+
+fn is_initialized(repo: &gix::Repository, name: &BStr) -> bool {
+    repo.config_snapshot()
+        .string(format!("submodule.{}.url", name).as_str())
+        .is_some()
+}
+
+/// Clone missing submodules and check out the commits recorded in the superproject
+pub fn update(
+    paths: Vec<PathBuf>,
+    init: bool,
+    recursive: bool,
+    depth: Option<NonZeroU32>,
+    force: bool,
+    quiet: bool,
+) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    let opts = CloneOptions {
+        recurse_submodules: recursive,
+        shallow: depth
+            .map(gix::remote::fetch::Shallow::DepthAtRemote)
+            .unwrap_or_default(),
+        quiet,
+        ..Default::default()
+    };
+    update_inner(&repo, &paths, init, force, &opts)
+}
+
+fn update_inner(
+    repo: &gix::Repository,
+    paths: &[PathBuf],
+    init: bool,
+    force: bool,
+    opts: &CloneOptions,
+) -> Result<()> {
+    for submodule in selected(repo, paths)? {
+        if init {
+            init_submodule(repo, &submodule)?;
+        } else if !is_initialized(repo, submodule.name()) {
+            continue;
+        }
+
+        let worktree = if submodule.state()?.repository_exists {
+            match update_existing(&submodule, force, opts)? {
+                Some(worktree) => worktree,
+                None => continue,
+            }
+        } else {
+            match clone_submodule(repo, &submodule, opts)? {
+                Some(worktree) => worktree,
+                None => continue,
+            }
+        };
+
+        if opts.recurse_submodules {
+            let submodule_repo = gix::open(&worktree)?;
+            update_inner(&submodule_repo, &[], init, force, opts)?;
+        }
+    }
+    Ok(())
+}
+
+/// Bring an already cloned submodule to the commit recorded in the superproject.
+///
+/// Like `git checkout`, this refuses to touch a submodule with local modifications unless `force` is set,
+/// which also checks out submodules that are at the recorded commit already.
+fn update_existing(
+    submodule: &gix::Submodule<'_>,
+    force: bool,
+    opts: &CloneOptions,
+) -> Result<Option<PathBuf>> {
+    let path = submodule.path()?.into_owned();
+    let Some(recorded) = submodule.index_id()? else {
+        return Ok(None);
+    };
+    let Some(submodule_repo) = submodule.open()? else {
+        return Ok(None);
+    };
+    let worktree = submodule.work_dir()?;
+    let update = submodule.update()?.unwrap_or_default();
+    let is_at_recorded = submodule_repo.head_id().ok().map(|id| id.detach()) == Some(recorded);
+    if is_at_recorded && !(force && update == Update::Checkout) {
+        return Ok(Some(worktree));
+    }
+
+    if update == Update::None {
+        if !opts.quiet {
+            println!("Skipping submodule '{}'", path);
+        }
+        return Ok(None);
+    }
+    // Only checkouts can be forced, rebasing and merging always keep local modifications.
+    let discard = force && update == Update::Checkout;
+    if !discard && !matches!(update, Update::Command(_)) && submodule_repo.is_dirty()? {
+        bail!(
+            "Unable to update submodule path '{}' to '{}' as it has local modifications (use --force to discard them)",
+            path,
+            recorded
+        );
+    }
+
+    if !submodule_repo.has_object(recorded) {
+        fetch_default_remote(&submodule_repo, &opts.shallow)?;
+    }
+    if !submodule_repo.has_object(recorded) {
+        bail!(
+            "Fetched in submodule path '{}', but it did not contain {}",
+            path,
+            recorded
+        );
+    }
+
+    match &update {
+        Update::None => unreachable!("skipped above"),
+        Update::Checkout => {
+            detach_head(
+                &submodule_repo,
+                recorded,
+                &format!("checkout: moving to {}", recorded),
+            )?;
+            let tree_id = submodule_repo.find_commit(recorded)?.tree_id()?.detach();
+            checkout_tree(&submodule_repo, tree_id)?;
+            if !opts.quiet {
+                println!("Submodule path '{}': checked out '{}'", path, recorded);
+            }
+        }
+        Update::Rebase | Update::Merge => {
+            // Without local commits on top, rebasing or merging amounts to a fast-forward.
+            let head = submodule_repo.head_id()?.detach();
+            let is_fast_forward = submodule_repo
+                .find_commit(recorded)?
+                .id()
+                .ancestors()
+                .all()?
+                .filter_map(Result::ok)
+                .any(|info| info.id == head);
+            let verb = if update == Update::Rebase {
+                "rebase"
+            } else {
+                "merge"
+            };
+            if !is_fast_forward {
+                bail!(
+                    "Unable to {} '{}' in submodule path '{}'",
+                    verb,
+                    recorded,
+                    path
+                );
+            }
+            let message = format!("{}: fast-forward to {}", verb, recorded);
+            match submodule_repo.head_name()? {
+                Some(branch) => {
+                    submodule_repo.reference(
+                        branch.as_bstr(),
+                        recorded,
+                        gix::refs::transaction::PreviousValue::Any,
+                        message.as_str(),
+                    )?;
+                }
+                None => detach_head(&submodule_repo, recorded, &message)?,
+            }
+            let tree_id = submodule_repo.find_commit(recorded)?.tree_id()?.detach();
+            checkout_tree(&submodule_repo, tree_id)?;
+            if !opts.quiet {
+                let done = if update == Update::Rebase {
+                    "rebased into"
+                } else {
+                    "merged in"
+                };
+                println!("Submodule path '{}': {} '{}'", path, done, recorded);
+            }
+        }
+        Update::Command(command) => {
+            run_shell(&format!("{} {}", command, recorded), &worktree, &[]).map_err(|_| {
+                anyhow!(
+                    "Execution of '{} {}' failed in submodule path '{}'",
+                    command,
+                    recorded,
+                    path
+                )
+            })?;
+            if !opts.quiet {
+                println!("Submodule path '{}': '{} {}'", path, command, recorded);
+            }
+        }
+    }
+    Ok(Some(worktree))
+}
+
+fn fetch_default_remote(
+    repo: &gix::Repository,
+    shallow: &gix::remote::fetch::Shallow,
+) -> Result<()> {
+    let remote = repo
+        .find_default_remote(gix::remote::Direction::Fetch)
+        .ok_or_else(|| anyhow!("submodule has no remote to fetch from"))??;
+    remote
+        .connect(gix::remote::Direction::Fetch)?
+        .prepare_fetch(gix::progress::Discard, Default::default())?
+        .with_shallow(shallow.clone())
+        .receive(gix::progress::Discard, &gix::interrupt::IS_INTERRUPTED)?;
+    Ok(())
+}
+
+/// Update the remote URL of submodules to the one recorded in `.gitmodules`
+pub fn sync(paths: Vec<PathBuf>, recursive: bool) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    sync_inner(&repo, &paths, recursive)
+}
+
+fn sync_inner(repo: &gix::Repository, paths: &[PathBuf], recursive: bool) -> Result<()> {
+    for submodule in selected(repo, paths)? {
+        if !is_initialized(repo, submodule.name()) {
+            continue;
+        }
+        let url = resolve_url(repo, &submodule.url()?.to_bstring().to_string());
+        println!("Synchronizing submodule url for '{}'", submodule.path()?);
+
+        let mut config = open_local_config(repo)?;
+        config.set_raw_value_by("submodule", Some(submodule.name()), "url", url.as_str())?;
+        write_local_config(repo, &config)?;
+
+        let Some(submodule_repo) = submodule.open()? else {
+            continue;
+        };
+        let remote_name = submodule_repo
+            .remote_default_name(gix::remote::Direction::Fetch)
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| "origin".into());
+        let mut config = open_local_config(&submodule_repo)?;
+        config.set_raw_value_by(
+            "remote",
+            Some(remote_name.as_str().into()),
+            "url",
+            url.as_str(),
+        )?;
+        write_local_config(&submodule_repo, &config)?;
+
+        if recursive {
+            sync_inner(&submodule_repo, &[], recursive)?;
+        }
+    }
+    Ok(())
+}
+
+/// Run a shell command in each checked out submodule
+pub fn foreach(command: Vec<String>, recursive: bool, quiet: bool) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    let Some(toplevel) = repo.workdir().map(Path::to_owned) else {
+        println!("fatal: this operation must be run in a work tree");
+        return Ok(());
+    };
+    if let Err(e) = foreach_inner(
+        &repo,
+        &command.join(" "),
+        recursive,
+        quiet,
+        Path::new(""),
+        &toplevel,
+    ) {
+        println!("fatal: {}", e);
+    }
+    Ok(())
+}
+
+fn foreach_inner(
+    repo: &gix::Repository,
+    command: &str,
+    recursive: bool,
+    quiet: bool,
+    prefix: &Path,
+    toplevel: &Path,
+) -> Result<()> {
+    let Some(submodules) = repo.submodules()? else {
+        return Ok(());
+    };
+    for submodule in submodules {
+        let Some(submodule_repo) = submodule.open()? else {
+            continue;
+        };
+        let path = submodule.path()?.to_string();
+        let display_path = prefix.join(&path);
+        let worktree = submodule.work_dir()?;
+        let sha1 = submodule_repo
+            .head_id()
+            .map(|id| id.to_string())
+            .unwrap_or_default();
+
+        if !quiet {
+            println!("Entering '{}'", display_path.display());
+        }
+        let display_path = display_path.display().to_string();
+        run_shell(
+            command,
+            &worktree,
+            &[
+                ("name", submodule.name().to_string()),
+                ("sm_path", path.clone()),
+                ("displaypath", display_path.clone()),
+                ("sha1", sha1),
+                ("toplevel", toplevel.display().to_string()),
+            ],
+        )
+        .map_err(|_| anyhow!("run_command returned non-zero status for {}", display_path))?;
+
+        if recursive {
+            foreach_inner(
+                &submodule_repo,
+                command,
+                recursive,
+                quiet,
+                Path::new(&display_path),
+                &worktree,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn run_shell(command: &str, dir: &Path, env: &[(&str, String)]) -> Result<()> {
+    let status = std::process::Command::new(if cfg!(windows) { "cmd" } else { "sh" })
+        .arg(if cfg!(windows) { "/C" } else { "-c" })
+        .arg(command)
+        .current_dir(dir)
+        .envs(env.iter().map(|(key, value)| (*key, value)))
+        .status()?;
+    if !status.success() {
+        bail!("'{}' failed with {}", command, status);
+    }
+    Ok(())
+}
+
+/// Unregister submodules and remove their worktrees, keeping the repositories in `.git/modules`
+pub fn deinit(paths: Vec<PathBuf>, all: bool, force: bool) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    if paths.is_empty() && !all {
+        println!("fatal: Use '--all' if you really want to deinitialize all submodules");
+        return Ok(());
+    }
+
+    for submodule in selected(&repo, &paths)? {
+        let path = submodule.path()?.to_string();
+        let worktree = submodule.work_dir()?;
+
+        if let Some(submodule_repo) = submodule.open()? {
+            if !force && submodule_repo.is_dirty()? {
+                println!(
+                    "error: the following file has local modifications:\n    {}\n(use --force to discard them)",
+                    path
+                );
+                return Ok(());
+            }
+            if submodule.state()?.is_old_form {
+                println!(
+                    "fatal: Submodule work tree '{}' contains a .git directory. This will be replaced with a .git file by using absorbgitdirs.",
+                    path
+                );
+                return Ok(());
+            }
+        }
+
+        if worktree.is_dir() {
+            std::fs::remove_dir_all(&worktree)?;
+            std::fs::create_dir_all(&worktree)?;
+            println!("Cleared directory '{}'", path);
+        }
+
+        if is_initialized(&repo, submodule.name()) {
+            let url = repo
+                .config_snapshot()
+                .string(format!("submodule.{}.url", submodule.name()).as_str())
+                .map(|url| url.to_string())
+                .unwrap_or_default();
+            let mut config = open_local_config(&repo)?;
+            config.remove_section("submodule", Some(submodule.name()));
+            write_local_config(&repo, &config)?;
+            println!(
+                "Submodule '{}' ({}) unregistered for path '{}'",
+                submodule.name(),
+                url,
+                path
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Move the repositories of submodules into `.git/modules` of the superproject
+pub fn absorbgitdirs(paths: Vec<PathBuf>) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    for submodule in selected(&repo, &paths)? {
+        if !submodule.state()?.is_old_form {
+            continue;
+        }
+        let path = submodule.path()?.to_string();
+        println!(
+            "Migrating git directory of '{}' from '{}/.git' to '.git/modules/{}'",
+            path,
+            path,
+            submodule.name()
+        );
+        absorb_git_dir(&repo, submodule.name(), &submodule.work_dir()?)?;
+    }
+    Ok(())
+}
+
+fn open_repo() -> Option<gix::Repository> {
+    match gix::discover(".") {
+        Ok(repo) => Some(repo),
+        Err(_) => {
+            println!("fatal: not a git repository (or any of the parent directories): .git");
+            None
+        }
+    }
+}
+
+/// The submodules of `repo` at or below any of `paths`, or all of them if `paths` is empty.
+fn selected<'repo>(
+    repo: &'repo gix::Repository,
+    paths: &[PathBuf],
+) -> Result<Vec<gix::Submodule<'repo>>> {
+    let Some(submodules) = repo.submodules()? else {
+        return Ok(Vec::new());
+    };
+    let paths: Vec<String> = paths
+        .iter()
+        .map(|path| {
+            gix::path::to_unix_separators_on_windows(gix::path::into_bstr(path.as_path()))
+                .to_str_lossy()
+                .trim_end_matches('/')
+                .trim_start_matches("./")
+                .to_string()
+        })
+        .collect();
+
+    let mut selected = Vec::new();
+    for submodule in submodules {
+        let path = submodule.path()?.to_string();
+        let is_selected = paths.is_empty()
+            || paths.iter().any(|prefix| {
+                prefix.is_empty()
+                    || prefix == "."
+                    || path == *prefix
+                    || path.starts_with(&format!("{}/", prefix))
+            });
+        if is_selected {
+            selected.push(submodule);
+        }
+    }
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    use super::super::clone::Options as CloneOptions;
+    use super::update_existing;
+
+    /// Run `git` in `dir`, returning its trimmed output.
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args([
+                "-c",
+                "user.name=A U Thor",
+                "-c",
+                "user.email=author@example.com",
+                "-c",
+                "protocol.file.allow=always",
+            ])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .expect("git can be run");
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8(output.stdout).unwrap().trim().to_owned()
+    }
+
+    /// A superproject with the submodule `sub` whose worktree is one commit behind the recorded one and has
+    /// a modified `file`, or `None` if git isn't installed.
+    fn superproject(root: &Path) -> Option<PathBuf> {
+        Command::new("git").arg("--version").output().ok()?;
+        let upstream = root.join("upstream");
+        std::fs::create_dir_all(&upstream).unwrap();
+        git(&upstream, &["init", "--quiet", "--initial-branch=main"]);
+        for content in ["one\n", "two\n"] {
+            std::fs::write(upstream.join("file"), content).unwrap();
+            git(&upstream, &["add", "file"]);
+            git(&upstream, &["commit", "--quiet", "-m", content.trim()]);
+        }
+
+        let dir = root.join("super");
+        std::fs::create_dir_all(&dir).unwrap();
+        git(&dir, &["init", "--quiet", "--initial-branch=main"]);
+        git(
+            &dir,
+            &[
+                "submodule",
+                "--quiet",
+                "add",
+                upstream.to_str().unwrap(),
+                "sub",
+            ],
+        );
+        git(&dir, &["commit", "--quiet", "-m", "add sub"]);
+        git(&dir.join("sub"), &["checkout", "--quiet", "HEAD~1"]);
+        std::fs::write(dir.join("sub/file"), "local\n").unwrap();
+        Some(dir)
+    }
+
+    fn quiet() -> CloneOptions {
+        CloneOptions {
+            quiet: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn update_keeps_local_modifications_without_force() {
+        let root = tempfile::tempdir().unwrap();
+        let Some(dir) = superproject(root.path()) else {
+            return;
+        };
+        let repo = gix::open(&dir).unwrap();
+        let submodule = repo.submodules().unwrap().unwrap().next().unwrap();
+        let head = git(&dir.join("sub"), &["rev-parse", "HEAD"]);
+
+        assert!(update_existing(&submodule, false, &quiet()).is_err());
+        assert_eq!(git(&dir.join("sub"), &["rev-parse", "HEAD"]), head);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("sub/file")).unwrap(),
+            "local\n"
+        );
+    }
+
+    #[test]
+    fn update_discards_local_modifications_with_force() {
+        let root = tempfile::tempdir().unwrap();
+        let Some(dir) = superproject(root.path()) else {
+            return;
+        };
+        let repo = gix::open(&dir).unwrap();
+        let submodule = repo.submodules().unwrap().unwrap().next().unwrap();
+        let recorded = submodule.index_id().unwrap().unwrap();
+
+        update_existing(&submodule, true, &quiet()).unwrap();
+        assert_eq!(
+            git(&dir.join("sub"), &["rev-parse", "HEAD"]),
+            recorded.to_string()
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("sub/file")).unwrap(),
+            "two\n"
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use anyhow::{bail, Result};
+use gix::bstr::{BStr, ByteSlice};
+use std::path::{Component, Path, PathBuf};
+
+use super::config_utils::{open_local_config, write_local_config};
+
+/// Resolve a submodule `url` like `../lib.git` against the URL of the superproject's default remote,
+/// or against its worktree if it has no remote. Absolute URLs are returned unchanged.
+pub fn resolve_url(repo: &gix::Repository, url: &str) -> String {
+    if !(url.starts_with("./") || url.starts_with("../")) {
+        return url.to_string();
+    }
+
+    let base = repo
+        .find_default_remote(gix::remote::Direction::Fetch)
+        .and_then(Result::ok)
+        .and_then(|remote| {
+            remote
+                .url(gix::remote::Direction::Fetch)
+                .map(|url| url.to_bstring().to_string())
+        })
+        .unwrap_or_else(|| {
+            repo.workdir()
+                .unwrap_or_else(|| repo.path())
+                .display()
+                .to_string()
+        });
+    join_relative_url(&base, url)
+}
+
+/// Apply the `./` and `../` components of `relative` to `base`, the way `git submodule` does.
+fn join_relative_url(base: &str, relative: &str) -> String {
+    let mut base = base.trim_end_matches('/').to_string();
+    let mut relative = relative;
+    loop {
+        if let Some(rest) = relative.strip_prefix("./") {
+            relative = rest;
+        } else if let Some(rest) = relative.strip_prefix("../") {
+            relative = rest;
+            // The separator may also be the colon of scp-like URLs, as in `host:path/repo.git`.
+            match base.rfind(['/', ':']) {
+                Some(idx) if base[idx..].starts_with(':') => base.truncate(idx + 1),
+                Some(idx) => base.truncate(idx),
+                None => base = ".".into(),
+            }
+        } else {
+            break;
+        }
+    }
+    if base.ends_with(':') {
+        format!("{}{}", base, relative)
+    } else {
+        format!("{}/{}", base, relative)
+    }
+}
+
+/// Record the submodule `name` as initialized in the superproject configuration, like `git submodule init` does
+pub fn register(repo: &gix::Repository, name: &BStr, url: &str) -> Result<()> {
+    let mut config = open_local_config(repo)?;
+    config.set_raw_value_by("submodule", Some(name), "url", url)?;
+    config.set_raw_value_by("submodule", Some(name), "active", "true")?;
+    write_local_config(repo, &config)
+}
+
+/// Move the `.git` directory of the submodule checked out at `worktree` into `.git/modules/<name>`
+/// of the superproject, leaving a `.git` file pointing to it.
+pub fn absorb_git_dir(repo: &gix::Repository, name: &BStr, worktree: &Path) -> Result<()> {
+    let dot_git = worktree.join(".git");
+    if !dot_git.is_dir() {
+        return Ok(());
+    }
+
+    let git_dir = repo
+        .common_dir()
+        .join("modules")
+        .join(gix::path::from_bstr(name));
+    if git_dir.exists() {
+        bail!(
+            "cannot absorb '{}' as '{}' already exists",
+            worktree.display(),
+            git_dir.display()
+        );
+    }
+    if let Some(parent) = git_dir.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::rename(&dot_git, &git_dir)?;
+
+    let worktree = gix::path::realpath(worktree)?;
+    let git_dir = gix::path::realpath(&git_dir)?;
+    std::fs::write(
+        &dot_git,
+        format!(
+            "gitdir: {}\n",
+            to_unix_path(&relative_path(&worktree, &git_dir))
+        ),
+    )?;
+
+    let config_path = git_dir.join("config");
+    let mut config =
+        gix::config::File::from_path_no_includes(config_path.clone(), gix::config::Source::Local)?;
+    config.set_raw_value_by(
+        "core",
+        None,
+        "worktree",
+        to_unix_path(&relative_path(&git_dir, &worktree)).as_str(),
+    )?;
+    let mut buf = Vec::new();
+    config.write_to(&mut buf)?;
+    std::fs::write(config_path, buf)?;
+    Ok(())
+}
+
+/// The path to get from directory `from` to `to`, both of which must be absolute.
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component<'_>> = from.components().collect();
+    let to: Vec<Component<'_>> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut path = PathBuf::new();
+    for _ in common..from.len() {
+        path.push("..");
+    }
+    for component in &to[common..] {
+        path.push(component);
+    }
+    path
+}
+
+fn to_unix_path(path: &Path) -> String {
+    gix::path::to_unix_separators_on_windows(gix::path::into_bstr(path))
+        .to_str_lossy()
+        .into_owned()
+}
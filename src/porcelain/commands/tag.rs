@@ -0,0 +1,322 @@
+use anyhow::{anyhow, Result};
+use gix::bstr::ByteSlice;
+use gix::refs::transaction::PreviousValue;
+use std::path::PathBuf;
+
+use super::commit_utils::configured_committer;
+use super::editor_utils::{cleanup, edit, Cleanup};
+use super::revision_utils::rev_parse_single;
+use super::signing_utils::{sign, sign_by_default, signing_key};
+use super::verify::verify_tag;
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// List tags matching the patterns given as arguments
+    pub list: bool,
+    /// Delete the tags given as arguments
+    pub delete: bool,
+    /// Verify the signatures of the tags given as arguments
+    pub verify: bool,
+    /// Create an annotated tag object
+    pub annotate: bool,
+    /// Create a signed tag object, `None` if unset to fall back to `tag.gpgSign`
+    pub sign: Option<bool>,
+    /// Sign with this key instead of the default one
+    pub local_user: Option<String>,
+    /// Paragraphs of the tag message, one per `-m`
+    pub messages: Vec<String>,
+    /// Read the message from this file, or from stdin if it's `-`
+    pub file: Option<PathBuf>,
+    /// Replace an existing tag
+    pub force: bool,
+}
+
+/// Create, list, delete or verify tags depending on `opts`, with `args` being tag names or patterns.
+pub fn run(args: Vec<String>, opts: Options) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    if opts.delete {
+        return delete(&repo, &args);
+    }
+    if opts.verify {
+        let mut failed = false;
+        for name in &args {
+            match repo.try_find_reference(format!("refs/tags/{}", name).as_str())? {
+                Some(reference) => {
+                    failed |= !verify_tag(&repo, name, reference.id().detach(), false, false)?
+                }
+                None => {
+                    println!("error: tag '{}' not found.", name);
+                    failed = true;
+                }
+            }
+        }
+        if failed {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if opts.list || args.is_empty() {
+        return list(&repo, &args);
+    }
+    if args.len() > 2 {
+        println!("fatal: too many arguments");
+        return Ok(());
+    }
+    create(&repo, &args[0], args.get(1).map(String::as_str), opts)
+}
+
+/// Print the names of all tags matching one of `patterns`, or all tags without patterns.
+fn list(repo: &gix::Repository, patterns: &[String]) -> Result<()> {
+    let mut names = Vec::new();
+    for reference in repo.references()?.tags()? {
+        let reference = reference.map_err(|e| anyhow!("{}", e))?;
+        let name = reference.name().shorten().to_owned();
+        let matches = patterns.is_empty()
+            || patterns.iter().any(|pattern| {
+                gix::glob::wildmatch(
+                    pattern.as_bytes().as_bstr(),
+                    name.as_bstr(),
+                    gix::glob::wildmatch::Mode::empty(),
+                )
+            });
+        if matches {
+            names.push(name);
+        }
+    }
+    names.sort();
+    for name in names {
+        println!("{}", name);
+    }
+    Ok(())
+}
+
+fn delete(repo: &gix::Repository, names: &[String]) -> Result<()> {
+    let mut failed = false;
+    for name in names {
+        match repo.try_find_reference(format!("refs/tags/{}", name).as_str())? {
+            Some(reference) => {
+                let id = reference.id().detach();
+                reference.delete()?;
+                println!("Deleted tag '{}' (was {})", name, id.to_hex_with_len(7));
+            }
+            None => {
+                println!("error: tag '{}' not found.", name);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Create the tag `name` pointing to `target`, or `HEAD`, as a tag object if there is a message or a signature.
+fn create(repo: &gix::Repository, name: &str, target: Option<&str>, opts: Options) -> Result<()> {
+    if gix::refs::FullName::try_from(format!("refs/tags/{}", name)).is_err() {
+        println!("fatal: '{}' is not a valid tag name.", name);
+        return Ok(());
+    }
+    let spec = target.unwrap_or("HEAD");
+    let Some(object) = rev_parse_single(repo, spec)
+        .ok()
+        .and_then(|id| id.object().ok())
+    else {
+        println!("fatal: Failed to resolve '{}' as a valid ref.", spec);
+        return Ok(());
+    };
+    let previous = repo
+        .try_find_reference(format!("refs/tags/{}", name).as_str())?
+        .map(|reference| reference.id().detach());
+    if previous.is_some() && !opts.force {
+        println!("fatal: tag '{}' already exists", name);
+        return Ok(());
+    }
+
+    let sign = opts.local_user.is_some()
+        || opts
+            .sign
+            .unwrap_or_else(|| sign_by_default(repo, "tag.gpgSign"));
+    let annotated = sign || opts.annotate || !opts.messages.is_empty() || opts.file.is_some();
+    let id = if annotated {
+        let signing_key = if sign {
+            match signing_key(repo, opts.local_user.as_deref())? {
+                Some(key) => Some(key),
+                None => return Ok(()),
+            }
+        } else {
+            None
+        };
+        let Some(message) = tag_message(repo, name, &opts)? else {
+            return Ok(());
+        };
+        let Some(id) = write_tag(repo, name, &object, &message, signing_key.as_deref())? else {
+            println!("fatal: unable to sign the tag");
+            return Ok(());
+        };
+        id
+    } else {
+        object.id
+    };
+
+    repo.tag_reference(name, id, PreviousValue::Any)?;
+    if let Some(previous) = previous.filter(|previous| *previous != id) {
+        println!(
+            "Updated tag '{}' (was {})",
+            name,
+            previous.to_hex_with_len(7)
+        );
+    }
+    Ok(())
+}
+
+/// The message of the new tag `name`, from `-m`, `-F` or the editor, with comments removed.
+///
+/// Returns `None` after reporting that the message can't be read or is empty.
+fn tag_message(repo: &gix::Repository, name: &str, opts: &Options) -> Result<Option<String>> {
+    let message = if let Some(file) = &opts.file {
+        let read = if file.as_os_str() == "-" {
+            std::io::read_to_string(std::io::stdin())
+        } else {
+            std::fs::read_to_string(file)
+        };
+        match read {
+            Ok(message) => message,
+            Err(err) => {
+                println!(
+                    "fatal: could not open or read '{}': {}",
+                    file.display(),
+                    err
+                );
+                return Ok(None);
+            }
+        }
+    } else if !opts.messages.is_empty() {
+        opts.messages.join("\n\n")
+    } else {
+        let path = repo.path().join("TAG_EDITMSG");
+        std::fs::write(
+            &path,
+            format!(
+                "\n#\n# Write a message for tag:\n#   {}\n# Lines starting with '#' will be ignored.\n#\n",
+                name
+            ),
+        )?;
+        let Some(edited) = edit(repo, &path)? else {
+            println!("The tag message has been left in {}", path.display());
+            return Ok(None);
+        };
+        edited
+    };
+    let message = cleanup(&message, Cleanup::Strip);
+    if message.is_empty() && opts.file.is_none() && opts.messages.is_empty() {
+        println!("fatal: no tag message?");
+        return Ok(None);
+    }
+    Ok(Some(message))
+}
+
+/// Write a tag object for `object` and return its id, or `None` if it couldn't be signed with `signing_key`.
+fn write_tag(
+    repo: &gix::Repository,
+    name: &str,
+    object: &gix::Object<'_>,
+    message: &str,
+    signing_key: Option<&str>,
+) -> Result<Option<gix::ObjectId>> {
+    let mut tag = gix::objs::Tag {
+        target: object.id,
+        target_kind: object.kind,
+        name: name.into(),
+        tagger: Some(configured_committer(repo)?),
+        message: message.into(),
+        pgp_signature: None,
+    };
+    if let Some(key) = signing_key {
+        // The signature covers the tag as it would be written without it, and is appended to the message.
+        let mut payload = Vec::new();
+        gix::objs::WriteTo::write_to(&tag, &mut payload)?;
+        let Some(signature) = sign(repo, &payload, key)? else {
+            return Ok(None);
+        };
+        // The newline ending the message separates it from the signature when the tag is written.
+        tag.message = message.strip_suffix('\n').unwrap_or(message).into();
+        tag.pgp_signature = Some(signature.into());
+    }
+    Ok(Some(repo.write_object(&tag)?.detach()))
+}
+
+fn open_repo() -> Option<gix::Repository> {
+    match gix::discover(".") {
+        Ok(repo) => Some(repo),
+        Err(_) => {
+            println!("fatal: not a git repository (or any of the parent directories): .git");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tag_message, Options};
+
+    /// A repository whose `core.editor` runs `script` on the message file, passed as `$1`.
+    fn repo_with_editor(root: &std::path::Path, script: &str) -> gix::Repository {
+        let repo = gix::init(root.join("repo")).unwrap();
+        let editor = root.join("editor.sh");
+        std::fs::write(&editor, script).unwrap();
+        let config = repo.path().join("config");
+        let mut content = std::fs::read_to_string(&config).unwrap();
+        content.push_str(&format!("[core]\n\teditor = sh '{}'\n", editor.display()));
+        std::fs::write(&config, content).unwrap();
+        gix::open(repo.path()).unwrap()
+    }
+
+    fn annotated() -> Options {
+        Options {
+            annotate: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn edited_message_is_stripped() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with_editor(
+            root.path(),
+            "grep -q '^#   v1.0$' \"$1\" || exit 1\n\
+             printf '\\nRelease 1.0  \\n# a comment\\n\\n' >>\"$1\"\n",
+        );
+        let message = tag_message(&repo, "v1.0", &annotated()).unwrap();
+        assert_eq!(message.as_deref(), Some("Release 1.0\n"));
+    }
+
+    #[test]
+    fn empty_message_aborts() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with_editor(root.path(), "exit 0\n");
+        assert_eq!(tag_message(&repo, "v1.0", &annotated()).unwrap(), None);
+    }
+
+    #[test]
+    fn failing_editor_aborts() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with_editor(root.path(), "exit 1\n");
+        assert_eq!(tag_message(&repo, "v1.0", &annotated()).unwrap(), None);
+        assert!(repo.path().join("TAG_EDITMSG").exists());
+    }
+
+    #[test]
+    fn given_message_is_not_edited() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with_editor(root.path(), "exit 1\n");
+        let opts = Options {
+            messages: vec!["Release 1.0".into(), "Notes".into()],
+            ..annotated()
+        };
+        let message = tag_message(&repo, "v1.0", &opts).unwrap();
+        assert_eq!(message.as_deref(), Some("Release 1.0\n\nNotes\n"));
+    }
+}
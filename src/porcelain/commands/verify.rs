@@ -0,0 +1,118 @@
+use anyhow::Result;
+use gix::bstr::ByteSlice;
+
+use super::revision_utils::rev_parse_single;
+use super::signing_utils::{split_signature, verify, verify_commit, Verification};
+
+/// Check the signatures of `commits`, printing what the signing program says about them.
+pub fn commit(commits: Vec<String>, verbose: bool, raw: bool) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    let mut failed = false;
+    for name in &commits {
+        let Ok(id) = rev_parse_single(&repo, name) else {
+            println!("error: commit '{}' not found.", name);
+            failed = true;
+            continue;
+        };
+        let object = id.object()?;
+        if object.kind != gix::object::Kind::Commit {
+            println!(
+                "error: {}: cannot verify a non-commit object of type {}.",
+                name, object.kind
+            );
+            failed = true;
+            continue;
+        }
+        let commit = object.into_commit();
+        if verbose {
+            let payload = match commit.signature()? {
+                Some((_, signed_data)) => signed_data.to_bstring(),
+                None => commit.data.as_bstr().to_owned(),
+            };
+            print!("{}", payload);
+        }
+        match verify_commit(&repo, &commit)? {
+            Some(verification) => failed |= !report(&verification, raw),
+            None => failed = true,
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Check the signatures of the annotated `tags`, printing what the signing program says about them.
+pub fn tag(tags: Vec<String>, verbose: bool, raw: bool) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    let mut failed = false;
+    for name in &tags {
+        let Ok(id) = rev_parse_single(&repo, name) else {
+            println!("error: tag '{}' not found.", name);
+            failed = true;
+            continue;
+        };
+        failed |= !verify_tag(&repo, name, id.detach(), verbose, raw)?;
+    }
+    if failed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Verify the tag object `id` found by `name`, returning whether its signature is good.
+pub(super) fn verify_tag(
+    repo: &gix::Repository,
+    name: &str,
+    id: gix::ObjectId,
+    verbose: bool,
+    raw: bool,
+) -> Result<bool> {
+    let object = repo.find_object(id)?;
+    if object.kind != gix::object::Kind::Tag {
+        println!(
+            "error: {}: cannot verify a non-tag object of type {}.",
+            name, object.kind
+        );
+        return Ok(false);
+    }
+    let Some((payload, signature)) = split_signature(&object.data) else {
+        if verbose {
+            print!("{}", object.data.as_bstr());
+        }
+        println!("error: no signature found");
+        return Ok(false);
+    };
+    if verbose {
+        print!("{}", payload.as_bstr());
+    }
+    let verification = verify(repo, payload, signature)?;
+    Ok(report(&verification, raw))
+}
+
+/// Print the output of the signing program to stderr, like git does, and return whether the signature is good.
+fn report(verification: &Verification, raw: bool) -> bool {
+    eprint!(
+        "{}",
+        if raw {
+            &verification.status
+        } else {
+            &verification.output
+        }
+    );
+    verification.good
+}
+
+fn open_repo() -> Option<gix::Repository> {
+    match gix::discover(".") {
+        Ok(repo) => Some(repo),
+        Err(_) => {
+            println!("fatal: not a git repository (or any of the parent directories): .git");
+            None
+        }
+    }
+}
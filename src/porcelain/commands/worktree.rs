@@ -0,0 +1,503 @@
+use anyhow::Result;
+use gix::bstr::ByteSlice;
+use gix::refs::transaction::PreviousValue;
+use std::path::{Path, PathBuf};
+
+use super::hook_utils::run_hook;
+use super::index_utils::checkout_tree;
+use super::revision_utils::rev_parse_single;
+
+#[derive(Debug, Clone, Default)]
+pub struct AddOptions {
+    /// Create a new branch with this name for the worktree
+    pub new_branch: Option<String>,
+    /// Detach `HEAD` in the new worktree, even if a branch was given
+    pub detach: bool,
+    /// Check out a branch even if it's checked out in another worktree already
+    pub force: bool,
+    /// Keep the new worktree locked so it isn't pruned
+    pub lock: bool,
+    /// Why the worktree is locked
+    pub reason: Option<String>,
+}
+
+/// A worktree along with what's checked out in it
+struct Entry {
+    path: PathBuf,
+    /// The private git directory, `None` for the main worktree
+    admin_dir: Option<PathBuf>,
+    head: Option<gix::ObjectId>,
+    /// The branch `HEAD` refers to, if it's not detached
+    branch: Option<String>,
+    bare: bool,
+    lock_reason: Option<String>,
+    /// Why the worktree can be pruned, if it can
+    prunable: Option<&'static str>,
+}
+
+/// Create a linked worktree at `path` and check out `commitish` in it, or a new branch named after `path`.
+pub fn add(path: PathBuf, commitish: Option<String>, opts: AddOptions) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    let path = std::env::current_dir()?.join(path);
+    if path.exists() && (!path.is_dir() || std::fs::read_dir(&path)?.next().is_some()) {
+        println!("fatal: '{}' already exists", path.display());
+        return Ok(());
+    }
+
+    let resolve = |spec: &str| -> Option<gix::ObjectId> {
+        let object = rev_parse_single(&repo, spec).ok()?.object().ok()?;
+        object.peel_to_commit().ok().map(|commit| commit.id)
+    };
+    let existing_branch = |name: &str| -> Option<String> {
+        let full_name = format!("refs/heads/{}", name);
+        repo.try_find_reference(full_name.as_str())
+            .ok()
+            .flatten()
+            .map(|_| full_name)
+    };
+
+    // Without a branch or commit, a branch named after the worktree is used, and created from `HEAD` if needed.
+    let base_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (new_branch, branch, spec) = match (&opts.new_branch, &commitish) {
+        (Some(new_branch), spec) => (
+            Some(new_branch.clone()),
+            None,
+            spec.clone().unwrap_or("HEAD".into()),
+        ),
+        (None, Some(spec)) if !opts.detach => (None, existing_branch(spec), spec.clone()),
+        (None, Some(spec)) => (None, None, spec.clone()),
+        (None, None) if opts.detach => (None, None, "HEAD".into()),
+        (None, None) => match existing_branch(&base_name) {
+            Some(branch) => (None, Some(branch), base_name.clone()),
+            None => (Some(base_name.clone()), None, "HEAD".into()),
+        },
+    };
+    let Some(commit) = resolve(&spec) else {
+        println!("fatal: invalid reference: {}", spec);
+        return Ok(());
+    };
+
+    if let Some(branch) = &branch {
+        if !opts.force {
+            if let Some(entry) = worktrees(&repo)?
+                .into_iter()
+                .find(|entry| entry.branch.as_deref() == Some(branch.as_str()))
+            {
+                println!(
+                    "fatal: '{}' is already used by worktree at '{}'",
+                    branch.trim_start_matches("refs/heads/"),
+                    entry.path.display()
+                );
+                return Ok(());
+            }
+        }
+    }
+    let branch = match &new_branch {
+        Some(name) => {
+            let full_name = format!("refs/heads/{}", name);
+            if existing_branch(name).is_some() {
+                println!("fatal: a branch named '{}' already exists", name);
+                return Ok(());
+            }
+            repo.reference(
+                full_name.as_str(),
+                commit,
+                PreviousValue::MustNotExist,
+                format!("branch: Created from {}", spec),
+            )?;
+            Some(full_name)
+        }
+        None => branch,
+    };
+
+    // The private git directory is named after the worktree, with a number added if that's taken.
+    let worktrees_dir = repo.common_dir().join("worktrees");
+    let mut id = base_name.clone();
+    let mut counter = 1;
+    while worktrees_dir.join(&id).exists() {
+        id = format!("{}{}", base_name, counter);
+        counter += 1;
+    }
+    let admin_dir = worktrees_dir.join(&id);
+    std::fs::create_dir_all(&admin_dir)?;
+    std::fs::create_dir_all(&path)?;
+    let path = path.canonicalize()?;
+    let admin_dir = admin_dir.canonicalize()?;
+
+    std::fs::write(
+        admin_dir.join("gitdir"),
+        format!("{}\n", path.join(".git").display()),
+    )?;
+    std::fs::write(admin_dir.join("commondir"), "../..\n")?;
+    std::fs::write(
+        admin_dir.join("HEAD"),
+        match &branch {
+            Some(branch) => format!("ref: {}\n", branch),
+            None => format!("{}\n", commit),
+        },
+    )?;
+    if opts.lock {
+        std::fs::write(admin_dir.join("locked"), opts.reason.unwrap_or_default())?;
+    }
+    std::fs::write(
+        path.join(".git"),
+        format!("gitdir: {}\n", admin_dir.display()),
+    )?;
+
+    match (&new_branch, &branch) {
+        (Some(name), _) => println!("Preparing worktree (new branch '{}')", name),
+        (None, Some(branch)) => println!(
+            "Preparing worktree (checking out '{}')",
+            branch.trim_start_matches("refs/heads/")
+        ),
+        (None, None) => println!(
+            "Preparing worktree (detached HEAD {})",
+            commit.to_hex_with_len(7)
+        ),
+    }
+    let worktree_repo = gix::open(&path)?;
+    let commit = worktree_repo.find_commit(commit)?;
+    checkout_tree(&worktree_repo, commit.tree_id()?.detach())?;
+    let null = gix::ObjectId::null(repo.object_hash());
+    run_hook(
+        &worktree_repo,
+        "post-checkout",
+        &[&null.to_string(), &commit.id.to_string(), "1"],
+        None,
+    )?;
+    println!(
+        "HEAD is now at {} {}",
+        commit.id.to_hex_with_len(7),
+        commit
+            .message_raw_sloppy()
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .as_bstr()
+    );
+    Ok(())
+}
+
+/// List the main worktree and all linked worktrees, in git's machine-readable format with `porcelain`.
+pub fn list(porcelain: bool) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    let entries = worktrees(&repo)?;
+
+    if porcelain {
+        for entry in &entries {
+            println!("worktree {}", entry.path.display());
+            if entry.bare {
+                println!("bare");
+            } else {
+                if let Some(head) = entry.head {
+                    println!("HEAD {}", head);
+                }
+                match &entry.branch {
+                    Some(branch) => println!("branch {}", branch),
+                    None => println!("detached"),
+                }
+            }
+            match entry.lock_reason.as_deref() {
+                Some("") => println!("locked"),
+                Some(reason) => println!("locked {}", reason),
+                None => {}
+            }
+            if let Some(reason) = entry.prunable {
+                println!("prunable {}", reason);
+            }
+            println!();
+        }
+        return Ok(());
+    }
+
+    let width = entries
+        .iter()
+        .map(|entry| entry.path.display().to_string().len())
+        .max()
+        .unwrap_or(0);
+    for entry in &entries {
+        let path = entry.path.display().to_string();
+        let mut line = format!("{:width$} ", path);
+        if entry.bare {
+            line.push_str("(bare)");
+        } else {
+            line.push_str(&match entry.head {
+                Some(head) => head.to_hex_with_len(7).to_string(),
+                None => "0000000".into(),
+            });
+            match &entry.branch {
+                Some(branch) => {
+                    line.push_str(&format!(" [{}]", branch.trim_start_matches("refs/heads/")))
+                }
+                None => line.push_str(" (detached HEAD)"),
+            }
+        }
+        if entry.lock_reason.is_some() {
+            line.push_str(" locked");
+        }
+        if entry.prunable.is_some() {
+            line.push_str(" prunable");
+        }
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// Delete the linked worktree at `worktree` along with its administrative files.
+///
+/// Modified or untracked files need one `force`, locked worktrees need two.
+pub fn remove(worktree: PathBuf, force: u8) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    let Some((entry, admin_dir)) = find_linked(&repo, &worktree)? else {
+        return Ok(());
+    };
+    if let Some(reason) = &entry.lock_reason {
+        if force < 2 {
+            print_locked("remove", reason);
+            return Ok(());
+        }
+    }
+
+    if force == 0 && entry.path.is_dir() {
+        let worktree_repo = gix::open(&entry.path)?;
+        let mut status = worktree_repo
+            .status(gix::progress::Discard)?
+            .into_iter(None)?;
+        if status.next().transpose()?.is_some() {
+            println!(
+                "fatal: '{}' contains modified or untracked files, use --force to delete it",
+                worktree.display()
+            );
+            return Ok(());
+        }
+    }
+
+    if entry.path.is_dir() {
+        std::fs::remove_dir_all(&entry.path)?;
+    }
+    std::fs::remove_dir_all(admin_dir)?;
+    remove_empty_worktrees_dir(&repo);
+    Ok(())
+}
+
+/// Remove the administrative files of linked worktrees whose directory doesn't exist anymore.
+pub fn prune(dry_run: bool, verbose: bool) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    for entry in worktrees(&repo)? {
+        let (Some(admin_dir), Some(reason)) = (&entry.admin_dir, entry.prunable) else {
+            continue;
+        };
+        if entry.lock_reason.is_some() {
+            continue;
+        }
+        if dry_run || verbose {
+            println!(
+                "Removing worktrees/{}: {}",
+                admin_dir.file_name().unwrap_or_default().to_string_lossy(),
+                reason
+            );
+        }
+        if !dry_run {
+            std::fs::remove_dir_all(admin_dir)?;
+        }
+    }
+    if !dry_run {
+        remove_empty_worktrees_dir(&repo);
+    }
+    Ok(())
+}
+
+/// Prevent the linked worktree at `worktree` from being pruned, moved or removed.
+pub fn lock(worktree: PathBuf, reason: Option<String>) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    let Some((entry, admin_dir)) = find_linked(&repo, &worktree)? else {
+        return Ok(());
+    };
+    match entry.lock_reason.as_deref() {
+        Some("") => println!("fatal: '{}' is already locked", worktree.display()),
+        Some(reason) => println!(
+            "fatal: '{}' is already locked, reason: {}",
+            worktree.display(),
+            reason
+        ),
+        None => std::fs::write(admin_dir.join("locked"), reason.unwrap_or_default())?,
+    }
+    Ok(())
+}
+
+pub fn unlock(worktree: PathBuf) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    let Some((entry, admin_dir)) = find_linked(&repo, &worktree)? else {
+        return Ok(());
+    };
+    if entry.lock_reason.is_none() {
+        println!("fatal: '{}' is not locked", worktree.display());
+        return Ok(());
+    }
+    std::fs::remove_file(admin_dir.join("locked"))?;
+    Ok(())
+}
+
+/// Move the linked worktree at `worktree` to `new_path`, or into it if it's an existing directory.
+pub fn move_to(worktree: PathBuf, new_path: PathBuf, force: u8) -> Result<()> {
+    let Some(repo) = open_repo() else {
+        return Ok(());
+    };
+    let Some((entry, admin_dir)) = find_linked(&repo, &worktree)? else {
+        return Ok(());
+    };
+    if let Some(reason) = &entry.lock_reason {
+        if force < 2 {
+            print_locked("move", reason);
+            return Ok(());
+        }
+    }
+
+    let mut destination = std::env::current_dir()?.join(new_path);
+    if destination.is_dir() {
+        if let Some(name) = entry.path.file_name() {
+            destination = destination.join(name);
+        }
+    }
+    if destination.exists() {
+        println!("fatal: '{}' already exists", destination.display());
+        return Ok(());
+    }
+    std::fs::rename(&entry.path, &destination)?;
+    let destination = destination.canonicalize()?;
+    std::fs::write(
+        admin_dir.join("gitdir"),
+        format!("{}\n", destination.join(".git").display()),
+    )?;
+    Ok(())
+}
+
+fn open_repo() -> Option<gix::Repository> {
+    match gix::discover(".") {
+        Ok(repo) => Some(repo),
+        Err(_) => {
+            println!("fatal: not a git repository (or any of the parent directories): .git");
+            None
+        }
+    }
+}
+
+/// The main worktree followed by all linked worktrees.
+fn worktrees(repo: &gix::Repository) -> Result<Vec<Entry>> {
+    let main_repo = repo.main_repo()?;
+    let (main_head, main_branch) = read_head(repo, main_repo.git_dir());
+    let mut entries = vec![Entry {
+        path: main_repo
+            .workdir()
+            .unwrap_or_else(|| main_repo.git_dir())
+            .to_owned(),
+        admin_dir: None,
+        head: main_head,
+        branch: main_branch,
+        bare: main_repo.workdir().is_none(),
+        lock_reason: None,
+        prunable: None,
+    }];
+
+    for proxy in repo.worktrees()? {
+        let (head, branch) = read_head(repo, proxy.git_dir());
+        let (path, prunable) = match proxy.base() {
+            Ok(path) if path.is_dir() => (path, None),
+            Ok(path) => (path, Some("gitdir file points to non-existent location")),
+            Err(_) => (proxy.git_dir().to_owned(), Some("invalid gitdir file")),
+        };
+        entries.push(Entry {
+            path,
+            admin_dir: Some(proxy.git_dir().to_owned()),
+            head,
+            branch,
+            bare: false,
+            lock_reason: proxy
+                .lock_reason()
+                .map(|reason| reason.to_str_lossy().into_owned()),
+            prunable,
+        });
+    }
+    Ok(entries)
+}
+
+/// The commit and branch in the `HEAD` file of `git_dir`, which may belong to another worktree than `repo`.
+fn read_head(repo: &gix::Repository, git_dir: &Path) -> (Option<gix::ObjectId>, Option<String>) {
+    let Ok(head) = std::fs::read_to_string(git_dir.join("HEAD")) else {
+        return (None, None);
+    };
+    let head = head.trim();
+    match head.strip_prefix("ref: ") {
+        Some(branch) => {
+            let id = repo
+                .try_find_reference(branch)
+                .ok()
+                .flatten()
+                .and_then(|mut reference| reference.peel_to_id_in_place().ok())
+                .map(|id| id.detach());
+            (id, Some(branch.to_string()))
+        }
+        None => (gix::ObjectId::from_hex(head.as_bytes()).ok(), None),
+    }
+}
+
+/// The linked worktree at `path` and its private git directory, or `None` after reporting why there is none.
+fn find_linked(repo: &gix::Repository, path: &Path) -> Result<Option<(Entry, PathBuf)>> {
+    let wanted = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+    let same = |candidate: &Path| {
+        candidate
+            .canonicalize()
+            .unwrap_or_else(|_| candidate.to_owned())
+            == wanted
+    };
+    for entry in worktrees(repo)? {
+        let name_matches = entry
+            .admin_dir
+            .as_ref()
+            .and_then(|dir| dir.file_name())
+            .is_some_and(|name| Path::new(name) == path);
+        if !same(&entry.path) && !name_matches {
+            continue;
+        }
+        return Ok(match entry.admin_dir.clone() {
+            Some(admin_dir) => Some((entry, admin_dir)),
+            None => {
+                println!("fatal: '{}' is a main working tree", path.display());
+                None
+            }
+        });
+    }
+    println!("fatal: '{}' is not a working tree", path.display());
+    Ok(None)
+}
+
+fn print_locked(action: &str, reason: &str) {
+    if reason.is_empty() {
+        println!("fatal: cannot {} a locked working tree;", action);
+    } else {
+        println!(
+            "fatal: cannot {} a locked working tree, lock reason: {}",
+            action, reason
+        );
+    }
+    println!("use '{} -f -f' to override or unlock first", action);
+}
+
+fn remove_empty_worktrees_dir(repo: &gix::Repository) {
+    std::fs::remove_dir(repo.common_dir().join("worktrees")).ok();
+}
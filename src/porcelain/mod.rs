@@ -1,6 +1,7 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use gix::bstr::BString;
+use std::num::NonZeroU32;
 use std::path::PathBuf;
 
 pub mod commands;
@@ -42,12 +43,42 @@ pub enum Commands {
         /// Create a bare repository
         #[clap(long)]
         bare: bool,
-        /// Number of commits to fetch
+        /// Don't check out HEAD after the clone is complete
+        #[clap(long, short = 'n')]
+        no_checkout: bool,
+        /// Create a shallow clone with history truncated to the given number of commits
         #[clap(long)]
-        depth: Option<u32>,
+        depth: Option<NonZeroU32>,
+        /// Create a shallow clone with history after the given date
+        #[clap(long, value_parser = crate::shared::AsTime, value_name = "DATE")]
+        shallow_since: Option<gix::date::Time>,
+        /// Create a shallow clone with history excluding commits reachable from the given ref
+        #[clap(long, value_parser = crate::shared::AsPartialRefName, value_name = "REF")]
+        shallow_exclude: Vec<gix::refs::PartialName>,
+        /// Check out the given branch instead of the remote HEAD
+        #[clap(short = 'b', long)]
+        branch: Option<String>,
+        /// Only fetch the history of a single branch (implied by the shallow options)
+        #[clap(long, overrides_with = "no_single_branch")]
+        single_branch: bool,
+        /// Fetch the history of all branches, even when creating a shallow clone
+        #[clap(long, overrides_with = "single_branch")]
+        no_single_branch: bool,
+        /// Create a partial clone that omits the objects matched by the filter, like `blob:none` or `tree:0`
+        #[clap(long, value_name = "FILTER-SPEC")]
+        filter: Option<commands::promisor::Filter>,
         /// Clone all submodules recursively
         #[clap(long)]
         recurse_submodules: bool,
+        /// Don't report progress or informational messages
+        #[clap(long, short = 'q')]
+        quiet: bool,
+        /// Report progress on stderr even if it isn't a terminal
+        #[clap(long, overrides_with = "no_progress")]
+        progress: bool,
+        /// Never report progress
+        #[clap(long, overrides_with = "progress")]
+        no_progress: bool,
     },
 
     /// Create an empty Git repository or reinitialize an existing one
@@ -77,15 +108,54 @@ pub enum Commands {
     /// Record changes to the repository
     #[clap(display_order = 4)]
     Commit {
-        /// Commit message
+        /// Commit message, with multiple -m options becoming separate paragraphs
         #[clap(short = 'm', long)]
-        message: Option<String>,
+        message: Vec<String>,
+        /// Read the commit message from a file, or from stdin with '-'
+        #[clap(short = 'F', long, conflicts_with = "message")]
+        file: Option<PathBuf>,
         /// Add all modified files before committing
         #[clap(short = 'a', long)]
         all: bool,
         /// Allow empty commits
         #[clap(long)]
         allow_empty: bool,
+        /// Replace the tip of the current branch with a new commit
+        #[clap(long)]
+        amend: bool,
+        /// Override the commit author, given as 'Name <email>' or as a pattern matching an existing author
+        #[clap(long)]
+        author: Option<String>,
+        /// Override the author date
+        #[clap(long)]
+        date: Option<String>,
+        /// Make the committer the author of the amended commit
+        #[clap(long)]
+        reset_author: bool,
+        /// Add a Signed-off-by trailer for the committer
+        #[clap(short = 's', long)]
+        signoff: bool,
+        /// Add a trailer, given as 'key=value' or 'key: value'
+        #[clap(long = "trailer")]
+        trailers: Vec<String>,
+        /// Bypass the pre-commit and commit-msg hooks
+        #[clap(short = 'n', long)]
+        no_verify: bool,
+        /// Edit the message given with -m or -F
+        #[clap(short = 'e', long)]
+        edit: bool,
+        /// Use the amended commit's message without launching an editor
+        #[clap(long, conflicts_with = "edit")]
+        no_edit: bool,
+        /// How to clean up the message: strip, whitespace, verbatim, scissors or default
+        #[clap(long, value_name = "MODE")]
+        cleanup: Option<String>,
+        /// Sign the commit, with the default key unless KEYID is given
+        #[clap(short = 'S', long, value_name = "KEYID", num_args = 0..=1, default_missing_value = "")]
+        gpg_sign: Option<String>,
+        /// Don't sign the commit, overriding commit.gpgSign
+        #[clap(long, conflicts_with = "gpg_sign")]
+        no_gpg_sign: bool,
     },
 
     /// Show changes between commits, commit and working tree, etc
@@ -113,6 +183,9 @@ pub enum Commands {
         /// Show commit graph
         #[clap(long)]
         graph: bool,
+        /// Check the signatures of signed commits
+        #[clap(long)]
+        show_signature: bool,
     },
 
     /// Show the working tree status
@@ -131,18 +204,54 @@ pub enum Commands {
     Fetch {
         /// Remote name or URL to fetch from
         remote: Option<String>,
-        /// Refspecs to fetch
+        /// Refspecs to fetch, or more remotes with `--multiple`
         #[clap(value_parser = crate::shared::AsBString)]
         refspecs: Vec<BString>,
         /// Show what would be done, without making any changes
         #[clap(long, short = 'n')]
         dry_run: bool,
-        /// Show additional information
+        /// Show additional information, including refs that are up to date
         #[clap(long, short = 'v')]
         verbose: bool,
         /// Fetch all remotes
         #[clap(long)]
         all: bool,
+        /// Allow several remotes to be given, and fetch from each of them
+        #[clap(long)]
+        multiple: bool,
+        /// Remove remote-tracking refs that no longer exist on the remote
+        #[clap(long, short = 'p', overrides_with = "no_prune")]
+        prune: bool,
+        /// Keep remote-tracking refs that no longer exist on the remote, overriding `fetch.prune`
+        #[clap(long, overrides_with = "prune")]
+        no_prune: bool,
+        /// Fetch all tags from the remote
+        #[clap(long, short = 't', overrides_with = "no_tags")]
+        tags: bool,
+        /// Don't fetch any tags, not even those pointing at fetched commits
+        #[clap(long, overrides_with = "tags")]
+        no_tags: bool,
+        /// Limit fetching to the given number of commits from the tip of each remote branch
+        #[clap(long, conflicts_with_all = &["deepen", "unshallow"])]
+        depth: Option<NonZeroU32>,
+        /// Deepen the history of a shallow repository by the given number of commits
+        #[clap(long, conflicts_with = "unshallow")]
+        deepen: Option<u32>,
+        /// Convert a shallow repository into a complete one
+        #[clap(long)]
+        unshallow: bool,
+        /// Only fetch the objects not matched by the filter, and fetch the rest on demand
+        #[clap(long, value_name = "FILTER-SPEC")]
+        filter: Option<commands::promisor::Filter>,
+        /// Don't report progress or informational messages
+        #[clap(long, short = 'q')]
+        quiet: bool,
+        /// Report progress on stderr even if it isn't a terminal
+        #[clap(long, overrides_with = "no_progress")]
+        progress: bool,
+        /// Never report progress
+        #[clap(long, overrides_with = "progress")]
+        no_progress: bool,
     },
 
     /// Join two or more development histories together
@@ -159,22 +268,37 @@ pub enum Commands {
         /// Fast-forward only
         #[clap(long)]
         ff_only: bool,
+        /// Edit the merge message, even if it was given with -m
+        #[clap(short = 'e', long, overrides_with = "no_edit")]
+        edit: bool,
+        /// Use the merge message as it is, without starting an editor
+        #[clap(long, overrides_with = "edit")]
+        no_edit: bool,
     },
 
     /// Reset current HEAD to the specified state
     #[clap(display_order = 10)]
     Reset {
-        /// Commit to reset to
+        /// Commit to reset to, or the tree-ish to take the entries at <paths> from
         commit: Option<String>,
+        /// Only reset the index entries at these paths, leaving HEAD alone
+        #[clap(last = true)]
+        paths: Vec<PathBuf>,
         /// Reset index but not working tree (default)
-        #[clap(long, conflicts_with_all = &["soft", "hard"])]
+        #[clap(long, conflicts_with_all = &["soft", "hard", "keep", "merge"])]
         mixed: bool,
         /// Only reset HEAD
-        #[clap(long, conflicts_with_all = &["mixed", "hard"])]
+        #[clap(long, conflicts_with_all = &["mixed", "hard", "keep", "merge"])]
         soft: bool,
         /// Reset HEAD, index, and working tree
-        #[clap(long, conflicts_with_all = &["soft", "mixed"])]
+        #[clap(long, conflicts_with_all = &["soft", "mixed", "keep", "merge"])]
         hard: bool,
+        /// Reset HEAD, index and the files that differ from the commit, refusing if they have local changes
+        #[clap(long, conflicts_with_all = &["soft", "mixed", "hard", "merge"])]
+        keep: bool,
+        /// Reset HEAD, index and the files that differ from the commit, refusing if they have unstaged changes
+        #[clap(long, conflicts_with_all = &["soft", "mixed", "hard", "keep"])]
+        merge: bool,
     },
 
     /// Get and set repository or global options
@@ -201,6 +325,8 @@ pub enum Commands {
         /// Show remote url after name
         #[clap(short, long)]
         verbose: bool,
+        #[clap(subcommand)]
+        command: Option<RemoteCommands>,
     },
 
     /// Show various types of objects
@@ -257,6 +383,401 @@ pub enum Commands {
         ranges: Vec<std::ops::RangeInclusive<u32>>,
     },
 
+    /// Initialize, update or inspect submodules
+    #[clap(display_order = 16)]
+    Submodule {
+        #[clap(subcommand)]
+        command: Option<SubmoduleCommands>,
+    },
+
+    /// List references in a remote repository
+    #[clap(display_order = 17)]
+    LsRemote {
+        /// Remote name or URL to list refs of, defaulting to the remote of the current branch
+        repository: Option<String>,
+        /// Only show refs whose trailing path components match one of these patterns
+        patterns: Vec<String>,
+        /// Only show branches
+        #[clap(long, short = 'b', visible_alias = "branches")]
+        heads: bool,
+        /// Only show tags
+        #[clap(long, short = 't')]
+        tags: bool,
+        /// Don't show peeled tags or pseudo-refs like HEAD
+        #[clap(long)]
+        refs: bool,
+        /// Show the ref a symbolic ref like HEAD points to
+        #[clap(long)]
+        symref: bool,
+        /// Exit with status 2 if no matching refs are found
+        #[clap(long)]
+        exit_code: bool,
+    },
+
+    /// Restore working tree files or index entries
+    #[clap(display_order = 18)]
+    Restore {
+        /// The files to restore
+        paths: Vec<PathBuf>,
+        /// Restore from this tree-ish instead of the index, or HEAD for the index
+        #[clap(long, short = 's')]
+        source: Option<String>,
+        /// Restore the index
+        #[clap(long, short = 'S')]
+        staged: bool,
+        /// Restore the working tree (default unless --staged is given)
+        #[clap(long, short = 'W')]
+        worktree: bool,
+    },
+
+    /// Remove files from the working tree and from the index
+    #[clap(display_order = 19)]
+    Rm {
+        /// The files to remove
+        paths: Vec<PathBuf>,
+        /// Only remove from the index, keeping the working tree files
+        #[clap(long)]
+        cached: bool,
+        /// Allow recursive removal of directories
+        #[clap(short = 'r')]
+        recursive: bool,
+        /// Remove files even if they have local changes
+        #[clap(long, short = 'f')]
+        force: bool,
+        /// Don't list the removed files
+        #[clap(long, short = 'q')]
+        quiet: bool,
+    },
+
+    /// Move or rename a file, a directory, or a symlink
+    #[clap(display_order = 20)]
+    Mv {
+        /// The sources to move, followed by the destination
+        #[clap(required = true, num_args = 2..)]
+        paths: Vec<PathBuf>,
+        /// Overwrite existing destination files
+        #[clap(long, short = 'f')]
+        force: bool,
+        /// Only show what would be moved
+        #[clap(long, short = 'n')]
+        dry_run: bool,
+        /// Skip sources that can't be moved instead of failing
+        #[clap(short = 'k')]
+        skip_errors: bool,
+        /// Report the names of moved files
+        #[clap(long, short = 'v')]
+        verbose: bool,
+    },
+
+    /// Stash the changes in a dirty working directory away
+    #[clap(display_order = 21)]
+    Stash {
+        #[clap(subcommand)]
+        command: Option<StashCommands>,
+    },
+
+    /// Apply the changes introduced by some existing commits
+    #[clap(display_order = 22)]
+    CherryPick {
+        /// Commits to apply, or ranges like a..b
+        commits: Vec<String>,
+        /// Continue after resolving conflicts
+        #[clap(long = "continue", conflicts_with_all = &["abort", "skip"])]
+        resume: bool,
+        /// Go back to the state before the cherry-pick started
+        #[clap(long, conflicts_with_all = &["resume", "skip"])]
+        abort: bool,
+        /// Skip the commit that stopped and continue with the rest
+        #[clap(long, conflicts_with_all = &["resume", "abort"])]
+        skip: bool,
+        /// Apply the changes without committing them
+        #[clap(long, short = 'n')]
+        no_commit: bool,
+        /// Append a line saying which commit was cherry-picked to the message
+        #[clap(short = 'x')]
+        record_origin: bool,
+        /// The parent number of merge commits to apply the changes relative to, starting at 1
+        #[clap(long, short = 'm')]
+        mainline: Option<usize>,
+    },
+
+    /// Revert some existing commits
+    #[clap(display_order = 23)]
+    Revert {
+        /// Commits to revert, or ranges like a..b
+        commits: Vec<String>,
+        /// Continue after resolving conflicts
+        #[clap(long = "continue", conflicts_with_all = &["abort", "skip"])]
+        resume: bool,
+        /// Go back to the state before the revert started
+        #[clap(long, conflicts_with_all = &["resume", "skip"])]
+        abort: bool,
+        /// Skip the commit that stopped and continue with the rest
+        #[clap(long, conflicts_with_all = &["resume", "abort"])]
+        skip: bool,
+        /// Revert the changes without committing
+        #[clap(long, short = 'n')]
+        no_commit: bool,
+        /// The parent number of merge commits to revert the changes relative to, starting at 1
+        #[clap(long, short = 'm')]
+        mainline: Option<usize>,
+    },
+
+    /// Reapply commits on top of another base tip
+    #[clap(display_order = 24)]
+    Rebase {
+        /// The branch to compare against, the upstream of the current branch if not given
+        upstream: Option<String>,
+        /// The commit to replay the commits onto instead of the upstream
+        #[clap(long)]
+        onto: Option<String>,
+        /// Continue after resolving conflicts
+        #[clap(long = "continue", conflicts_with_all = &["abort", "skip"])]
+        resume: bool,
+        /// Go back to the state before the rebase started
+        #[clap(long, conflicts_with_all = &["resume", "skip"])]
+        abort: bool,
+        /// Skip the commit that stopped and continue with the rest
+        #[clap(long, conflicts_with_all = &["resume", "abort"])]
+        skip: bool,
+        /// Stash local changes before the rebase and apply them again afterwards
+        #[clap(long)]
+        autostash: bool,
+        /// How to rebase merge commits, only 'no' is supported, which makes the history linear
+        #[clap(
+            long,
+            value_name = "MODE",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "rebase-cousins"
+        )]
+        rebase_merges: Option<String>,
+        /// Drop merge commits and make the history linear, which is the default
+        #[clap(long, conflicts_with = "rebase_merges")]
+        no_rebase_merges: bool,
+        /// Bypass the pre-rebase hook
+        #[clap(long)]
+        no_verify: bool,
+    },
+
+    /// Manage multiple working trees
+    #[clap(display_order = 25)]
+    Worktree {
+        #[clap(subcommand)]
+        command: WorktreeCommands,
+    },
+
+    /// Create, list, delete or verify tags
+    #[clap(display_order = 26)]
+    Tag {
+        /// The tag to create and the object it points to, or the tags or patterns to act on
+        args: Vec<String>,
+        /// List tags, optionally only those matching the given patterns
+        #[clap(short = 'l', long)]
+        list: bool,
+        /// Delete the given tags
+        #[clap(short = 'd', long, conflicts_with_all = ["list", "verify"])]
+        delete: bool,
+        /// Verify the signatures of the given tags
+        #[clap(short = 'v', long, conflicts_with = "list")]
+        verify: bool,
+        /// Make an unsigned, annotated tag object
+        #[clap(short = 'a', long)]
+        annotate: bool,
+        /// Make a signed tag with the default key
+        #[clap(short = 's', long)]
+        sign: bool,
+        /// Don't sign the tag, overriding tag.gpgSign
+        #[clap(long, conflicts_with = "sign")]
+        no_sign: bool,
+        /// Make a signed tag with the given key
+        #[clap(short = 'u', long, value_name = "KEYID")]
+        local_user: Option<String>,
+        /// Tag message, with multiple -m options becoming separate paragraphs
+        #[clap(short = 'm', long)]
+        message: Vec<String>,
+        /// Read the tag message from a file, or from stdin with '-'
+        #[clap(short = 'F', long, conflicts_with = "message")]
+        file: Option<PathBuf>,
+        /// Replace an existing tag
+        #[clap(short = 'f', long)]
+        force: bool,
+    },
+
+    /// Check the signatures of commits
+    #[clap(display_order = 27)]
+    VerifyCommit {
+        /// The commits to verify
+        #[clap(required = true)]
+        commits: Vec<String>,
+        /// Print the contents of the commit before verifying it
+        #[clap(short = 'v', long)]
+        verbose: bool,
+        /// Print the raw status output of the signing program instead of the human-readable one
+        #[clap(long)]
+        raw: bool,
+    },
+
+    /// Check the signatures of tags
+    #[clap(display_order = 28)]
+    VerifyTag {
+        /// The tags to verify
+        #[clap(required = true)]
+        tags: Vec<String>,
+        /// Print the contents of the tag before verifying it
+        #[clap(short = 'v', long)]
+        verbose: bool,
+        /// Print the raw status output of the signing program instead of the human-readable one
+        #[clap(long)]
+        raw: bool,
+    },
+
+    /// Remove untracked files from the working tree
+    #[clap(display_order = 29)]
+    Clean {
+        /// Only remove untracked files matching these paths
+        pathspec: Vec<String>,
+        /// Don't remove anything, just show what would be removed
+        #[clap(short = 'n', long)]
+        dry_run: bool,
+        /// Remove files even if clean.requireForce is set, and nested repositories if given twice
+        #[clap(short = 'f', long, action = clap::ArgAction::Count)]
+        force: u8,
+        /// Only report errors, not the files that are removed
+        #[clap(short = 'q', long)]
+        quiet: bool,
+        /// Remove untracked directories as well as untracked files
+        #[clap(short = 'd')]
+        directories: bool,
+        /// Remove ignored files too, without using the standard ignore rules
+        #[clap(short = 'x')]
+        ignored: bool,
+        /// Remove only files ignored by git
+        #[clap(short = 'X', conflicts_with = "ignored")]
+        only_ignored: bool,
+        /// Add a pattern to the ignore rules, which applies even with -x
+        #[clap(short = 'e', long = "exclude", value_name = "PATTERN")]
+        excludes: Vec<String>,
+    },
+
+    /// Create an archive of the files in a tree
+    #[clap(display_order = 30)]
+    Archive {
+        /// The commit or tree to archive
+        #[clap(value_name = "TREE-ISH")]
+        treeish: String,
+        /// Only archive these paths
+        paths: Vec<PathBuf>,
+        /// The archive format, tar, tgz or zip, guessed from the output file name by default
+        #[clap(long)]
+        format: Option<String>,
+        /// Put this in front of every path in the archive, like 'project/'
+        #[clap(long)]
+        prefix: Option<String>,
+        /// Write the archive to this file instead of stdout
+        #[clap(short = 'o', long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Print lines matching a pattern in tracked files, the index or trees
+    #[clap(display_order = 31)]
+    Grep {
+        /// The pattern to search for
+        pattern: String,
+        /// Trees to search instead of the worktree, followed by paths to limit the search to
+        #[clap(value_name = "TREE")]
+        trees: Vec<String>,
+        /// Only search these paths
+        #[clap(last = true)]
+        pathspec: Vec<PathBuf>,
+        /// Ignore case differences between the pattern and the files
+        #[clap(short = 'i', long)]
+        ignore_case: bool,
+        /// Only match the pattern at word boundaries
+        #[clap(short = 'w', long)]
+        word_regexp: bool,
+        /// Prefix matching lines with their line number
+        #[clap(short = 'n', long)]
+        line_number: bool,
+        /// Only print the names of files with matches
+        #[clap(short = 'l', long)]
+        files_with_matches: bool,
+        /// Print the number of matching lines of each file
+        #[clap(short = 'c', long)]
+        count: bool,
+        /// Interpret the pattern as an extended regular expression
+        #[clap(short = 'E', long, conflicts_with_all = &["fixed_strings", "perl_regexp"])]
+        extended_regexp: bool,
+        /// Interpret the pattern as a literal string
+        #[clap(short = 'F', long, conflicts_with = "perl_regexp")]
+        fixed_strings: bool,
+        /// Interpret the pattern as a Perl-compatible regular expression, without lookaround and backreferences
+        #[clap(short = 'P', long)]
+        perl_regexp: bool,
+        /// Search the blobs in the index instead of the worktree
+        #[clap(long)]
+        cached: bool,
+    },
+
+    /// Summarize history by listing commit subjects per author
+    #[clap(display_order = 32)]
+    Shortlog {
+        /// The commits to summarize, like 'v1.0..HEAD', HEAD by default
+        #[clap(value_name = "REVISION-RANGE")]
+        revision: Option<String>,
+        /// Only print the number of commits per author
+        #[clap(short = 's', long)]
+        summary: bool,
+        /// Sort by the number of commits instead of alphabetically
+        #[clap(short = 'n', long)]
+        numbered: bool,
+        /// Show the email address of each author
+        #[clap(short = 'e', long)]
+        email: bool,
+        /// Group by author, committer or the identities in trailer:<key>, may be repeated
+        #[clap(long = "group", value_name = "TYPE")]
+        groups: Vec<String>,
+    },
+
+    /// Show or prune the history of where references pointed
+    #[clap(display_order = 33, args_conflicts_with_subcommands = true)]
+    Reflog {
+        #[clap(subcommand)]
+        command: Option<ReflogCommands>,
+        /// The reference to show the reflog of, HEAD by default
+        reference: Option<String>,
+    },
+
+    /// Pack objects and references, expire reflogs and prune unreachable objects
+    #[clap(display_order = 34)]
+    Gc {
+        /// Only run if there are too many loose objects or packs, per gc.auto and gc.autoPackLimit
+        #[clap(long)]
+        auto: bool,
+        /// Prune unreachable objects older than this, like '2.weeks.ago', 'now' or 'never'
+        #[clap(
+            long,
+            value_name = "DATE",
+            require_equals = true,
+            num_args = 0..=1,
+            default_missing_value = "2.weeks.ago"
+        )]
+        prune: Option<String>,
+        /// Keep all unreachable objects
+        #[clap(long, conflicts_with = "prune")]
+        no_prune: bool,
+        /// Don't report automatic packing
+        #[clap(short = 'q', long)]
+        quiet: bool,
+    },
+
+    /// Run tasks that keep the repository fast
+    #[clap(display_order = 35)]
+    Maintenance {
+        #[clap(subcommand)]
+        command: MaintenanceCommands,
+    },
+
     /// Access to low-level plumbing commands
     #[clap(display_order = 100, hide = true)]
     Plumbing {
@@ -265,6 +786,318 @@ pub enum Commands {
     },
 }
 
+#[derive(Debug, Subcommand)]
+pub enum RemoteCommands {
+    /// Add a remote named <name> for the repository at <url>
+    Add {
+        name: String,
+        url: String,
+        /// Fetch from the new remote right away
+        #[clap(short = 'f')]
+        fetch: bool,
+        /// Only track the given branch, may be repeated
+        #[clap(short = 't', value_name = "BRANCH")]
+        track: Vec<String>,
+        /// Set up the remote as a mirror to fetch from or push to, or both if no value is given
+        #[clap(long, require_equals = true, value_name = "fetch|push")]
+        mirror: Option<Option<commands::remote::Mirror>>,
+    },
+    /// Remove the remote, its remote-tracking branches and configuration
+    #[clap(visible_alias = "rm")]
+    Remove { name: String },
+    /// Rename the remote, its remote-tracking branches and configuration
+    Rename { old: String, new: String },
+    /// Change the URLs of the remote
+    SetUrl {
+        name: String,
+        #[clap(value_name = "NEWURL")]
+        new_url: String,
+        #[clap(value_name = "OLDURL")]
+        old_url: Option<String>,
+        /// Change the push URLs instead of the fetch URLs
+        #[clap(long)]
+        push: bool,
+        /// Add a new URL instead of changing the existing ones
+        #[clap(long, conflicts_with = "delete")]
+        add: bool,
+        /// Delete all URLs containing <NEWURL>
+        #[clap(long)]
+        delete: bool,
+    },
+    /// Print the URLs of the remote
+    GetUrl {
+        name: String,
+        /// Print the push URLs instead of the fetch URLs
+        #[clap(long)]
+        push: bool,
+        /// Print all URLs instead of just the first one
+        #[clap(long)]
+        all: bool,
+    },
+    /// Show information about the remotes
+    Show {
+        names: Vec<String>,
+        /// Don't query the remotes for their branches
+        #[clap(short = 'n')]
+        no_query: bool,
+    },
+    /// Delete remote-tracking branches that no longer exist on the remotes
+    Prune {
+        #[clap(required = true)]
+        names: Vec<String>,
+        /// Only report what would be pruned
+        #[clap(long, short = 'n')]
+        dry_run: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SubmoduleCommands {
+    /// Show the status of submodules
+    Status {
+        /// Only show submodules at or below these paths
+        paths: Vec<PathBuf>,
+        /// Recurse into nested submodules
+        #[clap(long)]
+        recursive: bool,
+    },
+    /// Register submodules in the repository configuration
+    Init {
+        /// Only initialize submodules at or below these paths
+        paths: Vec<PathBuf>,
+    },
+    /// Clone missing submodules and check out the commits recorded in the superproject
+    Update {
+        /// Only update submodules at or below these paths
+        paths: Vec<PathBuf>,
+        /// Initialize submodules that aren't initialized yet
+        #[clap(long)]
+        init: bool,
+        /// Recurse into nested submodules
+        #[clap(long)]
+        recursive: bool,
+        /// Create shallow clones with history truncated to the given number of commits
+        #[clap(long)]
+        depth: Option<NonZeroU32>,
+        /// Discard local modifications in submodules, and check them out even if they are at the recorded commit
+        #[clap(long, short = 'f')]
+        force: bool,
+        /// Only print error messages
+        #[clap(long, short = 'q')]
+        quiet: bool,
+    },
+    /// Update the remote URLs of submodules to the ones in .gitmodules
+    Sync {
+        /// Only synchronize submodules at or below these paths
+        paths: Vec<PathBuf>,
+        /// Recurse into nested submodules
+        #[clap(long)]
+        recursive: bool,
+    },
+    /// Run a shell command in each checked out submodule
+    Foreach {
+        /// The command to run, with $name, $sm_path, $displaypath, $sha1 and $toplevel set
+        #[clap(required = true, trailing_var_arg = true)]
+        command: Vec<String>,
+        /// Recurse into nested submodules
+        #[clap(long)]
+        recursive: bool,
+        /// Don't print the name of each submodule before running the command
+        #[clap(long, short = 'q')]
+        quiet: bool,
+    },
+    /// Unregister submodules and remove their working trees
+    Deinit {
+        /// The submodules to deinitialize
+        paths: Vec<PathBuf>,
+        /// Deinitialize all submodules
+        #[clap(long)]
+        all: bool,
+        /// Remove submodule working trees even if they contain local modifications
+        #[clap(long, short = 'f')]
+        force: bool,
+    },
+    /// Move the git directories of submodules into .git/modules of the superproject
+    Absorbgitdirs {
+        /// Only absorb submodules at or below these paths
+        paths: Vec<PathBuf>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum StashCommands {
+    /// Save local changes as a new stash entry and revert them to HEAD
+    Push {
+        /// Only stash the changes to these paths
+        #[clap(last = true)]
+        paths: Vec<PathBuf>,
+        /// Describe the stash entry with this message
+        #[clap(long, short = 'm')]
+        message: Option<String>,
+        /// Also stash untracked files and remove them
+        #[clap(long, short = 'u')]
+        include_untracked: bool,
+        /// Don't report the saved entry
+        #[clap(long, short = 'q')]
+        quiet: bool,
+    },
+    /// Apply a stash entry and remove it from the stash list
+    Pop {
+        /// The entry to apply, like stash@{1}, defaulting to the latest
+        stash: Option<String>,
+        /// Also restore the changes that were staged
+        #[clap(long)]
+        index: bool,
+        /// Don't show the status afterwards
+        #[clap(long, short = 'q')]
+        quiet: bool,
+    },
+    /// Apply a stash entry, keeping it in the stash list
+    Apply {
+        /// The entry to apply, like stash@{1}, defaulting to the latest
+        stash: Option<String>,
+        /// Also restore the changes that were staged
+        #[clap(long)]
+        index: bool,
+        /// Don't show the status afterwards
+        #[clap(long, short = 'q')]
+        quiet: bool,
+    },
+    /// List the stash entries
+    List,
+    /// Show the changes recorded in a stash entry
+    Show {
+        /// The entry to show, defaulting to the latest
+        stash: Option<String>,
+        /// Show the changes as a patch instead of a diffstat
+        #[clap(long, short = 'p')]
+        patch: bool,
+    },
+    /// Remove a single stash entry
+    Drop {
+        /// The entry to remove, defaulting to the latest
+        stash: Option<String>,
+        /// Don't report the removed entry
+        #[clap(long, short = 'q')]
+        quiet: bool,
+    },
+    /// Remove all stash entries
+    Clear,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ReflogCommands {
+    /// Show the reflog of a reference, most recent entry first
+    Show {
+        /// The reference to show the reflog of, HEAD by default
+        reference: Option<String>,
+    },
+    /// Remove old entries from reflogs
+    Expire {
+        /// The references whose reflogs to prune
+        references: Vec<String>,
+        /// Prune the reflogs of all references
+        #[clap(long)]
+        all: bool,
+        /// Remove entries older than this, like '90.days.ago', 'now' or 'never'
+        #[clap(long, value_name = "TIME")]
+        expire: Option<String>,
+        /// Remove entries older than this whose commit is no longer reachable from the reference
+        #[clap(long, value_name = "TIME")]
+        expire_unreachable: Option<String>,
+    },
+    /// Remove single reflog entries, like HEAD@{2}
+    Delete {
+        #[clap(required = true)]
+        entries: Vec<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MaintenanceCommands {
+    /// Run the gc task, which packs objects and writes the commit-graph
+    Run {
+        /// Only run if there are too many loose objects or packs, per gc.auto and gc.autoPackLimit
+        #[clap(long)]
+        auto: bool,
+        /// Don't report automatic packing
+        #[clap(short = 'q', long)]
+        quiet: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum WorktreeCommands {
+    /// Create a new working tree and check out a branch or commit in it
+    Add {
+        /// Where to create the working tree
+        path: PathBuf,
+        /// The branch or commit to check out, a branch named after the path if not given
+        commitish: Option<String>,
+        /// Create a new branch with this name and check it out
+        #[clap(short = 'b')]
+        new_branch: Option<String>,
+        /// Detach HEAD in the new working tree
+        #[clap(long, conflicts_with = "new_branch")]
+        detach: bool,
+        /// Check out the branch even if it is checked out in another working tree
+        #[clap(long, short = 'f')]
+        force: bool,
+        /// Keep the new working tree locked
+        #[clap(long)]
+        lock: bool,
+        /// Why the new working tree is locked
+        #[clap(long, requires = "lock")]
+        reason: Option<String>,
+    },
+    /// List all working trees
+    List {
+        /// Use git's machine-readable format
+        #[clap(long)]
+        porcelain: bool,
+    },
+    /// Delete a working tree
+    Remove {
+        /// The working tree to delete
+        worktree: PathBuf,
+        /// Delete it even with local changes, and even if locked when given twice
+        #[clap(long, short = 'f', action = clap::ArgAction::Count)]
+        force: u8,
+    },
+    /// Remove the administrative files of working trees that no longer exist
+    Prune {
+        /// Only report what would be removed
+        #[clap(long, short = 'n')]
+        dry_run: bool,
+        /// Report all removals
+        #[clap(long, short = 'v')]
+        verbose: bool,
+    },
+    /// Prevent a working tree from being pruned, moved or deleted
+    Lock {
+        /// The working tree to lock
+        worktree: PathBuf,
+        /// Why the working tree is locked
+        #[clap(long)]
+        reason: Option<String>,
+    },
+    /// Allow a locked working tree to be pruned, moved or deleted again
+    Unlock {
+        /// The working tree to unlock
+        worktree: PathBuf,
+    },
+    /// Move a working tree to a new location
+    Move {
+        /// The working tree to move
+        worktree: PathBuf,
+        /// The new location, or an existing directory to move it into
+        new_path: PathBuf,
+        /// Move it even if locked when given twice
+        #[clap(long, short = 'f', action = clap::ArgAction::Count)]
+        force: u8,
+    },
+}
+
 #[derive(Debug, Subcommand)]
 pub enum PlumbingCommands {
     /// All original gitoxide plumbing commands
@@ -290,9 +1123,49 @@ pub fn main() -> Result<()> {
             repository,
             directory,
             bare,
+            no_checkout,
             depth,
+            shallow_since,
+            shallow_exclude,
+            branch,
+            single_branch,
+            no_single_branch,
+            filter,
             recurse_submodules,
-        } => commands::clone::run(repository, directory, bare, depth, recurse_submodules),
+            quiet,
+            progress,
+            no_progress,
+        } => {
+            let is_shallow =
+                depth.is_some() || shallow_since.is_some() || !shallow_exclude.is_empty();
+            let shallow = if let Some(depth) = depth {
+                gix::remote::fetch::Shallow::DepthAtRemote(depth)
+            } else if !shallow_exclude.is_empty() {
+                gix::remote::fetch::Shallow::Exclude {
+                    remote_refs: shallow_exclude,
+                    since_cutoff: shallow_since,
+                }
+            } else if let Some(cutoff) = shallow_since {
+                gix::remote::fetch::Shallow::Since { cutoff }
+            } else {
+                gix::remote::fetch::Shallow::default()
+            };
+            commands::clone::run(
+                repository,
+                directory,
+                commands::clone::Options {
+                    bare,
+                    recurse_submodules,
+                    shallow,
+                    branch,
+                    single_branch: single_branch || (is_shallow && !no_single_branch),
+                    filter,
+                    quiet,
+                    progress: (progress || no_progress).then_some(progress),
+                    no_checkout,
+                },
+            )
+        }
         Commands::Add {
             pathspec,
             all,
@@ -300,9 +1173,42 @@ pub fn main() -> Result<()> {
         } => commands::add::run(&args.repository, pathspec, all, update),
         Commands::Commit {
             message,
+            file,
             all,
             allow_empty,
-        } => commands::commit::run(&args.repository, message, all, allow_empty),
+            amend,
+            author,
+            date,
+            reset_author,
+            signoff,
+            trailers,
+            no_verify,
+            edit,
+            no_edit,
+            cleanup,
+            gpg_sign,
+            no_gpg_sign,
+        } => commands::commit::run(
+            &args.repository,
+            commands::commit::Options {
+                messages: message,
+                file,
+                all,
+                allow_empty,
+                amend,
+                author,
+                date,
+                reset_author,
+                signoff,
+                trailers,
+                no_verify,
+                edit,
+                no_edit,
+                cleanup,
+                gpg_sign,
+                no_gpg_sign,
+            },
+        ),
         Commands::Diff {
             pathspec,
             cached,
@@ -312,7 +1218,8 @@ pub fn main() -> Result<()> {
             max_count,
             oneline,
             graph,
-        } => commands::log::run(max_count, oneline, graph),
+            show_signature,
+        } => commands::log::run(max_count, oneline, graph, show_signature),
         Commands::Status {
             short,
             untracked_files,
@@ -323,27 +1230,89 @@ pub fn main() -> Result<()> {
             dry_run,
             verbose,
             all,
-        } => commands::fetch::run(&args.repository, remote, refspecs, dry_run, verbose, all),
+            multiple,
+            prune,
+            no_prune,
+            tags,
+            no_tags,
+            depth,
+            deepen,
+            unshallow,
+            filter,
+            quiet,
+            progress,
+            no_progress,
+        } => {
+            let shallow = if let Some(depth) = depth {
+                gix::remote::fetch::Shallow::DepthAtRemote(depth)
+            } else if let Some(deepen) = deepen {
+                gix::remote::fetch::Shallow::Deepen(deepen)
+            } else {
+                gix::remote::fetch::Shallow::default()
+            };
+            commands::fetch::run(
+                &args.repository,
+                remote,
+                refspecs,
+                commands::fetch::Options {
+                    dry_run,
+                    verbose,
+                    all,
+                    multiple,
+                    prune: (prune || no_prune).then_some(prune),
+                    tags: (tags || no_tags).then_some(tags),
+                    shallow,
+                    unshallow,
+                    filter,
+                    quiet,
+                    progress: (progress || no_progress).then_some(progress),
+                },
+            )
+        }
         Commands::Merge {
             commits,
             message,
             no_commit,
             ff_only,
-        } => commands::merge::run(&args.repository, commits, message, no_commit, ff_only),
+            edit,
+            no_edit,
+        } => commands::merge::run(
+            &args.repository,
+            commits,
+            commands::merge::Options {
+                message,
+                no_commit,
+                ff_only,
+                edit: if edit {
+                    Some(true)
+                } else if no_edit {
+                    Some(false)
+                } else {
+                    None
+                },
+            },
+        ),
         Commands::Reset {
             commit,
+            paths,
             mixed: _,
             soft,
             hard,
+            keep,
+            merge,
         } => {
             let mode = if soft {
                 commands::reset::ResetMode::Soft
             } else if hard {
                 commands::reset::ResetMode::Hard
+            } else if keep {
+                commands::reset::ResetMode::Keep
+            } else if merge {
+                commands::reset::ResetMode::Merge
             } else {
                 commands::reset::ResetMode::Mixed
             };
-            commands::reset::run(mode, commit)
+            commands::reset::run(mode, commit, paths)
         }
         Commands::Config {
             key,
@@ -352,7 +1321,43 @@ pub fn main() -> Result<()> {
             global,
             local,
         } => commands::config::run(&args.repository, key, value, global, local),
-        Commands::Remote { verbose } => commands::remote::run(&args.repository, verbose),
+        Commands::Remote { verbose, command } => match command {
+            None => commands::remote::run(&args.repository, verbose),
+            Some(RemoteCommands::Add {
+                name,
+                url,
+                fetch,
+                track,
+                mirror,
+            }) => commands::remote::add(
+                name,
+                url,
+                commands::remote::AddOptions {
+                    fetch,
+                    track,
+                    mirror: mirror.map(|mirror| mirror.unwrap_or(commands::remote::Mirror::Both)),
+                },
+            ),
+            Some(RemoteCommands::Remove { name }) => commands::remote::remove(name),
+            Some(RemoteCommands::Rename { old, new }) => commands::remote::rename(old, new),
+            Some(RemoteCommands::SetUrl {
+                name,
+                new_url,
+                old_url,
+                push,
+                add,
+                delete,
+            }) => commands::remote::set_url(name, new_url, old_url, push, add, delete),
+            Some(RemoteCommands::GetUrl { name, push, all }) => {
+                commands::remote::get_url(name, push, all)
+            }
+            Some(RemoteCommands::Show { names, no_query }) => {
+                commands::remote::show(names, no_query)
+            }
+            Some(RemoteCommands::Prune { names, dry_run }) => {
+                commands::remote::prune(names, dry_run)
+            }
+        },
         Commands::Show {
             objects,
             format,
@@ -382,6 +1387,384 @@ pub fn main() -> Result<()> {
             statistics,
             ranges,
         } => commands::blame::run(&args.repository, file, statistics, ranges),
+        Commands::Submodule { command } => match command {
+            None => commands::submodule::status(Vec::new(), false),
+            Some(SubmoduleCommands::Status { paths, recursive }) => {
+                commands::submodule::status(paths, recursive)
+            }
+            Some(SubmoduleCommands::Init { paths }) => commands::submodule::init(paths),
+            Some(SubmoduleCommands::Update {
+                paths,
+                init,
+                recursive,
+                depth,
+                force,
+                quiet,
+            }) => commands::submodule::update(paths, init, recursive, depth, force, quiet),
+            Some(SubmoduleCommands::Sync { paths, recursive }) => {
+                commands::submodule::sync(paths, recursive)
+            }
+            Some(SubmoduleCommands::Foreach {
+                command,
+                recursive,
+                quiet,
+            }) => commands::submodule::foreach(command, recursive, quiet),
+            Some(SubmoduleCommands::Deinit { paths, all, force }) => {
+                commands::submodule::deinit(paths, all, force)
+            }
+            Some(SubmoduleCommands::Absorbgitdirs { paths }) => {
+                commands::submodule::absorbgitdirs(paths)
+            }
+        },
+        Commands::LsRemote {
+            repository,
+            patterns,
+            heads,
+            tags,
+            refs,
+            symref,
+            exit_code,
+        } => commands::ls_remote::run(
+            repository,
+            patterns,
+            commands::ls_remote::Options {
+                heads,
+                tags,
+                refs,
+                symref,
+                exit_code,
+            },
+        ),
+        Commands::Restore {
+            paths,
+            source,
+            staged,
+            worktree,
+        } => commands::restore::run(paths, source, staged, worktree),
+        Commands::Rm {
+            paths,
+            cached,
+            recursive,
+            force,
+            quiet,
+        } => commands::rm::run(paths, cached, recursive, force, quiet),
+        Commands::Mv {
+            paths,
+            force,
+            dry_run,
+            skip_errors,
+            verbose,
+        } => commands::mv::run(
+            paths,
+            commands::mv::Options {
+                force,
+                dry_run,
+                skip_errors,
+                verbose,
+            },
+        ),
+        Commands::Stash { command } => match command {
+            None => commands::stash::push(Vec::new(), Default::default()),
+            Some(StashCommands::Push {
+                paths,
+                message,
+                include_untracked,
+                quiet,
+            }) => commands::stash::push(
+                paths,
+                commands::stash::PushOptions {
+                    message,
+                    include_untracked,
+                    quiet,
+                },
+            ),
+            Some(StashCommands::Pop {
+                stash,
+                index,
+                quiet,
+            }) => commands::stash::apply(stash, index, true, quiet),
+            Some(StashCommands::Apply {
+                stash,
+                index,
+                quiet,
+            }) => commands::stash::apply(stash, index, false, quiet),
+            Some(StashCommands::List) => commands::stash::list(),
+            Some(StashCommands::Show { stash, patch }) => commands::stash::show(stash, patch),
+            Some(StashCommands::Drop { stash, quiet }) => commands::stash::drop(stash, quiet),
+            Some(StashCommands::Clear) => commands::stash::clear(),
+        },
+        Commands::CherryPick {
+            commits,
+            resume,
+            abort,
+            skip,
+            no_commit,
+            record_origin,
+            mainline,
+        } => {
+            let action = commands::sequencer::Action::CherryPick;
+            if resume {
+                commands::sequencer::resume(action)
+            } else if abort {
+                commands::sequencer::abort(action)
+            } else if skip {
+                commands::sequencer::skip(action)
+            } else {
+                commands::sequencer::run(
+                    action,
+                    commits,
+                    commands::sequencer::Options {
+                        no_commit,
+                        record_origin,
+                        mainline,
+                    },
+                )
+            }
+        }
+        Commands::Revert {
+            commits,
+            resume,
+            abort,
+            skip,
+            no_commit,
+            mainline,
+        } => {
+            let action = commands::sequencer::Action::Revert;
+            if resume {
+                commands::sequencer::resume(action)
+            } else if abort {
+                commands::sequencer::abort(action)
+            } else if skip {
+                commands::sequencer::skip(action)
+            } else {
+                commands::sequencer::run(
+                    action,
+                    commits,
+                    commands::sequencer::Options {
+                        no_commit,
+                        record_origin: false,
+                        mainline,
+                    },
+                )
+            }
+        }
+        Commands::Rebase {
+            upstream,
+            onto,
+            resume,
+            abort,
+            skip,
+            autostash,
+            rebase_merges,
+            no_rebase_merges: _,
+            no_verify,
+        } => {
+            if resume {
+                commands::rebase::resume()
+            } else if abort {
+                commands::rebase::abort()
+            } else if skip {
+                commands::rebase::skip()
+            } else {
+                commands::rebase::run(commands::rebase::Options {
+                    upstream,
+                    onto,
+                    autostash,
+                    rebase_merges,
+                    no_verify,
+                })
+            }
+        }
+        Commands::Worktree { command } => match command {
+            WorktreeCommands::Add {
+                path,
+                commitish,
+                new_branch,
+                detach,
+                force,
+                lock,
+                reason,
+            } => commands::worktree::add(
+                path,
+                commitish,
+                commands::worktree::AddOptions {
+                    new_branch,
+                    detach,
+                    force,
+                    lock,
+                    reason,
+                },
+            ),
+            WorktreeCommands::List { porcelain } => commands::worktree::list(porcelain),
+            WorktreeCommands::Remove { worktree, force } => {
+                commands::worktree::remove(worktree, force)
+            }
+            WorktreeCommands::Prune { dry_run, verbose } => {
+                commands::worktree::prune(dry_run, verbose)
+            }
+            WorktreeCommands::Lock { worktree, reason } => {
+                commands::worktree::lock(worktree, reason)
+            }
+            WorktreeCommands::Unlock { worktree } => commands::worktree::unlock(worktree),
+            WorktreeCommands::Move {
+                worktree,
+                new_path,
+                force,
+            } => commands::worktree::move_to(worktree, new_path, force),
+        },
+        Commands::Tag {
+            args,
+            list,
+            delete,
+            verify,
+            annotate,
+            sign,
+            no_sign,
+            local_user,
+            message,
+            file,
+            force,
+        } => commands::tag::run(
+            args,
+            commands::tag::Options {
+                list,
+                delete,
+                verify,
+                annotate,
+                sign: if sign {
+                    Some(true)
+                } else if no_sign {
+                    Some(false)
+                } else {
+                    None
+                },
+                local_user,
+                messages: message,
+                file,
+                force,
+            },
+        ),
+        Commands::VerifyCommit {
+            commits,
+            verbose,
+            raw,
+        } => commands::verify::commit(commits, verbose, raw),
+        Commands::VerifyTag { tags, verbose, raw } => commands::verify::tag(tags, verbose, raw),
+        Commands::Clean {
+            pathspec,
+            dry_run,
+            force,
+            quiet,
+            directories,
+            ignored,
+            only_ignored,
+            excludes,
+        } => commands::clean::run(
+            pathspec,
+            commands::clean::Options {
+                dry_run,
+                force,
+                quiet,
+                directories,
+                ignored,
+                only_ignored,
+                excludes,
+            },
+        ),
+        Commands::Archive {
+            treeish,
+            paths,
+            format,
+            prefix,
+            output,
+        } => commands::archive::run(
+            treeish,
+            paths,
+            commands::archive::Options {
+                format,
+                prefix,
+                output,
+            },
+        ),
+        Commands::Grep {
+            pattern,
+            trees,
+            pathspec,
+            ignore_case,
+            word_regexp,
+            line_number,
+            files_with_matches,
+            count,
+            extended_regexp,
+            fixed_strings,
+            perl_regexp,
+            cached,
+        } => commands::grep::run(
+            pattern,
+            trees,
+            pathspec,
+            commands::grep::Options {
+                ignore_case,
+                word_regexp,
+                line_number,
+                files_with_matches,
+                count,
+                pattern_type: if extended_regexp {
+                    commands::grep::PatternType::Extended
+                } else if fixed_strings {
+                    commands::grep::PatternType::Fixed
+                } else if perl_regexp {
+                    commands::grep::PatternType::Perl
+                } else {
+                    commands::grep::PatternType::Basic
+                },
+                cached,
+            },
+        ),
+        Commands::Shortlog {
+            revision,
+            summary,
+            numbered,
+            email,
+            groups,
+        } => commands::shortlog::run(
+            revision,
+            commands::shortlog::Options {
+                summary,
+                numbered,
+                email,
+                groups,
+            },
+        ),
+        Commands::Reflog { command, reference } => match command {
+            None => commands::reflog::show(reference),
+            Some(ReflogCommands::Show { reference }) => commands::reflog::show(reference),
+            Some(ReflogCommands::Expire {
+                references,
+                all,
+                expire,
+                expire_unreachable,
+            }) => commands::reflog::expire(references, all, expire, expire_unreachable),
+            Some(ReflogCommands::Delete { entries }) => commands::reflog::delete(entries),
+        },
+        Commands::Gc {
+            auto,
+            prune,
+            no_prune,
+            quiet,
+        } => commands::gc::run(commands::gc::Options {
+            auto,
+            prune,
+            no_prune,
+            quiet,
+        }),
+        Commands::Maintenance { command } => match command {
+            MaintenanceCommands::Run { auto, quiet } => commands::gc::run(commands::gc::Options {
+                auto,
+                quiet,
+                ..Default::default()
+            }),
+        },
         Commands::Plumbing { command } => match command {
             PlumbingCommands::External(_args) => crate::plumbing::main(),
         },